@@ -0,0 +1,18 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 inColor;
+
+layout(binding = 0) uniform Ubo {
+  mat4 view;
+  mat4 projection;
+} ubo;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+  outColor = inColor;
+  gl_Position = ubo.projection * ubo.view * vec4(inPosition, 1.0);
+}
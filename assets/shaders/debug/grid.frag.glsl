@@ -0,0 +1,54 @@
+#version 450
+
+layout(location = 0) in vec2 inUV;
+
+layout(binding = 0) uniform Ubo {
+  mat4 viewProjection;
+  mat4 inverseViewProjection;
+  vec4 color;
+  float spacing;
+  float fadeDistance;
+} ubo;
+
+layout(location = 0) out vec4 outColor;
+
+// The fraction of a line covered by the pixel, using screen space derivatives to keep lines a pixel wide
+float gridLine(vec2 coordinate)
+{
+    vec2 derivative = fwidth(coordinate);
+    vec2 grid = abs(fract(coordinate - 0.5) - 0.5) / derivative;
+    return 1.0 - min(min(grid.x, grid.y), 1.0);
+}
+
+vec3 unproject(vec2 ndc, float depth)
+{
+    vec4 position = ubo.inverseViewProjection * vec4(ndc, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+void main()
+{
+    // The ray through the pixel is intersected with the ground plane at y = 0
+    vec2 ndc = inUV * 2.0 - 1.0;
+    vec3 nearPoint = unproject(ndc, 0.0);
+    vec3 farPoint = unproject(ndc, 1.0);
+    float t = -nearPoint.y / (farPoint.y - nearPoint.y);
+    if (t <= 0.0) {
+        discard;
+    }
+
+    vec3 position = nearPoint + t * (farPoint - nearPoint);
+    vec4 clipPosition = ubo.viewProjection * vec4(position, 1.0);
+    float depth = clipPosition.z / clipPosition.w;
+    if (depth > 1.0) {
+        discard;
+    }
+    gl_FragDepth = depth;
+
+    float fade = 1.0 - clamp(distance(position, nearPoint) / ubo.fadeDistance, 0.0, 1.0);
+    float alpha = gridLine(position.xz / ubo.spacing) * ubo.color.a * fade;
+    if (alpha <= 0.0) {
+        discard;
+    }
+    outColor = vec4(ubo.color.rgb, alpha);
+}
@@ -0,0 +1,72 @@
+#version 450
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0) uniform sampler2D color;
+
+layout(binding = 1) buffer ExposureState {
+  // Zero until the first frame has been measured
+  float averageLuminance;
+  float exposure;
+} state;
+
+layout(binding = 2) uniform FrameData {
+  float deltaTime;
+} frame;
+
+layout(push_constant) uniform AutoExposure {
+  float minExposure;
+  float maxExposure;
+  // How quickly the exposure follows changes in brightness
+  float adaptationSpeed;
+  // The luminance the average scene luminance is exposed to
+  float keyValue;
+} autoExposure;
+
+// Each invocation averages a block of samples, spread evenly over the whole image
+const uint SamplesPerInvocation = 4;
+const uint InvocationCount = 16 * 16;
+const float GridSize = 16.0 * SamplesPerInvocation;
+
+// Keeps black pixels from pulling the logarithm to negative infinity
+const float MinLuminance = 0.0001;
+const vec3 Luminance = vec3(0.2126, 0.7152, 0.0722);
+
+shared float logLuminances[InvocationCount];
+
+void main() {
+    float logLuminance = 0.0;
+    for (uint y = 0; y < SamplesPerInvocation; y++) {
+        for (uint x = 0; x < SamplesPerInvocation; x++) {
+            vec2 cell = vec2(gl_LocalInvocationID.xy * SamplesPerInvocation + uvec2(x, y));
+            vec3 rgb = textureLod(color, (cell + 0.5) / GridSize, 0.0).rgb;
+            logLuminance += log(max(dot(rgb, Luminance), MinLuminance));
+        }
+    }
+    logLuminances[gl_LocalInvocationIndex] = logLuminance;
+    memoryBarrierShared();
+    barrier();
+
+    for (uint stride = InvocationCount / 2; stride > 0; stride /= 2) {
+        if (gl_LocalInvocationIndex < stride) {
+            logLuminances[gl_LocalInvocationIndex] += logLuminances[gl_LocalInvocationIndex + stride];
+        }
+        memoryBarrierShared();
+        barrier();
+    }
+
+    if (gl_LocalInvocationIndex != 0) {
+        return;
+    }
+
+    float luminance = exp(logLuminances[0] / (GridSize * GridSize));
+
+    // Exponential smoothing, so the adaptation doesn't depend on the frame rate
+    float adaptation = 1.0 - exp(-frame.deltaTime * autoExposure.adaptationSpeed);
+    float averageLuminance = state.averageLuminance > 0.0
+        ? mix(state.averageLuminance, luminance, adaptation)
+        : luminance;
+
+    state.averageLuminance = averageLuminance;
+    state.exposure = clamp(autoExposure.keyValue / averageLuminance, autoExposure.minExposure, autoExposure.maxExposure);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 inUV;
+
+layout(binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform DepthView {
+  float near;
+  float far;
+} depthView;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Depth is stored in the [0, 1] range of a perspective projection
+    float d = texture(depth, inUV).r;
+    float linearDepth = depthView.near * depthView.far / (depthView.far - d * (depthView.far - depthView.near));
+    float shade = clamp((linearDepth - depthView.near) / (depthView.far - depthView.near), 0.0, 1.0);
+    outColor = vec4(vec3(shade), 1.0);
+}
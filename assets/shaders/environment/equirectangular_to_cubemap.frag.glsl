@@ -19,6 +19,13 @@ vec2 SampleSphericalMap(vec3 v)
 void main()
 {
   vec2 uv = SampleSphericalMap(normalize(inPos));
-  vec3 color = texture(equirectangularMap, uv).rgb;
+
+  // The longitude wraps around behind -X, where the implicit derivatives would span
+  // the whole texture and select its smallest mip, leaving a seam down the face
+  vec2 dx = dFdx(uv);
+  vec2 dy = dFdy(uv);
+  dx.x -= round(dx.x);
+  dy.x -= round(dy.x);
+  vec3 color = textureGrad(equirectangularMap, uv, dx, dy).rgb;
   outColor = vec4(color, 1.0);
 }
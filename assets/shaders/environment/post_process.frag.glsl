@@ -4,8 +4,92 @@ layout(location = 0) in vec2 inUV;
 
 layout(binding = 0) uniform sampler2D color;
 
+// Written by the auto exposure compute pass
+layout(binding = 2) readonly buffer ExposureState {
+  float averageLuminance;
+  float exposure;
+} exposureState;
+
+layout(push_constant) uniform PostProcess {
+  int fxaaEnabled;
+  // Amount of subpixel aliasing removal, from 0.0 (off) to 1.0 (softest)
+  float fxaaSubpixel;
+  // Minimum local contrast, relative to the brightest neighbor, required to smooth an edge
+  float fxaaEdgeThreshold;
+  int autoExposureEnabled;
+  float exposure;
+} postProcess;
+
 layout(location = 0) out vec4 outColor;
 
+const vec3 Luma = vec3(0.299, 0.587, 0.114);
+
+// Skips dark edges that aren't noticeably aliased
+const float FxaaEdgeThresholdMin = 0.0312;
+const float FxaaReduceMin = 1.0 / 128.0;
+const float FxaaReduceMul = 1.0 / 8.0;
+const float FxaaSpanMax = 8.0;
+
+float exposure;
+
+// The scene color is high dynamic range, anti-aliasing works on the exposed color
+vec3 exposed(vec2 uv)
+{
+    return clamp(texture(color, uv).rgb * exposure, 0.0, 1.0);
+}
+
+vec3 fxaa(vec2 uv)
+{
+    vec2 texel = 1.0 / vec2(textureSize(color, 0));
+
+    vec3 rgbNW = exposed(uv + vec2(-1.0, -1.0) * texel);
+    vec3 rgbNE = exposed(uv + vec2(1.0, -1.0) * texel);
+    vec3 rgbSW = exposed(uv + vec2(-1.0, 1.0) * texel);
+    vec3 rgbSE = exposed(uv + vec2(1.0, 1.0) * texel);
+    vec3 rgbM = exposed(uv);
+
+    float lumaNW = dot(rgbNW, Luma);
+    float lumaNE = dot(rgbNE, Luma);
+    float lumaSW = dot(rgbSW, Luma);
+    float lumaSE = dot(rgbSE, Luma);
+    float lumaM = dot(rgbM, Luma);
+
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+    float lumaRange = lumaMax - lumaMin;
+
+    // Only pixels on an edge are smoothed
+    if (lumaRange < max(FxaaEdgeThresholdMin, lumaMax * postProcess.fxaaEdgeThreshold)) {
+        return rgbM;
+    }
+
+    // Blend along the edge, perpendicular to the luma gradient
+    vec2 direction = vec2(
+        -((lumaNW + lumaNE) - (lumaSW + lumaSE)),
+        ((lumaNW + lumaSW) - (lumaNE + lumaSE)));
+
+    float directionReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * (0.25 * FxaaReduceMul), FxaaReduceMin);
+    float inverseDirectionMin = 1.0 / (min(abs(direction.x), abs(direction.y)) + directionReduce);
+    direction = clamp(direction * inverseDirectionMin, vec2(-FxaaSpanMax), vec2(FxaaSpanMax)) * texel;
+
+    vec3 rgbA = 0.5 * (
+        exposed(uv + direction * (1.0 / 3.0 - 0.5)) +
+        exposed(uv + direction * (2.0 / 3.0 - 0.5)));
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (
+        exposed(uv + direction * -0.5) +
+        exposed(uv + direction * 0.5));
+
+    // The wider blend is rejected if it samples past the edge
+    float lumaB = dot(rgbB, Luma);
+    vec3 result = (lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB;
+
+    // Single pixel features are softened towards their neighborhood
+    float lumaAverage = (lumaNW + lumaNE + lumaSW + lumaSE) * 0.25;
+    float subpixelBlend = smoothstep(0.0, 1.0, clamp(abs(lumaAverage - lumaM) / lumaRange, 0.0, 1.0));
+    vec3 rgbAverage = (rgbNW + rgbNE + rgbSW + rgbSE + rgbM) * 0.2;
+    return mix(result, rgbAverage, subpixelBlend * subpixelBlend * postProcess.fxaaSubpixel);
+}
+
 void main() {
     // Chromatic Aberration
     /* float strength = 10.0; */
@@ -22,5 +106,11 @@ void main() {
     /* newColor.b = texture(color, uvB).b; */
     /* outColor = newColor; */
 
-    outColor = texture(color, inUV);
+    exposure = postProcess.autoExposureEnabled == 1 ? exposureState.exposure : postProcess.exposure;
+
+    if (postProcess.fxaaEnabled == 1) {
+        outColor = vec4(fxaa(inUV), texture(color, inUV).a);
+    } else {
+        outColor = vec4(exposed(inUV), texture(color, inUV).a);
+    }
 }
@@ -0,0 +1,36 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in float inSize;
+layout(location = 2) in vec4 inColor;
+
+layout(binding = 0) uniform Ubo {
+  mat4 view;
+  mat4 projection;
+} ubo;
+
+layout(push_constant) uniform PushConstants {
+  float viewportHeight;
+  float maxPointSize;
+} pushConstants;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+  outColor = inColor;
+  gl_PointSize = 1.0;
+
+  // Unused particle slots are moved outside of the clip volume
+  if (inSize <= 0.0) {
+    gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+    return;
+  }
+
+  gl_Position = ubo.projection * ubo.view * vec4(inPosition, 1.0);
+
+  // The world space size is projected to pixels, perspective projections shrink it with distance
+  float pixels = inSize * abs(ubo.projection[1][1]) * 0.5 * pushConstants.viewportHeight / gl_Position.w;
+  gl_PointSize = clamp(pixels, 1.0, pushConstants.maxPointSize);
+}
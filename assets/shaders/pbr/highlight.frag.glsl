@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Only the base color factor at the start of the material block is read
+layout(push_constant) uniform PushConstants {
+  vec4 color;
+} pushConstants;
+
+layout(location = 0) out vec4 outColor;
+
+void main()
+{
+  outColor = pushConstants.color;
+}
@@ -8,13 +8,22 @@ layout (location = 0) in vec3 inWorldPos;
 layout (location = 1) in vec3 inNormal;
 layout (location = 2) in vec2 inUV0;
 layout (location = 3) in vec2 inUV1;
+layout (location = 4) in vec4 inColor0;
+layout (location = 5) in vec4 inTangent;
+// Index into the indirect materials, or -1 for direct draws
+layout (location = 6) flat in int inIndirectMaterial;
 
-layout(binding = 2) uniform sampler2D textures[100];
 layout(binding = 3) uniform samplerCube irradiance_cubemap;
 layout(binding = 4) uniform samplerCube prefilter_cubemap;
 layout(binding = 5) uniform sampler2D brdflut;
-
-layout(push_constant) uniform Material {
+layout(binding = 6) uniform sampler2D shadowMap;
+layout(binding = 8) uniform sampler2D transmissionMap;
+// Set by the renderer, larger than 100 when descriptor indexing is supported.
+// Unused slots are either unbound or hold a dummy texture.
+layout(constant_id = 0) const uint TEXTURE_CAPACITY = 100;
+layout(binding = 11) uniform sampler2D textures[TEXTURE_CAPACITY];
+
+struct Material {
   vec4 baseColorFactor;
   vec3 emissiveFactor;
   int colorTextureSet;
@@ -26,26 +35,83 @@ layout(push_constant) uniform Material {
   float roughnessFactor;
   int alphaMode;
   float alphaCutoff;
-} material;
+  int uvSets;
+  float transmissionFactor;
+  int transmissionTextureSet;
+  float clearcoatFactor;
+  float clearcoatRoughnessFactor;
+  int clearcoatTextureSet;
+  int clearcoatRoughnessTextureSet;
+  float normalScale;
+  float occlusionStrength;
+  float ior;
+  float specularFactor;
+  int specularTextureSet;
+  vec3 specularColorFactor;
+  int specularColorTextureSet;
+};
+
+// The bit of each texture in the uv sets of a material
+#define COLOR_TEXTURE 0
+#define METALLIC_ROUGHNESS_TEXTURE 1
+#define NORMAL_TEXTURE 2
+#define OCCLUSION_TEXTURE 3
+#define EMISSIVE_TEXTURE 4
+#define TRANSMISSION_TEXTURE 5
+#define CLEARCOAT_TEXTURE 6
+#define CLEARCOAT_ROUGHNESS_TEXTURE 7
+#define SPECULAR_TEXTURE 8
+#define SPECULAR_COLOR_TEXTURE 9
+
+layout(push_constant) uniform PushConstants {
+  Material material;
+} pushConstants;
+
+// Indirect draws can't push their own material
+layout(std430, binding = 10) readonly buffer IndirectMaterials {
+  Material materials[];
+} indirectMaterials;
+
+Material material;
 
 layout(location = 0) out vec4 outColor;
 
 #define MAX_NUM_JOINTS 128
+#define MAX_NUM_LIGHTS 4
+
+struct Light
+{
+    vec3 direction;
+    float range;
+    vec3 color;
+    float intensity;
+    vec3 position;
+    float innerConeCos;
+    float outerConeCos;
+    int type;
+    vec2 padding;
+};
 
 layout(binding = 0) uniform UboView {
   mat4 view;
   mat4 projection;
   vec4 cameraPosition;
+  mat4 lightSpaceMatrix;
   mat4 jointMatrices[MAX_NUM_JOINTS];
+  vec4 lightInfo; // x: light count, y: image based lighting intensity
+  Light lights[MAX_NUM_LIGHTS];
 } uboView;
 
 const float M_PI = 3.141592653589793;
 const float minRoughness = 0.04;
 const vec3 LightColor = vec3(1.0);
-const float OcclusionStrength = 1.0f;
 const float EmissiveFactor = 1.0f;
 const float Gamma = 2.2f;
 const float Exposure = 4.5f;
+const float ShadowBias = 0.005f;
+const float ShadowAmbient = 0.1f;
+const float RefractionStrength = 0.03f;
+const float TransmissionBlurTexels = 8.0f;
 
 vec3 Uncharted2Tonemap(vec3 color)
 {
@@ -66,6 +132,11 @@ vec4 tonemap(vec4 color)
 	return vec4(pow(outcol, vec3(1.0f / Gamma)), color.a);
 }
 
+vec2 selectUV(int materialTexture)
+{
+  return ((material.uvSets >> materialTexture) & 1) == 1 ? inUV1 : inUV0;
+}
+
 // Find the normal for this fragment, pulling either from a predefined normal map
 // or from the interpolated mesh normal and tangent attributes.
 vec3 getNormal()
@@ -75,12 +146,22 @@ vec3 getNormal()
   }
 
 	// Perturb normal, see http://www.thetenthplanet.de/archives/1180
-	vec3 tangentNormal = texture(textures[material.normalTextureSet], inUV0).xyz * 2.0 - 1.0;
+	vec2 uv = selectUV(NORMAL_TEXTURE);
+	vec3 tangentNormal = texture(textures[material.normalTextureSet], uv).xyz * 2.0 - 1.0;
+	tangentNormal *= vec3(material.normalScale, material.normalScale, 1.0);
+
+	// Use the vertex tangent frame when one was provided or generated
+	if (inTangent.w != 0.0) {
+		vec3 N = normalize(inNormal);
+		vec3 T = normalize(inTangent.xyz);
+		vec3 B = cross(N, T) * inTangent.w;
+		return normalize(mat3(T, B, N) * tangentNormal);
+	}
 
 	vec3 q1 = dFdx(inWorldPos);
 	vec3 q2 = dFdy(inWorldPos);
-	vec2 st1 = dFdx(inUV0);
-	vec2 st2 = dFdy(inUV0);
+	vec2 st1 = dFdx(uv);
+	vec2 st2 = dFdy(uv);
 
 	vec3 N = normalize(inNormal);
 	vec3 T = normalize(q1 * st2.t - q2 * st1.t);
@@ -96,19 +177,6 @@ vec4 SRGBtoLINEAR(vec4 srgbIn)
   return vec4(linOut,srgbIn.w);;
 }
 
-struct Light
-{
-    vec3 direction;
-    float range;
-    vec3 color;
-    float intensity;
-    vec3 position;
-    float innerConeCos;
-    float outerConeCos;
-    int type;
-    vec2 padding;
-};
-
 const int LightType_Directional = 0;
 const int LightType_Point = 1;
 const int LightType_Spot = 2;
@@ -137,57 +205,111 @@ float getSpotAttenuation(vec3 pointToLight, vec3 spotDirection, float outerConeC
     return 0.0;
 }
 
+// Percentage closer filtering over a 3x3 texel neighborhood
+float filterPCF(vec3 worldPos)
+{
+    vec4 shadowCoord = uboView.lightSpaceMatrix * vec4(worldPos, 1.0);
+    shadowCoord /= shadowCoord.w;
+
+    if (shadowCoord.z <= 0.0 || shadowCoord.z >= 1.0) {
+        return 1.0;
+    }
+
+    vec2 uv = shadowCoord.xy * 0.5 + 0.5;
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0));
+
+    float shadow = 0.0;
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            float closestDepth = texture(shadowMap, uv + vec2(x, y) * texelSize).r;
+            shadow += (shadowCoord.z - ShadowBias) > closestDepth ? ShadowAmbient : 1.0;
+        }
+    }
+    return shadow / 9.0;
+}
+
+// Rough refraction approximation that samples the opaque scene behind the surface,
+// offset along the view space normal and blurred by the surface roughness
+vec3 getTransmittedLight(vec3 n, float perceptualRoughness)
+{
+    vec2 texelSize = 1.0 / vec2(textureSize(transmissionMap, 0));
+    vec3 viewNormal = mat3(uboView.view) * n;
+    vec2 uv = gl_FragCoord.xy * texelSize - viewNormal.xy * RefractionStrength;
+
+    vec2 blur = texelSize * perceptualRoughness * TransmissionBlurTexels;
+    vec3 transmitted = vec3(0.0);
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            transmitted += texture(transmissionMap, clamp(uv + vec2(x, y) * blur, 0.0, 1.0)).rgb;
+        }
+    }
+    return transmitted / 9.0;
+}
+
+// The specular lobe of the clearcoat layer, which has a fixed F0 of 0.04
+float getClearcoatSpecular(vec3 n, vec3 l, vec3 v, float alphaRoughness)
+{
+    vec3 h = normalize(l + v);
+    float NdotL = clamp(dot(n, l), 0.001, 1.0);
+    float NdotV = clamp(abs(dot(n, v)), 0.001, 1.0);
+    float NdotH = clamp(dot(n, h), 0.0, 1.0);
+    float VdotH = clamp(dot(v, h), 0.0, 1.0);
+
+    float F = 0.04 + 0.96 * pow(clamp(1.0 - VdotH, 0.0, 1.0), 5.0);
+
+    float r = alphaRoughness;
+    float attenuationL = 2.0 * NdotL / (NdotL + sqrt(r * r + (1.0 - r * r) * (NdotL * NdotL)));
+    float attenuationV = 2.0 * NdotV / (NdotV + sqrt(r * r + (1.0 - r * r) * (NdotV * NdotV)));
+    float G = attenuationL * attenuationV;
+
+    float roughnessSq = alphaRoughness * alphaRoughness;
+    float f = (NdotH * roughnessSq - NdotH) * NdotH + 1.0;
+    float D = roughnessSq / (M_PI * f * f);
+
+    // Already multiplied by NdotL, which cancels out of the denominator
+    return F * G * D / (4.0 * NdotV);
+}
+
 void main()
 {
-    Light lights[2] = Light[](
-            Light(
-                vec3(0.0, -10.0, 0.0),   // direction
-                1.0,                  // range
-                vec3(1.0, 0.0, 1.0),   // color
-                1.0,                  // intensity
-                vec3(10.0, 10.0, 10.0), // position
-                10.0,                  // inner cone cos
-                100.0,                 // outer cone cos
-                0,                     // type
-                vec2(0.0, 0.0)         // padding
-                ),
-            Light(
-                vec3(0.0, -10.0, 0.0), // direction
-                10.0,                // range
-                vec3(0.0, 1.0, 0.0), // color
-                1.0,                // intensity
-                vec3(1.0, 6.0, 1.0), // position
-                10.0,                // inner cone cos
-                100.0,               // outer cone cos
-                2,                   // type
-                vec2(0.0, 0.0)       // padding
-                )
-                );
+    if (inIndirectMaterial >= 0) {
+        material = indirectMaterials.materials[inIndirectMaterial];
+    } else {
+        material = pushConstants.material;
+    }
 
     float perceptualRoughness;
     float metallic;
     vec3 diffuseColor;
     vec4 baseColor;
 
-    vec3 f0 = vec3(0.04);
-
+    // Color textures use sRGB formats, so they are already linear when sampled
     if (material.colorTextureSet > -1) {
-        vec4 albedoMap = texture(textures[material.colorTextureSet], inUV0);
-        baseColor = SRGBtoLINEAR(albedoMap) * material.baseColorFactor;
+        vec4 albedoMap = texture(textures[material.colorTextureSet], selectUV(COLOR_TEXTURE));
+        baseColor = albedoMap * material.baseColorFactor;
     } else {
         baseColor = material.baseColorFactor;
     }
 
+    baseColor *= inColor0;
+
     if (material.alphaMode == 2 && baseColor.a < material.alphaCutoff) {
         discard;
     }
 
+    // Masked primitives are opaque, but with alpha to coverage the alpha decides how many samples are covered.
+    // It is sharpened to ramp up over a pixel past the cutoff, so edges are smooth without thinning out.
+    float alpha = baseColor.a;
+    if (material.alphaMode == 2) {
+        alpha = clamp((baseColor.a - material.alphaCutoff) / max(fwidth(baseColor.a), 0.0001) + 0.5, 0.0, 1.0);
+    }
+
     float minRoughness = 1.0;
     perceptualRoughness = material.roughnessFactor;
     metallic = material.metallicFactor;
     if (material.metallicRoughnessTextureSet > -1)
     {
-        vec4 physicalDescriptor = texture(textures[material.metallicRoughnessTextureSet], inUV0);
+        vec4 physicalDescriptor = texture(textures[material.metallicRoughnessTextureSet], selectUV(METALLIC_ROUGHNESS_TEXTURE));
         perceptualRoughness = physicalDescriptor.g * perceptualRoughness;
         metallic = physicalDescriptor.b * metallic;
     } else {
@@ -195,9 +317,33 @@ void main()
         metallic = clamp(metallic, 0.0, 1.0);
     }
 
+    // The dielectric reflectance at normal incidence comes from the index of refraction,
+    // tinted and weighted by KHR_materials_specular. The defaults give the usual 0.04.
+    vec3 f0 = vec3(pow((material.ior - 1.0) / (material.ior + 1.0), 2.0)) * material.specularColorFactor;
+    if (material.specularColorTextureSet > -1) {
+        f0 *= texture(textures[material.specularColorTextureSet], selectUV(SPECULAR_COLOR_TEXTURE)).rgb;
+    }
+    f0 = min(f0, vec3(1.0));
+    float specularWeight = material.specularFactor;
+    if (material.specularTextureSet > -1) {
+        specularWeight *= texture(textures[material.specularTextureSet], selectUV(SPECULAR_TEXTURE)).a;
+    }
+    f0 *= specularWeight;
+
     diffuseColor = baseColor.rgb * (vec3(1.0) - f0);
     diffuseColor *= 1.0 - metallic;
 
+    // Transmitted light replaces the diffuse lighting, metals don't transmit
+    float transmission = 0.0;
+    if (material.transmissionFactor > 0.0) {
+        transmission = material.transmissionFactor;
+        if (material.transmissionTextureSet > -1) {
+            transmission *= texture(textures[material.transmissionTextureSet], selectUV(TRANSMISSION_TEXTURE)).r;
+        }
+        transmission *= 1.0 - metallic;
+        diffuseColor *= 1.0 - transmission;
+    }
+
     float alphaRoughness = perceptualRoughness * perceptualRoughness;
 
     vec3 specularColor = mix(f0, baseColor.rgb, metallic);
@@ -212,10 +358,31 @@ void main()
     vec3 v = normalize(uboView.cameraPosition.xyz - inWorldPos); // Vector from surface point to camera
     float NdotV = clamp(abs(dot(n, v)), 0.001, 1.0);
 
+    // The clearcoat layer sits on top of the base material and ignores its normal map
+    float clearcoat = 0.0;
+    float clearcoatRoughness = 0.0;
+    vec3 clearcoatNormal = normalize(inNormal);
+    vec3 clearcoatColor = vec3(0.0);
+    if (material.clearcoatFactor > 0.0) {
+        clearcoat = material.clearcoatFactor;
+        if (material.clearcoatTextureSet > -1) {
+            clearcoat *= texture(textures[material.clearcoatTextureSet], selectUV(CLEARCOAT_TEXTURE)).r;
+        }
+        clearcoatRoughness = material.clearcoatRoughnessFactor;
+        if (material.clearcoatRoughnessTextureSet > -1) {
+            clearcoatRoughness *= texture(textures[material.clearcoatRoughnessTextureSet], selectUV(CLEARCOAT_ROUGHNESS_TEXTURE)).g;
+        }
+        clearcoatRoughness = clamp(clearcoatRoughness, 0.0, 1.0);
+    }
+
     vec3 color = vec3(0.0, 0.0, 0.0);
 
-    for(int i = 0; i < 2; ++i) {
-        Light light = lights[i];
+    // Only the primary light casts shadows
+    float shadow = filterPCF(inWorldPos);
+
+    int lightCount = int(uboView.lightInfo.x);
+    for(int i = 0; i < lightCount; ++i) {
+        Light light = uboView.lights[i];
 
         vec3 pointToLight = -light.direction;
         float rangeAttenuation = 1.0;
@@ -237,6 +404,9 @@ void main()
         }
 
         vec3 intensity = rangeAttenuation * spotAttenuation * light.intensity * light.color;
+        if (i == 0) {
+            intensity *= shadow;
+        }
 
         vec3 l = normalize(pointToLight); // Vector from surface point to light
         vec3 h = normalize(l+v);          // Half vector between both l and v
@@ -271,33 +441,60 @@ void main()
         vec3 diffuseContrib = (1.0 - F) * diffuseColor / M_PI;
         vec3 specContrib = F * G * D / (4.0 * NdotL * NdotV);
         color += NdotL * intensity * (diffuseContrib + specContrib);
+
+        if (clearcoat > 0.0) {
+            float clearcoatAlphaRoughness = max(clearcoatRoughness * clearcoatRoughness, 0.001);
+            clearcoatColor += intensity * getClearcoatSpecular(clearcoatNormal, l, v, clearcoatAlphaRoughness);
+        }
     }
 
     // retrieve a scale and bias to F0
-    float prefilterMipLevels = 10; // mip_levels for a 512x512px cubemap face
+    float prefilterMipLevels = float(textureQueryLevels(prefilter_cubemap));
     float lod = (perceptualRoughness * prefilterMipLevels);
     vec3 brdf = (texture(brdflut, vec2(NdotV, 1.0 - perceptualRoughness))).rgb;
 
-    vec3 diffuseLight = SRGBtoLINEAR(tonemap(texture(irradiance_cubemap, n))).rgb;
+    float iblIntensity = uboView.lightInfo.y;
+
+    vec3 diffuseLight = SRGBtoLINEAR(tonemap(texture(irradiance_cubemap, n))).rgb * iblIntensity;
     vec3 diffuse = diffuseLight * diffuseColor;
 
     vec3 reflection = -normalize(reflect(v, n));
     reflection.y *= -1.0f;
 
-    vec3 specularLight = SRGBtoLINEAR(tonemap(textureLod(prefilter_cubemap, reflection, lod))).rgb;
+    vec3 specularLight = SRGBtoLINEAR(tonemap(textureLod(prefilter_cubemap, reflection, lod))).rgb * iblIntensity;
     vec3 specular = specularLight * (specularColor * brdf.x + brdf.y);
 
     color += diffuse + specular;
 
+    if (transmission > 0.0) {
+        color += getTransmittedLight(n, perceptualRoughness) * baseColor.rgb * transmission;
+    }
+
     if (material.occlusionTextureSet > -1) {
-        float ao = texture(textures[material.occlusionTextureSet], inUV0).r;
-        color = mix(color, color * ao, OcclusionStrength);
+        float ao = texture(textures[material.occlusionTextureSet], selectUV(OCCLUSION_TEXTURE)).r;
+        color = mix(color, color * ao, material.occlusionStrength);
     }
 
+    vec3 emissive = material.emissiveFactor * EmissiveFactor;
     if (material.emissiveTextureSet > -1) {
-        vec3 emissive = SRGBtoLINEAR(texture(textures[material.emissiveTextureSet], inUV0)).rgb * EmissiveFactor;
-        color += emissive;
+        emissive *= texture(textures[material.emissiveTextureSet], selectUV(EMISSIVE_TEXTURE)).rgb;
+    }
+    color += emissive;
+
+    // The base layer is darkened by the light the clearcoat reflects
+    if (clearcoat > 0.0) {
+        float clearcoatNdotV = clamp(abs(dot(clearcoatNormal, v)), 0.001, 1.0);
+        float clearcoatFresnel = 0.04 + 0.96 * pow(1.0 - clearcoatNdotV, 5.0);
+
+        vec3 clearcoatReflection = -normalize(reflect(v, clearcoatNormal));
+        clearcoatReflection.y *= -1.0f;
+        float clearcoatLod = clearcoatRoughness * prefilterMipLevels;
+        vec3 clearcoatBrdf = texture(brdflut, vec2(clearcoatNdotV, 1.0 - clearcoatRoughness)).rgb;
+        vec3 clearcoatLight = SRGBtoLINEAR(tonemap(textureLod(prefilter_cubemap, clearcoatReflection, clearcoatLod))).rgb * iblIntensity;
+        clearcoatColor += clearcoatLight * (0.04 * clearcoatBrdf.x + clearcoatBrdf.y);
+
+        color = color * (1.0 - clearcoat * clearcoatFresnel) + clearcoatColor * clearcoat;
     }
 
-    outColor = vec4(color, baseColor.a);
+    outColor = vec4(color, alpha);
 }
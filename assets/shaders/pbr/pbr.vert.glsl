@@ -8,13 +8,22 @@ layout (location = 2) in vec2 inUV0;
 layout (location = 3) in vec2 inUV1;
 layout (location = 4) in vec4 inJoint0;
 layout (location = 5) in vec4 inWeight0;
+layout (location = 6) in vec4 inColor0;
+layout (location = 7) in vec4 inTangent;
+layout (location = 8) in mat4 inInstanceModel;
+layout (location = 12) in float inMorphOffset;
+// X value is the indirect material index, or -1 for direct draws.
+// Y value is the offset of the mesh's entry in the mesh storage buffer, in vec4s.
+layout (location = 13) in vec2 inIndirectDraw;
 
 #define MAX_NUM_JOINTS 128
+#define MAX_NUM_MORPH_TARGETS 8
 
 layout(binding = 0) uniform UboView {
   mat4 view;
   mat4 projection;
   vec4 cameraPosition;
+  mat4 lightSpaceMatrix;
   mat4 jointMatrices[MAX_NUM_JOINTS];
 } uboView;
 
@@ -22,28 +31,69 @@ layout(binding = 1) uniform UboInstance {
   mat4 model;
   float jointCount;
   float jointOffset;
+  float morphTargetCount;
+  float morphOffset;
+  vec4 morphWeights[MAX_NUM_MORPH_TARGETS / 4];
 } uboInstance;
 
+// Position and normal deltas of each morph target, per morphed vertex
+layout(std430, binding = 7) readonly buffer MorphTargets {
+  vec4 deltas[];
+} morphTargets;
+
+// The dynamic uniform buffer entries of every mesh, for indirect draws that can't use a dynamic offset.
+// Indirect draws are never skinned or morphed, so only the model matrix is read.
+layout(std430, binding = 9) readonly buffer Meshes {
+  vec4 entries[];
+} meshes;
+
 layout (location = 0) out vec3 outWorldPos;
 layout (location = 1) out vec3 outNormal;
 layout (location = 2) out vec2 outUV0;
 layout (location = 3) out vec2 outUV1;
+layout (location = 4) out vec4 outColor0;
+layout (location = 5) out vec4 outTangent;
+layout (location = 6) flat out int outIndirectMaterial;
 
 void main()
 {
+  bool indirect = inIndirectDraw.x >= 0.0;
+  mat4 meshModel = uboInstance.model;
+  if (indirect) {
+    int entry = int(inIndirectDraw.y);
+    meshModel = mat4(meshes.entries[entry], meshes.entries[entry + 1], meshes.entries[entry + 2], meshes.entries[entry + 3]);
+  }
+
+  vec3 position = inPos;
+  vec3 normal = inNormal;
+  int morphTargetCount = indirect ? 0 : int(uboInstance.morphTargetCount);
+  if (morphTargetCount > 0 && inMorphOffset >= 0.0) {
+    int base = int(uboInstance.morphOffset + inMorphOffset);
+    for (int morphTarget = 0; morphTarget < morphTargetCount; morphTarget++) {
+      float weight = uboInstance.morphWeights[morphTarget / 4][morphTarget % 4];
+      position += weight * morphTargets.deltas[base + morphTarget * 2].xyz;
+      normal += weight * morphTargets.deltas[base + morphTarget * 2 + 1].xyz;
+    }
+  }
+
   mat4 skinMatrix = mat4(1.0);
-  if (uboInstance.jointCount > 0.0) {
+  if (!indirect && uboInstance.jointCount > 0.0) {
     skinMatrix =
       inWeight0.x * uboView.jointMatrices[int(inJoint0.x + uboInstance.jointOffset)] +
       inWeight0.y * uboView.jointMatrices[int(inJoint0.y + uboInstance.jointOffset)] +
       inWeight0.z * uboView.jointMatrices[int(inJoint0.z + uboInstance.jointOffset)] +
       inWeight0.w * uboView.jointMatrices[int(inJoint0.w + uboInstance.jointOffset)];
   }
-  vec4 locPos = uboInstance.model * skinMatrix * vec4(inPos, 1.0);
-  outNormal = normalize(transpose(inverse(mat3(uboInstance.model * skinMatrix))) * inNormal);
+  mat4 model = inInstanceModel * meshModel;
+  vec4 locPos = model * skinMatrix * vec4(position, 1.0);
+  outNormal = normalize(transpose(inverse(mat3(model * skinMatrix))) * normal);
   locPos.y = -locPos.y;
   outWorldPos = locPos.xyz / locPos.w;
   outUV0 = inUV0;
   outUV1 = inUV1;
+  outColor0 = inColor0;
+  outIndirectMaterial = int(inIndirectDraw.x);
+  outTangent = vec4(normalize(mat3(model * skinMatrix) * inTangent.xyz), inTangent.w);
   gl_Position =  uboView.projection * uboView.view * vec4(outWorldPos, 1.0);
+  gl_PointSize = 1.0;
 }
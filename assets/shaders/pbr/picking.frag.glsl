@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+layout(push_constant) uniform PushConstants {
+  uint id;
+} pushConstants;
+
+layout (location = 0) out uint outId;
+
+void main()
+{
+  outId = pushConstants.id;
+}
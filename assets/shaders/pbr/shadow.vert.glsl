@@ -0,0 +1,64 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+layout (location = 0) in vec3 inPos;
+layout (location = 1) in vec3 inNormal;
+layout (location = 2) in vec2 inUV0;
+layout (location = 3) in vec2 inUV1;
+layout (location = 4) in vec4 inJoint0;
+layout (location = 5) in vec4 inWeight0;
+layout (location = 8) in mat4 inInstanceModel;
+layout (location = 12) in float inMorphOffset;
+
+#define MAX_NUM_JOINTS 128
+#define MAX_NUM_MORPH_TARGETS 8
+
+layout(binding = 0) uniform UboView {
+  mat4 view;
+  mat4 projection;
+  vec4 cameraPosition;
+  mat4 lightSpaceMatrix;
+  mat4 jointMatrices[MAX_NUM_JOINTS];
+} uboView;
+
+layout(binding = 1) uniform UboInstance {
+  mat4 model;
+  float jointCount;
+  float jointOffset;
+  float morphTargetCount;
+  float morphOffset;
+  vec4 morphWeights[MAX_NUM_MORPH_TARGETS / 4];
+} uboInstance;
+
+// Position and normal deltas of each morph target, per morphed vertex
+layout(std430, binding = 7) readonly buffer MorphTargets {
+  vec4 deltas[];
+} morphTargets;
+
+void main()
+{
+  vec3 position = inPos;
+  int morphTargetCount = int(uboInstance.morphTargetCount);
+  if (morphTargetCount > 0 && inMorphOffset >= 0.0) {
+    int base = int(uboInstance.morphOffset + inMorphOffset);
+    for (int morphTarget = 0; morphTarget < morphTargetCount; morphTarget++) {
+      float weight = uboInstance.morphWeights[morphTarget / 4][morphTarget % 4];
+      position += weight * morphTargets.deltas[base + morphTarget * 2].xyz;
+    }
+  }
+
+  mat4 skinMatrix = mat4(1.0);
+  if (uboInstance.jointCount > 0.0) {
+    skinMatrix =
+      inWeight0.x * uboView.jointMatrices[int(inJoint0.x + uboInstance.jointOffset)] +
+      inWeight0.y * uboView.jointMatrices[int(inJoint0.y + uboInstance.jointOffset)] +
+      inWeight0.z * uboView.jointMatrices[int(inJoint0.z + uboInstance.jointOffset)] +
+      inWeight0.w * uboView.jointMatrices[int(inJoint0.w + uboInstance.jointOffset)];
+  }
+  mat4 model = inInstanceModel * uboInstance.model;
+  vec4 locPos = model * skinMatrix * vec4(position, 1.0);
+  locPos.y = -locPos.y;
+  vec3 worldPos = locPos.xyz / locPos.w;
+  gl_Position = uboView.lightSpaceMatrix * vec4(worldPos, 1.0);
+}
@@ -14,8 +14,13 @@ fn main() -> Result<()> {
 
     let shader_directory = "assets/shaders";
     let shader_glob = shader_directory.to_owned() + "/**/*.glsl";
+    // Build script output is hidden unless it is a cargo warning
     if compile_shaders(&shader_glob).is_err() {
         error!("Failed to recompile shaders!");
+        println!(
+            "cargo:warning=Failed to compile the shaders in {}, see shadercompilation.log",
+            shader_directory
+        );
     }
 
     Ok(())
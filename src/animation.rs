@@ -0,0 +1,119 @@
+use crate::system::System;
+use legion::prelude::*;
+
+// Selects the animation an asset instance plays.
+// Instances without this component play the first animation of their asset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationState {
+    // The first animation is played when no name is selected
+    pub name: Option<String>,
+    pub time: f32,
+    pub speed: f32,
+    // One-shot animations hold their last pose once they finish
+    pub looping: bool,
+    // Crossfades into a second animation, played at the same time
+    pub blend: Option<AnimationBlend>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationBlend {
+    pub name: String,
+    // Zero plays only the selected animation and one only the blended animation
+    pub weight: f32,
+}
+
+impl Default for AnimationState {
+    fn default() -> Self {
+        Self {
+            name: None,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            blend: None,
+        }
+    }
+}
+
+impl AnimationState {
+    pub fn new(name: &str, looping: bool) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            looping,
+            ..Default::default()
+        }
+    }
+
+    // Restarts playback with the named animation
+    pub fn set_animation(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+        self.time = 0.0;
+        self.blend = None;
+    }
+
+    pub fn blend_with(&mut self, name: &str, weight: f32) {
+        self.blend = Some(AnimationBlend {
+            name: name.to_string(),
+            weight,
+        });
+    }
+}
+
+// Lets an instance with an AnimationState be paused and scrubbed for inspection.
+// A paused instance holds its current pose, whether or not its animation loops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationControl {
+    pub playing: bool,
+    // Multiplies the speed of the animation state
+    pub speed: f32,
+    // An absolute time to jump to, applied on the next update
+    pub seek: Option<f32>,
+}
+
+impl Default for AnimationControl {
+    fn default() -> Self {
+        Self {
+            playing: true,
+            speed: 1.0,
+            seek: None,
+        }
+    }
+}
+
+impl AnimationControl {
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.seek = Some(time);
+    }
+}
+
+pub fn animation_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("animation")
+        .read_resource::<System>()
+        .with_query(<(Write<AnimationState>, TryWrite<AnimationControl>)>::query())
+        .build(move |_, world, system, query| {
+            let delta_time = system.delta_time as f32;
+            for (mut state, control) in query.iter_mut(world) {
+                let mut control = match control {
+                    Some(control) => control,
+                    None => {
+                        state.time += state.speed * delta_time;
+                        continue;
+                    }
+                };
+
+                // A seeked time is shown as is for the frame it was set on
+                if let Some(time) = control.seek.take() {
+                    state.time = time.max(0.0);
+                } else if control.playing {
+                    state.time += state.speed * control.speed * delta_time;
+                }
+            }
+        })
+}
@@ -1,30 +1,131 @@
+#[cfg(feature = "gamepad")]
+use crate::input::GamepadBackend;
+#[cfg(feature = "headless")]
+use crate::renderer::HeadlessRenderer;
 use crate::{
+    animation::{animation_system, AnimationControl, AnimationState},
     camera::{
-        fps_camera_controls_system, orbital_camera_controls_system, FreeCamera, OrbitalCamera,
+        activate_asset_camera, active_camera, camera_system, fps_camera_controls_system,
+        inactive_cameras, orbital_camera_controls_system, remove_asset_camera_views, ActiveCamera,
+        Camera, OrbitalCamera,
+    },
+    gui::{Gui, GuiFrame, Inspection, InspectorRequest, ShowSceneWindow, ShowStats},
+    hierarchy::{transform_hierarchy_system, WorldTransform},
+    input::{Action, Input, KeyBindings},
+    particles::{particle_system, ParticleEmitter},
+    renderer::{
+        AssetName, Backend, DebugShapes, DeviceLost, DeviceSettings, Environment, IblSettings,
+        Light, LightKind, NullRenderer, RenderScene, RenderSettings, Renderer, SceneCamera,
+        Transform,
     },
-    gui::Gui,
-    input::Input,
-    renderer::{AssetName, Backend, Renderer, Transform},
     system::System,
 };
 use anyhow::{Context, Result};
 use legion::prelude::*;
-use log::debug;
+use log::{debug, error, info, warn};
 use nalgebra_glm as glm;
 use serde::Deserialize;
 use simplelog::*;
-use std::fs::File;
+use std::{
+    fs::{self, File},
+    path::Path,
+};
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, VirtualKeyCode},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     width: i64,
     height: i64,
+    #[serde(default)]
+    backend: Backend,
+    #[serde(default)]
+    key_bindings: KeyBindings,
+    #[serde(default)]
+    show_stats: bool,
+    // Shows the scene inside a movable gui window instead of filling the screen
+    #[serde(default)]
+    scene_window: bool,
+    // Anisotropic filtering level, clamped to what the device supports
+    #[serde(default)]
+    anisotropy: Option<f32>,
+    // Device features and extensions to request from vulkan
+    #[serde(default)]
+    device: DeviceSettings,
+    // Fewer frames in flight lower the input latency, more can raise the frame rate
+    #[serde(default)]
+    frames_in_flight: Option<u32>,
+    // The number of swapchain images to request, clamped to what the surface supports.
+    // Mailbox presentation needs at least three to avoid waiting on the display.
+    #[serde(default)]
+    swapchain_image_count: Option<u32>,
+    // An hdr image or DDS cubemap to use for the skybox and image based lighting
+    #[serde(default)]
+    environment: Option<String>,
+    // Six images, one per cubemap face, used instead of the environment image when set
+    #[serde(default)]
+    environment_faces: Option<EnvironmentFaces>,
+    // A directory to cache the cubemaps computed from the environment in
+    #[serde(default)]
+    environment_cache: Option<String>,
+    #[serde(default)]
+    ibl: IblSettings,
+    // Splits the screen horizontally with a second camera looking at the scene from the side
+    #[serde(default)]
+    split_screen: bool,
+    #[serde(default)]
+    orbital_camera: OrbitalCameraSettings,
+    // Places a particle emitter at this position in the world
+    #[serde(default)]
+    particle_emitter: Option<[f32; 3]>,
+    // Renders a single frame to this image path without opening a window, then exits
+    #[cfg(feature = "headless")]
+    #[serde(default)]
+    headless_output: Option<String>,
+}
+
+// Zoom limits and speeds of the orbital camera, useful for inspecting tiny or huge assets
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct OrbitalCameraSettings {
+    min_radius: f32,
+    max_radius: f32,
+    zoom_sensitivity: f32,
+    rotate_sensitivity: f32,
+    // How quickly the camera moves over to a focused entity
+    target_smoothing: f32,
+}
+
+impl Default for OrbitalCameraSettings {
+    fn default() -> Self {
+        Self {
+            min_radius: 0.1,
+            max_radius: 1000.0,
+            zoom_sensitivity: 0.3,
+            rotate_sensitivity: 1.0,
+            target_smoothing: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnvironmentFaces {
+    right: String,
+    left: String,
+    top: String,
+    bottom: String,
+    front: String,
+    back: String,
+}
+
+// The entity that was last clicked on
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub entity: Option<Entity>,
 }
 
 #[derive(Default)]
@@ -35,11 +136,21 @@ impl App {
     pub const LOG_FILE: &'static str = "dragonglass.log";
     pub const SETTINGS_FILE: &'static str = "settings.toml";
 
+    // Settings can be overridden with environment variables, such as DRAGONGLASS_BACKEND=null
+    pub const SETTINGS_ENVIRONMENT_PREFIX: &'static str = "DRAGONGLASS";
+
     pub fn run() -> Result<()> {
         Self::setup_logger()?;
 
         let settings = Self::load_settings()?;
 
+        #[cfg(feature = "headless")]
+        {
+            if let Some(output) = settings.headless_output.as_ref() {
+                return Self::run_headless(&settings, output);
+            }
+        }
+
         let event_loop = EventLoop::new();
         let mut window = WindowBuilder::new()
             .with_title(Self::TITLE)
@@ -56,31 +167,37 @@ impl App {
 
         let mut resources = Resources::default();
         resources.insert(Input::default());
+        resources.insert(settings.key_bindings.clone());
         resources.insert(System::new(window_dimensions));
+        resources.insert(RenderSettings::default());
+        resources.insert(Selection::default());
+        resources.insert(ShowStats(settings.show_stats));
+        resources.insert(ShowSceneWindow(settings.scene_window));
+        resources.insert(Self::environment(&settings));
+        resources.insert(DebugShapes::default());
 
         let universe = Universe::new();
-        let mut world = universe.create_world();
-
-        // FIXME: Add tag to mark this as the main camera
-        world.insert((), vec![(OrbitalCamera::default(),)]);
-
-        world.insert(
-            (),
-            vec![(
-                Transform::default(),
-                AssetName("assets/models/MetalRoughSpheres.glb".to_string()),
-            )],
-        );
+        let mut world = Self::create_world(&universe, &settings);
 
         let mut update_schedule = Schedule::builder()
             .add_system(fps_camera_controls_system())
             .add_system(orbital_camera_controls_system())
             .flush()
+            .add_system(camera_system())
+            .add_system(animation_system())
+            .add_system(particle_system())
+            .add_system(transform_hierarchy_system())
+            .flush()
             .build();
 
+        #[cfg(feature = "gamepad")]
+        let mut gamepads = GamepadBackend::new()
+            .map_err(|error| warn!("Gamepad input is unavailable: {}", error))
+            .ok();
+
         let mut gui = Gui::new(&window);
-        let mut renderer = Renderer::create_backend(&Backend::Vulkan, &mut window)?;
-        renderer.initialize(&world, &mut gui.context_mut());
+        let mut renderer =
+            Self::create_renderer(&settings, &mut window, &mut gui, &world, &resources)?;
 
         event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
@@ -99,30 +216,641 @@ impl App {
                 let system = resources
                     .get::<System>()
                     .expect("Failed to get system resource!");
+                let was_left_clicked = input.mouse.is_left_clicked;
                 input.handle_event(&event, system.window_center());
-                input.allowed = !gui.capturing_input();
+                input.allowed = !gui.capturing_input() && !gui.is_rebinding();
 
-                if input.is_key_pressed(VirtualKeyCode::Escape) {
+                if input.allowed && input.mouse.is_left_clicked && !was_left_clicked {
+                    if let Some(position) =
+                        gui.scene_position(input.mouse.position, system.window_dimensions)
+                    {
+                        let entity = renderer.pick(position.x as _, position.y as _);
+                        debug!("Selected entity: {:?}", entity);
+                        if let Some(mut selection) = resources.get_mut::<Selection>() {
+                            selection.entity = entity;
+                        }
+                    }
+                }
+
+                let key_bindings = resources
+                    .get::<KeyBindings>()
+                    .expect("Failed to get key bindings resource!");
+                if !gui.is_rebinding() && input.is_action_pressed(&key_bindings, Action::Exit) {
                     *control_flow = ControlFlow::Exit;
                 }
             }
 
             match event {
                 Event::NewEvents { .. } => {
+                    #[cfg(feature = "gamepad")]
+                    if let (Some(gamepads), Some(mut input)) =
+                        (gamepads.as_mut(), resources.get_mut::<Input>())
+                    {
+                        gamepads.poll(&mut input.gamepad);
+                    }
                     update_schedule.execute(&mut world, &mut resources);
                 }
+                Event::WindowEvent {
+                    event: WindowEvent::Resized(size),
+                    ..
+                } => {
+                    renderer.resize(size.width, size.height);
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => {
+                    if let Err(error) =
+                        Self::load_dropped_asset(&mut world, renderer.as_mut(), &path)
+                    {
+                        error!("{:?}", error);
+                    }
+                }
                 Event::MainEventsCleared => {
+                    let load_progress = renderer.load_progress();
+                    let input = resources
+                        .get::<Input>()
+                        .expect("Failed to get input resource!");
+                    let system = resources
+                        .get::<System>()
+                        .expect("Failed to get system resource!");
+                    let show_stats = resources
+                        .get::<ShowStats>()
+                        .map(|show_stats| *show_stats)
+                        .unwrap_or_default();
+                    let show_scene_window = resources
+                        .get::<ShowSceneWindow>()
+                        .map(|show_scene_window| *show_scene_window)
+                        .unwrap_or_default();
+                    let scene_texture = renderer.scene_texture().filter(|_| show_scene_window.0);
+                    // Taken from the previous gui frame, since the draw data borrows the gui
+                    let scene_window_dimensions = gui.scene_window_dimensions();
+                    let mut key_bindings = resources
+                        .get_mut::<KeyBindings>()
+                        .expect("Failed to get key bindings resource!");
+                    let inspection = Self::inspection(&world, &resources, renderer.as_ref());
+                    if gui.show_bounds() {
+                        Self::queue_selection_bounds(&world, &resources, renderer.as_ref());
+                    }
                     let draw_data = gui
-                        .render_frame(&window)
+                        .render_frame(
+                            &window,
+                            GuiFrame {
+                                load_progress: &load_progress,
+                                gamepad: &input.gamepad,
+                                system: &system,
+                                render_stats: &renderer.stats(),
+                                show_stats,
+                                scene_texture,
+                                key_bindings: &mut key_bindings,
+                                inspection,
+                            },
+                        )
                         .expect("Failed to render gui frame!");
 
-                    renderer.render(&world, &resources, &draw_data);
+                    let render_result =
+                        Self::render_scene(&world, &resources).and_then(|mut render_scene| {
+                            render_scene.scene_window_dimensions = scene_window_dimensions;
+                            Self::reconcile_assets(renderer.as_mut(), &render_scene);
+                            renderer.render(&render_scene, draw_data)
+                        });
+                    match render_result {
+                        Err(render_error) if render_error.is::<DeviceLost>() => {
+                            warn!("{} Recreating the renderer", render_error);
+                            // The lost device and the surface it presents to are released
+                            // first, since a window can only have one surface at a time
+                            renderer = Box::new(NullRenderer);
+                            match Self::create_renderer(
+                                &settings,
+                                &mut window,
+                                &mut gui,
+                                &world,
+                                &resources,
+                            ) {
+                                Ok(new_renderer) => renderer = new_renderer,
+                                Err(create_error) => {
+                                    error!("{:?}", create_error);
+                                    *control_flow = ControlFlow::Exit;
+                                }
+                            }
+                        }
+                        Err(render_error) => {
+                            error!("{:?}", render_error);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                        Ok(_) => {}
+                    }
+
+                    let selected_entity = resources
+                        .get::<Selection>()
+                        .and_then(|selection| selection.entity);
+                    for request in gui.take_inspector_requests() {
+                        if let Some(entity) = selected_entity {
+                            let result = match request {
+                                InspectorRequest::SaveThumbnail => {
+                                    Self::save_thumbnail(&world, entity, renderer.as_mut())
+                                }
+                                InspectorRequest::Focus => {
+                                    Self::focus_on(&mut world, entity, renderer.as_ref())
+                                }
+                                InspectorRequest::Orbit => remove_asset_camera_views(&mut world),
+                                InspectorRequest::SelectScene(index) => {
+                                    Self::asset_name(&world, entity).and_then(|asset_name| {
+                                        renderer.set_asset_scene(&asset_name, index)
+                                    })
+                                }
+                                InspectorRequest::ViewThroughCamera(index) => {
+                                    Self::view_through_camera(
+                                        &mut world,
+                                        entity,
+                                        renderer.as_ref(),
+                                        index,
+                                    )
+                                }
+                                request => {
+                                    Self::apply_inspector_request(&mut world, entity, request)
+                                }
+                            };
+                            if let Err(error) = result {
+                                warn!("{:?}", error);
+                            }
+                        }
+                    }
+
+                    // Debug shapes are queued again by the next update
+                    if let Some(mut debug_shapes) = resources.get_mut::<DebugShapes>() {
+                        debug_shapes.clear();
+                    }
                 }
                 _ => {}
             }
         });
     }
 
+    // Also used to start over when the GPU device is lost,
+    // since every resource the renderer holds belonged to the old device
+    fn create_renderer(
+        settings: &Settings,
+        window: &mut Window,
+        gui: &mut Gui,
+        world: &World,
+        resources: &Resources,
+    ) -> Result<Box<dyn Renderer>> {
+        debug!("Creating {:?} renderer backend", settings.backend);
+        let mut renderer = <dyn Renderer>::create_backend(
+            &settings.backend,
+            window,
+            settings.anisotropy,
+            &settings.device,
+            settings.frames_in_flight,
+            settings.swapchain_image_count,
+        )?;
+        let mut render_scene = Self::render_scene(world, resources)?;
+        render_scene.environment_cache = settings.environment_cache.clone();
+        render_scene.ibl_settings = settings.ibl;
+        renderer.initialize(&render_scene, &mut gui.context_mut())?;
+        Ok(renderer)
+    }
+
+    // Dropped files are uploaded right away and placed at the origin
+    fn load_dropped_asset(
+        world: &mut World,
+        renderer: &mut dyn Renderer,
+        path: &Path,
+    ) -> Result<()> {
+        let asset_name = path.display().to_string();
+        if !renderer.loaded_assets().contains(&asset_name) {
+            let bytes = fs::read(path).with_context(|| format!("path: {}", asset_name))?;
+            let base_dir = path.parent().unwrap_or_else(|| Path::new("./"));
+            renderer.load_asset_from_bytes(&asset_name, &bytes, base_dir)?;
+        }
+        world.insert((), vec![(Transform::default(), AssetName(asset_name))]);
+        Ok(())
+    }
+
+    // Loads the assets that entities were given since the last frame
+    // and unloads the ones that no entity uses anymore
+    fn reconcile_assets(renderer: &mut dyn Renderer, render_scene: &RenderScene) {
+        let asset_names = render_scene.asset_names();
+        for loaded_asset in renderer.loaded_assets() {
+            if !asset_names.contains(&loaded_asset) {
+                renderer.unload_asset(&loaded_asset);
+            }
+        }
+        for asset_name in asset_names.iter() {
+            renderer.load_asset(asset_name);
+        }
+    }
+
+    fn inspection(
+        world: &World,
+        resources: &Resources,
+        renderer: &dyn Renderer,
+    ) -> Option<Inspection> {
+        let entity = resources.get::<Selection>()?.entity?;
+        let asset_name = world.get_component::<AssetName>(entity)?.0.clone();
+        Some(Inspection {
+            animations: renderer.asset_animations(&asset_name),
+            animation: world
+                .get_component::<AnimationState>(entity)
+                .map(|animation| (*animation).clone()),
+            control: world
+                .get_component::<AnimationControl>(entity)
+                .map(|control| (*control).clone()),
+            cameras: renderer
+                .asset_cameras(&asset_name)
+                .into_iter()
+                .map(|camera| camera.name)
+                .collect(),
+            scenes: renderer.asset_scenes(&asset_name),
+            asset_name,
+        })
+    }
+
+    const THUMBNAIL_SIZE: u32 = 256;
+
+    // Saved to the working directory, named after the asset
+    fn save_thumbnail(world: &World, entity: Entity, renderer: &mut dyn Renderer) -> Result<()> {
+        let asset_name = Self::asset_name(world, entity)?;
+        let stem = Path::new(&asset_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("asset");
+        let output = format!("{}_thumbnail.png", stem);
+        renderer
+            .render_thumbnail(&asset_name, Self::THUMBNAIL_SIZE)?
+            .save(&output)
+            .with_context(|| format!("thumbnail output path: {}", output))?;
+        info!("Saved thumbnail of '{}' to {}", asset_name, output);
+        Ok(())
+    }
+
+    // Draws the box around the selected entity's asset, the boxes of its primitives
+    // and the axes of its transform
+    fn queue_selection_bounds(world: &World, resources: &Resources, renderer: &dyn Renderer) {
+        let entity = match resources
+            .get::<Selection>()
+            .and_then(|selection| selection.entity)
+        {
+            Some(entity) => entity,
+            None => return,
+        };
+        let mut debug_shapes = match resources.get_mut::<DebugShapes>() {
+            Some(debug_shapes) => debug_shapes,
+            None => return,
+        };
+
+        if let Some(transform) = world.get_component::<Transform>(entity) {
+            debug_shapes.add_axes(&transform, 1.0);
+        }
+
+        let asset_name = match world.get_component::<AssetName>(entity) {
+            Some(asset_name) => asset_name.0.clone(),
+            None => return,
+        };
+        let world_transform = world
+            .get_component::<WorldTransform>(entity)
+            .map_or_else(glm::Mat4::identity, |transform| transform.0);
+        if let Some(bounding_box) = renderer.asset_bounding_box(&asset_name) {
+            debug_shapes.add_aabb(
+                &bounding_box.transform(&world_transform),
+                glm::vec4(1.0, 1.0, 0.0, 1.0),
+            );
+        }
+        for bounding_box in renderer.asset_primitive_bounding_boxes(&asset_name) {
+            debug_shapes.add_aabb(
+                &bounding_box.transform(&world_transform),
+                glm::vec4(0.0, 1.0, 1.0, 1.0),
+            );
+        }
+    }
+
+    // Moves the active orbital camera over to frame the entity's asset
+    fn focus_on(world: &mut World, entity: Entity, renderer: &dyn Renderer) -> Result<()> {
+        let asset_name = Self::asset_name(world, entity)?;
+        let world_transform = world
+            .get_component::<WorldTransform>(entity)
+            .map_or_else(glm::Mat4::identity, |transform| transform.0);
+        let bounding_box = renderer
+            .asset_bounding_box(&asset_name)
+            .with_context(|| format!("Failed to focus on '{}' before it loaded!", asset_name))?
+            .transform(&world_transform);
+        let query = <Write<OrbitalCamera>>::query().filter(tag::<ActiveCamera>());
+        for mut camera in query.iter_mut(world) {
+            camera.set_target_smoothly(bounding_box.center());
+            camera.focus_on(&bounding_box);
+        }
+        Ok(())
+    }
+
+    // Only one asset camera is viewed through at a time
+    fn view_through_camera(
+        world: &mut World,
+        entity: Entity,
+        renderer: &dyn Renderer,
+        index: usize,
+    ) -> Result<()> {
+        let asset_name = Self::asset_name(world, entity)?;
+        let asset_camera = renderer
+            .asset_cameras(&asset_name)
+            .into_iter()
+            .nth(index)
+            .with_context(|| format!("Failed to find camera {} of '{}'!", index, asset_name))?;
+        let world_transform = world
+            .get_component::<WorldTransform>(entity)
+            .map_or_else(glm::Mat4::identity, |transform| transform.0);
+        remove_asset_camera_views(world)?;
+        activate_asset_camera(world, &asset_camera, &world_transform)?;
+        Ok(())
+    }
+
+    fn asset_name(world: &World, entity: Entity) -> Result<String> {
+        let asset_name = world
+            .get_component::<AssetName>(entity)
+            .context("Failed to get the asset name of the selected entity!")?;
+        Ok(asset_name.0.clone())
+    }
+
+    fn apply_inspector_request(
+        world: &mut World,
+        entity: Entity,
+        request: InspectorRequest,
+    ) -> Result<()> {
+        let mut animation = world.get_component_mut::<AnimationState>(entity);
+        match request {
+            InspectorRequest::PlayAnimation { name, looping } => match animation.as_mut() {
+                Some(animation) => animation.set_animation(&name),
+                None => {
+                    drop(animation);
+                    world.add_component(entity, AnimationState::new(&name, looping))?;
+                }
+            },
+            InspectorRequest::SetLooping(looping) => match animation.as_mut() {
+                Some(animation) => animation.looping = looping,
+                None => {
+                    drop(animation);
+                    world.add_component(
+                        entity,
+                        AnimationState {
+                            looping,
+                            ..Default::default()
+                        },
+                    )?;
+                }
+            },
+            // The inspector only offers blending once the entity has an animation
+            InspectorRequest::BlendWith { name, weight } => {
+                if let Some(animation) = animation.as_mut() {
+                    animation.blend_with(&name, weight);
+                }
+            }
+            InspectorRequest::StopBlending => {
+                if let Some(animation) = animation.as_mut() {
+                    animation.blend = None;
+                }
+            }
+            InspectorRequest::Play | InspectorRequest::Pause | InspectorRequest::SetTime(_) => {
+                drop(animation);
+                Self::apply_animation_control(world, entity, request)?;
+            }
+            // Handled by the caller, these change more than the selected entity
+            InspectorRequest::SaveThumbnail
+            | InspectorRequest::Focus
+            | InspectorRequest::ViewThroughCamera(_)
+            | InspectorRequest::Orbit
+            | InspectorRequest::SelectScene(_) => {}
+        }
+        Ok(())
+    }
+
+    // The control is added the first time the entity is paused or scrubbed
+    fn apply_animation_control(
+        world: &mut World,
+        entity: Entity,
+        request: InspectorRequest,
+    ) -> Result<()> {
+        if world.get_component::<AnimationControl>(entity).is_none() {
+            world.add_component(entity, AnimationControl::default())?;
+        }
+        let mut control = world
+            .get_component_mut::<AnimationControl>(entity)
+            .context("Failed to get the animation control of the selected entity!")?;
+        match request {
+            InspectorRequest::Play => control.play(),
+            InspectorRequest::Pause => control.pause(),
+            InspectorRequest::SetTime(time) => control.set_time(time),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn environment(settings: &Settings) -> Environment {
+        if let Some(faces) = settings.environment_faces.clone() {
+            return Environment::Faces {
+                right: faces.right,
+                left: faces.left,
+                top: faces.top,
+                bottom: faces.bottom,
+                front: faces.front,
+                back: faces.back,
+            };
+        }
+        settings
+            .environment
+            .as_deref()
+            .map(Environment::from_path)
+            .unwrap_or_default()
+    }
+
+    fn create_world(universe: &Universe, settings: &Settings) -> World {
+        let mut world = universe.create_world();
+
+        let camera_settings = settings.orbital_camera;
+        let mut orbital_camera = OrbitalCamera::default();
+        orbital_camera.set_zoom_sensitivity(camera_settings.zoom_sensitivity);
+        orbital_camera.set_rotate_sensitivity(camera_settings.rotate_sensitivity);
+        orbital_camera.set_target_smoothing(camera_settings.target_smoothing);
+        if let Err(error) =
+            orbital_camera.set_radius_limits(camera_settings.min_radius, camera_settings.max_radius)
+        {
+            warn!("Ignoring the orbital camera radius limits: {}", error);
+        }
+        let mut camera = Camera::default();
+        camera.set_view(&orbital_camera.view_matrix());
+        world.insert((ActiveCamera,), vec![(orbital_camera, camera)]);
+
+        if settings.split_screen {
+            let mut side_camera = Camera::default();
+            side_camera.set_view(&glm::look_at(
+                &glm::vec3(10.0, 0.0, 0.0),
+                &glm::Vec3::zeros(),
+                &glm::Vec3::y(),
+            ));
+            world.insert((), vec![(side_camera,)]);
+        }
+
+        world.insert(
+            (),
+            vec![
+                (Light {
+                    kind: LightKind::Directional,
+                    direction: glm::vec3(0.0, -10.0, 0.0),
+                    color: glm::vec3(1.0, 0.0, 1.0),
+                    ..Default::default()
+                },),
+                (Light {
+                    kind: LightKind::Spot,
+                    direction: glm::vec3(0.0, -10.0, 0.0),
+                    position: glm::vec3(1.0, 6.0, 1.0),
+                    color: glm::vec3(0.0, 1.0, 0.0),
+                    range: 10.0,
+                    ..Default::default()
+                },),
+                (Light {
+                    kind: LightKind::Point,
+                    position: glm::vec3(-2.0, 4.0, 2.0),
+                    color: glm::vec3(1.0, 0.9, 0.7),
+                    range: 10.0,
+                    ..Default::default()
+                },),
+            ],
+        );
+
+        world.insert(
+            (),
+            vec![(
+                Transform::default(),
+                AssetName("assets/models/MetalRoughSpheres.glb".to_string()),
+            )],
+        );
+
+        if let Some(position) = settings.particle_emitter {
+            world.insert((), vec![(ParticleEmitter::new(glm::make_vec3(&position)),)]);
+        }
+
+        world
+    }
+
+    // Renders without a window once every asset has loaded and saves the frame as an image
+    #[cfg(feature = "headless")]
+    fn run_headless(settings: &Settings, output: &str) -> Result<()> {
+        let dimensions = glm::vec2(settings.width as _, settings.height as _);
+
+        let mut resources = Resources::default();
+        resources.insert(System::new(dimensions));
+        resources.insert(RenderSettings::default());
+        resources.insert(Self::environment(settings));
+
+        let universe = Universe::new();
+        let mut world = Self::create_world(&universe, settings);
+
+        // Nothing moves without a window, so the world transforms only need to be composed once.
+        // The first pass gives entities a world transform and the second fills it in.
+        let mut schedule = Schedule::builder()
+            .add_system(transform_hierarchy_system())
+            .flush()
+            .add_system(transform_hierarchy_system())
+            .build();
+        schedule.execute(&mut world, &mut resources);
+
+        debug!("Creating headless renderer");
+        let mut renderer = HeadlessRenderer::new(
+            settings.width as _,
+            settings.height as _,
+            settings.anisotropy,
+        )?;
+        let mut render_scene = Self::render_scene(&world, &resources)?;
+        render_scene.environment_cache = settings.environment_cache.clone();
+        render_scene.ibl_settings = settings.ibl;
+        renderer.initialize(&render_scene)?;
+
+        renderer.wait_for_assets()?;
+        renderer.render(&render_scene)?;
+
+        renderer
+            .read_pixels()?
+            .save(output)
+            .with_context(|| format!("headless output path: {}", output))?;
+        info!("Saved headless frame to {}", output);
+        Ok(())
+    }
+
+    // Extracts the data the renderer needs from the world for the current frame
+    fn render_scene(world: &World, resources: &Resources) -> Result<RenderScene> {
+        let mut render_scene = RenderScene::default();
+
+        let camera = active_camera(world)?;
+        render_scene.view = camera.view_matrix();
+        render_scene.camera_position = camera.position;
+        render_scene.projection = camera.projection;
+        render_scene.split_cameras = inactive_cameras(world)
+            .iter()
+            .map(|camera| SceneCamera {
+                view: camera.view_matrix(),
+                position: camera.position,
+                projection: camera.projection,
+            })
+            .collect();
+
+        if let Some(render_settings) = resources.get::<RenderSettings>() {
+            render_scene.render_settings = *render_settings;
+        }
+
+        if let Some(environment) = resources.get::<Environment>() {
+            render_scene.environment = environment.clone();
+        }
+
+        if let Some(debug_shapes) = resources.get::<DebugShapes>() {
+            render_scene.debug_shapes = debug_shapes.clone();
+        }
+
+        if let Some(selection) = resources.get::<Selection>() {
+            render_scene.selected_entities = selection.entity.into_iter().collect();
+        }
+
+        if let Some(system) = resources.get::<System>() {
+            render_scene.delta_time = system.delta_time as f32;
+            render_scene.window_dimensions = system.window_dimensions;
+        }
+
+        // Assets are drawn with their local transform until their world transform is composed
+        let (entities, (assets, animations)): (_, (_, _)) = <(
+            Read<AssetName>,
+            Read<Transform>,
+            TryRead<WorldTransform>,
+            TryRead<AnimationState>,
+        )>::query()
+        .iter_entities(world)
+        .map(|(entity, (name, transform, world_transform, animation))| {
+            let world_matrix = world_transform
+                .map(|world_transform| world_transform.0)
+                .unwrap_or_else(|| transform.matrix());
+            (
+                entity,
+                (
+                    ((*name).clone(), world_matrix),
+                    animation.map(|animation| (*animation).clone()),
+                ),
+            )
+        })
+        .unzip();
+        render_scene.entities = entities;
+        render_scene.assets = assets;
+        render_scene.animations = animations;
+
+        render_scene.lights = <Read<Light>>::query()
+            .iter(world)
+            .map(|light| *light)
+            .collect();
+
+        render_scene.particle_emitters = <Read<ParticleEmitter>>::query()
+            .iter(world)
+            .map(|emitter| (*emitter).clone())
+            .collect();
+
+        Ok(render_scene)
+    }
+
     fn setup_logger() -> Result<()> {
         CombinedLogger::init(vec![
             TermLogger::new(LevelFilter::max(), Config::default(), TerminalMode::Mixed),
@@ -142,6 +870,9 @@ impl App {
         config
             .merge(config::File::with_name(Self::SETTINGS_FILE))
             .with_context(|| format!("settings file path: {}", Self::SETTINGS_FILE.to_string()))?;
+        config.merge(config::Environment::with_prefix(
+            Self::SETTINGS_ENVIRONMENT_PREFIX,
+        ))?;
         let settings: Settings = config.try_into()?;
         Ok(settings)
     }
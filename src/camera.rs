@@ -1,10 +1,17 @@
-use crate::{input::Input, system::System};
+use crate::{
+    input::{Action, Input, KeyBindings},
+    renderer::{Aabb, AssetCamera, AssetCameraProjection},
+    system::System,
+};
+use anyhow::{anyhow, bail, ensure, Result};
 use legion::prelude::*;
 use nalgebra_glm as glm;
-use winit::event::VirtualKeyCode;
 
-// TODO: Make camera abstraction
+// Full stick deflection turns the camera this far per second
+const GAMEPAD_LOOK_DEGREES_PER_SECOND: f32 = 120.0;
+const GAMEPAD_ZOOM_PER_SECOND: f32 = 5.0;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CameraDirection {
     Forward,
     Backward,
@@ -117,37 +124,37 @@ impl FreeCamera {
 pub fn fps_camera_controls_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("fps_camera_controls")
         .read_resource::<Input>()
+        .read_resource::<KeyBindings>()
         .read_resource::<System>()
         .with_query(<Write<FreeCamera>>::query())
-        .build(move |_, world, (input, system), query| {
+        .build(move |_, world, (input, key_bindings, system), query| {
             let delta_time = system.delta_time as f32;
+            let key_bindings: &KeyBindings = key_bindings;
             for mut camera in query.iter_mut(world) {
-                if input.is_key_pressed(VirtualKeyCode::W) {
-                    camera.translate(CameraDirection::Forward, delta_time);
-                }
-
-                if input.is_key_pressed(VirtualKeyCode::A) {
-                    camera.translate(CameraDirection::Left, delta_time);
-                }
-
-                if input.is_key_pressed(VirtualKeyCode::S) {
-                    camera.translate(CameraDirection::Backward, delta_time);
-                }
-
-                if input.is_key_pressed(VirtualKeyCode::D) {
-                    camera.translate(CameraDirection::Right, delta_time);
-                }
-
-                if input.is_key_pressed(VirtualKeyCode::LShift) {
-                    camera.translate(CameraDirection::Down, delta_time);
-                }
-
-                if input.is_key_pressed(VirtualKeyCode::Space) {
-                    camera.translate(CameraDirection::Up, delta_time);
+                let controls = [
+                    (Action::MoveForward, CameraDirection::Forward),
+                    (Action::MoveLeft, CameraDirection::Left),
+                    (Action::MoveBackward, CameraDirection::Backward),
+                    (Action::MoveRight, CameraDirection::Right),
+                    (Action::MoveDown, CameraDirection::Down),
+                    (Action::MoveUp, CameraDirection::Up),
+                ];
+                for (action, direction) in controls.iter() {
+                    if input.is_action_pressed(key_bindings, *action) {
+                        camera.translate(*direction, delta_time);
+                    }
                 }
 
                 let offset = input.mouse.offset_from_center;
                 camera.process_mouse_movement(offset.x, offset.y);
+
+                let movement = input.gamepad.movement();
+                camera.translate(CameraDirection::Forward, movement.y * delta_time);
+                camera.translate(CameraDirection::Right, movement.x * delta_time);
+
+                let look = input.gamepad.look() * GAMEPAD_LOOK_DEGREES_PER_SECOND * delta_time
+                    / camera.sensitivity;
+                camera.process_mouse_movement(-look.x, -look.y);
             }
         })
 }
@@ -155,37 +162,139 @@ pub fn fps_camera_controls_system() -> Box<dyn Schedulable> {
 pub struct OrbitalCamera {
     direction: glm::Vec2,
     r: f32,
+    // The point orbited around
+    target: glm::Vec3,
+    // The point the target is moving towards when it is moved smoothly
+    target_destination: glm::Vec3,
+    // How quickly a smoothly moved target catches up, per second
+    target_smoothing: f32,
+    min_radius: f32,
+    max_radius: f32,
+    zoom_sensitivity: f32,
+    rotate_sensitivity: f32,
 }
 
 impl OrbitalCamera {
+    // Keeps the camera from flipping over the poles
+    const MIN_POLAR_ANGLE_DEGREES: f32 = 10.0;
+    const MAX_POLAR_ANGLE_DEGREES: f32 = 170.0;
+
+    // Framed bounding boxes fill about a 60 degree field of view
+    const FOCUS_DISTANCE_SCALE: f32 = 2.0;
+
+    pub fn set_radius_limits(&mut self, min_radius: f32, max_radius: f32) -> Result<()> {
+        ensure!(
+            min_radius > 0.0,
+            "The minimum radius must be greater than zero, found {}",
+            min_radius
+        );
+        ensure!(
+            max_radius >= min_radius,
+            "The maximum radius ({}) must not be less than the minimum radius ({})",
+            max_radius,
+            min_radius
+        );
+        self.min_radius = min_radius;
+        self.max_radius = max_radius;
+        self.r = glm::clamp_scalar(self.r, min_radius, max_radius);
+        Ok(())
+    }
+
+    pub fn zoom_sensitivity(&self) -> f32 {
+        self.zoom_sensitivity
+    }
+
+    pub fn set_zoom_sensitivity(&mut self, zoom_sensitivity: f32) {
+        self.zoom_sensitivity = zoom_sensitivity;
+    }
+
+    pub fn rotate_sensitivity(&self) -> f32 {
+        self.rotate_sensitivity
+    }
+
+    pub fn set_rotate_sensitivity(&mut self, rotate_sensitivity: f32) {
+        self.rotate_sensitivity = rotate_sensitivity;
+    }
+
+    pub fn target(&self) -> glm::Vec3 {
+        self.target
+    }
+
+    // Moves the orbit to the new target immediately, keeping the angle and radius
+    pub fn set_target(&mut self, target: glm::Vec3) {
+        self.target = target;
+        self.target_destination = target;
+    }
+
+    // Moves the orbit to the new target over the next few frames,
+    // which is smoother for following a moving entity or recentering on a selection
+    pub fn set_target_smoothly(&mut self, target: glm::Vec3) {
+        self.target_destination = target;
+    }
+
+    pub fn set_target_smoothing(&mut self, target_smoothing: f32) {
+        self.target_smoothing = target_smoothing;
+    }
+
+    // Places the camera at the eye, orbiting the target.
+    // The radius and polar angle are clamped to their limits.
+    pub fn look_at(&mut self, eye: glm::Vec3, target: glm::Vec3) {
+        self.set_target(target);
+        let offset = eye - target;
+        let distance = offset.magnitude();
+        self.r = glm::clamp_scalar(distance, self.min_radius, self.max_radius);
+        if distance > 0.0 {
+            let direction = offset / distance;
+            self.direction = glm::vec2(
+                direction.x.atan2(direction.z),
+                glm::clamp_scalar(
+                    direction.y.acos(),
+                    Self::MIN_POLAR_ANGLE_DEGREES.to_radians(),
+                    Self::MAX_POLAR_ANGLE_DEGREES.to_radians(),
+                ),
+            );
+        }
+    }
+
+    // Moves a smoothly moved target towards its destination
+    pub fn update(&mut self, delta_time: f32) {
+        let amount = 1.0 - (-self.target_smoothing * delta_time).exp();
+        self.target = glm::lerp(&self.target, &self.target_destination, amount);
+    }
+
+    // Sets the radius so the bounding sphere of the box is in view.
+    // The maximum radius grows if the box is too large to be framed otherwise.
+    pub fn focus_on(&mut self, aabb: &Aabb) {
+        let radius =
+            (aabb.extents().magnitude() * 0.5 * Self::FOCUS_DISTANCE_SCALE).max(self.min_radius);
+        self.max_radius = self.max_radius.max(radius);
+        self.r = radius;
+    }
+
     pub fn position(&self) -> glm::Vec3 {
         let direction = glm::vec3(
             self.direction.y.sin() * self.direction.x.sin(),
             self.direction.y.cos(),
             self.direction.y.sin() * self.direction.x.cos(),
         );
-        direction * self.r
+        self.target + direction * self.r
     }
 
     pub fn rotate(&mut self, position_delta: &glm::Vec2) {
         self.direction.x -= position_delta.x;
         self.direction.y = glm::clamp_scalar(
             self.direction.y - position_delta.y,
-            10.0_f32.to_radians(),
-            170.0_f32.to_radians(),
+            Self::MIN_POLAR_ANGLE_DEGREES.to_radians(),
+            Self::MAX_POLAR_ANGLE_DEGREES.to_radians(),
         );
     }
 
     pub fn forward(&mut self, r: f32) {
-        self.r -= r;
+        self.r = glm::clamp_scalar(self.r - r, self.min_radius, self.max_radius);
     }
 
     pub fn view_matrix(&self) -> glm::Mat4 {
-        glm::look_at(
-            &self.position(),
-            &glm::vec3(0.0, 0.0, 0.0),
-            &glm::vec3(0.0, 1.0, 0.0),
-        )
+        glm::look_at(&self.position(), &self.target, &glm::vec3(0.0, 1.0, 0.0))
     }
 }
 
@@ -194,26 +303,267 @@ impl Default for OrbitalCamera {
         Self {
             direction: glm::vec2(0_f32.to_radians(), 45_f32.to_radians()),
             r: 5.0,
+            target: glm::Vec3::zeros(),
+            target_destination: glm::Vec3::zeros(),
+            target_smoothing: 10.0,
+            min_radius: 0.1,
+            max_radius: 1000.0,
+            zoom_sensitivity: 0.3,
+            rotate_sensitivity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraProjection {
+    pub fov_y_degrees: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for CameraProjection {
+    fn default() -> Self {
+        Self {
+            fov_y_degrees: 70.0,
+            near: 0.1,
+            far: 1000.0,
         }
     }
 }
 
+impl CameraProjection {
+    pub fn new(fov_y_degrees: f32, near: f32, far: f32) -> Result<Self> {
+        let projection = Self {
+            fov_y_degrees,
+            near,
+            far,
+        };
+        projection.validate()?;
+        Ok(projection)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.near > 0.0,
+            "The near plane must be greater than zero, found {}",
+            self.near
+        );
+        ensure!(
+            self.far > self.near,
+            "The far plane ({}) must be greater than the near plane ({})",
+            self.far,
+            self.near
+        );
+        ensure!(
+            self.fov_y_degrees > 0.0 && self.fov_y_degrees < 180.0,
+            "The vertical field of view must be between 0 and 180 degrees, found {}",
+            self.fov_y_degrees
+        );
+        Ok(())
+    }
+
+    pub fn matrix(&self, aspect_ratio: f32) -> glm::Mat4 {
+        glm::perspective_zo(
+            aspect_ratio,
+            self.fov_y_degrees.to_radians(),
+            self.near,
+            self.far,
+        )
+    }
+}
+
 pub fn orbital_camera_controls_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("orbital_camera_controls")
         .read_resource::<Input>()
         .read_resource::<System>()
         .with_query(<Write<OrbitalCamera>>::query())
         .build(move |_, world, (input, system), query| {
-            if !input.allowed {
-                return;
-            }
-
             let delta_time = system.delta_time as f32;
             for mut camera in query.iter_mut(world) {
-                camera.forward(input.mouse.wheel_delta.y * 0.3);
+                camera.update(delta_time);
+
+                if !input.allowed {
+                    continue;
+                }
+
+                let zoom_sensitivity = camera.zoom_sensitivity();
+                camera.forward(input.mouse.wheel_delta.y * zoom_sensitivity);
                 if input.mouse.is_left_clicked {
-                    camera.rotate(&(input.mouse.position_delta * delta_time));
+                    let rotate_sensitivity = camera.rotate_sensitivity();
+                    camera.rotate(&(input.mouse.position_delta * rotate_sensitivity * delta_time));
                 }
+
+                camera.forward(input.gamepad.zoom() * GAMEPAD_ZOOM_PER_SECOND * delta_time);
+                let look = input.gamepad.look()
+                    * GAMEPAD_LOOK_DEGREES_PER_SECOND.to_radians()
+                    * delta_time;
+                camera.rotate(&look);
+            }
+        })
+}
+
+// The camera the scene is rendered from.
+// Camera controllers on the same entity keep it up to date.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: glm::Vec3,
+    pub orientation: glm::Quat,
+    pub projection: CameraProjection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: glm::vec3(0.0, 0.0, 10.0),
+            orientation: glm::quat_identity(),
+            projection: CameraProjection::default(),
+        }
+    }
+}
+
+impl Camera {
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        glm::quat_to_mat4(&glm::quat_inverse(&self.orientation)) * glm::translation(&-self.position)
+    }
+
+    // Takes the position and orientation from a view matrix
+    pub fn set_view(&mut self, view: &glm::Mat4) {
+        let transform = glm::inverse(view);
+        self.position = transform.column(3).xyz();
+        self.orientation = glm::to_quat(&transform);
+    }
+
+    // Views the scene through a camera authored in an asset placed with the given world matrix.
+    // The aspect ratio always follows the viewport, and infinite projections use the default far plane.
+    pub fn from_asset_camera(
+        asset_camera: &AssetCamera,
+        asset_transform: &glm::Mat4,
+    ) -> Result<Self> {
+        let projection = match asset_camera.projection {
+            AssetCameraProjection::Perspective {
+                fov_y_degrees,
+                near,
+                far,
+                ..
+            } => CameraProjection::new(
+                fov_y_degrees,
+                near,
+                far.unwrap_or(CameraProjection::default().far),
+            )?,
+            AssetCameraProjection::Orthographic { .. } => bail!(
+                "Failed to view through camera '{}', orthographic projections are not supported!",
+                asset_camera.name
+            ),
+        };
+        let mut camera = Self {
+            projection,
+            ..Default::default()
+        };
+        camera.set_view(&asset_camera.view_matrix(asset_transform));
+        Ok(camera)
+    }
+}
+
+// Tags the camera entity the scene is rendered from.
+// Only one camera should be active, use set_active_camera to switch between them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveCamera;
+
+// Tags the camera entities created to view through cameras authored in assets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AssetCameraView;
+
+pub fn active_camera(world: &World) -> Result<Camera> {
+    <Read<Camera>>::query()
+        .filter(tag::<ActiveCamera>())
+        .iter(world)
+        .next()
+        .map(|camera| *camera)
+        .ok_or_else(|| {
+            anyhow!("No camera is active! Tag an entity with a Camera component as ActiveCamera.")
+        })
+}
+
+// Every camera that isn't active, rendered beside the active camera in split screen.
+// The orbital camera is left out, it is only inactive while viewing through an asset camera.
+pub fn inactive_cameras(world: &World) -> Vec<Camera> {
+    <Read<Camera>>::query()
+        .filter(!tag::<ActiveCamera>() & !component::<OrbitalCamera>())
+        .iter(world)
+        .map(|camera| *camera)
+        .collect()
+}
+
+pub fn set_active_camera(world: &mut World, entity: Entity) -> Result<()> {
+    let active_cameras = <Read<Camera>>::query()
+        .filter(tag::<ActiveCamera>())
+        .iter_entities(world)
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    for active_camera in active_cameras {
+        world.remove_tag::<ActiveCamera>(active_camera)?;
+    }
+    world.add_tag(entity, ActiveCamera)?;
+    Ok(())
+}
+
+// Creates a camera entity viewing through a camera authored in an asset and makes it active
+pub fn activate_asset_camera(
+    world: &mut World,
+    asset_camera: &AssetCamera,
+    asset_transform: &glm::Mat4,
+) -> Result<Entity> {
+    let camera = Camera::from_asset_camera(asset_camera, asset_transform)?;
+    let entity = world.insert((AssetCameraView,), vec![(camera,)])[0];
+    set_active_camera(world, entity)?;
+    Ok(entity)
+}
+
+// Removes the cameras created to view through asset cameras and makes the orbital camera active again.
+// The orbital camera picks up from where the active asset camera was.
+pub fn remove_asset_camera_views(world: &mut World) -> Result<()> {
+    let views = <Read<Camera>>::query()
+        .filter(tag::<AssetCameraView>())
+        .iter_entities(world)
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    let active_view = <Read<Camera>>::query()
+        .filter(tag::<AssetCameraView>() & tag::<ActiveCamera>())
+        .iter(world)
+        .map(|camera| camera.position)
+        .next();
+    for view in views {
+        world.delete(view);
+    }
+
+    let orbital_camera = <Read<OrbitalCamera>>::query()
+        .iter_entities(world)
+        .map(|(entity, _)| entity)
+        .next();
+    if let Some(entity) = orbital_camera {
+        if let (Some(eye), Some(mut camera)) = (
+            active_view,
+            world.get_component_mut::<OrbitalCamera>(entity),
+        ) {
+            let target = camera.target();
+            camera.look_at(eye, target);
+        }
+        set_active_camera(world, entity)?;
+    }
+    Ok(())
+}
+
+// Copies the view of camera controllers into the camera on the same entity
+pub fn camera_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("camera")
+        .with_query(<(Read<OrbitalCamera>, Write<Camera>)>::query())
+        .with_query(<(Read<FreeCamera>, Write<Camera>)>::query())
+        .build(move |_, world, _, (orbital_query, free_query)| {
+            for (orbital_camera, mut camera) in orbital_query.iter_mut(world) {
+                camera.set_view(&orbital_camera.view_matrix());
+            }
+            for (free_camera, mut camera) in free_query.iter_mut(world) {
+                camera.set_view(&free_camera.view_matrix());
             }
         })
 }
@@ -1,14 +1,95 @@
+use crate::{
+    animation::{AnimationControl, AnimationState},
+    input::{Action, Gamepad, KeyBindings},
+    renderer::{AnimationInfo, LoadProgress, RenderStats, SceneInfo},
+    system::System,
+};
 use anyhow::Result;
-use imgui::{im_str, Condition, Context, DrawData, FontConfig, FontSource};
+use imgui::{
+    im_str, Condition, Context, DrawData, FontConfig, FontSource, Image, ProgressBar, Selectable,
+    Slider, TextureId, Ui,
+};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
-use winit::{event::Event, window::Window};
+use nalgebra_glm as glm;
+use std::collections::VecDeque;
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    window::Window,
+};
+
+// Shows the frame statistics overlay while set
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ShowStats(pub bool);
+
+// Shows the scene inside a movable gui window while set, instead of filling the screen
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ShowSceneWindow(pub bool);
+
+// Where the scene image was drawn in the last gui frame, in gui coordinates
+#[derive(Debug, Clone, Copy)]
+struct SceneRegion {
+    position: [f32; 2],
+    size: [f32; 2],
+    hovered: bool,
+}
+
+// What the gui shows for the current frame
+pub struct GuiFrame<'a> {
+    pub load_progress: &'a LoadProgress,
+    pub gamepad: &'a Gamepad,
+    pub system: &'a System,
+    pub render_stats: &'a RenderStats,
+    pub show_stats: ShowStats,
+    pub scene_texture: Option<TextureId>,
+    pub key_bindings: &'a mut KeyBindings,
+    pub inspection: Option<Inspection>,
+}
+
+// The selected entity, shown in the inspector window
+pub struct Inspection {
+    pub asset_name: String,
+    pub animations: Vec<AnimationInfo>,
+    // Missing until an animation is selected, in which case the first animation plays
+    pub animation: Option<AnimationState>,
+    pub control: Option<AnimationControl>,
+    // The names of the cameras authored in the asset
+    pub cameras: Vec<String>,
+    pub scenes: Vec<SceneInfo>,
+}
+
+// Changes made in the inspector window, applied to the selected entity by the app
+#[derive(Debug, Clone, PartialEq)]
+pub enum InspectorRequest {
+    PlayAnimation { name: String, looping: bool },
+    SetLooping(bool),
+    BlendWith { name: String, weight: f32 },
+    StopBlending,
+    Play,
+    Pause,
+    SetTime(f32),
+    SaveThumbnail,
+    Focus,
+    ViewThroughCamera(usize),
+    Orbit,
+    SelectScene(usize),
+}
 
 pub struct Gui {
     context: Context,
     platform: WinitPlatform,
+    // Frame times in milliseconds, oldest first
+    frame_times: VecDeque<f32>,
+    scene_region: Option<SceneRegion>,
+    // The action waiting for a key to be pressed to bind it to
+    rebinding: Option<Action>,
+    pressed_key: Option<VirtualKeyCode>,
+    inspector_requests: Vec<InspectorRequest>,
+    show_bounds: bool,
 }
 
 impl Gui {
+    pub const FRAME_TIME_HISTORY: usize = 120;
+
     pub fn new(window: &Window) -> Self {
         let mut context = Context::create();
         context.set_ini_filename(None);
@@ -25,17 +106,73 @@ impl Gui {
         }]);
         context.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
 
+        // Dragging over the scene window moves the camera instead of the window
+        context.io_mut().config_windows_move_from_title_bar_only = true;
+
         platform.attach_window(context.io_mut(), &window, HiDpiMode::Rounded);
 
-        Self { context, platform }
+        Self {
+            context,
+            platform,
+            frame_times: VecDeque::with_capacity(Self::FRAME_TIME_HISTORY),
+            scene_region: None,
+            rebinding: None,
+            pressed_key: None,
+            inspector_requests: Vec::new(),
+            show_bounds: false,
+        }
     }
 
     pub fn handle_event<T>(&mut self, event: &Event<T>, window: &Window) {
         self.platform
             .handle_event(self.context.io_mut(), &window, &event);
+
+        if self.rebinding.is_none() {
+            return;
+        }
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(keycode),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.pressed_key = Some(*keycode);
+        }
     }
 
-    pub fn render_frame(&mut self, window: &Window) -> Result<&DrawData> {
+    pub fn render_frame(&mut self, window: &Window, frame: GuiFrame) -> Result<&DrawData> {
+        let GuiFrame {
+            load_progress,
+            gamepad,
+            system,
+            render_stats,
+            show_stats,
+            scene_texture,
+            key_bindings,
+            inspection,
+        } = frame;
+
+        if let (Some(action), Some(keycode)) = (self.rebinding, self.pressed_key.take()) {
+            key_bindings.bind(action, &[keycode]);
+            self.rebinding = None;
+        }
+
+        // Frame times are recorded while the overlay is hidden
+        // so the graph is already filled in when it is shown
+        if self.frame_times.len() == Self::FRAME_TIME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times
+            .push_back(system.delta_time as f32 * 1000.0);
+
         self.platform
             .prepare_frame(self.context.io_mut(), &window)?;
 
@@ -52,8 +189,46 @@ impl Gui {
                     "Mouse Position: ({:.1},{:.1})",
                     mouse_pos[0], mouse_pos[1]
                 ));
+                if gamepad.connected {
+                    ui.text(format!("Gamepad: {}", gamepad.name));
+                } else {
+                    ui.text(im_str!("Gamepad: Not connected"));
+                }
             });
 
+        if !load_progress.is_complete() {
+            imgui::Window::new(im_str!("Loading"))
+                .size([300.0, 60.0], Condition::FirstUseEver)
+                .build(&ui, || {
+                    let overlay_text = im_str!(
+                        "Loaded {} of {} assets",
+                        load_progress.loaded,
+                        load_progress.total
+                    );
+                    ProgressBar::new(load_progress.fraction())
+                        .overlay_text(&overlay_text)
+                        .build(&ui);
+                });
+        }
+
+        if show_stats.0 {
+            let frame_times = self.frame_times.iter().copied().collect::<Vec<_>>();
+            Self::render_stats(&ui, &frame_times, render_stats);
+        }
+
+        Self::render_controls(&ui, key_bindings, &mut self.rebinding);
+
+        if let Some(inspection) = inspection.as_ref() {
+            Self::render_inspector(
+                &ui,
+                inspection,
+                &mut self.inspector_requests,
+                &mut self.show_bounds,
+            );
+        }
+
+        self.scene_region = scene_texture.and_then(|texture| Self::render_scene(&ui, texture));
+
         self.platform.prepare_render(&ui, &window);
 
         let draw_data = ui.render();
@@ -61,11 +236,280 @@ impl Gui {
         Ok(draw_data)
     }
 
+    fn render_stats(ui: &Ui, frame_times: &[f32], render_stats: &RenderStats) {
+        let average_frame_time = if frame_times.is_empty() {
+            0.0
+        } else {
+            frame_times.iter().sum::<f32>() / frame_times.len() as f32
+        };
+        let fps = if average_frame_time > 0.0 {
+            1000.0 / average_frame_time
+        } else {
+            0.0
+        };
+        let max_frame_time = frame_times.iter().copied().fold(0.0, f32::max);
+
+        imgui::Window::new(im_str!("Stats"))
+            .size([300.0, 140.0], Condition::FirstUseEver)
+            .position([10.0, 120.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("FPS: {:.1}", fps));
+                let overlay_text = im_str!("{:.2} ms", average_frame_time);
+                ui.plot_lines(im_str!("Frame time"), frame_times)
+                    .overlay_text(&overlay_text)
+                    .scale_min(0.0)
+                    .scale_max(max_frame_time.max(1.0))
+                    .graph_size([0.0, 40.0])
+                    .build();
+                ui.text(format!("Draw calls: {}", render_stats.draw_calls));
+                ui.text(format!("Instances drawn: {}", render_stats.instances));
+            });
+    }
+
+    fn render_controls(ui: &Ui, key_bindings: &mut KeyBindings, rebinding: &mut Option<Action>) {
+        imgui::Window::new(im_str!("Controls"))
+            .size([300.0, 240.0], Condition::FirstUseEver)
+            .position([10.0, 270.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(ui, || {
+                for action in Action::ALL.iter().copied() {
+                    if ui.button(&im_str!("{:?}", action), [120.0, 0.0]) {
+                        *rebinding = Some(action);
+                    }
+                    ui.same_line(0.0);
+                    if *rebinding == Some(action) {
+                        ui.text(im_str!("Press a key..."));
+                    } else {
+                        ui.text(format!("{:?}", key_bindings.keys(action)));
+                    }
+                }
+                ui.separator();
+                if ui.button(im_str!("Reset to defaults"), [0.0, 0.0]) {
+                    key_bindings.reset_to_default();
+                    *rebinding = None;
+                }
+            });
+    }
+
+    fn render_inspector(
+        ui: &Ui,
+        inspection: &Inspection,
+        requests: &mut Vec<InspectorRequest>,
+        show_bounds: &mut bool,
+    ) {
+        imgui::Window::new(im_str!("Inspector"))
+            .size([300.0, 240.0], Condition::FirstUseEver)
+            .position([10.0, 520.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("Asset: {}", inspection.asset_name));
+                if ui.button(im_str!("Focus"), [80.0, 0.0]) {
+                    requests.push(InspectorRequest::Focus);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Save thumbnail"), [120.0, 0.0]) {
+                    requests.push(InspectorRequest::SaveThumbnail);
+                }
+                ui.checkbox(im_str!("Show bounds"), show_bounds);
+
+                // Assets with a single scene have nothing to choose from
+                if inspection.scenes.len() > 1 {
+                    ui.separator();
+                    ui.text(im_str!("Scenes"));
+                    for (index, scene) in inspection.scenes.iter().enumerate() {
+                        let label = im_str!("{}##scene{}", scene.name, index);
+                        if Selectable::new(&label).selected(scene.active).build(ui) {
+                            requests.push(InspectorRequest::SelectScene(index));
+                        }
+                    }
+                }
+
+                if !inspection.cameras.is_empty() {
+                    ui.separator();
+                    ui.text(im_str!("Cameras"));
+                    for (index, name) in inspection.cameras.iter().enumerate() {
+                        let label = im_str!("{}##camera{}", name, index);
+                        if Selectable::new(&label).build(ui) {
+                            requests.push(InspectorRequest::ViewThroughCamera(index));
+                        }
+                    }
+                    if ui.button(im_str!("Orbit"), [80.0, 0.0]) {
+                        requests.push(InspectorRequest::Orbit);
+                    }
+                }
+
+                let first_animation = match inspection.animations.first() {
+                    Some(animation) => animation,
+                    None => return,
+                };
+                let animation = inspection.animation.as_ref();
+                let selected = animation
+                    .and_then(|animation| animation.name.as_ref())
+                    .unwrap_or(&first_animation.name);
+                let looping = animation.is_none_or(|animation| animation.looping);
+
+                ui.separator();
+                ui.text(im_str!("Animations"));
+                for (index, info) in inspection.animations.iter().enumerate() {
+                    let label = im_str!("{} ({:.2}s)##{}", info.name, info.duration, index);
+                    if Selectable::new(&label)
+                        .selected(info.name == *selected)
+                        .build(ui)
+                    {
+                        requests.push(InspectorRequest::PlayAnimation {
+                            name: info.name.clone(),
+                            looping,
+                        });
+                    }
+                }
+
+                let mut looping = looping;
+                if ui.checkbox(im_str!("Loop"), &mut looping) {
+                    requests.push(InspectorRequest::SetLooping(looping));
+                }
+
+                // Playback and blending need an animation to control
+                let animation = match animation {
+                    Some(animation) => animation,
+                    None => return,
+                };
+
+                let playing = inspection
+                    .control
+                    .as_ref()
+                    .is_none_or(|control| control.playing);
+                if playing {
+                    if ui.button(im_str!("Pause"), [80.0, 0.0]) {
+                        requests.push(InspectorRequest::Pause);
+                    }
+                } else if ui.button(im_str!("Play"), [80.0, 0.0]) {
+                    requests.push(InspectorRequest::Play);
+                }
+
+                let duration = inspection
+                    .animations
+                    .iter()
+                    .find(|info| info.name == *selected)
+                    .map_or(0.0, |info| info.duration);
+                if duration > 0.0 {
+                    let mut time = if animation.looping {
+                        animation.time % duration
+                    } else {
+                        animation.time.min(duration)
+                    };
+                    if Slider::new(im_str!("Time"), 0.0..=duration).build(ui, &mut time) {
+                        requests.push(InspectorRequest::SetTime(time));
+                    }
+                }
+                let blend = animation.blend.as_ref();
+                let mut weight = blend.map_or(0.5, |blend| blend.weight);
+
+                ui.separator();
+                ui.text(im_str!("Blend into"));
+                if Selectable::new(im_str!("None"))
+                    .selected(blend.is_none())
+                    .build(ui)
+                {
+                    requests.push(InspectorRequest::StopBlending);
+                }
+                for (index, info) in inspection.animations.iter().enumerate() {
+                    let label = im_str!("{}##blend{}", info.name, index);
+                    let selected = blend.is_some_and(|blend| blend.name == info.name);
+                    if Selectable::new(&label).selected(selected).build(ui) {
+                        requests.push(InspectorRequest::BlendWith {
+                            name: info.name.clone(),
+                            weight,
+                        });
+                    }
+                }
+
+                if let Some(blend) = blend {
+                    if Slider::new(im_str!("Weight"), 0.0..=1.0).build(ui, &mut weight) {
+                        requests.push(InspectorRequest::BlendWith {
+                            name: blend.name.clone(),
+                            weight,
+                        });
+                    }
+                }
+            });
+    }
+
+    // The changes made in the inspector since this was last called
+    pub fn take_inspector_requests(&mut self) -> Vec<InspectorRequest> {
+        std::mem::take(&mut self.inspector_requests)
+    }
+
+    // The scene image fills the window, so the renderer can size the scene to match it
+    fn render_scene(ui: &Ui, texture: TextureId) -> Option<SceneRegion> {
+        let mut scene_region = None;
+        imgui::Window::new(im_str!("Scene"))
+            .size([640.0, 480.0], Condition::FirstUseEver)
+            .position([320.0, 10.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let size = ui.content_region_avail();
+                if size[0] < 1.0 || size[1] < 1.0 {
+                    return;
+                }
+                Image::new(texture, size).build(ui);
+                scene_region = Some(SceneRegion {
+                    position: ui.item_rect_min(),
+                    size,
+                    hovered: ui.is_item_hovered(),
+                });
+            });
+        scene_region
+    }
+
     pub fn context_mut(&mut self) -> &mut Context {
         &mut self.context
     }
 
+    // Keys pressed while rebinding a control only go to the gui
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    // Whether the bounds of the selected entity are drawn, set from the inspector
+    pub fn show_bounds(&self) -> bool {
+        self.show_bounds
+    }
+
+    // The mouse is left to the app while it is over the scene window
     pub fn capturing_input(&self) -> bool {
-        self.context.io().want_capture_keyboard || self.context.io().want_capture_mouse
+        let io = self.context.io();
+        let scene_hovered = self
+            .scene_region
+            .is_some_and(|scene_region| scene_region.hovered);
+        io.want_capture_keyboard || (io.want_capture_mouse && !scene_hovered)
+    }
+
+    // The size of the scene window in physical pixels, if the scene was drawn in one
+    pub fn scene_window_dimensions(&self) -> Option<glm::Vec2> {
+        let scale = self.context.io().display_framebuffer_scale;
+        self.scene_region.map(|scene_region| {
+            glm::vec2(
+                scene_region.size[0] * scale[0],
+                scene_region.size[1] * scale[1],
+            )
+        })
+    }
+
+    // Maps a position in the window, in physical pixels, to where it would be
+    // if the scene filled the window. Positions outside of the scene window map to nothing.
+    pub fn scene_position(
+        &self,
+        position: glm::Vec2,
+        window_dimensions: glm::Vec2,
+    ) -> Option<glm::Vec2> {
+        let scene_region = match self.scene_region {
+            Some(scene_region) => scene_region,
+            None => return Some(position),
+        };
+        let scale = self.context.io().display_framebuffer_scale;
+        let x = (position.x / scale[0] - scene_region.position[0]) / scene_region.size[0];
+        let y = (position.y / scale[1] - scene_region.position[1]) / scene_region.size[1];
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return None;
+        }
+        Some(glm::vec2(x * window_dimensions.x, y * window_dimensions.y))
     }
 }
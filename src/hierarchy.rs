@@ -0,0 +1,98 @@
+use crate::renderer::Transform;
+use legion::prelude::*;
+use log::error;
+use nalgebra_glm as glm;
+
+// Attaches an entity to another, so its Transform is relative to the parent's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parent(pub Entity);
+
+// The Transform of an entity composed with the transforms of its parents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldTransform(pub glm::Mat4);
+
+impl Default for WorldTransform {
+    fn default() -> Self {
+        Self(glm::Mat4::identity())
+    }
+}
+
+// Entities with a Transform are given a WorldTransform when the commands are flushed.
+// A parent that leads back to its child is removed to break the cycle.
+pub fn transform_hierarchy_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("transform_hierarchy")
+        .read_component::<Transform>()
+        .read_component::<Parent>()
+        .write_component::<WorldTransform>()
+        .with_query(<Read<Transform>>::query())
+        .with_query(<Read<Transform>>::query().filter(!component::<WorldTransform>()))
+        .build(
+            move |commands, world, _, (transform_query, new_transform_query)| {
+                for (entity, _) in new_transform_query.iter_entities(world) {
+                    commands.add_component(entity, WorldTransform::default());
+                }
+
+                let entities = transform_query
+                    .iter_entities(world)
+                    .map(|(entity, _)| entity)
+                    .collect::<Vec<_>>();
+
+                // Entities whose parent was removed this frame are treated as roots
+                let mut broken_links = Vec::new();
+                for entity in entities {
+                    let matrix = compose_world_transform(world, entity, &mut broken_links);
+                    if let Some(mut world_transform) =
+                        world.get_component_mut::<WorldTransform>(entity)
+                    {
+                        world_transform.0 = matrix;
+                    }
+                }
+
+                for link in broken_links {
+                    commands.remove_component::<Parent>(link);
+                }
+            },
+        )
+}
+
+fn compose_world_transform(
+    world: &SubWorld,
+    entity: Entity,
+    broken_links: &mut Vec<Entity>,
+) -> glm::Mat4 {
+    let mut matrix = match world.get_component::<Transform>(entity) {
+        Some(transform) => transform.matrix(),
+        None => return glm::Mat4::identity(),
+    };
+
+    let mut visited = vec![entity];
+    let mut current = entity;
+    while let Some(parent) = world
+        .get_component::<Parent>(current)
+        .map(|parent| parent.0)
+    {
+        if broken_links.contains(&current) {
+            break;
+        }
+
+        if visited.contains(&parent) {
+            error!(
+                "Transform hierarchy cycle detected, detaching {:?} from its parent {:?}!",
+                current, parent
+            );
+            broken_links.push(current);
+            break;
+        }
+
+        // Parents without a transform, such as deleted entities, end the chain
+        match world.get_component::<Transform>(parent) {
+            Some(transform) => matrix = transform.matrix() * matrix,
+            None => break,
+        }
+
+        visited.push(parent);
+        current = parent;
+    }
+
+    matrix
+}
@@ -1,4 +1,5 @@
 use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use winit::event::{
     ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
@@ -10,6 +11,7 @@ pub type KeyMap = HashMap<VirtualKeyCode, ElementState>;
 pub struct Input {
     pub keystates: KeyMap,
     pub mouse: Mouse,
+    pub gamepad: Gamepad,
     pub allowed: bool,
 }
 
@@ -18,6 +20,14 @@ impl Input {
         self.keystates.contains_key(&keycode) && self.keystates[&keycode] == ElementState::Pressed
     }
 
+    // True when any of the keys bound to the action are pressed
+    pub fn is_action_pressed(&self, key_bindings: &KeyBindings, action: Action) -> bool {
+        key_bindings
+            .keys(action)
+            .iter()
+            .any(|keycode| self.is_key_pressed(*keycode))
+    }
+
     pub fn handle_event<T>(&mut self, event: &Event<T>, window_center: glm::Vec2) {
         match event {
             Event::WindowEvent { event, .. } => match *event {
@@ -41,6 +51,88 @@ impl Input {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Exit,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Exit,
+    ];
+}
+
+// Maps logical actions to the keys that trigger them.
+// Actions missing from the settings file keep their default keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub move_forward: Vec<VirtualKeyCode>,
+    pub move_backward: Vec<VirtualKeyCode>,
+    pub move_left: Vec<VirtualKeyCode>,
+    pub move_right: Vec<VirtualKeyCode>,
+    pub move_up: Vec<VirtualKeyCode>,
+    pub move_down: Vec<VirtualKeyCode>,
+    pub exit: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            move_forward: vec![VirtualKeyCode::W],
+            move_backward: vec![VirtualKeyCode::S],
+            move_left: vec![VirtualKeyCode::A],
+            move_right: vec![VirtualKeyCode::D],
+            move_up: vec![VirtualKeyCode::Space],
+            move_down: vec![VirtualKeyCode::LShift],
+            exit: vec![VirtualKeyCode::Escape],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: Action) -> &[VirtualKeyCode] {
+        match action {
+            Action::MoveForward => &self.move_forward,
+            Action::MoveBackward => &self.move_backward,
+            Action::MoveLeft => &self.move_left,
+            Action::MoveRight => &self.move_right,
+            Action::MoveUp => &self.move_up,
+            Action::MoveDown => &self.move_down,
+            Action::Exit => &self.exit,
+        }
+    }
+
+    pub fn bind(&mut self, action: Action, keys: &[VirtualKeyCode]) {
+        let bound_keys = match action {
+            Action::MoveForward => &mut self.move_forward,
+            Action::MoveBackward => &mut self.move_backward,
+            Action::MoveLeft => &mut self.move_left,
+            Action::MoveRight => &mut self.move_right,
+            Action::MoveUp => &mut self.move_up,
+            Action::MoveDown => &mut self.move_down,
+            Action::Exit => &mut self.exit,
+        };
+        *bound_keys = keys.to_vec();
+    }
+
+    pub fn reset_to_default(&mut self) {
+        *self = Self::default();
+    }
+}
+
 #[derive(Default)]
 pub struct Mouse {
     pub is_left_clicked: bool,
@@ -98,3 +190,165 @@ impl Mouse {
         }
     }
 }
+
+#[cfg(feature = "gamepad")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GamepadSettings {
+    // Stick deflection below this is ignored
+    pub deadzone: f32,
+    pub sensitivity: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.15,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+// Gamepad events are translated into this state by whichever backend polls the device.
+// With nothing connected every action reads as zero,
+// so keyboard and mouse controls behave as they do without a gamepad.
+#[derive(Debug, Default)]
+pub struct Gamepad {
+    pub connected: bool,
+    pub name: String,
+    pub left_stick: glm::Vec2,
+    pub right_stick: glm::Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+    pub settings: GamepadSettings,
+}
+
+impl Gamepad {
+    #[cfg(feature = "gamepad")]
+    pub fn connect(&mut self, name: &str) {
+        self.connected = true;
+        self.name = name.to_string();
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn disconnect(&mut self) {
+        let settings = self.settings;
+        *self = Self {
+            settings,
+            ..Default::default()
+        };
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn set_axis(&mut self, axis: GamepadAxis, value: f32) {
+        let value = glm::clamp_scalar(value, -1.0, 1.0);
+        match axis {
+            GamepadAxis::LeftStickX => self.left_stick.x = value,
+            GamepadAxis::LeftStickY => self.left_stick.y = value,
+            GamepadAxis::RightStickX => self.right_stick.x = value,
+            GamepadAxis::RightStickY => self.right_stick.y = value,
+            GamepadAxis::LeftTrigger => self.left_trigger = value,
+            GamepadAxis::RightTrigger => self.right_trigger = value,
+        }
+    }
+
+    // Left stick, x is strafing and y is moving forward
+    pub fn movement(&self) -> glm::Vec2 {
+        self.apply_settings(&self.left_stick)
+    }
+
+    // Right stick, x is yaw and y is pitch
+    pub fn look(&self) -> glm::Vec2 {
+        self.apply_settings(&self.right_stick)
+    }
+
+    // Right trigger zooms in, left trigger zooms out
+    pub fn zoom(&self) -> f32 {
+        if !self.connected {
+            return 0.0;
+        }
+        (self.right_trigger - self.left_trigger) * self.settings.sensitivity
+    }
+
+    fn apply_settings(&self, stick: &glm::Vec2) -> glm::Vec2 {
+        let magnitude = glm::length(stick);
+        if !self.connected || magnitude < self.settings.deadzone {
+            return glm::Vec2::zeros();
+        }
+
+        // Rescale so the output starts from zero at the edge of the deadzone
+        let deadzone = self.settings.deadzone.min(0.99);
+        let scaled_magnitude = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+        stick / magnitude * scaled_magnitude * self.settings.sensitivity
+    }
+}
+
+// Polls gilrs and mirrors the first connected gamepad into the input state
+#[cfg(feature = "gamepad")]
+pub struct GamepadBackend {
+    gilrs: gilrs::Gilrs,
+    active: Option<gilrs::GamepadId>,
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadBackend {
+    pub fn new() -> anyhow::Result<Self> {
+        let gilrs = gilrs::Gilrs::new()
+            .map_err(|error| anyhow::anyhow!("Failed to create gamepad context: {}", error))?;
+        Ok(Self {
+            gilrs,
+            active: None,
+        })
+    }
+
+    pub fn poll(&mut self, gamepad: &mut Gamepad) {
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                gilrs::EventType::Disconnected if self.active == Some(id) => {
+                    self.active = None;
+                    gamepad.disconnect();
+                }
+                _ if self.active != Some(id) => {}
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    if let Some(axis) = Self::axis(axis) {
+                        gamepad.set_axis(axis, value);
+                    }
+                }
+                gilrs::EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+                    gamepad.set_axis(GamepadAxis::LeftTrigger, value);
+                }
+                gilrs::EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+                    gamepad.set_axis(GamepadAxis::RightTrigger, value);
+                }
+                _ => {}
+            }
+        }
+
+        // Gamepads connected before startup never send a connected event
+        if self.active.is_none() {
+            if let Some((id, device)) = self.gilrs.gamepads().next() {
+                self.active = Some(id);
+                gamepad.connect(device.name());
+            }
+        }
+    }
+
+    fn axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+        match axis {
+            gilrs::Axis::LeftStickX => Some(GamepadAxis::LeftStickX),
+            gilrs::Axis::LeftStickY => Some(GamepadAxis::LeftStickY),
+            gilrs::Axis::RightStickX => Some(GamepadAxis::RightStickX),
+            gilrs::Axis::RightStickY => Some(GamepadAxis::RightStickY),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,10 @@
+mod animation;
 mod app;
 mod camera;
 mod gui;
+mod hierarchy;
 mod input;
+mod particles;
 mod renderer;
 mod system;
 
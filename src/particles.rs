@@ -0,0 +1,124 @@
+use crate::system::System;
+use legion::prelude::*;
+use nalgebra_glm as glm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Particle {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    // Seconds since the particle was spawned
+    pub age: f32,
+}
+
+// Spawns particles that are simulated on the cpu and drawn as additive point sprites.
+// Particles don't interact with the scene, so this suits effects like smoke or sparks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleEmitter {
+    pub position: glm::Vec3,
+    // Particles spawned per second
+    pub spawn_rate: f32,
+    // Seconds each particle lives for
+    pub lifetime: f32,
+    pub velocity: glm::Vec3,
+    // Each axis of the velocity of a spawned particle is offset by up to this much
+    pub spread: f32,
+    // Applied to every particle, such as gravity pulling sparks down
+    pub acceleration: glm::Vec3,
+    // The world space size of each particle
+    pub size: f32,
+    // Particles fade from the start color to the end color over their lifetime
+    pub start_color: glm::Vec4,
+    pub end_color: glm::Vec4,
+    // An image file multiplied with the particle color, a soft round sprite is used when unset
+    pub texture: Option<String>,
+    // Nothing is spawned while the emitter has this many particles
+    pub max_particles: usize,
+    pub particles: Vec<Particle>,
+    // Fractions of a particle left over from previous spawns
+    spawn_accumulator: f32,
+    seed: u32,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            position: glm::Vec3::zeros(),
+            spawn_rate: 20.0,
+            lifetime: 2.0,
+            velocity: glm::vec3(0.0, 1.0, 0.0),
+            spread: 0.25,
+            acceleration: glm::Vec3::zeros(),
+            size: 0.1,
+            start_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            end_color: glm::vec4(1.0, 1.0, 1.0, 0.0),
+            texture: None,
+            max_particles: 1000,
+            particles: Vec::new(),
+            spawn_accumulator: 0.0,
+            seed: 0x9E37_79B9,
+        }
+    }
+}
+
+impl ParticleEmitter {
+    pub fn new(position: glm::Vec3) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        let acceleration = self.acceleration;
+        for particle in self.particles.iter_mut() {
+            particle.age += delta_time;
+            particle.velocity += acceleration * delta_time;
+            particle.position += particle.velocity * delta_time;
+        }
+        let lifetime = self.lifetime;
+        self.particles.retain(|particle| particle.age < lifetime);
+
+        self.spawn_accumulator += self.spawn_rate.max(0.0) * delta_time;
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            if self.particles.len() >= self.max_particles {
+                continue;
+            }
+            let offset = glm::vec3(self.random(), self.random(), self.random()) * self.spread;
+            self.particles.push(Particle {
+                position: self.position,
+                velocity: self.velocity + offset,
+                age: 0.0,
+            });
+        }
+    }
+
+    pub fn particle_color(&self, particle: &Particle) -> glm::Vec4 {
+        let progress = if self.lifetime > 0.0 {
+            (particle.age / self.lifetime).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        glm::lerp(&self.start_color, &self.end_color, progress)
+    }
+
+    // A xorshift step, mapped to [-1, 1]
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+pub fn particle_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("particles")
+        .read_resource::<System>()
+        .with_query(<Write<ParticleEmitter>>::query())
+        .build(move |_, world, system, query| {
+            let delta_time = system.delta_time as f32;
+            for mut emitter in query.iter_mut(world) {
+                emitter.update(delta_time);
+            }
+        })
+}
@@ -1,32 +1,383 @@
+mod null;
 mod vulkan;
 
-use crate::renderer::vulkan::VulkanRenderer;
+use crate::{
+    animation::AnimationState, camera::CameraProjection, particles::ParticleEmitter,
+    renderer::vulkan::VulkanRenderer,
+};
 use anyhow::Result;
-use imgui::{Context, DrawData};
-use legion::prelude::*;
+use imgui::{Context, DrawData, TextureId};
+use legion::prelude::Entity;
 use nalgebra::{Matrix4, Quaternion, UnitQuaternion};
 use nalgebra_glm as glm;
+use serde::Deserialize;
+use std::{fmt, path::Path};
 use winit::window::Window;
 
-#[derive(Debug)]
+pub use null::NullRenderer;
+pub use vulkan::DeviceSettings;
+#[cfg(feature = "headless")]
+pub use vulkan::HeadlessRenderer;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Backend {
+    #[default]
     Vulkan,
+    // Renders nothing, useful for running the app without a gpu
+    Null,
+}
+
+// The images the skybox and image based lighting are created from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Environment {
+    // An equirectangular hdr image
+    Hdr(String),
+    // A DDS file holding all six faces of a cubemap
+    Cubemap(String),
+    // Six images, one per cubemap face
+    Faces {
+        right: String,
+        left: String,
+        top: String,
+        bottom: String,
+        front: String,
+        back: String,
+    },
+}
+
+impl Environment {
+    pub const DEFAULT_HDR: &'static str = "assets/skyboxes/walk_of_fame/walk_of_fame.hdr";
+
+    // DDS files are loaded as cubemaps, anything else as an hdr image
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".dds") {
+            Environment::Cubemap(path.to_string())
+        } else {
+            Environment::Hdr(path.to_string())
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::Hdr(Self::DEFAULT_HDR.to_string())
+    }
+}
+
+// Quality settings for the maps baked from the environment.
+// Lower settings bake faster, which is useful for previews.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct IblSettings {
+    pub irradiance_dim: u32,
+    pub prefilter_dim: u32,
+    // Each mip level is prefiltered for a higher roughness
+    pub prefilter_mips: u32,
+    // Samples taken per texel while prefiltering
+    pub sample_count: u32,
+    // Added to the mip level the prefilter map is sampled at.
+    // Negative values sharpen reflections and positive values soften them.
+    pub prefilter_lod_bias: f32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self {
+            irradiance_dim: 64,
+            prefilter_dim: 512,
+            prefilter_mips: 10,
+            sample_count: 32,
+            prefilter_lod_bias: 0.0,
+        }
+    }
 }
 
-// FIXME: Make the renderer trait take something more specific than the world and resources
 pub trait Renderer {
-    fn initialize(&mut self, world: &World, imgui: &mut Context);
-    fn render(&mut self, world: &World, resources: &Resources, draw_data: &DrawData);
+    // Fails if the environment maps can't be created
+    fn initialize(&mut self, scene: &RenderScene, imgui: &mut Context) -> Result<()>;
+    // Recreates the render targets for the new window size.
+    // Rendering is skipped while either dimension is zero.
+    fn resize(&mut self, width: u32, height: u32);
+    // Fails with `DeviceLost` if the GPU device was lost
+    fn render(&mut self, scene: &RenderScene, draw_data: &DrawData) -> Result<()>;
+    fn load_progress(&self) -> LoadProgress;
+    // The assets that have finished loading, in the order they were received
+    fn loaded_assets(&self) -> Vec<String>;
+    // Streams in an asset after initialization, its instances are drawn once it is received
+    fn load_asset(&mut self, asset_name: &str);
+    // Uploads an asset that is already in memory, such as one read from an archive or downloaded.
+    // External buffers and images are resolved relative to the base directory.
+    fn load_asset_from_bytes(
+        &mut self,
+        asset_name: &str,
+        bytes: &[u8],
+        base_dir: &Path,
+    ) -> Result<()>;
+    // Waits for the gpu to finish with the asset before releasing it
+    fn unload_asset(&mut self, asset_name: &str);
+    // Returns the entity whose asset covers the given pixel of the window
+    fn pick(&mut self, x: u32, y: u32) -> Option<Entity>;
+    // Counters from the most recently recorded frame
+    fn stats(&self) -> RenderStats;
+    // The gui texture holding the rendered scene, for showing it inside a gui window
+    fn scene_texture(&self) -> Option<TextureId>;
+    // Renders a single asset framed by an orbital camera into a square image of the given size
+    fn render_thumbnail(&mut self, asset_name: &str, size: u32) -> Result<image::RgbaImage>;
+    // The cameras authored in an asset, empty until the asset has been received
+    fn asset_cameras(&self, asset_name: &str) -> Vec<AssetCamera>;
+    // The animations of an asset, empty until the asset has been received
+    fn asset_animations(&self, asset_name: &str) -> Vec<AnimationInfo>;
+    // The bounds of an asset in asset space, missing until the asset has been received
+    fn asset_bounding_box(&self, asset_name: &str) -> Option<Aabb>;
+    // One box per primitive of an asset in asset space, empty until the asset has been received
+    fn asset_primitive_bounding_boxes(&self, asset_name: &str) -> Vec<Aabb>;
+    // The scenes of an asset, empty until the asset has been received
+    fn asset_scenes(&self, asset_name: &str) -> Vec<SceneInfo>;
+    // The document's default scene is active until another one is selected
+    fn set_asset_scene(&mut self, asset_name: &str, index: usize) -> Result<()>;
 }
 
-impl dyn Renderer {
-    pub fn create_backend(backend: &Backend, window: &mut Window) -> Result<impl Renderer> {
-        match backend {
-            Backend::Vulkan => VulkanRenderer::new(window),
+#[derive(Debug, Clone, Copy)]
+pub struct SceneCamera {
+    pub view: glm::Mat4,
+    pub position: glm::Vec3,
+    pub projection: CameraProjection,
+}
+
+// Everything the renderer needs to know about the world for a single frame
+#[derive(Debug, Clone)]
+pub struct RenderScene {
+    pub view: glm::Mat4,
+    pub camera_position: glm::Vec3,
+    pub projection: CameraProjection,
+    // Rendered side by side with the main camera, each in an equal share of the screen
+    pub split_cameras: Vec<SceneCamera>,
+    // Each asset instance with its world matrix
+    pub assets: Vec<(AssetName, glm::Mat4)>,
+    // The entity each asset belongs to, in the same order as the assets
+    pub entities: Vec<Entity>,
+    // The animation each asset plays, in the same order as the assets
+    pub animations: Vec<Option<AnimationState>>,
+    // Highlighted when the render settings enable it
+    pub selected_entities: Vec<Entity>,
+    pub lights: Vec<Light>,
+    pub delta_time: f32,
+    pub window_dimensions: glm::Vec2,
+    // Set while the scene is shown inside a gui window instead of filling the screen.
+    // The camera aspect ratio follows these dimensions instead of the window.
+    pub scene_window_dimensions: Option<glm::Vec2>,
+    pub render_settings: RenderSettings,
+    // Only read when the renderer is initialized
+    pub environment: Environment,
+    pub ibl_settings: IblSettings,
+    // Where the cubemaps computed from the environment are cached, caching is disabled when unset
+    pub environment_cache: Option<String>,
+    pub debug_shapes: DebugShapes,
+    pub particle_emitters: Vec<ParticleEmitter>,
+}
+
+impl Default for RenderScene {
+    fn default() -> Self {
+        Self {
+            view: glm::Mat4::identity(),
+            camera_position: glm::Vec3::zeros(),
+            projection: CameraProjection::default(),
+            split_cameras: Vec::new(),
+            assets: Vec::new(),
+            entities: Vec::new(),
+            animations: Vec::new(),
+            selected_entities: Vec::new(),
+            lights: Vec::new(),
+            delta_time: 0.0,
+            window_dimensions: glm::Vec2::zeros(),
+            scene_window_dimensions: None,
+            render_settings: RenderSettings::default(),
+            environment: Environment::default(),
+            ibl_settings: IblSettings::default(),
+            environment_cache: None,
+            debug_shapes: DebugShapes::default(),
+            particle_emitters: Vec::new(),
+        }
+    }
+}
+
+impl RenderScene {
+    // The main camera followed by the split screen cameras, from left to right
+    pub fn cameras(&self) -> Vec<SceneCamera> {
+        let main_camera = SceneCamera {
+            view: self.view,
+            position: self.camera_position,
+            projection: self.projection,
+        };
+        let mut cameras = vec![main_camera];
+        cameras.extend_from_slice(&self.split_cameras);
+        cameras
+    }
+
+    // One name per instance, in the same order as the assets
+    pub fn asset_names(&self) -> Vec<String> {
+        self.assets
+            .iter()
+            .map(|(name, _)| name.0.to_string())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub direction: glm::Vec3,
+    pub position: glm::Vec3,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    // A range of zero or less means the light has no range limit
+    pub range: f32,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Directional,
+            direction: glm::vec3(0.0, -1.0, 0.0),
+            position: glm::Vec3::zeros(),
+            color: glm::vec3(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            range: -1.0,
+            inner_cone_cos: 1.0,
+            outer_cone_cos: std::f32::consts::FRAC_PI_4.cos(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+    pub start: glm::Vec3,
+    pub end: glm::Vec3,
+    pub color: glm::Vec4,
+}
+
+// Unlit wireframe shapes for visualizing things like bounding boxes and axes.
+// The shapes are drawn for a single frame, so they are queued again every frame.
+#[derive(Debug, Default, Clone)]
+pub struct DebugShapes {
+    // Unit cubes centered on the origin, placed by their transform
+    pub boxes: Vec<(Transform, glm::Vec4)>,
+    pub lines: Vec<DebugLine>,
+}
+
+impl DebugShapes {
+    pub fn add_box(&mut self, transform: Transform, color: glm::Vec4) {
+        self.boxes.push((transform, color));
+    }
+
+    pub fn add_aabb(&mut self, aabb: &Aabb, color: glm::Vec4) {
+        let transform = Transform::new(aabb.center(), glm::Quat::identity(), aabb.extents());
+        self.add_box(transform, color);
+    }
+
+    pub fn add_line(&mut self, start: glm::Vec3, end: glm::Vec3, color: glm::Vec4) {
+        self.lines.push(DebugLine { start, end, color });
+    }
+
+    // Red, green and blue lines along the x, y and z axes of the transform
+    pub fn add_axes(&mut self, transform: &Transform, length: f32) {
+        let matrix = transform.matrix();
+        let origin = (matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+        for axis in 0..3 {
+            let mut direction = glm::Vec4::zeros();
+            direction[axis] = length;
+            let mut color = glm::vec4(0.0, 0.0, 0.0, 1.0);
+            color[axis] = 1.0;
+            let end = (matrix * (direction + glm::vec4(0.0, 0.0, 0.0, 1.0))).xyz();
+            self.add_line(origin, end, color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.boxes.clear();
+        self.lines.clear();
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: usize,
+    // Instanced draws count every instance they render
+    pub instances: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn is_complete(&self) -> bool {
+        self.loaded >= self.total
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.loaded as f32 / self.total as f32
         }
     }
 }
 
+// Returned by `Renderer::render` when the GPU device was lost, such as after a driver reset.
+// Every GPU resource belongs to the lost device, so nothing can be recovered in place.
+// The backend has to be created and initialized again, which rebuilds the vulkan context,
+// the swapchain and render targets, the pbr scene with its assets and environment maps,
+// and the gui renderer with its font atlas.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceLost;
+
+impl fmt::Display for DeviceLost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The GPU device was lost!")
+    }
+}
+
+impl std::error::Error for DeviceLost {}
+
+impl dyn Renderer {
+    // The anisotropy level defaults to the highest level the device supports, up to 16.
+    // Two frames are in flight by default, up to three are supported.
+    // The swapchain gets one more image than the surface's minimum by default.
+    pub fn create_backend(
+        backend: &Backend,
+        window: &mut Window,
+        anisotropy: Option<f32>,
+        device_settings: &DeviceSettings,
+        frames_in_flight: Option<u32>,
+        swapchain_image_count: Option<u32>,
+    ) -> Result<Box<dyn Renderer>> {
+        let renderer: Box<dyn Renderer> = match backend {
+            Backend::Vulkan => Box::new(VulkanRenderer::new(
+                window,
+                anisotropy,
+                device_settings,
+                frames_in_flight,
+                swapchain_image_count,
+            )?),
+            Backend::Null => Box::new(NullRenderer),
+        };
+        Ok(renderer)
+    }
+}
+
 /// # Safety
 ///
 /// This method will convert any slice to a byte slice.
@@ -36,10 +387,242 @@ pub unsafe fn byte_slice_from<T: Sized>(data: &T) -> &[u8] {
     std::slice::from_raw_parts(data_ptr, std::mem::size_of::<T>())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssetName(pub String);
 
-#[derive(Debug)]
+// An axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: glm::Vec3, max: glm::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> glm::Vec3 {
+        self.max - self.min
+    }
+
+    // The smallest box holding both boxes
+    pub fn merge(&self, other: &Self) -> Self {
+        Self::new(
+            glm::min2(&self.min, &other.min),
+            glm::max2(&self.max, &other.max),
+        )
+    }
+
+    // The box holding all eight corners of this box after they are transformed
+    pub fn transform(&self, matrix: &glm::Mat4) -> Self {
+        let corners = (0..8).map(|corner| {
+            let point = glm::vec3(
+                if corner & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if corner & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if corner & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            );
+            (matrix * point.push(1.0)).xyz()
+        });
+        let mut aabb = Self::new(glm::Vec3::repeat(f32::MAX), glm::Vec3::repeat(f32::MIN));
+        for corner in corners {
+            aabb = aabb.merge(&Self::new(corner, corner));
+        }
+        aabb
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationInfo {
+    pub name: String,
+    // In seconds
+    pub duration: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneInfo {
+    pub name: String,
+    // Only the active scene of an asset is drawn
+    pub active: bool,
+}
+
+// The projection of a camera authored in an asset, with the parameters stored in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssetCameraProjection {
+    Perspective {
+        fov_y_degrees: f32,
+        // Falls back to the aspect ratio of the viewport when missing
+        aspect_ratio: Option<f32>,
+        near: f32,
+        // An infinite projection when missing
+        far: Option<f32>,
+    },
+    Orthographic {
+        // Half the width and height of the view volume
+        x_magnification: f32,
+        y_magnification: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+// A camera authored in an asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetCamera {
+    pub name: String,
+    pub projection: AssetCameraProjection,
+    // The global transform of the camera node, relative to the asset.
+    // The camera looks down its local -Z axis.
+    pub transform: glm::Mat4,
+}
+
+impl AssetCamera {
+    // The view from the camera, once the asset is placed with the given world matrix
+    pub fn view_matrix(&self, asset_transform: &glm::Mat4) -> glm::Mat4 {
+        glm::inverse(&(asset_transform * self.transform))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub clear_color: [f32; 4],
+    pub draw_skybox: bool,
+    // Records scene draws into secondary command buffers across threads
+    pub parallel_recording: bool,
+    // MSAA sample count for the scene pass (1, 2, 4 or 8), clamped to what the device supports.
+    // Post-processing and the gui are not multisampled.
+    pub msaa_samples: u32,
+    // The scene is rendered at this fraction of the window size, from 0.25 to 2.0,
+    // and rescaled to the window while post-processing.
+    // Lower values trade quality for speed and higher values supersample.
+    pub render_scale: f32,
+    // Lays down the depth of opaque geometry before shading it,
+    // so each pixel is only shaded once
+    pub depth_prepass: bool,
+    // Fast approximate anti-aliasing, applied while post-processing
+    pub fxaa: bool,
+    // Amount of subpixel aliasing removal, from 0.0 (off) to 1.0 (softest)
+    pub fxaa_subpixel: f32,
+    // Minimum local contrast, relative to the brightest neighbor, required to smooth an edge
+    pub fxaa_edge_threshold: f32,
+    // Draws every primitive without culling back faces, for debugging
+    pub double_sided: bool,
+    // Draws the boxes and lines queued in the debug shapes
+    pub draw_debug_shapes: bool,
+    // Displays the linearized scene depth instead of the lit scene, for debugging.
+    // The scene is rendered single sampled while this is enabled.
+    pub show_depth: bool,
+    // Draws an infinite grid on the ground plane, for orientation
+    pub draw_grid: bool,
+    pub grid: GridSettings,
+    // Scales the high dynamic range scene color before it is displayed,
+    // ignored while auto exposure is enabled
+    pub exposure: f32,
+    // Adapts the exposure to the average luminance of the scene over time
+    pub auto_exposure: bool,
+    pub auto_exposure_settings: AutoExposureSettings,
+    // Scales the diffuse and specular light from the environment maps,
+    // to balance image based lighting against the scene's lights
+    pub ibl_intensity: f32,
+    // Draws the selected entities again with a constant color added on top of them
+    pub highlight_selection: bool,
+    // The alpha scales how strongly the color is added
+    pub highlight_color: [f32; 4],
+    // Draws an outline around the silhouette of the selected entities, over the rest of the scene
+    pub outline_selection: bool,
+    pub outline_color: [f32; 4],
+    // How much larger than the selected entities the outlined copies of them are drawn
+    pub outline_scale: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            clear_color: [0.39, 0.58, 0.93, 1.0],
+            draw_skybox: true,
+            parallel_recording: true,
+            msaa_samples: 1,
+            render_scale: 1.0,
+            depth_prepass: false,
+            fxaa: false,
+            fxaa_subpixel: 0.75,
+            fxaa_edge_threshold: 0.125,
+            double_sided: false,
+            draw_debug_shapes: true,
+            show_depth: false,
+            draw_grid: false,
+            grid: GridSettings::default(),
+            exposure: 1.0,
+            auto_exposure: false,
+            auto_exposure_settings: AutoExposureSettings::default(),
+            ibl_intensity: 1.0,
+            highlight_selection: false,
+            highlight_color: [1.0, 0.6, 0.1, 0.5],
+            outline_selection: false,
+            outline_color: [1.0, 0.6, 0.1, 1.0],
+            outline_scale: 1.03,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposureSettings {
+    // The exposure stays within this range, however bright or dark the scene is
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    // How quickly the exposure follows changes in brightness, higher adapts faster
+    pub adaptation_speed: f32,
+    // The luminance the average luminance of the scene is exposed to
+    pub key_value: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_exposure: 0.1,
+            max_exposure: 10.0,
+            adaptation_speed: 1.5,
+            key_value: 0.18,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridSettings {
+    // The distance between grid lines in world units
+    pub spacing: f32,
+    pub color: [f32; 4],
+    // The grid fades out completely at this distance from the camera
+    pub fade_distance: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            color: [0.5, 0.5, 0.5, 0.8],
+            fade_distance: 50.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Transform {
     pub translation: glm::Vec3,
     pub rotation: glm::Quat,
@@ -65,6 +648,19 @@ impl Transform {
         }
     }
 
+    // Lerps the translation and scale and slerps the rotation
+    pub fn interpolate(&self, other: &Transform, weight: f32) -> Transform {
+        Self {
+            translation: glm::mix(&self.translation, &other.translation, weight),
+            rotation: glm::quat_normalize(&glm::quat_slerp(
+                &self.rotation,
+                &other.rotation,
+                weight,
+            )),
+            scale: glm::mix(&self.scale, &other.scale, weight),
+        }
+    }
+
     pub fn matrix(&self) -> glm::Mat4 {
         Matrix4::new_translation(&self.translation)
             * Matrix4::from(UnitQuaternion::from_quaternion(self.rotation))
@@ -0,0 +1,92 @@
+use crate::renderer::{
+    Aabb, AnimationInfo, AssetCamera, LoadProgress, RenderScene, RenderStats, Renderer, SceneInfo,
+};
+use anyhow::{bail, Result};
+use imgui::{Context, DrawData, TextureId};
+use legion::prelude::Entity;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn initialize(&mut self, _scene: &RenderScene, _imgui: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) {}
+
+    fn render(&mut self, _scene: &RenderScene, _draw_data: &DrawData) -> Result<()> {
+        Ok(())
+    }
+
+    fn load_progress(&self) -> LoadProgress {
+        LoadProgress::default()
+    }
+
+    fn loaded_assets(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn load_asset(&mut self, _asset_name: &str) {}
+
+    fn load_asset_from_bytes(
+        &mut self,
+        asset_name: &str,
+        _bytes: &[u8],
+        _base_dir: &Path,
+    ) -> Result<()> {
+        bail!(
+            "Failed to load '{}', the null renderer loads no assets!",
+            asset_name
+        )
+    }
+
+    fn unload_asset(&mut self, _asset_name: &str) {}
+
+    fn pick(&mut self, _x: u32, _y: u32) -> Option<Entity> {
+        None
+    }
+
+    fn stats(&self) -> RenderStats {
+        RenderStats::default()
+    }
+
+    fn scene_texture(&self) -> Option<TextureId> {
+        None
+    }
+
+    fn render_thumbnail(&mut self, asset_name: &str, _size: u32) -> Result<image::RgbaImage> {
+        bail!(
+            "Failed to render a thumbnail of '{}', the null renderer draws nothing!",
+            asset_name
+        )
+    }
+
+    fn asset_cameras(&self, _asset_name: &str) -> Vec<AssetCamera> {
+        Vec::new()
+    }
+
+    fn asset_animations(&self, _asset_name: &str) -> Vec<AnimationInfo> {
+        Vec::new()
+    }
+
+    fn asset_bounding_box(&self, _asset_name: &str) -> Option<Aabb> {
+        None
+    }
+
+    fn asset_primitive_bounding_boxes(&self, _asset_name: &str) -> Vec<Aabb> {
+        Vec::new()
+    }
+
+    fn asset_scenes(&self, _asset_name: &str) -> Vec<SceneInfo> {
+        Vec::new()
+    }
+
+    fn set_asset_scene(&mut self, asset_name: &str, _index: usize) -> Result<()> {
+        bail!(
+            "Failed to select a scene of '{}', the null renderer loads no assets!",
+            asset_name
+        )
+    }
+}
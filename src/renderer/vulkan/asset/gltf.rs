@@ -1,17 +1,22 @@
+use super::{import_slice, ImportedImage};
 use crate::renderer::{
     vulkan::{
         core::VulkanContext,
         resource::{
-            image::{TextureBundle, TextureDescription},
+            image::{SamplerSettings, TextureBundle, TextureDescription},
             CommandPool,
         },
     },
-    Transform,
+    Aabb, AssetCamera, AssetCameraProjection, Transform,
 };
+use anyhow::{ensure, Context};
 use ash::vk;
-use gltf::animation::{util::ReadOutputs, Interpolation};
-use log::trace;
-use nalgebra::Quaternion;
+use gltf::{
+    animation::{util::ReadOutputs, Interpolation},
+    mesh::Mode,
+};
+use log::{trace, warn};
+use nalgebra::{Quaternion, Rotation3, UnitQuaternion};
 use nalgebra_glm as glm;
 use petgraph::{
     dot::{Config, Dot},
@@ -19,7 +24,7 @@ use petgraph::{
     prelude::*,
     visit::Dfs,
 };
-use std::{fmt, sync::Arc};
+use std::{collections::HashSet, fmt, path::Path, sync::Arc};
 
 #[derive(Debug)]
 pub enum TransformationSet {
@@ -33,6 +38,12 @@ pub type NodeGraph = Graph<Node, ()>;
 
 pub struct Node {
     pub local_transform: Transform,
+    // Static weights from the node or mesh, updated by animations
+    pub morph_weights: Vec<f32>,
+    // The pose the node was loaded with. Animations are sampled on top of it,
+    // so channels a clip doesn't animate don't keep what another clip or instance wrote.
+    pub rest_transform: Transform,
+    pub rest_morph_weights: Vec<f32>,
     pub mesh: Option<Mesh>,
     pub skin: Option<Skin>,
     pub gltf_index: usize,
@@ -57,6 +68,9 @@ pub struct Scene {
 pub struct Mesh {
     pub primitives: Vec<Primitive>,
     pub mesh_id: usize,
+    // Every primitive of a mesh has the same number of morph targets
+    pub morph_target_count: usize,
+    pub weights: Vec<f32>,
 }
 
 pub struct Skin {
@@ -70,9 +84,18 @@ pub struct Joint {
 }
 
 pub struct Primitive {
+    // Primitives without indices are drawn straight from their vertices
+    pub indexed: bool,
     pub number_of_indices: u32,
     pub first_index: u32,
+    pub number_of_vertices: u32,
+    pub first_vertex: u32,
     pub material_index: Option<usize>,
+    // Center of the primitive's bounding box in mesh space
+    pub centroid: glm::Vec3,
+    // In mesh space, from the accessor bounds of the positions
+    pub bounding_box: Aabb,
+    pub topology: vk::PrimitiveTopology,
 }
 
 // TODO: Properly decouple the animation state from the asset as a component to make it reusable.
@@ -83,63 +106,585 @@ pub struct Animation {
     pub name: String,
 }
 
+impl Animation {
+    pub fn duration(&self) -> f32 {
+        self.max_animation_time
+    }
+
+    fn wrap_time(&self, time: f32, looping: bool) -> f32 {
+        let duration = self.max_animation_time;
+        if looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.max(0.0).min(duration)
+        }
+    }
+
+    // Applies every channel targeting a node to its rest pose
+    fn pose(&self, node: &Node, time: f32) -> NodePose {
+        let mut pose = NodePose {
+            transform: node.rest_transform.clone(),
+            morph_weights: node.rest_morph_weights.clone(),
+        };
+        for channel in self
+            .channels
+            .iter()
+            .filter(|channel| channel.target_gltf_index == node.gltf_index)
+        {
+            channel.apply(&mut pose, time);
+        }
+        pose
+    }
+}
+
+// The properties of a node that animations change
+pub struct NodePose {
+    pub transform: Transform,
+    pub morph_weights: Vec<f32>,
+}
+
+impl NodePose {
+    pub fn interpolate(&self, other: &NodePose, weight: f32) -> NodePose {
+        NodePose {
+            transform: self.transform.interpolate(&other.transform, weight),
+            morph_weights: self
+                .morph_weights
+                .iter()
+                .zip(other.morph_weights.iter())
+                .map(|(first, second)| first + (second - first) * weight)
+                .collect(),
+        }
+    }
+}
+
+impl Channel {
+    // Channels only affect the transform between their first and last keyframes
+    fn apply(&self, pose: &mut NodePose, time: f32) {
+        let transform = &mut pose.transform;
+        let mut input_iter = self.inputs.iter().enumerate().peekable();
+        while let Some((previous_key, previous_time)) = input_iter.next() {
+            if let Some((next_key, next_time)) = input_iter.peek() {
+                let next_key = *next_key;
+                let next_time = **next_time;
+                let previous_time = *previous_time;
+
+                if time < previous_time || time > next_time {
+                    continue;
+                }
+
+                let keyframe_duration = next_time - previous_time;
+                let factor = if keyframe_duration > 0.0 {
+                    (time - previous_time) / keyframe_duration
+                } else {
+                    0.0
+                };
+                let sample = |value: &dyn Fn(usize) -> glm::Vec4| {
+                    interpolate_keyframes(
+                        self.interpolation,
+                        value,
+                        previous_key,
+                        next_key,
+                        keyframe_duration,
+                        factor,
+                    )
+                };
+
+                match &self.transformations {
+                    TransformationSet::Translations(translations) => {
+                        transform.translation =
+                            sample(&|element| translations[element].push(0.0)).xyz();
+                    }
+                    TransformationSet::Rotations(rotations) => {
+                        let rotation = if self.interpolation == Interpolation::Linear {
+                            let start = rotations[previous_key];
+                            let end = rotations[next_key];
+                            let start_quat =
+                                Quaternion::new(start[3], start[0], start[1], start[2]);
+                            let end_quat = Quaternion::new(end[3], end[0], end[1], end[2]);
+                            glm::quat_slerp(&start_quat, &end_quat, factor)
+                        } else {
+                            let rotation = sample(&|element| rotations[element]);
+                            Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2])
+                        };
+                        transform.rotation = glm::quat_normalize(&rotation);
+                    }
+                    TransformationSet::Scales(scales) => {
+                        transform.scale = sample(&|element| scales[element].push(0.0)).xyz();
+                    }
+                    // Weights are stored as one value per target for each keyframe element
+                    TransformationSet::MorphTargetWeights(weights) => {
+                        let count = weights.len() / (self.inputs.len() * self.elements_per_key());
+                        pose.morph_weights = (0..count.min(pose.morph_weights.len()))
+                            .map(|target| {
+                                sample(&|element| {
+                                    glm::vec4(weights[element * count + target], 0.0, 0.0, 0.0)
+                                })
+                                .x
+                            })
+                            .collect();
+                    }
+                }
+            }
+        }
+    }
+
+    // Cubic spline keyframes hold an in tangent, a value and an out tangent
+    fn elements_per_key(&self) -> usize {
+        match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        }
+    }
+}
+
+// Samples a channel between two keyframes, where the factor is how far the time is between them.
+// The value is looked up by keyframe element, so cubic spline keyframes span three elements.
+fn interpolate_keyframes(
+    interpolation: Interpolation,
+    value: &dyn Fn(usize) -> glm::Vec4,
+    previous_key: usize,
+    next_key: usize,
+    keyframe_duration: f32,
+    factor: f32,
+) -> glm::Vec4 {
+    match interpolation {
+        Interpolation::Step => value(previous_key),
+        Interpolation::Linear => glm::mix(&value(previous_key), &value(next_key), factor),
+        Interpolation::CubicSpline => {
+            let t = factor;
+            let t2 = t * t;
+            let t3 = t2 * t;
+            let previous_value = value(previous_key * 3 + 1);
+            let previous_out_tangent = value(previous_key * 3 + 2);
+            let next_in_tangent = value(next_key * 3);
+            let next_value = value(next_key * 3 + 1);
+            previous_value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + previous_out_tangent * (keyframe_duration * (t3 - 2.0 * t2 + t))
+                + next_value * (-2.0 * t3 + 3.0 * t2)
+                + next_in_tangent * (keyframe_duration * (t3 - t2))
+        }
+    }
+}
+
 pub struct Channel {
     target_gltf_index: usize,
     inputs: Vec<f32>,
     transformations: TransformationSet,
-    _interpolation: Interpolation,
+    interpolation: Interpolation,
+}
+
+// Material extensions that are not parsed by the gltf crate
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialExtensions {
+    // KHR_materials_emissive_strength
+    pub emissive_strength: f32,
+    // KHR_materials_transmission
+    pub transmission_factor: f32,
+    pub transmission_texture: Option<ExtensionTexture>,
+    // KHR_materials_clearcoat
+    pub clearcoat_factor: f32,
+    pub clearcoat_texture: Option<ExtensionTexture>,
+    pub clearcoat_roughness_factor: f32,
+    pub clearcoat_roughness_texture: Option<ExtensionTexture>,
+    // KHR_materials_ior
+    pub ior: f32,
+    // KHR_materials_specular
+    pub specular_factor: f32,
+    pub specular_texture: Option<ExtensionTexture>,
+    pub specular_color_factor: [f32; 3],
+    pub specular_color_texture: Option<ExtensionTexture>,
+}
+
+// A texture referenced by a material extension
+#[derive(Debug, Clone, Copy)]
+pub struct ExtensionTexture {
+    pub index: usize,
+    pub tex_coord: usize,
+}
+
+impl ExtensionTexture {
+    fn from_json(texture: &serde_json::Value) -> Option<Self> {
+        let index = texture["index"].as_u64()? as usize;
+        let tex_coord = texture["texCoord"].as_u64().unwrap_or(0) as usize;
+        Some(Self { index, tex_coord })
+    }
+}
+
+impl Default for MaterialExtensions {
+    fn default() -> Self {
+        Self {
+            emissive_strength: 1.0,
+            transmission_factor: 0.0,
+            transmission_texture: None,
+            clearcoat_factor: 0.0,
+            clearcoat_texture: None,
+            clearcoat_roughness_factor: 0.0,
+            clearcoat_roughness_texture: None,
+            ior: 1.5,
+            specular_factor: 1.0,
+            specular_texture: None,
+            specular_color_factor: [1.0, 1.0, 1.0],
+            specular_color_texture: None,
+        }
+    }
+}
+
+impl MaterialExtensions {
+    fn from_json(material: &serde_json::Value) -> Self {
+        let mut extensions = Self::default();
+        let json_extensions = &material["extensions"];
+
+        if let Some(emissive_strength) =
+            json_extensions["KHR_materials_emissive_strength"]["emissiveStrength"].as_f64()
+        {
+            extensions.emissive_strength = emissive_strength as f32;
+        }
+
+        let transmission = &json_extensions["KHR_materials_transmission"];
+        if let Some(transmission_factor) = transmission["transmissionFactor"].as_f64() {
+            extensions.transmission_factor = transmission_factor as f32;
+        }
+        extensions.transmission_texture =
+            ExtensionTexture::from_json(&transmission["transmissionTexture"]);
+
+        let clearcoat = &json_extensions["KHR_materials_clearcoat"];
+        if let Some(clearcoat_factor) = clearcoat["clearcoatFactor"].as_f64() {
+            extensions.clearcoat_factor = clearcoat_factor as f32;
+        }
+        extensions.clearcoat_texture = ExtensionTexture::from_json(&clearcoat["clearcoatTexture"]);
+        if let Some(clearcoat_roughness_factor) = clearcoat["clearcoatRoughnessFactor"].as_f64() {
+            extensions.clearcoat_roughness_factor = clearcoat_roughness_factor as f32;
+        }
+        extensions.clearcoat_roughness_texture =
+            ExtensionTexture::from_json(&clearcoat["clearcoatRoughnessTexture"]);
+
+        if let Some(ior) = json_extensions["KHR_materials_ior"]["ior"].as_f64() {
+            extensions.ior = ior as f32;
+        }
+
+        let specular = &json_extensions["KHR_materials_specular"];
+        if let Some(specular_factor) = specular["specularFactor"].as_f64() {
+            extensions.specular_factor = specular_factor as f32;
+        }
+        extensions.specular_texture = ExtensionTexture::from_json(&specular["specularTexture"]);
+        if let Some(specular_color_factor) = specular["specularColorFactor"].as_array() {
+            for (component, value) in extensions
+                .specular_color_factor
+                .iter_mut()
+                .zip(specular_color_factor.iter())
+            {
+                *component = value.as_f64().unwrap_or(1.0) as f32;
+            }
+        }
+        extensions.specular_color_texture =
+            ExtensionTexture::from_json(&specular["specularColorTexture"]);
+
+        extensions
+    }
+}
+
+// A texture along with the block-compressed version of it,
+// provided through KHR_texture_basisu or MSFT_texture_dds, when one could be loaded
+pub struct TextureSource {
+    pub description: TextureDescription,
+    pub compressed: Option<TextureDescription>,
+    pub sampler_settings: SamplerSettings,
+}
+
+impl TextureSource {
+    // The compressed texture is only used if the device can sample it
+    fn select(&self, context: &VulkanContext) -> &TextureDescription {
+        let required_features = vk::FormatFeatureFlags::SAMPLED_IMAGE
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        match self.compressed.as_ref() {
+            Some(compressed)
+                if context
+                    .physical_device_format_properties(compressed.format)
+                    .optimal_tiling_features
+                    .contains(required_features) =>
+            {
+                compressed
+            }
+            _ => &self.description,
+        }
+    }
+}
+
+// Everything in an asset that can be loaded without the gpu,
+// which allows it to be loaded on a worker thread
+pub struct GltfAssetData {
+    pub gltf: gltf::Document,
+    pub material_extensions: Vec<MaterialExtensions>,
+    pub textures: Vec<TextureSource>,
+    pub scenes: Vec<Scene>,
+    pub active_scene: usize,
+    pub number_of_meshes: usize,
+    pub animations: Vec<Animation>,
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub morph_targets: Vec<glm::Vec4>,
+}
+
+impl GltfAssetData {
+    pub fn load(asset_name: &str) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(asset_name).with_context(|| format!("path: {}", asset_name))?;
+        let base = Path::new(asset_name)
+            .parent()
+            .unwrap_or_else(|| Path::new("./"));
+        Self::from_bytes(&bytes, base).with_context(|| format!("path: {}", asset_name))
+    }
+
+    // Loads a gltf or glb file that is already in memory, such as one read from an archive.
+    // External buffers and images are resolved relative to the base directory.
+    pub fn from_bytes(bytes: &[u8], base_dir: &Path) -> anyhow::Result<Self> {
+        let (gltf, buffers, images) = import_slice(bytes, base_dir)?;
+
+        let json = GltfAsset::read_json(bytes);
+        if let Err(error) = json.as_ref() {
+            warn!("Failed to read extensions: {}", error);
+        }
+        let json = json.unwrap_or(serde_json::Value::Null);
+
+        let material_extensions =
+            GltfAsset::load_material_extensions(&json, gltf.materials().len());
+
+        let srgb_textures = GltfAsset::srgb_texture_indices(&gltf, &material_extensions);
+
+        // Materials reference textures rather than images,
+        // so each texture gets its own image and sampler
+        let textures = gltf
+            .textures()
+            .map(|texture| {
+                let is_srgb = srgb_textures.contains(&texture.index());
+                let description =
+                    Self::load_texture_description(&images[texture.source().index()], is_srgb)?;
+
+                let extensions = &json["textures"][texture.index()]["extensions"];
+                let compressed = ["KHR_texture_basisu", "MSFT_texture_dds"]
+                    .iter()
+                    .filter_map(|extension| extensions[*extension]["source"].as_u64())
+                    .filter_map(|index| images.get(index as usize))
+                    .find(|image| !matches!(image, ImportedImage::Decoded(_)))
+                    .and_then(
+                        |image| match Self::load_texture_description(image, is_srgb) {
+                            Ok(description) => Some(description),
+                            Err(error) => {
+                                warn!(
+                                    "Falling back to the uncompressed source of texture {}: {}",
+                                    texture.index(),
+                                    error
+                                );
+                                None
+                            }
+                        },
+                    );
+
+                let sampler_settings = SamplerSettings::from_gltf(&texture.sampler());
+
+                // Mip levels the sampler never reads aren't generated
+                let (description, compressed) = if sampler_settings.mipmapping {
+                    (description, compressed)
+                } else {
+                    (
+                        description.without_mipmaps(),
+                        compressed.map(TextureDescription::without_mipmaps),
+                    )
+                };
+
+                Ok(TextureSource {
+                    description,
+                    compressed,
+                    sampler_settings,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let animations = GltfAsset::prepare_animations(&gltf, &buffers);
+
+        let (mut scenes, vertices, indices, morph_targets) =
+            GltfAsset::prepare_scenes(&gltf, &buffers);
+        // Meshes of every scene get an id, so switching scenes doesn't change the ids
+        let number_of_meshes = GltfAsset::update_ubo_indices(&mut scenes);
+
+        // Documents without a default scene show their first scene
+        let active_scene = gltf.default_scene().map_or(0, |scene| scene.index());
+
+        Ok(Self {
+            gltf,
+            material_extensions,
+            textures,
+            scenes,
+            active_scene,
+            number_of_meshes,
+            animations,
+            vertices,
+            indices,
+            morph_targets,
+        })
+    }
+
+    fn load_texture_description(
+        image: &ImportedImage,
+        is_srgb: bool,
+    ) -> anyhow::Result<TextureDescription> {
+        match image {
+            ImportedImage::Decoded(data) => TextureDescription::from_gltf(data, is_srgb),
+            ImportedImage::Ktx2(bytes) => TextureDescription::from_ktx2(bytes, is_srgb),
+            ImportedImage::Dds(bytes) => TextureDescription::from_dds(bytes, is_srgb),
+        }
+    }
 }
 
 pub struct GltfAsset {
     pub gltf: gltf::Document,
+    pub material_extensions: Vec<MaterialExtensions>,
     pub textures: Vec<TextureBundle>,
     pub scenes: Vec<Scene>,
+    // Only the nodes of this scene are walked, posed and drawn
+    active_scene: usize,
+    // Computed when the asset is loaded or a scene is selected, so they don't follow animations
+    bounding_box: Option<Aabb>,
+    // In the asset's space, as the active scene is posed when it is selected
+    primitive_bounding_boxes: Vec<Aabb>,
     pub number_of_meshes: usize,
     pub animations: Vec<Animation>,
     pub vertices: Vec<f32>,
     pub indices: Vec<u32>,
+    // Position and normal deltas of each morph target for every morphed vertex
+    pub morph_targets: Vec<glm::Vec4>,
 }
 
 impl GltfAsset {
     pub const DEFAULT_NAME: &'static str = "<Unnamed>";
 
+    // This needs to match the defined value in the shaders
+    pub const MAX_NUM_MORPH_TARGETS: usize = 8;
+
+    // A model matrix followed by the instance's indirect draw, as in the pbr InstanceVertex
+    pub const INSTANCE_STRIDE: usize = (16 + 2) * std::mem::size_of::<f32>();
+
+    // Loads and uploads a gltf or glb file on the calling thread
     pub fn new(
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
         asset_name: &str,
-    ) -> GltfAsset {
-        let (gltf, buffers, asset_textures) =
-            gltf::import(&asset_name).expect("Couldn't import file!");
+    ) -> anyhow::Result<GltfAsset> {
+        let bytes = std::fs::read(asset_name).with_context(|| format!("path: {}", asset_name))?;
+        let base_dir = Path::new(asset_name)
+            .parent()
+            .unwrap_or_else(|| Path::new("./"));
+        Self::from_bytes(context, command_pool, &bytes, base_dir)
+            .with_context(|| format!("path: {}", asset_name))
+    }
+
+    // Loads and uploads a gltf or glb file that is already in memory
+    pub fn from_bytes(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        bytes: &[u8],
+        base_dir: &Path,
+    ) -> anyhow::Result<GltfAsset> {
+        let data = GltfAssetData::from_bytes(bytes, base_dir)?;
+        Self::from_data(context, command_pool, data)
+    }
 
-        let textures: Result<Vec<_>, _> = asset_textures
+    // Uploads the textures of an asset that has already been loaded
+    pub fn from_data(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        data: GltfAssetData,
+    ) -> anyhow::Result<GltfAsset> {
+        let textures = data
+            .textures
             .iter()
-            .map(|image_data| {
-                let description = TextureDescription::from_gltf(&image_data).unwrap();
-                TextureBundle::new(context.clone(), command_pool, &description)
+            .map(|texture| {
+                TextureBundle::new(
+                    context.clone(),
+                    command_pool,
+                    texture.select(&context),
+                    &texture.sampler_settings,
+                )
             })
-            .collect();
-        let textures = textures.unwrap();
-
-        let animations = Self::prepare_animations(&gltf, &buffers);
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        let (mut scenes, vertices, indices) = Self::prepare_scenes(&gltf, &buffers);
-        Self::update_ubo_indices(&mut scenes);
+        let mut asset = GltfAsset {
+            gltf: data.gltf,
+            material_extensions: data.material_extensions,
+            textures,
+            scenes: data.scenes,
+            active_scene: data.active_scene,
+            bounding_box: None,
+            primitive_bounding_boxes: Vec::new(),
+            number_of_meshes: data.number_of_meshes,
+            animations: data.animations,
+            vertices: data.vertices,
+            indices: data.indices,
+            morph_targets: data.morph_targets,
+        };
+        asset.update_bounding_boxes();
+        Ok(asset)
+    }
 
-        let number_of_meshes = gltf.nodes().filter(|node| node.mesh().is_some()).count();
+    // Base color, emissive and specular color textures hold color data,
+    // while the remaining material textures hold linear data
+    fn srgb_texture_indices(
+        gltf: &gltf::Document,
+        material_extensions: &[MaterialExtensions],
+    ) -> HashSet<usize> {
+        let mut indices = HashSet::new();
+        for material in gltf.materials() {
+            if let Some(info) = material.pbr_metallic_roughness().base_color_texture() {
+                indices.insert(info.texture().index());
+            }
+            if let Some(info) = material.emissive_texture() {
+                indices.insert(info.texture().index());
+            }
+        }
+        indices.extend(
+            material_extensions
+                .iter()
+                .filter_map(|extensions| extensions.specular_color_texture)
+                .map(|texture| texture.index),
+        );
+        indices
+    }
 
-        GltfAsset {
-            gltf,
-            textures,
-            scenes,
-            number_of_meshes,
-            animations,
-            vertices,
-            indices,
+    // The gltf crate drops extensions it doesn't know about,
+    // so they are read from the raw json instead
+    fn load_material_extensions(
+        json: &serde_json::Value,
+        number_of_materials: usize,
+    ) -> Vec<MaterialExtensions> {
+        let mut extensions = vec![MaterialExtensions::default(); number_of_materials];
+
+        if let Some(materials) = json["materials"].as_array() {
+            for (index, material) in materials.iter().enumerate().take(number_of_materials) {
+                extensions[index] = MaterialExtensions::from_json(material);
+            }
         }
+
+        extensions
+    }
+
+    fn read_json(bytes: &[u8]) -> anyhow::Result<serde_json::Value> {
+        let json = if bytes.starts_with(b"glTF") {
+            let glb = gltf::Glb::from_slice(bytes)?;
+            serde_json::from_slice(&glb.json)?
+        } else {
+            serde_json::from_slice(bytes)?
+        };
+        Ok(json)
     }
 
+    // Nodes authored with a matrix are decomposed into the same TRS form as other nodes,
+    // so animation channels, which only target TRS, compose with them the same way
     fn determine_transform(node: &gltf::Node) -> Transform {
-        let (translation, rotation, scale) = node.transform().decomposed();
+        let (translation, rotation, scale) = match node.transform() {
+            gltf::scene::Transform::Matrix { matrix } => {
+                return Self::decompose_matrix(&glm::Mat4::from(matrix));
+            }
+            transform => transform.decomposed(),
+        };
 
         let translation: glm::Vec3 = translation.into();
         let scale: glm::Vec3 = scale.into();
@@ -148,12 +693,66 @@ impl GltfAsset {
         Transform::new(translation, rotation, scale)
     }
 
+    // Zero scale is allowed in glTF matrices, but it collapses an axis of the rotation,
+    // so that axis is rebuilt from the other two instead of dividing by zero
+    fn decompose_matrix(matrix: &glm::Mat4) -> Transform {
+        let translation = matrix.column(3).xyz();
+        let mut axes = [
+            matrix.column(0).xyz(),
+            matrix.column(1).xyz(),
+            matrix.column(2).xyz(),
+        ];
+        let mut scale = glm::vec3(axes[0].norm(), axes[1].norm(), axes[2].norm());
+        // A mirrored matrix is decomposed with a negative z scale
+        if glm::mat4_to_mat3(matrix).determinant() < 0.0 {
+            scale.z = -scale.z;
+        }
+
+        let collapsed_axes = (0..3)
+            .filter(|axis| scale[*axis].abs() < f32::EPSILON)
+            .collect::<Vec<_>>();
+        for axis in 0..3 {
+            if !collapsed_axes.contains(&axis) {
+                axes[axis] /= scale[axis];
+            }
+        }
+
+        let rotation = match collapsed_axes.as_slice() {
+            [] => Self::rotation_from_axes(&axes),
+            [axis] => {
+                axes[*axis] = axes[(axis + 1) % 3].cross(&axes[(axis + 2) % 3]);
+                Self::rotation_from_axes(&axes)
+            }
+            // Any rotation that keeps the direction of the remaining axis is equivalent
+            [first, second] => {
+                let axis = 3 - first - second;
+                let helper = if axes[axis].x.abs() < 0.9 {
+                    glm::Vec3::x()
+                } else {
+                    glm::Vec3::y()
+                };
+                axes[(axis + 1) % 3] = axes[axis].cross(&helper).normalize();
+                axes[(axis + 2) % 3] = axes[axis].cross(&axes[(axis + 1) % 3]);
+                Self::rotation_from_axes(&axes)
+            }
+            _ => glm::Quat::identity(),
+        };
+
+        Transform::new(translation, rotation, scale)
+    }
+
+    fn rotation_from_axes(axes: &[glm::Vec3; 3]) -> glm::Quat {
+        let rotation = Rotation3::from_matrix_unchecked(glm::Mat3::from_columns(axes));
+        glm::quat_normalize(&UnitQuaternion::from_rotation_matrix(&rotation))
+    }
+
     fn prepare_scenes(
         gltf: &gltf::Document,
         buffers: &[gltf::buffer::Data],
-    ) -> (Vec<Scene>, Vec<f32>, Vec<u32>) {
+    ) -> (Vec<Scene>, Vec<f32>, Vec<u32>, Vec<glm::Vec4>) {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
+        let mut morph_targets = Vec::new();
         let mut scenes: Vec<Scene> = Vec::new();
         for scene in gltf.scenes() {
             let mut node_graphs: Vec<NodeGraph> = Vec::new();
@@ -166,13 +765,14 @@ impl GltfAsset {
                     NodeIndex::new(0_usize),
                     &mut vertices,
                     &mut indices,
+                    &mut morph_targets,
                 );
                 node_graphs.push(node_graph);
             }
             let name = scene.name().unwrap_or(&Self::DEFAULT_NAME).to_string();
             scenes.push(Scene { node_graphs, name });
         }
-        (scenes, vertices, indices)
+        (scenes, vertices, indices, morph_targets)
     }
 
     fn load_skin(node: &gltf::Node, buffers: &[gltf::buffer::Data]) -> Option<Skin> {
@@ -212,12 +812,29 @@ impl GltfAsset {
         parent_index: NodeIndex,
         vertices: &mut Vec<f32>,
         indices: &mut Vec<u32>,
+        morph_targets: &mut Vec<glm::Vec4>,
     ) {
-        let mesh = Self::load_mesh(node, buffers, vertices, indices);
+        let mesh = Self::load_mesh(node, buffers, vertices, indices, morph_targets);
         let skin = Self::load_skin(node, buffers);
         let name = node.name().unwrap_or(&Self::DEFAULT_NAME).to_string();
+
+        // Node weights take precedence over the weights of the mesh
+        let morph_weights = match (node.weights(), mesh.as_ref()) {
+            (Some(weights), Some(mesh)) => weights
+                .iter()
+                .copied()
+                .take(mesh.morph_target_count)
+                .collect(),
+            (None, Some(mesh)) => mesh.weights.clone(),
+            _ => Vec::new(),
+        };
+
+        let transform = Self::determine_transform(node);
         let node_info = Node {
-            local_transform: Self::determine_transform(node),
+            local_transform: transform.clone(),
+            rest_transform: transform,
+            rest_morph_weights: morph_weights.clone(),
+            morph_weights,
             mesh,
             skin,
             gltf_index: node.index(),
@@ -230,7 +847,15 @@ impl GltfAsset {
         }
 
         for child in node.children() {
-            Self::visit_children(&child, buffers, node_graph, node_index, vertices, indices);
+            Self::visit_children(
+                &child,
+                buffers,
+                node_graph,
+                node_index,
+                vertices,
+                indices,
+                morph_targets,
+            );
         }
     }
 
@@ -241,6 +866,9 @@ impl GltfAsset {
         let tex_coords_1_length = 2;
         let joints_0_length = 4;
         let weights_0_length = 4;
+        let color_0_length = 4;
+        let tangent_length = 4;
+        let morph_offset_length = 1;
 
         position_length
             + normal_length
@@ -248,6 +876,9 @@ impl GltfAsset {
             + tex_coords_1_length
             + joints_0_length
             + weights_0_length
+            + color_0_length
+            + tangent_length
+            + morph_offset_length
     }
 
     fn load_mesh(
@@ -255,9 +886,11 @@ impl GltfAsset {
         buffers: &[gltf::buffer::Data],
         vertices: &mut Vec<f32>,
         indices: &mut Vec<u32>,
+        morph_targets: &mut Vec<glm::Vec4>,
     ) -> Option<Mesh> {
         if let Some(mesh) = node.mesh() {
             let mut all_mesh_primitives = Vec::new();
+            let mut morph_target_count = 0;
             for primitive in mesh.primitives() {
                 let stride = Self::vertex_stride() * std::mem::size_of::<f32>();
 
@@ -317,47 +950,263 @@ impl GltfAsset {
                     convert_weights,
                 );
 
+                let colors_0 = reader.read_colors(0).map_or(
+                    vec![glm::vec4(1.0, 1.0, 1.0, 1.0); data_length],
+                    |colors| {
+                        colors
+                            .into_rgba_f32()
+                            .map(glm::Vec4::from)
+                            .collect::<Vec<_>>()
+                    },
+                );
+
+                let mut primitive_indices = reader
+                    .read_indices()
+                    .map(|read_indices| read_indices.into_u32().collect::<Vec<_>>());
+
+                // Line loops are drawn as strips that end where they started,
+                // so they are given indices when they don't have any
+                if primitive.mode() == Mode::LineLoop {
+                    let mut loop_indices = primitive_indices
+                        .unwrap_or_else(|| (0..data_length as u32).collect::<Vec<_>>());
+                    if let Some(first) = loop_indices.first().copied() {
+                        loop_indices.push(first);
+                    }
+                    primitive_indices = Some(loop_indices);
+                }
+
+                // Tangents are only generated when a normal map needs them
+                let normal_texture = primitive.material().normal_texture();
+                // Non-indexed primitives list their vertices in order
+                let triangle_indices = || {
+                    let indices = primitive_indices
+                        .clone()
+                        .unwrap_or_else(|| (0..data_length as u32).collect::<Vec<_>>());
+                    Self::triangle_list(primitive.mode(), &indices)
+                };
+                let tangents = match (reader.read_tangents(), normal_texture) {
+                    (Some(tangents), _) => tangents.map(glm::Vec4::from).collect::<Vec<_>>(),
+                    (None, Some(normal_texture)) => match triangle_indices() {
+                        Some(triangle_indices) => {
+                            let tex_coords = if normal_texture.tex_coord() == 1 {
+                                &tex_coords_1
+                            } else {
+                                &tex_coords_0
+                            };
+                            Self::generate_tangents(
+                                &positions,
+                                &normals,
+                                tex_coords,
+                                &triangle_indices,
+                            )
+                        }
+                        // Points and lines have no surface to build a tangent frame on
+                        None => vec![glm::vec4(0.0, 0.0, 0.0, 0.0); data_length],
+                    },
+                    (None, None) => vec![glm::vec4(0.0, 0.0, 0.0, 0.0); data_length],
+                };
+
+                // Targets past the limit supported by the shaders are ignored
+                let targets = reader
+                    .read_morph_targets()
+                    .map(|(positions, normals, _)| {
+                        let positions = positions
+                            .map_or(vec![glm::Vec3::zeros(); data_length], |positions| {
+                                positions.map(glm::Vec3::from).collect::<Vec<_>>()
+                            });
+                        let normals = normals
+                            .map_or(vec![glm::Vec3::zeros(); data_length], |normals| {
+                                normals.map(glm::Vec3::from).collect::<Vec<_>>()
+                            });
+                        (positions, normals)
+                    })
+                    .collect::<Vec<_>>();
+                if targets.len() > Self::MAX_NUM_MORPH_TARGETS {
+                    warn!(
+                        "Mesh '{}' has {} morph targets, only the first {} will be used!",
+                        mesh.name().unwrap_or(&Self::DEFAULT_NAME),
+                        targets.len(),
+                        Self::MAX_NUM_MORPH_TARGETS
+                    );
+                }
+                let targets = &targets[..targets.len().min(Self::MAX_NUM_MORPH_TARGETS)];
+                morph_target_count = targets.len();
+
                 for index in 0..positions.len() {
+                    // Each morphed vertex stores a position and normal delta per target
+                    let morph_offset = if targets.is_empty() {
+                        -1.0
+                    } else {
+                        let morph_offset = morph_targets.len() as f32;
+                        for (target_positions, target_normals) in targets.iter() {
+                            morph_targets.push(glm::vec3_to_vec4(&target_positions[index]));
+                            morph_targets.push(glm::vec3_to_vec4(&target_normals[index]));
+                        }
+                        morph_offset
+                    };
+
                     vertices.extend_from_slice(positions[index].as_slice());
                     vertices.extend_from_slice(normals[index].as_slice());
                     vertices.extend_from_slice(tex_coords_0[index].as_slice());
                     vertices.extend_from_slice(tex_coords_1[index].as_slice());
                     vertices.extend_from_slice(joints_0[index].as_slice());
                     vertices.extend_from_slice(weights_0[index].as_slice());
+                    vertices.extend_from_slice(colors_0[index].as_slice());
+                    vertices.extend_from_slice(tangents[index].as_slice());
+                    vertices.push(morph_offset);
                 }
 
                 let first_index = indices.len() as u32;
 
-                let primitive_indices = reader
-                    .read_indices()
-                    .map(|read_indices| {
-                        read_indices
-                            .into_u32()
-                            .map(|x| x + vertex_count)
-                            .collect::<Vec<_>>()
-                    })
-                    .expect("Failed to read indices!");
-                indices.extend_from_slice(&primitive_indices);
+                let mut number_of_indices = 0;
+                if let Some(primitive_indices) = primitive_indices.as_ref() {
+                    indices.extend(primitive_indices.iter().map(|index| index + vertex_count));
+                    number_of_indices = primitive_indices.len() as u32;
+                }
 
-                let number_of_indices = primitive_indices.len() as u32;
+                let bounding_box = primitive.bounding_box();
+                let bounding_box = Aabb::new(
+                    glm::Vec3::from(bounding_box.min),
+                    glm::Vec3::from(bounding_box.max),
+                );
+                let centroid = bounding_box.center();
 
                 all_mesh_primitives.push(Primitive {
+                    indexed: primitive_indices.is_some(),
                     first_index,
                     number_of_indices,
+                    first_vertex: vertex_count,
+                    number_of_vertices: data_length as u32,
                     material_index: primitive.material().index(),
+                    centroid,
+                    bounding_box,
+                    topology: Self::topology(primitive.mode()),
                 });
             }
 
+            let mut weights = mesh
+                .weights()
+                .map_or(Vec::new(), |weights| weights.to_vec());
+            weights.resize(morph_target_count, 0.0);
+
             Some(Mesh {
                 primitives: all_mesh_primitives,
                 mesh_id: 0,
+                morph_target_count,
+                weights,
             })
         } else {
             None
         }
     }
 
-    fn update_ubo_indices(scenes: &mut Vec<Scene>) {
+    fn topology(mode: Mode) -> vk::PrimitiveTopology {
+        match mode {
+            Mode::Points => vk::PrimitiveTopology::POINT_LIST,
+            Mode::Lines => vk::PrimitiveTopology::LINE_LIST,
+            Mode::LineLoop | Mode::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+            Mode::Triangles => vk::PrimitiveTopology::TRIANGLE_LIST,
+            Mode::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+            Mode::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN,
+        }
+    }
+
+    // Expands strips and fans into a triangle list, keeping the winding of every triangle
+    fn triangle_list(mode: Mode, indices: &[u32]) -> Option<Vec<u32>> {
+        let triangles = indices.len().saturating_sub(2);
+        match mode {
+            Mode::Triangles => Some(indices.to_vec()),
+            Mode::TriangleStrip => Some(
+                (0..triangles)
+                    .flat_map(|index| {
+                        // Every other triangle in a strip is wound the opposite way
+                        if index % 2 == 0 {
+                            vec![indices[index], indices[index + 1], indices[index + 2]]
+                        } else {
+                            vec![indices[index + 1], indices[index], indices[index + 2]]
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Mode::TriangleFan => Some(
+                (0..triangles)
+                    .flat_map(|index| vec![indices[0], indices[index + 1], indices[index + 2]])
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        }
+    }
+
+    // Per vertex tangents using Lengyel's method.
+    // The indices must describe a triangle list.
+    // The handedness of the tangent frame is stored in the w component.
+    pub fn generate_tangents(
+        positions: &[glm::Vec3],
+        normals: &[glm::Vec3],
+        tex_coords: &[glm::Vec2],
+        indices: &[u32],
+    ) -> Vec<glm::Vec4> {
+        let mut tangents = vec![glm::Vec3::zeros(); positions.len()];
+        let mut bitangents = vec![glm::Vec3::zeros(); positions.len()];
+
+        for triangle in indices.chunks_exact(3) {
+            let (i0, i1, i2) = (
+                triangle[0] as usize,
+                triangle[1] as usize,
+                triangle[2] as usize,
+            );
+
+            let edge_1 = positions[i1] - positions[i0];
+            let edge_2 = positions[i2] - positions[i0];
+            let delta_uv_1 = tex_coords[i1] - tex_coords[i0];
+            let delta_uv_2 = tex_coords[i2] - tex_coords[i0];
+
+            let determinant = delta_uv_1.x * delta_uv_2.y - delta_uv_2.x * delta_uv_1.y;
+            if determinant.abs() < f32::EPSILON {
+                continue;
+            }
+            let r = 1.0 / determinant;
+
+            let tangent = (edge_1 * delta_uv_2.y - edge_2 * delta_uv_1.y) * r;
+            let bitangent = (edge_2 * delta_uv_1.x - edge_1 * delta_uv_2.x) * r;
+
+            for index in [i0, i1, i2].iter() {
+                tangents[*index] += tangent;
+                bitangents[*index] += bitangent;
+            }
+        }
+
+        (0..positions.len())
+            .map(|index| {
+                let normal = normals[index];
+
+                // Gram-Schmidt orthogonalize
+                let mut tangent = tangents[index] - normal * glm::dot(&normal, &tangents[index]);
+                if glm::length(&tangent) < f32::EPSILON {
+                    // Pick any vector perpendicular to the normal
+                    let axis = if normal.x.abs() < 0.9 {
+                        glm::vec3(1.0, 0.0, 0.0)
+                    } else {
+                        glm::vec3(0.0, 1.0, 0.0)
+                    };
+                    tangent = glm::cross(&axis, &normal);
+                }
+                let tangent = glm::normalize(&tangent);
+
+                let handedness =
+                    if glm::dot(&glm::cross(&normal, &tangent), &bitangents[index]) < 0.0 {
+                        -1.0
+                    } else {
+                        1.0
+                    };
+
+                glm::vec4(tangent.x, tangent.y, tangent.z, handedness)
+            })
+            .collect::<Vec<_>>()
+    }
+
+    // Returns the number of meshes that were given an id
+    fn update_ubo_indices(scenes: &mut Vec<Scene>) -> usize {
         let mut indices = Vec::new();
         for (scene_index, scene) in scenes.iter().enumerate() {
             for (graph_index, graph) in scene.node_graphs.iter().enumerate() {
@@ -370,6 +1219,7 @@ impl GltfAsset {
             }
         }
 
+        let number_of_meshes = indices.len();
         for (mesh_id, (scene_index, graph_index, node_index)) in indices.into_iter().enumerate() {
             scenes[scene_index].node_graphs[graph_index][node_index]
                 .mesh
@@ -377,6 +1227,7 @@ impl GltfAsset {
                 .expect("Failed to get mesh!")
                 .mesh_id = mesh_id;
         }
+        number_of_meshes
     }
 
     fn prepare_animations(gltf: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<Animation> {
@@ -386,7 +1237,7 @@ impl GltfAsset {
             let mut channels = Vec::new();
             for channel in animation.channels() {
                 let sampler = channel.sampler();
-                let _interpolation = sampler.interpolation();
+                let interpolation = sampler.interpolation();
                 let target_gltf_index = channel.target().node().index();
                 let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
                 let inputs = reader.read_inputs().unwrap().collect::<Vec<_>>();
@@ -418,7 +1269,7 @@ impl GltfAsset {
                     target_gltf_index,
                     inputs,
                     transformations,
-                    _interpolation,
+                    interpolation,
                 });
             }
 
@@ -437,80 +1288,79 @@ impl GltfAsset {
         animations
     }
 
+    pub fn animation_index(&self, name: &str) -> Option<usize> {
+        self.animations
+            .iter()
+            .position(|animation| animation.name == name)
+    }
+
+    // Plays an animation using the time stored in the asset
     pub fn animate(&mut self, index: usize) {
         if self.animations.get(index).is_none() {
             return;
         }
         let mut animation = &mut self.animations[index];
 
-        // TODO: Allow for specifying a specific animation by name
         if animation.time > animation.max_animation_time {
             animation.time = 0.0;
         }
         if animation.time < 0.0 {
             animation.time = animation.max_animation_time;
         }
-        for channel in animation.channels.iter_mut() {
-            for scene in self.scenes.iter_mut() {
-                for graph in scene.node_graphs.iter_mut() {
-                    for node_index in graph.node_indices() {
-                        if graph[node_index].gltf_index == channel.target_gltf_index {
-                            let mut input_iter = channel.inputs.iter().enumerate().peekable();
-                            while let Some((previous_key, previous_time)) = input_iter.next() {
-                                if let Some((next_key, next_time)) = input_iter.peek() {
-                                    let next_key = *next_key;
-                                    let next_time = **next_time;
-                                    let previous_time = *previous_time;
-
-                                    if animation.time < previous_time || animation.time > next_time
-                                    {
-                                        continue;
-                                    }
-
-                                    let interpolation = (animation.time - previous_time)
-                                        / (next_time - previous_time);
-
-                                    // TODO: Interpolate with other methods
-                                    // Only Linear interpolation is used for now
-                                    match &channel.transformations {
-                                        TransformationSet::Translations(translations) => {
-                                            let start = translations[previous_key];
-                                            let end = translations[next_key];
-                                            let translation_vec =
-                                                glm::mix(&start, &end, interpolation);
-                                            graph[node_index].local_transform.translation =
-                                                translation_vec;
-                                        }
-                                        TransformationSet::Rotations(rotations) => {
-                                            let start = rotations[previous_key];
-                                            let end = rotations[next_key];
-                                            let start_quat = Quaternion::new(
-                                                start[3], start[0], start[1], start[2],
-                                            );
-                                            let end_quat =
-                                                Quaternion::new(end[3], end[0], end[1], end[2]);
-                                            let rotation_quat = glm::quat_slerp(
-                                                &start_quat,
-                                                &end_quat,
-                                                interpolation,
-                                            );
-                                            graph[node_index].local_transform.rotation =
-                                                glm::quat_normalize(&rotation_quat);
-                                        }
-                                        TransformationSet::Scales(scales) => {
-                                            let start = scales[previous_key];
-                                            let end = scales[next_key];
-                                            let scale_vec = glm::mix(&start, &end, interpolation);
-                                            graph[node_index].local_transform.scale = scale_vec;
-                                        }
-                                        TransformationSet::MorphTargetWeights(_weights) => {
-                                            unimplemented!()
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+        let time = animation.time;
+        self.animate_at(index, time, true);
+    }
+
+    // Poses the nodes with an animation sampled at the given time.
+    // One-shot animations hold their first and last poses outside of their duration.
+    pub fn animate_at(&mut self, index: usize, time: f32, looping: bool) {
+        let animation = match self.animations.get(index) {
+            Some(animation) => animation,
+            None => return,
+        };
+
+        let time = animation.wrap_time(time, looping);
+        if let Some(scene) = self.scenes.get_mut(self.active_scene) {
+            for graph in scene.node_graphs.iter_mut() {
+                for node_index in graph.node_indices() {
+                    let node = &mut graph[node_index];
+                    let pose = animation.pose(node, time);
+                    node.local_transform = pose.transform;
+                    node.morph_weights = pose.morph_weights;
+                }
+            }
+        }
+    }
+
+    // Crossfades between two animations, each given as its index and the time to sample it at.
+    // A weight of zero plays only the first animation and a weight of one only the second.
+    pub fn blend_animations_at(
+        &mut self,
+        first: (usize, f32),
+        second: (usize, f32),
+        weight: f32,
+        looping: bool,
+    ) {
+        let (first_animation, second_animation) =
+            match (self.animations.get(first.0), self.animations.get(second.0)) {
+                (Some(first_animation), Some(second_animation)) => {
+                    (first_animation, second_animation)
+                }
+                _ => return,
+            };
+
+        let first_time = first_animation.wrap_time(first.1, looping);
+        let second_time = second_animation.wrap_time(second.1, looping);
+        let weight = weight.clamp(0.0, 1.0);
+        if let Some(scene) = self.scenes.get_mut(self.active_scene) {
+            for graph in scene.node_graphs.iter_mut() {
+                for node_index in graph.node_indices() {
+                    let node = &mut graph[node_index];
+                    let first_pose = first_animation.pose(node, first_time);
+                    let second_pose = second_animation.pose(node, second_time);
+                    let pose = first_pose.interpolate(&second_pose, weight);
+                    node.local_transform = pose.transform;
+                    node.morph_weights = pose.morph_weights;
                 }
             }
         }
@@ -565,12 +1415,16 @@ impl GltfAsset {
         None
     }
 
+    // Nodes can be shared between scenes, so only the active scene is searched
     pub fn locate_node(&self, target_gltf_index: usize) -> Option<NodeLocation> {
-        for (scene_index, scene) in self.scenes.iter().enumerate() {
-            for (graph_index, graph) in scene.node_graphs.iter().enumerate() {
-                if let Some(node_index) = Self::matching_node_index(target_gltf_index, &graph) {
-                    return Some(NodeLocation::new(scene_index, graph_index, node_index));
-                }
+        let scene = self.scenes.get(self.active_scene)?;
+        for (graph_index, graph) in scene.node_graphs.iter().enumerate() {
+            if let Some(node_index) = Self::matching_node_index(target_gltf_index, &graph) {
+                return Some(NodeLocation::new(
+                    self.active_scene,
+                    graph_index,
+                    node_index,
+                ));
             }
         }
         None
@@ -597,25 +1451,119 @@ impl GltfAsset {
             })
     }
 
-    pub fn walk<F>(&self, action: F)
-    where
-        F: Fn(NodeIndex, &NodeGraph),
-    {
-        for scene in self.scenes.iter() {
-            for graph in scene.node_graphs.iter() {
-                let mut dfs = Dfs::new(&graph, NodeIndex::new(0));
-                while let Some(node_index) = dfs.next(&graph) {
-                    action(node_index, &graph);
-                }
+    // The box holding every primitive in the asset's space.
+    // Returns nothing for assets without meshes.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.bounding_box
+    }
+
+    // One box per primitive of the active scene, in the order they are walked
+    pub fn primitive_bounding_boxes(&self) -> &[Aabb] {
+        &self.primitive_bounding_boxes
+    }
+
+    // The accessor bounds of each primitive are placed with the global transform of its node
+    fn update_bounding_boxes(&mut self) {
+        let mut primitive_bounding_boxes = Vec::new();
+        self.walk_mut(|node_index, graph| {
+            if let Some(mesh) = graph[node_index].mesh.as_ref() {
+                let global_transform = Self::calculate_global_transform(node_index, graph);
+                primitive_bounding_boxes.extend(
+                    mesh.primitives
+                        .iter()
+                        .map(|primitive| primitive.bounding_box.transform(&global_transform)),
+                );
             }
-        }
+        });
+
+        self.bounding_box = primitive_bounding_boxes.iter().copied().fold(
+            None,
+            |bounding_box: Option<Aabb>, primitive_box| {
+                Some(match bounding_box {
+                    Some(bounding_box) => bounding_box.merge(&primitive_box),
+                    None => primitive_box,
+                })
+            },
+        );
+        self.primitive_bounding_boxes = primitive_bounding_boxes;
+    }
+
+    // Every node with a camera in the active scene, in the order it is walked
+    pub fn cameras(&self) -> Vec<AssetCamera> {
+        let mut cameras = Vec::new();
+        self.walk_mut(|node_index, graph| {
+            let node = &graph[node_index];
+            let camera = match self
+                .gltf
+                .nodes()
+                .nth(node.gltf_index)
+                .and_then(|node| node.camera())
+            {
+                Some(camera) => camera,
+                None => return,
+            };
+
+            let projection = match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => {
+                    AssetCameraProjection::Perspective {
+                        fov_y_degrees: perspective.yfov().to_degrees(),
+                        aspect_ratio: perspective.aspect_ratio(),
+                        near: perspective.znear(),
+                        far: perspective.zfar(),
+                    }
+                }
+                gltf::camera::Projection::Orthographic(orthographic) => {
+                    AssetCameraProjection::Orthographic {
+                        x_magnification: orthographic.xmag(),
+                        y_magnification: orthographic.ymag(),
+                        near: orthographic.znear(),
+                        far: orthographic.zfar(),
+                    }
+                }
+            };
+
+            let name = camera
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| node.name.clone());
+            cameras.push(AssetCamera {
+                name,
+                projection,
+                transform: Self::calculate_global_transform(node_index, graph),
+            });
+        });
+        cameras
+    }
+
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    pub fn active_scene(&self) -> usize {
+        self.active_scene
+    }
+
+    // The default scene of the document is active until another one is selected.
+    // Draws that were already built from the asset need to be built again.
+    pub fn set_active_scene(&mut self, index: usize) -> anyhow::Result<()> {
+        ensure!(
+            index < self.scenes.len(),
+            "Scene index {} is out of range for an asset with {} scenes!",
+            index,
+            self.scenes.len()
+        );
+        self.active_scene = index;
+        self.update_bounding_boxes();
+        Ok(())
     }
 
+    // Only the roots of the active scene are walked,
+    // so nodes that aren't part of it are never visited
     pub fn walk_mut<F>(&self, mut action: F)
     where
         F: FnMut(NodeIndex, &NodeGraph),
     {
-        for scene in self.scenes.iter() {
+        if let Some(scene) = self.scenes.get(self.active_scene) {
             for graph in scene.node_graphs.iter() {
                 let mut dfs = Dfs::new(&graph, NodeIndex::new(0));
                 while let Some(node_index) = dfs.next(&graph) {
@@ -625,7 +1573,7 @@ impl GltfAsset {
         }
     }
 
-    pub fn create_vertex_attributes() -> [vk::VertexInputAttributeDescription; 6] {
+    pub fn create_vertex_attributes() -> Vec<vk::VertexInputAttributeDescription> {
         let float_size = std::mem::size_of::<f32>();
         let position_description = vk::VertexInputAttributeDescription::builder()
             .binding(0)
@@ -669,23 +1617,83 @@ impl GltfAsset {
             .offset((14 * float_size) as _)
             .build();
 
-        [
+        let color_0_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(6)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((18 * float_size) as _)
+            .build();
+
+        let tangent_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(7)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((22 * float_size) as _)
+            .build();
+
+        // Index of the vertex's first morph target delta, or -1 if it isn't morphed
+        let morph_offset_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(12)
+            .format(vk::Format::R32_SFLOAT)
+            .offset((26 * float_size) as _)
+            .build();
+
+        let mut descriptions = vec![
             position_description,
             normal_description,
             tex_coord_0_description,
             tex_coord_1_description,
             joint_0_description,
             weight_0_description,
-        ]
+            color_0_description,
+            tangent_description,
+            morph_offset_description,
+        ];
+
+        // The per-instance model matrix occupies one location per column
+        let column_size = 4 * float_size;
+        descriptions.extend((0..4).map(|column| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(8 + column as u32)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset((column * column_size) as _)
+                .build()
+        }));
+
+        // Which indirect draw the instance belongs to, if any
+        descriptions.push(
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(13)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset((4 * column_size) as _)
+                .build(),
+        );
+
+        descriptions
     }
 
-    pub fn create_vertex_input_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+    pub fn create_vertex_input_descriptions() -> [vk::VertexInputBindingDescription; 2] {
         let vertex_input_binding_description = vk::VertexInputBindingDescription::builder()
             .binding(0)
-            .stride((18 * std::mem::size_of::<f32>()) as _)
+            .stride((Self::vertex_stride() * std::mem::size_of::<f32>()) as _)
             .input_rate(vk::VertexInputRate::VERTEX)
             .build();
-        [vertex_input_binding_description]
+        let instance_input_binding_description = vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(Self::INSTANCE_STRIDE as _)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build();
+        [
+            vertex_input_binding_description,
+            instance_input_binding_description,
+        ]
+    }
+
+    pub fn is_skinned(&self) -> bool {
+        self.gltf.skins().len() > 0
     }
 }
 
@@ -700,3 +1708,359 @@ impl NodeLocation {
         Self { scene, graph, node }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> Node {
+        Node {
+            local_transform: Transform::default(),
+            morph_weights: Vec::new(),
+            rest_transform: Transform::default(),
+            rest_morph_weights: Vec::new(),
+            mesh: None,
+            skin: None,
+            gltf_index: 0,
+            name: String::new(),
+        }
+    }
+
+    fn animation(channels: Vec<Channel>) -> Animation {
+        Animation {
+            time: 0.0,
+            channels,
+            max_animation_time: 1.0,
+            name: String::new(),
+        }
+    }
+
+    fn translation_channel(interpolation: Interpolation, translations: Vec<glm::Vec3>) -> Channel {
+        Channel {
+            target_gltf_index: 0,
+            inputs: vec![0.0, 1.0],
+            transformations: TransformationSet::Translations(translations),
+            interpolation,
+        }
+    }
+
+    fn quad_tangents(mode: Mode, indices: &[u32], tex_coords: &[glm::Vec2]) -> Vec<glm::Vec4> {
+        let positions = [
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(1.0, 1.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ];
+        let normals = [glm::vec3(0.0, 0.0, 1.0); 4];
+        let triangles = GltfAsset::triangle_list(mode, indices).expect("Expected triangles!");
+        GltfAsset::generate_tangents(&positions, &normals, tex_coords, &triangles)
+    }
+
+    #[test]
+    fn quad_tangents_follow_the_texture_coordinates() {
+        let tex_coords = [
+            glm::vec2(0.0, 0.0),
+            glm::vec2(1.0, 0.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec2(0.0, 1.0),
+        ];
+        let expected = glm::vec4(1.0, 0.0, 0.0, 1.0);
+        for (mode, indices) in [
+            (Mode::Triangles, vec![0, 1, 2, 0, 2, 3]),
+            (Mode::TriangleStrip, vec![0, 1, 3, 2]),
+            (Mode::TriangleFan, vec![0, 1, 2, 3]),
+        ]
+        .iter()
+        {
+            for tangent in quad_tangents(*mode, indices, &tex_coords) {
+                assert!(glm::distance(&tangent, &expected) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn mirrored_texture_coordinates_flip_the_handedness() {
+        let tex_coords = [
+            glm::vec2(1.0, 0.0),
+            glm::vec2(0.0, 0.0),
+            glm::vec2(0.0, 1.0),
+            glm::vec2(1.0, 1.0),
+        ];
+        let expected = glm::vec4(-1.0, 0.0, 0.0, -1.0);
+        for tangent in quad_tangents(Mode::Triangles, &[0, 1, 2, 0, 2, 3], &tex_coords) {
+            assert!(glm::distance(&tangent, &expected) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn lines_have_no_triangles() {
+        assert!(GltfAsset::triangle_list(Mode::Lines, &[0, 1, 2, 3]).is_none());
+        assert!(GltfAsset::triangle_list(Mode::Points, &[0, 1]).is_none());
+        assert_eq!(
+            GltfAsset::triangle_list(Mode::TriangleStrip, &[0, 1]),
+            Some(Vec::new())
+        );
+    }
+
+    fn assert_matrices_match(first: &glm::Mat4, second: &glm::Mat4) {
+        assert!(
+            (first - second).abs().max() < 1e-5,
+            "{} != {}",
+            first,
+            second
+        );
+    }
+
+    #[test]
+    fn decomposed_matrices_round_trip() {
+        let rotation = glm::quat_angle_axis(0.7, &glm::normalize(&glm::vec3(1.0, 2.0, 3.0)));
+        let transform = Transform::new(
+            glm::vec3(1.0, -2.0, 3.0),
+            rotation,
+            glm::vec3(2.0, 0.5, 3.0),
+        );
+        let decomposed = GltfAsset::decompose_matrix(&transform.matrix());
+
+        assert!(glm::distance(&decomposed.translation, &transform.translation) < 1e-5);
+        assert!(glm::distance(&decomposed.scale, &transform.scale) < 1e-5);
+        // q and -q are the same rotation
+        assert!(glm::quat_dot(&decomposed.rotation, &rotation).abs() > 1.0 - 1e-5);
+        assert_matrices_match(&decomposed.matrix(), &transform.matrix());
+    }
+
+    #[test]
+    fn mirrored_matrices_decompose_with_negative_scale() {
+        let rotation = glm::quat_angle_axis(0.3, &glm::Vec3::y());
+        let matrix = Transform::new(
+            glm::vec3(0.0, 1.0, 0.0),
+            rotation,
+            glm::vec3(-2.0, 1.0, 1.0),
+        )
+        .matrix();
+        let decomposed = GltfAsset::decompose_matrix(&matrix);
+
+        assert!(decomposed.scale.x * decomposed.scale.y * decomposed.scale.z < 0.0);
+        assert!(glm::distance(&decomposed.scale.abs(), &glm::vec3(2.0, 1.0, 1.0)) < 1e-5);
+        assert_matrices_match(&decomposed.matrix(), &matrix);
+    }
+
+    #[test]
+    fn zero_scale_keeps_a_valid_rotation() {
+        let rotation = glm::quat_angle_axis(1.2, &glm::Vec3::z());
+        let matrix =
+            Transform::new(glm::Vec3::zeros(), rotation, glm::vec3(1.0, 0.0, 2.0)).matrix();
+        let decomposed = GltfAsset::decompose_matrix(&matrix);
+
+        assert!((glm::quat_length(&decomposed.rotation) - 1.0).abs() < 1e-5);
+        assert!(glm::distance(&decomposed.scale, &glm::vec3(1.0, 0.0, 2.0)) < 1e-5);
+        assert_matrices_match(&decomposed.matrix(), &matrix);
+    }
+
+    // A parent with two children, one placed with a matrix and the other with TRS
+    fn matrix_and_trs_gltf() -> &'static str {
+        r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "translation": [0.0, 1.0, 0.0], "children": [1, 2] },
+                { "matrix": [0.0, 2.0, 0.0, 0.0,
+                             -2.0, 0.0, 0.0, 0.0,
+                             0.0, 0.0, 2.0, 0.0,
+                             2.0, 0.0, 0.0, 1.0] },
+                { "translation": [0.0, 0.0, 3.0],
+                  "rotation": [0.0, 0.7071068, 0.0, 0.7071068],
+                  "scale": [2.0, 2.0, 2.0] }
+            ]
+        }"#
+    }
+
+    #[test]
+    fn matrix_and_trs_siblings_are_placed_in_the_world() {
+        let data =
+            GltfAssetData::from_bytes(matrix_and_trs_gltf().as_bytes(), Path::new("./")).unwrap();
+        let graph = &data.scenes[0].node_graphs[0];
+        let world_position = |gltf_index: usize| {
+            let node_index = graph
+                .node_indices()
+                .find(|index| graph[*index].gltf_index == gltf_index)
+                .expect("Expected the node to be in the graph!");
+            let transform = GltfAsset::calculate_global_transform(node_index, graph);
+            glm::vec3(transform[12], transform[13], transform[14])
+        };
+
+        assert!(glm::distance(&world_position(1), &glm::vec3(2.0, 1.0, 0.0)) < 1e-5);
+        assert!(glm::distance(&world_position(2), &glm::vec3(0.0, 1.0, 3.0)) < 1e-5);
+    }
+
+    // Two triangles in one mesh, the first without indices and the second with them
+    fn mixed_indexing_gltf() -> String {
+        let positions = [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [3.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+        ];
+        let mut bytes = positions
+            .iter()
+            .flat_map(|position| {
+                position
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes().to_vec())
+            })
+            .collect::<Vec<_>>();
+        bytes.extend(
+            [0u32, 1, 2]
+                .iter()
+                .flat_map(|index| index.to_le_bytes().to_vec()),
+        );
+
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "meshes": [{{
+                    "primitives": [
+                        {{ "attributes": {{ "POSITION": 0 }} }},
+                        {{ "attributes": {{ "POSITION": 1 }}, "indices": 2 }}
+                    ]
+                }}],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] }},
+                    {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3,
+                       "type": "VEC3", "min": [2.0, 0.0, 0.0], "max": [3.0, 1.0, 0.0] }},
+                    {{ "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 72 }},
+                    {{ "buffer": 0, "byteOffset": 72, "byteLength": 12 }}
+                ],
+                "buffers": [{{
+                    "byteLength": {},
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}]
+            }}"#,
+            bytes.len(),
+            base64::encode(&bytes)
+        )
+    }
+
+    #[test]
+    fn non_indexed_primitives_record_their_vertex_range() {
+        let data =
+            GltfAssetData::from_bytes(mixed_indexing_gltf().as_bytes(), Path::new("./")).unwrap();
+        let graph = &data.scenes[0].node_graphs[0];
+        let mesh = graph
+            .node_indices()
+            .find_map(|index| graph[index].mesh.as_ref())
+            .expect("Expected a mesh!");
+
+        let non_indexed = &mesh.primitives[0];
+        assert!(!non_indexed.indexed);
+        assert_eq!(non_indexed.number_of_indices, 0);
+        assert_eq!(non_indexed.first_vertex, 0);
+        assert_eq!(non_indexed.number_of_vertices, 3);
+
+        let indexed = &mesh.primitives[1];
+        assert!(indexed.indexed);
+        assert_eq!(indexed.first_index, 0);
+        assert_eq!(indexed.number_of_indices, 3);
+        assert_eq!(indexed.first_vertex, 3);
+        assert_eq!(indexed.number_of_vertices, 3);
+
+        // Only the indexed primitive adds indices, offset past the earlier vertices
+        assert_eq!(data.indices, vec![3, 4, 5]);
+        assert_eq!(data.vertices.len(), 6 * GltfAsset::vertex_stride());
+    }
+
+    #[test]
+    fn wrap_time_loops_or_clamps() {
+        let animation = animation(Vec::new());
+        assert!((animation.wrap_time(1.25, true) - 0.25).abs() < 1e-6);
+        assert!((animation.wrap_time(-0.25, true) - 0.75).abs() < 1e-6);
+        assert_eq!(animation.wrap_time(1.25, false), 1.0);
+        assert_eq!(animation.wrap_time(-0.25, false), 0.0);
+    }
+
+    #[test]
+    fn step_holds_the_previous_keyframe() {
+        let channel = translation_channel(
+            Interpolation::Step,
+            vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 0.0)],
+        );
+        let pose = animation(vec![channel]).pose(&node(), 0.75);
+        assert_eq!(pose.transform.translation, glm::vec3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let channel = translation_channel(
+            Interpolation::Linear,
+            vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 4.0, 0.0)],
+        );
+        let pose = animation(vec![channel]).pose(&node(), 0.5);
+        assert!(glm::distance(&pose.transform.translation, &glm::vec3(1.0, 2.0, 0.0)) < 1e-6);
+    }
+
+    #[test]
+    fn cubic_spline_uses_the_tangents() {
+        // In tangent, value and out tangent of each keyframe
+        let channel = translation_channel(
+            Interpolation::CubicSpline,
+            vec![
+                glm::vec3(0.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, 0.0),
+                glm::vec3(1.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, 0.0),
+                glm::vec3(0.0, 2.0, 0.0),
+                glm::vec3(0.0, 0.0, 0.0),
+            ],
+        );
+        let pose = animation(vec![channel]).pose(&node(), 0.5);
+        // The out tangent contributes (t^3 - 2t^2 + t) and the value (-2t^3 + 3t^2)
+        assert!(glm::distance(&pose.transform.translation, &glm::vec3(0.125, 1.0, 0.0)) < 1e-6);
+    }
+
+    #[test]
+    fn blending_starts_from_the_rest_pose() {
+        let translating = animation(vec![translation_channel(
+            Interpolation::Linear,
+            vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 0.0)],
+        )]);
+        let scaling = animation(vec![Channel {
+            target_gltf_index: 0,
+            inputs: vec![0.0, 1.0],
+            transformations: TransformationSet::Scales(vec![
+                glm::vec3(1.0, 1.0, 1.0),
+                glm::vec3(3.0, 3.0, 3.0),
+            ]),
+            interpolation: Interpolation::Linear,
+        }]);
+
+        // A pose left behind by another clip must not leak into the blend
+        let mut node = node();
+        node.local_transform.translation = glm::vec3(10.0, 10.0, 10.0);
+
+        let first = translating.pose(&node, 1.0);
+        let second = scaling.pose(&node, 1.0);
+        for (weight, translation, scale) in
+            [(0.0, 2.0, 1.0), (0.5, 1.0, 2.0), (1.0, 0.0, 3.0)].iter()
+        {
+            let pose = first.interpolate(&second, *weight);
+            assert!(
+                glm::distance(
+                    &pose.transform.translation,
+                    &glm::vec3(*translation, 0.0, 0.0)
+                ) < 1e-6
+            );
+            assert!(
+                glm::distance(&pose.transform.scale, &glm::vec3(*scale, *scale, *scale)) < 1e-6
+            );
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::renderer::vulkan::resource::image::{DdsTexture, Ktx2Texture};
+use anyhow::{bail, ensure, Context, Result};
+use gltf::image::Format;
+use image::{DynamicImage, GenericImageView};
+use std::path::Path;
+
+// An image referenced by a gltf document.
+// KTX2 and DDS images, referenced by KHR_texture_basisu and MSFT_texture_dds,
+// are kept encoded because the image crate can't decode them.
+pub enum ImportedImage {
+    Decoded(gltf::image::Data),
+    Ktx2(Vec<u8>),
+    Dds(Vec<u8>),
+}
+
+pub type Import = (gltf::Document, Vec<gltf::buffer::Data>, Vec<ImportedImage>);
+
+// Mirrors gltf::import_slice, which fails on any image it can't decode.
+// External buffers and images are read relative to the base directory.
+pub fn import_slice(bytes: &[u8], base: &Path) -> Result<Import> {
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(bytes)?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let mut data = match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => read_uri(base, uri)?,
+            gltf::buffer::Source::Bin => blob
+                .take()
+                .context("Failed to find the binary chunk of the gltf file!")?,
+        };
+        ensure!(
+            data.len() >= buffer.length(),
+            "Buffer {} holds {} bytes instead of {}!",
+            buffer.index(),
+            data.len(),
+            buffer.length()
+        );
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        buffers.push(gltf::buffer::Data(data));
+    }
+
+    let images = document
+        .images()
+        .map(|image| {
+            let bytes = match image.source() {
+                gltf::image::Source::Uri { uri, .. } => read_uri(base, uri)?,
+                gltf::image::Source::View { view, .. } => {
+                    let begin = view.offset();
+                    let end = begin + view.length();
+                    buffers[view.buffer().index()].0[begin..end].to_vec()
+                }
+            };
+            decode_image(bytes)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((document, buffers, images))
+}
+
+fn read_uri(base: &Path, uri: &str) -> Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let data = match uri.split(";base64,").nth(1) {
+            Some(data) => data,
+            None => bail!("Only base64 data uris are supported!"),
+        };
+        return Ok(base64::decode(data)?);
+    }
+
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => match uri.strip_prefix("file:") {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => base.join(uri),
+        },
+    };
+    std::fs::read(&path).with_context(|| format!("path: {}", path.display()))
+}
+
+fn decode_image(bytes: Vec<u8>) -> Result<ImportedImage> {
+    if Ktx2Texture::is_ktx2(&bytes) {
+        return Ok(ImportedImage::Ktx2(bytes));
+    }
+
+    if DdsTexture::is_dds(&bytes) {
+        return Ok(ImportedImage::Dds(bytes));
+    }
+
+    let image = image::load_from_memory(&bytes)?;
+    let format = match image {
+        DynamicImage::ImageLuma8(_) => Format::R8,
+        DynamicImage::ImageLumaA8(_) => Format::R8G8,
+        DynamicImage::ImageRgb8(_) => Format::R8G8B8,
+        DynamicImage::ImageRgba8(_) => Format::R8G8B8A8,
+        DynamicImage::ImageBgr8(_) => Format::B8G8R8,
+        DynamicImage::ImageBgra8(_) => Format::B8G8R8A8,
+        DynamicImage::ImageLuma16(_) => Format::R16,
+        DynamicImage::ImageLumaA16(_) => Format::R16G16,
+        DynamicImage::ImageRgb16(_) => Format::R16G16B16,
+        DynamicImage::ImageRgba16(_) => Format::R16G16B16A16,
+    };
+    let (width, height) = image.dimensions();
+    Ok(ImportedImage::Decoded(gltf::image::Data {
+        pixels: image.to_bytes(),
+        format,
+        width,
+        height,
+    }))
+}
@@ -0,0 +1,74 @@
+use crate::renderer::{vulkan::asset::GltfAssetData, LoadProgress};
+use anyhow::Result;
+use std::{
+    collections::HashSet,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+// Loads assets on a worker thread and streams them back as they finish
+pub struct AssetLoader {
+    requests: Sender<String>,
+    receiver: Receiver<(String, Result<GltfAssetData>)>,
+    // Requested assets that haven't been received yet
+    pending: HashSet<String>,
+    progress: LoadProgress,
+}
+
+impl AssetLoader {
+    pub fn new(asset_names: &[String]) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<String>();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The loop ends once the loader is dropped along with its request sender
+            for asset_name in request_receiver.iter() {
+                let data = GltfAssetData::load(&asset_name);
+                if sender.send((asset_name, data)).is_err() {
+                    // The receiver was dropped, so nothing is waiting on the remaining assets
+                    break;
+                }
+            }
+        });
+
+        let mut loader = Self {
+            requests,
+            receiver,
+            pending: HashSet::new(),
+            progress: LoadProgress::default(),
+        };
+        asset_names
+            .iter()
+            .for_each(|asset_name| loader.load(asset_name));
+        loader
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    pub fn is_pending(&self, asset_name: &str) -> bool {
+        self.pending.contains(asset_name)
+    }
+
+    // Queues an asset to be loaded after any others that were already requested.
+    // Assets that are still pending are only loaded once.
+    pub fn load(&mut self, asset_name: &str) {
+        if !self.pending.insert(asset_name.to_string()) {
+            return;
+        }
+        self.progress.total += 1;
+        self.requests
+            .send(asset_name.to_string())
+            .expect("Failed to request an asset from the loader thread!");
+    }
+
+    // Returns every asset that has finished loading since the last call without blocking
+    pub fn receive(&mut self) -> Vec<(String, Result<GltfAssetData>)> {
+        let received = self.receiver.try_iter().collect::<Vec<_>>();
+        for (asset_name, _) in received.iter() {
+            self.pending.remove(asset_name);
+        }
+        self.progress.loaded += received.len();
+        received
+    }
+}
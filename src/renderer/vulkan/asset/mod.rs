@@ -1,3 +1,5 @@
-pub use self::gltf::*;
+pub use self::{gltf::*, import::*, loader::*};
 
 pub mod gltf;
+pub mod import;
+pub mod loader;
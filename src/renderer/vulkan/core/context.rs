@@ -1,13 +1,326 @@
-use crate::renderer::vulkan::core::{DebugLayer, Instance, LogicalDevice, PhysicalDevice, Surface};
-use anyhow::Result;
+use crate::renderer::vulkan::{
+    core::{Instance, LogicalDevice, PhysicalDevice, Surface},
+    resource::image::SamplerCache,
+};
+use anyhow::{bail, Context, Result};
 use ash::{
-    extensions::khr::Swapchain,
-    version::{DeviceV1_0, InstanceV1_0},
-    vk,
+    extensions::{ext::DebugUtils, khr::Swapchain},
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk::{self, make_version, Handle},
+};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_void,
 };
 use vk_mem::{Allocator, AllocatorCreateInfo};
 use winit::window::Window;
 
+// Memory usage in bytes, gathered from the allocator
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    // Bytes of device memory blocks, including the unused ranges within them
+    pub allocated_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl From<&vk_mem::ffi::VmaStatInfo> for MemoryUsage {
+    fn from(info: &vk_mem::ffi::VmaStatInfo) -> Self {
+        Self {
+            block_count: info.blockCount,
+            allocation_count: info.allocationCount,
+            allocated_bytes: info.usedBytes + info.unusedBytes,
+            used_bytes: info.usedBytes,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryStats {
+    // One entry per memory heap of the physical device
+    pub heaps: Vec<MemoryUsage>,
+    pub total: MemoryUsage,
+}
+
+// How a device feature is asked for when creating the context
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeatureRequest {
+    Disabled,
+    // Enabled when the device supports it
+    Optional,
+    // Creating the context fails when the device doesn't support it
+    Required,
+}
+
+// The device features a context was created with, or that a device supports
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DeviceFeatures {
+    pub sample_rate_shading: bool,
+    pub sampler_anisotropy: bool,
+    pub fill_mode_non_solid: bool,
+    pub wide_lines: bool,
+    pub large_points: bool,
+    pub multi_draw_indirect: bool,
+    pub draw_indirect_first_instance: bool,
+    // Non-uniform indexing into partially bound, runtime sized texture arrays.
+    // Requires Vulkan 1.1 and VK_EXT_descriptor_indexing.
+    pub descriptor_indexing: bool,
+}
+
+impl DeviceFeatures {
+    fn named(&self) -> [(&'static str, bool); 8] {
+        [
+            ("sampleRateShading", self.sample_rate_shading),
+            ("samplerAnisotropy", self.sampler_anisotropy),
+            ("fillModeNonSolid", self.fill_mode_non_solid),
+            ("wideLines", self.wide_lines),
+            ("largePoints", self.large_points),
+            ("multiDrawIndirect", self.multi_draw_indirect),
+            (
+                "drawIndirectFirstInstance",
+                self.draw_indirect_first_instance,
+            ),
+            ("descriptorIndexing", self.descriptor_indexing),
+        ]
+    }
+}
+
+// Device features and extensions requested in the settings.
+// Features that are left out keep the request of the default builder.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub sample_rate_shading: Option<FeatureRequest>,
+    pub sampler_anisotropy: Option<FeatureRequest>,
+    pub fill_mode_non_solid: Option<FeatureRequest>,
+    pub wide_lines: Option<FeatureRequest>,
+    pub large_points: Option<FeatureRequest>,
+    pub multi_draw_indirect: Option<FeatureRequest>,
+    pub draw_indirect_first_instance: Option<FeatureRequest>,
+    pub descriptor_indexing: Option<FeatureRequest>,
+    pub instance_extensions: Vec<String>,
+    pub device_extensions: Vec<String>,
+}
+
+// Chooses the device features and extensions a context is created with.
+// The default enables the features the renderer has always used.
+#[derive(Debug, Clone)]
+pub struct VulkanContextBuilder {
+    anisotropy: Option<f32>,
+    sample_rate_shading: FeatureRequest,
+    sampler_anisotropy: FeatureRequest,
+    fill_mode_non_solid: FeatureRequest,
+    wide_lines: FeatureRequest,
+    large_points: FeatureRequest,
+    multi_draw_indirect: FeatureRequest,
+    draw_indirect_first_instance: FeatureRequest,
+    descriptor_indexing: FeatureRequest,
+    instance_extensions: Vec<CString>,
+    device_extensions: Vec<CString>,
+}
+
+impl Default for VulkanContextBuilder {
+    fn default() -> Self {
+        Self {
+            anisotropy: None,
+            sample_rate_shading: FeatureRequest::Required,
+            sampler_anisotropy: FeatureRequest::Optional,
+            fill_mode_non_solid: FeatureRequest::Disabled,
+            // Pipelines fall back to thin lines when it isn't supported
+            wide_lines: FeatureRequest::Optional,
+            // Point sprites are drawn a single pixel wide when it isn't supported
+            large_points: FeatureRequest::Optional,
+            // Opaque primitives are batched into indirect draws when it is supported
+            multi_draw_indirect: FeatureRequest::Optional,
+            // Each indirect draw selects its instance slots with its first instance
+            draw_indirect_first_instance: FeatureRequest::Optional,
+            // The pbr texture array holds more than MAX_TEXTURES textures when it is supported
+            descriptor_indexing: FeatureRequest::Optional,
+            instance_extensions: Vec::new(),
+            device_extensions: Vec::new(),
+        }
+    }
+}
+
+impl VulkanContextBuilder {
+    // The anisotropy level is clamped to what the device supports
+    pub fn anisotropy(mut self, anisotropy: Option<f32>) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn sample_rate_shading(mut self, request: FeatureRequest) -> Self {
+        self.sample_rate_shading = request;
+        self
+    }
+
+    pub fn sampler_anisotropy(mut self, request: FeatureRequest) -> Self {
+        self.sampler_anisotropy = request;
+        self
+    }
+
+    // Needed for wireframe and point polygon modes
+    pub fn fill_mode_non_solid(mut self, request: FeatureRequest) -> Self {
+        self.fill_mode_non_solid = request;
+        self
+    }
+
+    // Needed for line widths other than 1.0
+    pub fn wide_lines(mut self, request: FeatureRequest) -> Self {
+        self.wide_lines = request;
+        self
+    }
+
+    // Needed for point sizes greater than 1.0
+    pub fn large_points(mut self, request: FeatureRequest) -> Self {
+        self.large_points = request;
+        self
+    }
+
+    // Needed for indirect draws with a draw count greater than one
+    pub fn multi_draw_indirect(mut self, request: FeatureRequest) -> Self {
+        self.multi_draw_indirect = request;
+        self
+    }
+
+    // Needed for indirect draws with a first instance other than zero
+    pub fn draw_indirect_first_instance(mut self, request: FeatureRequest) -> Self {
+        self.draw_indirect_first_instance = request;
+        self
+    }
+
+    pub fn descriptor_indexing(mut self, request: FeatureRequest) -> Self {
+        self.descriptor_indexing = request;
+        self
+    }
+
+    // Creating the context fails if the extension isn't supported
+    pub fn instance_extension(mut self, name: &CStr) -> Self {
+        self.instance_extensions.push(name.to_owned());
+        self
+    }
+
+    // Creating the context fails if the device doesn't support the extension
+    pub fn device_extension(mut self, name: &CStr) -> Self {
+        self.device_extensions.push(name.to_owned());
+        self
+    }
+
+    // Applies the requests from the settings on top of the ones already made
+    pub fn settings(mut self, settings: &DeviceSettings) -> Result<Self> {
+        if let Some(request) = settings.sample_rate_shading {
+            self = self.sample_rate_shading(request);
+        }
+        if let Some(request) = settings.sampler_anisotropy {
+            self = self.sampler_anisotropy(request);
+        }
+        if let Some(request) = settings.fill_mode_non_solid {
+            self = self.fill_mode_non_solid(request);
+        }
+        if let Some(request) = settings.wide_lines {
+            self = self.wide_lines(request);
+        }
+        if let Some(request) = settings.large_points {
+            self = self.large_points(request);
+        }
+        if let Some(request) = settings.multi_draw_indirect {
+            self = self.multi_draw_indirect(request);
+        }
+        if let Some(request) = settings.draw_indirect_first_instance {
+            self = self.draw_indirect_first_instance(request);
+        }
+        if let Some(request) = settings.descriptor_indexing {
+            self = self.descriptor_indexing(request);
+        }
+
+        for name in settings.instance_extensions.iter() {
+            let name = CString::new(name.as_str())
+                .with_context(|| format!("instance extension: {}", name))?;
+            self = self.instance_extension(&name);
+        }
+        for name in settings.device_extensions.iter() {
+            let name = CString::new(name.as_str())
+                .with_context(|| format!("device extension: {}", name))?;
+            self = self.device_extension(&name);
+        }
+        Ok(self)
+    }
+
+    pub fn build(&self, window: &Window) -> Result<VulkanContext> {
+        let instance = self.create_instance()?;
+        let surface = Surface::new(&instance, window);
+        VulkanContext::create(instance, Some(surface), self)
+    }
+
+    // Creates a context that can only render offscreen, with no surface or swapchain support
+    #[cfg(feature = "headless")]
+    pub fn build_headless(&self) -> Result<VulkanContext> {
+        let instance = self.create_instance()?;
+        VulkanContext::create(instance, None, self)
+    }
+
+    // Descriptor indexing features can only be queried through Vulkan 1.1
+    fn create_instance(&self) -> Result<Instance> {
+        let api_version = if self.descriptor_indexing == FeatureRequest::Disabled {
+            make_version(1, 0, 0)
+        } else {
+            make_version(1, 1, 0)
+        };
+        Instance::new(api_version, &self.instance_extensions)
+    }
+
+    fn requests(&self) -> [FeatureRequest; 8] {
+        [
+            self.sample_rate_shading,
+            self.sampler_anisotropy,
+            self.fill_mode_non_solid,
+            self.wide_lines,
+            self.large_points,
+            self.multi_draw_indirect,
+            self.draw_indirect_first_instance,
+            self.descriptor_indexing,
+        ]
+    }
+
+    // Fails with every required feature the device is missing
+    fn resolve_features(&self, supported: &DeviceFeatures) -> Result<DeviceFeatures> {
+        let missing_features = self
+            .requests()
+            .iter()
+            .zip(supported.named().iter())
+            .filter(|(request, (_, supported))| **request == FeatureRequest::Required && !supported)
+            .map(|(_, (name, _))| *name)
+            .collect::<Vec<_>>();
+        if !missing_features.is_empty() {
+            bail!(
+                "Failed to create the vulkan context, the device doesn't support these features: {}!",
+                missing_features.join(", ")
+            );
+        }
+
+        let enabled = |request: FeatureRequest, supported: bool| {
+            request != FeatureRequest::Disabled && supported
+        };
+        Ok(DeviceFeatures {
+            sample_rate_shading: enabled(self.sample_rate_shading, supported.sample_rate_shading),
+            sampler_anisotropy: enabled(self.sampler_anisotropy, supported.sampler_anisotropy),
+            fill_mode_non_solid: enabled(self.fill_mode_non_solid, supported.fill_mode_non_solid),
+            wide_lines: enabled(self.wide_lines, supported.wide_lines),
+            large_points: enabled(self.large_points, supported.large_points),
+            multi_draw_indirect: enabled(self.multi_draw_indirect, supported.multi_draw_indirect),
+            draw_indirect_first_instance: enabled(
+                self.draw_indirect_first_instance,
+                supported.draw_indirect_first_instance,
+            ),
+            descriptor_indexing: enabled(self.descriptor_indexing, supported.descriptor_indexing),
+        })
+    }
+}
+
 // The order the struct members here are declared in
 // is important because it determines the order
 // the fields are 'Drop'ped in
@@ -15,20 +328,79 @@ use winit::window::Window;
 // The drop order should be:
 // logical device -> physical device -> surface -> instance
 pub struct VulkanContext {
+    sampler_cache: SamplerCache,
+    // Used to name objects and label command buffer regions, if the extension is available
+    debug_utils: Option<DebugUtils>,
+    // One when anisotropic filtering is disabled
+    max_anisotropy: f32,
+    features: DeviceFeatures,
     allocator: vk_mem::Allocator,
     logical_device: LogicalDevice,
     physical_device: PhysicalDevice,
-    surface: Surface,
+    // Headless contexts are created without a surface
+    surface: Option<Surface>,
     instance: Instance,
 }
 
 impl VulkanContext {
-    pub fn new(window: &Window) -> Result<Self> {
-        let instance = Instance::new()?;
-        let surface = Surface::new(&instance, window);
-        let physical_device = PhysicalDevice::new(&instance, &surface)?;
+    // Used when no anisotropy level is configured and the device supports more
+    pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.0;
+
+    // Creates a context that can only render offscreen, with no surface or swapchain support
+    #[cfg(feature = "headless")]
+    pub fn headless(anisotropy: Option<f32>) -> Result<Self> {
+        VulkanContextBuilder::default()
+            .anisotropy(anisotropy)
+            .build_headless()
+    }
+
+    fn create(
+        instance: Instance,
+        surface: Option<Surface>,
+        builder: &VulkanContextBuilder,
+    ) -> Result<Self> {
+        let physical_device = PhysicalDevice::new(&instance, surface.as_ref())?;
 
-        let logical_device = Self::create_logical_device(&instance, &physical_device)?;
+        let supported_extensions = Self::supported_device_extensions(&instance, &physical_device)?;
+        let missing_extensions = builder
+            .device_extensions
+            .iter()
+            .filter(|extension| !supported_extensions.contains(extension))
+            .map(|extension| extension.to_string_lossy())
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            bail!(
+                "Failed to create the vulkan context, the device doesn't support these extensions: {}!",
+                missing_extensions.join(", ")
+            );
+        }
+
+        let supported_features =
+            Self::supported_features(&instance, &physical_device, &supported_extensions);
+        let features = builder.resolve_features(&supported_features)?;
+
+        let logical_device = Self::create_logical_device(
+            &instance,
+            &physical_device,
+            surface.is_some(),
+            &features,
+            &builder.device_extensions,
+        )?;
+
+        let max_anisotropy = if features.sampler_anisotropy {
+            let properties = unsafe {
+                instance
+                    .instance()
+                    .get_physical_device_properties(physical_device.physical_device())
+            };
+            builder
+                .anisotropy
+                .unwrap_or(Self::DEFAULT_MAX_ANISOTROPY)
+                .min(properties.limits.max_sampler_anisotropy)
+                .max(1.0)
+        } else {
+            1.0
+        };
 
         let allocator_create_info = AllocatorCreateInfo {
             device: (*logical_device.logical_device()).clone(),
@@ -39,7 +411,17 @@ impl VulkanContext {
 
         let allocator = Allocator::new(&allocator_create_info)?;
 
+        let debug_utils = if instance.debug_utils_enabled() {
+            Some(DebugUtils::new(instance.entry(), instance.instance()))
+        } else {
+            None
+        };
+
         Ok(Self {
+            sampler_cache: SamplerCache::default(),
+            debug_utils,
+            max_anisotropy,
+            features,
             allocator,
             instance,
             physical_device,
@@ -48,25 +430,145 @@ impl VulkanContext {
         })
     }
 
+    fn supported_device_extensions(
+        instance: &Instance,
+        physical_device: &PhysicalDevice,
+    ) -> Result<Vec<CString>> {
+        let extensions = unsafe {
+            instance
+                .instance()
+                .enumerate_device_extension_properties(physical_device.physical_device())
+        }?;
+        Ok(extensions
+            .iter()
+            .map(|extension| {
+                unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned()
+            })
+            .collect())
+    }
+
+    fn supported_features(
+        instance: &Instance,
+        physical_device: &PhysicalDevice,
+        supported_extensions: &[CString],
+    ) -> DeviceFeatures {
+        let features = unsafe {
+            instance
+                .instance()
+                .get_physical_device_features(physical_device.physical_device())
+        };
+        let properties = unsafe {
+            instance
+                .instance()
+                .get_physical_device_properties(physical_device.physical_device())
+        };
+
+        let vulkan_1_1 = make_version(1, 1, 0);
+        let descriptor_indexing = instance.api_version() >= vulkan_1_1
+            && properties.api_version >= vulkan_1_1
+            && supported_extensions
+                .iter()
+                .any(|extension| extension.as_c_str() == vk::ExtDescriptorIndexingFn::name())
+            && {
+                // The features2 builder can't chain structures in this version of ash
+                let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+                let mut features2 = vk::PhysicalDeviceFeatures2 {
+                    p_next: &mut indexing_features as *mut _ as *mut c_void,
+                    ..Default::default()
+                };
+                unsafe {
+                    instance.instance().get_physical_device_features2(
+                        physical_device.physical_device(),
+                        &mut features2,
+                    )
+                };
+                Self::descriptor_indexing_supported(&indexing_features)
+            };
+
+        DeviceFeatures {
+            sample_rate_shading: features.sample_rate_shading == vk::TRUE,
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            wide_lines: features.wide_lines == vk::TRUE,
+            large_points: features.large_points == vk::TRUE,
+            multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+            draw_indirect_first_instance: features.draw_indirect_first_instance == vk::TRUE,
+            descriptor_indexing,
+        }
+    }
+
+    fn descriptor_indexing_supported(
+        features: &vk::PhysicalDeviceDescriptorIndexingFeatures,
+    ) -> bool {
+        [
+            features.shader_sampled_image_array_non_uniform_indexing,
+            features.descriptor_binding_partially_bound,
+            features.descriptor_binding_variable_descriptor_count,
+            features.runtime_descriptor_array,
+        ]
+        .iter()
+        .all(|supported| *supported == vk::TRUE)
+    }
+
+    // The parts of descriptor indexing that are enabled when it is requested
+    fn descriptor_indexing_features() -> vk::PhysicalDeviceDescriptorIndexingFeatures {
+        vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .runtime_descriptor_array(true)
+            .build()
+    }
+
     fn create_logical_device(
         instance: &Instance,
         physical_device: &PhysicalDevice,
+        presentable: bool,
+        features: &DeviceFeatures,
+        additional_extensions: &[CString],
     ) -> Result<LogicalDevice> {
-        let device_extensions = [Swapchain::name().as_ptr()];
+        let mut device_extensions = additional_extensions
+            .iter()
+            .map(CString::as_c_str)
+            .collect::<Vec<_>>();
+        if presentable {
+            device_extensions.push(Swapchain::name());
+        }
+        if features.descriptor_indexing {
+            device_extensions.push(vk::ExtDescriptorIndexingFn::name());
+        }
+        // The additional extensions may repeat the ones enabled here
+        device_extensions.sort();
+        device_extensions.dedup();
+        let device_extensions = device_extensions
+            .iter()
+            .map(|extension| extension.as_ptr())
+            .collect::<Vec<_>>();
         let queue_creation_info_list = physical_device.build_queue_creation_info_list();
         let device_features = vk::PhysicalDeviceFeatures::builder()
             //.robust_buffer_access(true) // FIXME: Disable this in release builds
-            .sample_rate_shading(true)
-            .sampler_anisotropy(true)
+            .sample_rate_shading(features.sample_rate_shading)
+            .sampler_anisotropy(features.sampler_anisotropy)
+            .fill_mode_non_solid(features.fill_mode_non_solid)
+            .wide_lines(features.wide_lines)
+            .large_points(features.large_points)
+            .multi_draw_indirect(features.multi_draw_indirect)
+            .draw_indirect_first_instance(features.draw_indirect_first_instance)
             .build();
         let mut device_create_info_builder = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_creation_info_list)
             .enabled_extension_names(&device_extensions)
             .enabled_features(&device_features);
 
-        let layer_name_vec = Instance::required_layers();
+        let mut descriptor_indexing_features = Self::descriptor_indexing_features();
+        if features.descriptor_indexing {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut descriptor_indexing_features);
+        }
+
+        let layer_name_vec = instance.required_layers();
         let layer_name_pointers = layer_name_vec.layer_name_pointers();
-        if DebugLayer::validation_layers_enabled() {
+        if instance.validation_settings().enabled {
             device_create_info_builder =
                 device_create_info_builder.enabled_layer_names(&layer_name_pointers)
         }
@@ -78,6 +580,76 @@ impl VulkanContext {
         )
     }
 
+    // Names show up in validation messages and in graphics debuggers such as RenderDoc
+    pub fn set_debug_name<T: Handle>(&self, object: T, name: &str) {
+        let debug_utils = match self.debug_utils.as_ref() {
+            Some(debug_utils) => debug_utils,
+            None => return,
+        };
+
+        let object_name = CString::new(name).expect("Failed to build CString");
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(T::TYPE)
+            .object_handle(object.as_raw())
+            .object_name(&object_name)
+            .build();
+        let result = unsafe {
+            debug_utils.debug_utils_set_object_name(
+                self.logical_device.logical_device().handle(),
+                &name_info,
+            )
+        };
+        if let Err(error) = result {
+            warn!("Failed to set debug name '{}': {}", name, error);
+        }
+    }
+
+    // Labeled regions can be nested, and must be ended in the order they were begun
+    pub fn begin_debug_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            let label_name = CString::new(name).expect("Failed to build CString");
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&label_name)
+                .build();
+            unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+    }
+
+    pub fn end_debug_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+
+    // Rounds a uniform buffer entry size up to the alignment required for dynamic offsets
+    pub fn align_uniform_buffer_size(&self, size: vk::DeviceSize) -> vk::DeviceSize {
+        let minimum_ubo_alignment = self
+            .physical_device_properties()
+            .limits
+            .min_uniform_buffer_offset_alignment;
+        if minimum_ubo_alignment > 0 {
+            (size + minimum_ubo_alignment - 1) & !(minimum_ubo_alignment - 1)
+        } else {
+            size
+        }
+    }
+
+    // Rounds the requested sample count down to 1, 2, 4 or 8, clamped to what the device supports
+    pub fn clamp_samples(&self, samples: u32) -> vk::SampleCountFlags {
+        let requested = match samples {
+            0..=1 => vk::SampleCountFlags::TYPE_1,
+            2..=3 => vk::SampleCountFlags::TYPE_2,
+            4..=7 => vk::SampleCountFlags::TYPE_4,
+            _ => vk::SampleCountFlags::TYPE_8,
+        };
+        let max_usable_samples = self.max_usable_samples();
+        if requested.as_raw() > max_usable_samples.as_raw() {
+            max_usable_samples
+        } else {
+            requested
+        }
+    }
+
     pub fn max_usable_samples(&self) -> vk::SampleCountFlags {
         let properties = self.physical_device_properties();
         let color_sample_counts = properties.limits.framebuffer_color_sample_counts;
@@ -106,29 +678,49 @@ impl VulkanContext {
         tiling: vk::ImageTiling,
         features: vk::FormatFeatureFlags,
     ) -> vk::Format {
-        let candidates = vec![
+        let candidates = [
             vk::Format::D32_SFLOAT,
             vk::Format::D32_SFLOAT_S8_UINT,
             vk::Format::D24_UNORM_S8_UINT,
         ];
-        candidates
-            .iter()
-            .copied()
-            .find(|candidate| {
-                let properties = unsafe {
-                    self.instance()
-                        .get_physical_device_format_properties(self.physical_device(), *candidate)
-                };
+        self.find_supported_format(&candidates, tiling, features)
+            .expect("Failed to find a supported depth format")
+    }
+
+    // Only formats with a stencil aspect, every device supports at least one of them
+    pub fn determine_depth_stencil_format(
+        &self,
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> vk::Format {
+        let candidates = [
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ];
+        self.find_supported_format(&candidates, tiling, features)
+            .expect("Failed to find a supported depth stencil format")
+    }
 
-                let linear_tiling_feature_support = tiling == vk::ImageTiling::LINEAR
-                    && properties.linear_tiling_features.contains(features);
+    fn find_supported_format(
+        &self,
+        candidates: &[vk::Format],
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> Option<vk::Format> {
+        candidates.iter().copied().find(|candidate| {
+            let properties = unsafe {
+                self.instance()
+                    .get_physical_device_format_properties(self.physical_device(), *candidate)
+            };
 
-                let optimal_tiling_feature_support = tiling == vk::ImageTiling::OPTIMAL
-                    && properties.optimal_tiling_features.contains(features);
+            let linear_tiling_feature_support = tiling == vk::ImageTiling::LINEAR
+                && properties.linear_tiling_features.contains(features);
 
-                linear_tiling_feature_support || optimal_tiling_feature_support
-            })
-            .expect("Failed to find a supported depth format")
+            let optimal_tiling_feature_support = tiling == vk::ImageTiling::OPTIMAL
+                && properties.optimal_tiling_features.contains(features);
+
+            linear_tiling_feature_support || optimal_tiling_feature_support
+        })
     }
 
     pub fn physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
@@ -145,10 +737,83 @@ impl VulkanContext {
         }
     }
 
+    // Check these before relying on a feature that was only requested as optional
+    pub fn features(&self) -> DeviceFeatures {
+        self.features
+    }
+
+    pub fn max_anisotropy(&self) -> f32 {
+        self.max_anisotropy
+    }
+
+    // Every sampler is created through this, so the anisotropy level
+    // never exceeds the configured level or what the device supports
+    pub fn clamp_anisotropy(&self, create_info: &mut vk::SamplerCreateInfo) {
+        create_info.max_anisotropy = create_info.max_anisotropy.min(self.max_anisotropy).max(1.0);
+        if create_info.max_anisotropy <= 1.0 {
+            create_info.anisotropy_enable = vk::FALSE;
+        }
+    }
+
+    // The bias of every sampler has to stay within the device's limit
+    pub fn clamp_mip_lod_bias(&self, create_info: &mut vk::SamplerCreateInfo) {
+        let max_bias = self
+            .physical_device_properties()
+            .limits
+            .max_sampler_lod_bias;
+        create_info.mip_lod_bias = create_info.mip_lod_bias.max(-max_bias).min(max_bias);
+    }
+
+    // Every pipeline is created with a line width that the device can rasterize
+    pub fn clamp_line_width(&self, line_width: f32) -> f32 {
+        if (line_width - 1.0).abs() < f32::EPSILON {
+            return 1.0;
+        }
+
+        if !self.features.wide_lines {
+            warn!(
+                "Line width {} requires the wideLines feature, falling back to 1.0",
+                line_width
+            );
+            return 1.0;
+        }
+
+        let [min_line_width, max_line_width] =
+            self.physical_device_properties().limits.line_width_range;
+        line_width.max(min_line_width).min(max_line_width)
+    }
+
+    // Point sizes written by shaders are clamped to this
+    pub fn max_point_size(&self) -> f32 {
+        if !self.features.large_points {
+            return 1.0;
+        }
+        self.physical_device_properties().limits.point_size_range[1]
+    }
+
+    pub fn sampler_cache(&self) -> &SamplerCache {
+        &self.sampler_cache
+    }
+
     pub fn allocator(&self) -> &vk_mem::Allocator {
         &self.allocator
     }
 
+    // This traverses all of the allocator's blocks, so it shouldn't be called every frame
+    pub fn memory_stats(&self) -> Result<MemoryStats> {
+        let stats = self.allocator.calculate_stats()?;
+        let heap_count = self.physical_device_memory_properties().memory_heap_count as usize;
+        Ok(MemoryStats {
+            heaps: stats
+                .memoryHeap
+                .iter()
+                .take(heap_count)
+                .map(MemoryUsage::from)
+                .collect(),
+            total: MemoryUsage::from(&stats.total),
+        })
+    }
+
     pub fn instance(&self) -> &ash::Instance {
         self.instance.instance()
     }
@@ -158,11 +823,17 @@ impl VulkanContext {
     }
 
     pub fn surface(&self) -> &ash::extensions::khr::Surface {
-        self.surface.surface()
+        self.surface
+            .as_ref()
+            .expect("Headless contexts have no surface!")
+            .surface()
     }
 
     pub fn surface_khr(&self) -> ash::vk::SurfaceKHR {
-        self.surface.surface_khr()
+        self.surface
+            .as_ref()
+            .expect("Headless contexts have no surface!")
+            .surface_khr()
     }
 
     pub fn physical_device_memory_properties(&self) -> &ash::vk::PhysicalDeviceMemoryProperties {
@@ -185,6 +856,13 @@ impl VulkanContext {
             .present_queue_family_index()
     }
 
+    // None when the device has no dedicated transfer queue family
+    pub fn transfer_queue_family_index(&self) -> Option<u32> {
+        self.physical_device
+            .queue_family_index_set()
+            .transfer_queue_family_index()
+    }
+
     // TODO: Move these down to the logical device
     pub fn graphics_queue(&self) -> vk::Queue {
         unsafe {
@@ -202,6 +880,14 @@ impl VulkanContext {
         }
     }
 
+    pub fn transfer_queue(&self) -> Option<vk::Queue> {
+        self.transfer_queue_family_index().map(|index| unsafe {
+            self.logical_device()
+                .logical_device()
+                .get_device_queue(index, 0)
+        })
+    }
+
     pub fn wait_idle(&self) {
         unsafe {
             self.logical_device()
@@ -211,3 +897,19 @@ impl VulkanContext {
         }
     }
 }
+
+impl Drop for VulkanContext {
+    fn drop(&mut self) {
+        // Everything allocated through the context holds a reference to it,
+        // so any allocations left at this point have been leaked
+        if cfg!(debug_assertions) && !std::thread::panicking() {
+            if let Ok(stats) = self.memory_stats() {
+                debug_assert_eq!(
+                    stats.total.allocation_count, 0,
+                    "{} buffer and image allocations were leaked!",
+                    stats.total.allocation_count
+                );
+            }
+        }
+    }
+}
@@ -9,10 +9,104 @@ use ash::{
 };
 use log::{debug, error, info, trace, warn};
 use std::{
+    env,
     ffi::{CStr, CString},
     os::raw::c_void,
 };
 
+// Whether validation is enabled and which of its messages are forwarded to the log.
+// Validation is on for debug builds and with the vulkan-validation feature,
+// unless the environment says otherwise, so it can be disabled while profiling
+// or enabled in release builds to diagnose problems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidationSettings {
+    pub enabled: bool,
+    // Messages below this severity are dropped
+    pub min_severity: DebugUtilsMessageSeverityFlagsEXT,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(feature = "vulkan-validation") || cfg!(debug_assertions),
+            min_severity: DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        }
+    }
+}
+
+impl ValidationSettings {
+    // Set to 'on' or 'off' to force validation regardless of the build
+    pub const ENABLED_VARIABLE: &'static str = "DRAGONGLASS_VALIDATION";
+
+    // Set to 'verbose', 'info', 'warning' or 'error'
+    pub const SEVERITY_VARIABLE: &'static str = "DRAGONGLASS_VALIDATION_SEVERITY";
+
+    const SEVERITIES: [DebugUtilsMessageSeverityFlagsEXT; 4] = [
+        DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        DebugUtilsMessageSeverityFlagsEXT::INFO,
+        DebugUtilsMessageSeverityFlagsEXT::WARNING,
+        DebugUtilsMessageSeverityFlagsEXT::ERROR,
+    ];
+
+    // Values that can't be parsed are reported and the defaults are used instead
+    pub fn from_environment() -> Self {
+        let mut settings = Self::default();
+
+        if let Ok(value) = env::var(Self::ENABLED_VARIABLE) {
+            match Self::parse_enabled(&value) {
+                Some(enabled) => settings.enabled = enabled,
+                None => warn!(
+                    "{} is set to '{}', expected 'on' or 'off'",
+                    Self::ENABLED_VARIABLE,
+                    value
+                ),
+            }
+        }
+
+        if let Ok(value) = env::var(Self::SEVERITY_VARIABLE) {
+            match Self::parse_severity(&value) {
+                Some(min_severity) => settings.min_severity = min_severity,
+                None => warn!(
+                    "{} is set to '{}', expected 'verbose', 'info', 'warning' or 'error'",
+                    Self::SEVERITY_VARIABLE,
+                    value
+                ),
+            }
+        }
+
+        settings
+    }
+
+    fn parse_enabled(value: &str) -> Option<bool> {
+        match value.trim().to_lowercase().as_str() {
+            "1" | "on" | "true" => Some(true),
+            "0" | "off" | "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn parse_severity(value: &str) -> Option<DebugUtilsMessageSeverityFlagsEXT> {
+        match value.trim().to_lowercase().as_str() {
+            "verbose" => Some(DebugUtilsMessageSeverityFlagsEXT::VERBOSE),
+            "info" => Some(DebugUtilsMessageSeverityFlagsEXT::INFO),
+            "warning" => Some(DebugUtilsMessageSeverityFlagsEXT::WARNING),
+            "error" => Some(DebugUtilsMessageSeverityFlagsEXT::ERROR),
+            _ => None,
+        }
+    }
+
+    // The minimum severity along with every severity above it
+    pub fn message_severity(&self) -> DebugUtilsMessageSeverityFlagsEXT {
+        Self::SEVERITIES
+            .iter()
+            .filter(|severity| severity.as_raw() >= self.min_severity.as_raw())
+            .fold(
+                DebugUtilsMessageSeverityFlagsEXT::empty(),
+                |flags, severity| flags | *severity,
+            )
+    }
+}
+
 pub struct DebugLayer {
     debug_utils: DebugUtils,
     debug_utils_messenger: DebugUtilsMessengerEXT,
@@ -20,14 +114,15 @@ pub struct DebugLayer {
 
 impl DebugLayer {
     pub fn new(instance: &Instance) -> Result<Option<Self>> {
-        if !DebugLayer::validation_layers_enabled() {
+        let validation = instance.validation_settings();
+        if !validation.enabled {
             return Ok(None);
         }
 
         let debug_utils = DebugUtils::new(instance.entry(), instance.instance());
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
             .flags(vk::DebugUtilsMessengerCreateFlagsEXT::all())
-            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+            .message_severity(validation.message_severity())
             .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
             .pfn_user_callback(Some(vulkan_debug_callback))
             .build();
@@ -39,10 +134,6 @@ impl DebugLayer {
         }))
     }
 
-    pub fn validation_layers_enabled() -> bool {
-        cfg!(feature = "vulkan-validation") || cfg!(debug_assertions)
-    }
-
     pub fn debug_layer_names() -> LayerNameVec {
         LayerNameVec {
             layer_names: vec![LayerName::new("VK_LAYER_LUNARG_standard_validation")],
@@ -1,5 +1,7 @@
-use crate::renderer::vulkan::core::{surface_extension_names, DebugLayer, LayerNameVec};
-use anyhow::Result;
+use crate::renderer::vulkan::core::{
+    surface_extension_names, DebugLayer, LayerNameVec, ValidationSettings,
+};
+use anyhow::{bail, Result};
 use ash::{
     extensions::ext::DebugUtils,
     version::{EntryV1_0, InstanceV1_0},
@@ -26,15 +28,30 @@ impl ApplicationDescription for Instance {
 pub struct Instance {
     entry: ash::Entry,
     instance: ash::Instance,
+    // VK_EXT_debug_utils is enabled whenever it's available, so graphics debuggers can show labels
+    debug_utils_enabled: bool,
+    api_version: u32,
+    validation: ValidationSettings,
 }
 
 impl Instance {
-    pub fn new() -> Result<Self> {
+    // The api version is lowered to what the loader supports, so check `api_version` afterwards.
+    // Fails if any of the additional extensions aren't supported.
+    // Validation is configured from the environment, see `ValidationSettings`.
+    pub fn new(api_version: u32, additional_extensions: &[CString]) -> Result<Self> {
         let entry = ash::Entry::new()?;
-        Self::check_required_layers_supported(&entry);
-        let app_info = Self::build_application_creation_info()?;
-        let instance_extensions = Self::required_instance_extension_names();
-        let layer_name_vec = Self::required_layers();
+        let validation = ValidationSettings::from_environment();
+        Self::check_required_layers_supported(&entry, &validation);
+        Self::check_extensions_supported(&entry, additional_extensions)?;
+        let loader_version = entry
+            .try_enumerate_instance_version()?
+            .unwrap_or(Instance::API_VERSION);
+        let api_version = api_version.max(Instance::API_VERSION).min(loader_version);
+        let app_info = Self::build_application_creation_info(api_version)?;
+        let debug_utils_enabled = validation.enabled || Self::debug_utils_supported(&entry);
+        let mut instance_extensions = Self::required_instance_extension_names(debug_utils_enabled);
+        instance_extensions.extend(additional_extensions.iter().map(|name| name.as_ptr()));
+        let layer_name_vec = Self::validation_layers(&validation);
         let layer_name_pointers = layer_name_vec.layer_name_pointers();
         let instance_create_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
@@ -44,7 +61,58 @@ impl Instance {
 
         // TODO: List supported instance extensions
 
-        Ok(Self { entry, instance })
+        Ok(Self {
+            entry,
+            instance,
+            debug_utils_enabled,
+            api_version,
+            validation,
+        })
+    }
+
+    pub fn validation_settings(&self) -> ValidationSettings {
+        self.validation
+    }
+
+    pub fn debug_utils_enabled(&self) -> bool {
+        self.debug_utils_enabled
+    }
+
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
+    fn check_extensions_supported(entry: &ash::Entry, extensions: &[CString]) -> Result<()> {
+        let supported_extensions = entry.enumerate_instance_extension_properties()?;
+        let missing_extensions = extensions
+            .iter()
+            .filter(|extension| {
+                !supported_extensions.iter().any(|supported| {
+                    let name = unsafe { CStr::from_ptr(supported.extension_name.as_ptr()) };
+                    name == extension.as_c_str()
+                })
+            })
+            .map(|extension| extension.to_string_lossy())
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            bail!(
+                "Failed to create the instance, these extensions are not supported: {}!",
+                missing_extensions.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn debug_utils_supported(entry: &ash::Entry) -> bool {
+        entry
+            .enumerate_instance_extension_properties()
+            .map(|extensions| {
+                extensions.iter().any(|extension| {
+                    let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+                    name == DebugUtils::name()
+                })
+            })
+            .unwrap_or(false)
     }
 
     pub fn entry(&self) -> &ash::Entry {
@@ -55,22 +123,22 @@ impl Instance {
         &self.instance
     }
 
-    fn build_application_creation_info() -> Result<vk::ApplicationInfo> {
+    fn build_application_creation_info(api_version: u32) -> Result<vk::ApplicationInfo> {
         let app_name = CString::new(Instance::APPLICATION_NAME)?;
         let engine_name = CString::new(Instance::ENGINE_NAME)?;
         let app_info = vk::ApplicationInfo::builder()
             .application_name(&app_name)
             .engine_name(&engine_name)
-            .api_version(Instance::API_VERSION)
+            .api_version(api_version)
             .application_version(Instance::APPLICATION_VERSION)
             .engine_version(Instance::ENGINE_VERSION)
             .build();
         Ok(app_info)
     }
 
-    fn required_instance_extension_names() -> Vec<*const i8> {
+    fn required_instance_extension_names(debug_utils_enabled: bool) -> Vec<*const i8> {
         let mut instance_extension_names = surface_extension_names();
-        if DebugLayer::validation_layers_enabled() {
+        if debug_utils_enabled {
             instance_extension_names.push(DebugUtils::name().as_ptr());
         }
 
@@ -91,9 +159,13 @@ impl Instance {
         instance_extension_names
     }
 
-    pub fn required_layers() -> LayerNameVec {
+    pub fn required_layers(&self) -> LayerNameVec {
+        Self::validation_layers(&self.validation)
+    }
+
+    fn validation_layers(validation: &ValidationSettings) -> LayerNameVec {
         let mut layer_name_vec = LayerNameVec::new();
-        if DebugLayer::validation_layers_enabled() {
+        if validation.enabled {
             layer_name_vec
                 .layer_names
                 // TODO: Improve naming here
@@ -102,8 +174,8 @@ impl Instance {
         layer_name_vec
     }
 
-    fn check_required_layers_supported(entry: &ash::Entry) {
-        let layer_name_vec = Self::required_layers();
+    fn check_required_layers_supported(entry: &ash::Entry, validation: &ValidationSettings) {
+        let layer_name_vec = Self::validation_layers(validation);
         for layer_name in layer_name_vec.layer_names.iter() {
             let all_layers_supported = entry
                 .enumerate_instance_layer_properties()
@@ -30,13 +30,15 @@ impl LogicalDevice {
         &self.logical_device
     }
 
-    // TODO: Add error handling
-    pub fn wait_for_fence(&self, current_frame_synchronization: &CurrentFrameSynchronization) {
+    // Fails with ERROR_DEVICE_LOST if the device stops responding
+    pub fn wait_for_fence(
+        &self,
+        current_frame_synchronization: &CurrentFrameSynchronization,
+    ) -> ash::prelude::VkResult<()> {
         let in_flight_fences = [current_frame_synchronization.in_flight()];
         unsafe {
             self.logical_device
                 .wait_for_fences(&in_flight_fences, true, std::u64::MAX)
-                .expect("Failed to wait for fences!");
         }
     }
 
@@ -49,30 +51,40 @@ impl LogicalDevice {
         }
     }
 
+    // A lost device has nothing left to wait for,
+    // and its resources still need to be destroyed
     pub fn wait_idle(&self) {
-        unsafe {
-            self.logical_device
-                .device_wait_idle()
-                .expect("Failed to wait for the logical device to be idle!")
-        };
+        match unsafe { self.logical_device.device_wait_idle() } {
+            Ok(_) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+            Err(error) => panic!(
+                "Failed to wait for the logical device to be idle! Cause: {}",
+                error
+            ),
+        }
     }
 
     pub fn update_viewport(&self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+        self.set_viewport(
+            command_buffer,
+            vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+        );
+    }
+
+    // Restricts rendering to a region of the framebuffer, used to split the screen between cameras
+    pub fn set_viewport(&self, command_buffer: vk::CommandBuffer, region: vk::Rect2D) {
         let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: extent.width as _,
-            height: extent.height as _,
+            x: region.offset.x as _,
+            y: region.offset.y as _,
+            width: region.extent.width as _,
+            height: region.extent.height as _,
             min_depth: 0.0,
             max_depth: 1.0,
         };
         let viewports = [viewport];
-
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent,
-        };
-        let scissors = [scissor];
+        let scissors = [region];
 
         unsafe {
             self.logical_device
@@ -86,17 +98,62 @@ impl LogicalDevice {
         &self,
         command_buffer: vk::CommandBuffer,
         usage: vk::CommandBufferUsageFlags,
-        mut action: T,
+        action: T,
     ) where
         T: FnMut(),
     {
         let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder().flags(usage).build();
+        Self::record_with_begin_info(
+            &self.logical_device,
+            command_buffer,
+            &command_buffer_begin_info,
+            action,
+        );
+    }
 
-        let device = &self.logical_device;
+    // Secondary command buffers recorded here are executed inside the first subpass of the render pass
+    pub fn record_secondary_command_buffer<T>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        action: T,
+    ) where
+        T: FnMut(),
+    {
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(render_pass)
+            .subpass(0)
+            .framebuffer(framebuffer)
+            .build();
+
+        let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            )
+            .inheritance_info(&inheritance_info)
+            .build();
+
+        Self::record_with_begin_info(
+            &self.logical_device,
+            command_buffer,
+            &command_buffer_begin_info,
+            action,
+        );
+    }
 
+    fn record_with_begin_info<T>(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        command_buffer_begin_info: &vk::CommandBufferBeginInfo,
+        mut action: T,
+    ) where
+        T: FnMut(),
+    {
         unsafe {
             device
-                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                .begin_command_buffer(command_buffer, command_buffer_begin_info)
                 .expect("Failed to begin command buffer for the render pass!")
         };
 
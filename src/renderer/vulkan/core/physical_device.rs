@@ -2,7 +2,79 @@ use crate::renderer::vulkan::core::{DebugLayer, Instance, QueueFamilyIndexSet, S
 use anyhow::Result;
 use ash::{version::InstanceV1_0, vk};
 use log::info;
-use std::ffi::CStr;
+use std::{env, ffi::CStr, fmt};
+
+// A physical device that was considered when picking one to render with
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate {
+    // The device's position in enumeration order
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    // Why the device can't be used, if it can't
+    pub rejection: Option<String>,
+}
+
+impl fmt::Display for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({:?})", self.index, self.name, self.device_type)?;
+        if let Some(rejection) = self.rejection.as_ref() {
+            write!(f, " - {}", rejection)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceSelectionError {
+    // Every enumerated device was rejected, or none were enumerated at all
+    NoSuitableDevice(Vec<DeviceCandidate>),
+    // The device index override is out of range or not a number
+    InvalidOverride {
+        value: String,
+        number_of_devices: usize,
+    },
+    // The device index override points at a device that was rejected
+    UnsuitableOverride(DeviceCandidate),
+}
+
+impl fmt::Display for DeviceSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoSuitableDevice(candidates) if candidates.is_empty() => {
+                write!(
+                    f,
+                    "Failed to find a physical device, no vulkan devices were enumerated!"
+                )
+            }
+            Self::NoSuitableDevice(candidates) => {
+                write!(f, "Failed to find a suitable physical device!")?;
+                for candidate in candidates.iter() {
+                    write!(f, "\n    {}", candidate)?;
+                }
+                Ok(())
+            }
+            Self::InvalidOverride {
+                value,
+                number_of_devices,
+            } => write!(
+                f,
+                "{} is set to '{}', expected a device index below {}!",
+                PhysicalDevice::INDEX_OVERRIDE_VARIABLE,
+                value,
+                number_of_devices
+            ),
+            Self::UnsuitableOverride(candidate) => write!(
+                f,
+                "The physical device selected with {} is not suitable: {}!",
+                PhysicalDevice::INDEX_OVERRIDE_VARIABLE,
+                candidate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeviceSelectionError {}
 
 // The order of the struct fields
 // here matter because it determines drop order
@@ -14,8 +86,13 @@ pub struct PhysicalDevice {
 }
 
 impl PhysicalDevice {
-    pub fn new(instance: &Instance, surface: &Surface) -> Result<Self> {
-        let physical_device = Self::pick_physical_device(instance.instance(), surface);
+    // Forces the device at this index, in enumeration order, to be used on multi-gpu machines
+    pub const INDEX_OVERRIDE_VARIABLE: &'static str = "DRAGONGLASS_DEVICE_INDEX";
+
+    // Fails with a `DeviceSelectionError` when no device can be used
+    pub fn new(instance: &Instance, surface: Option<&Surface>) -> Result<Self> {
+        let (physical_device, queue_family_index_set) =
+            Self::pick_physical_device(instance.instance(), surface)?;
         let physical_device_memory_properties = unsafe {
             instance
                 .instance()
@@ -23,12 +100,6 @@ impl PhysicalDevice {
         };
         let debug_layer = DebugLayer::new(instance)?;
 
-        // TODO: This is called twice on the physical device that is deemed suitable.
-        // reduce it to one call, storing the set on the first pass
-        let queue_family_index_set =
-            QueueFamilyIndexSet::new(instance.instance(), physical_device, surface)
-                .expect("Failed to create queue family index set!");
-
         Ok(Self {
             physical_device,
             physical_device_memory_properties,
@@ -49,46 +120,118 @@ impl PhysicalDevice {
         &self.queue_family_index_set
     }
 
+    // Discrete gpus are preferred, falling back to integrated, virtual and cpu devices in that order.
+    // Devices of the same type are picked in enumeration order.
     fn pick_physical_device(
         instance: &ash::Instance,
-        surface: &Surface,
-    ) -> ash::vk::PhysicalDevice {
-        // Pick a physical device
-        let devices = unsafe {
-            instance
-                .enumerate_physical_devices()
-                .expect("Couldn't get physical devices")
+        surface: Option<&Surface>,
+    ) -> Result<(vk::PhysicalDevice, QueueFamilyIndexSet)> {
+        let devices = unsafe { instance.enumerate_physical_devices()? };
+
+        let index_override = match env::var(Self::INDEX_OVERRIDE_VARIABLE) {
+            Ok(value) => match value.trim().parse::<usize>() {
+                Ok(index) if index < devices.len() => Some(index),
+                _ => {
+                    return Err(DeviceSelectionError::InvalidOverride {
+                        value,
+                        number_of_devices: devices.len(),
+                    }
+                    .into())
+                }
+            },
+            Err(_) => None,
         };
 
-        // Pick the first suitable physical device
-        let physical_device = devices
-            .into_iter()
-            .find(|physical_device| {
-                Self::is_physical_device_suitable(instance, *physical_device, surface)
-            })
-            .expect("Failed to find a suitable physical device");
+        let mut candidates = Vec::new();
+        let mut suitable_devices = Vec::new();
+        for (index, physical_device) in devices.into_iter().enumerate() {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            let suitability = Self::suitability(instance, physical_device, surface);
+            let rejection = suitability.as_ref().err().cloned();
+            info!(
+                "Found physical device {}: {} ({:?})",
+                index, name, properties.device_type
+            );
+            if let Ok(queue_family_index_set) = suitability {
+                suitable_devices.push((
+                    index,
+                    properties.device_type,
+                    physical_device,
+                    queue_family_index_set,
+                ));
+            }
+            candidates.push(DeviceCandidate {
+                index,
+                name,
+                device_type: properties.device_type,
+                rejection,
+            });
+        }
 
-        // Log the name of the physical device that was selected
-        let props = unsafe { instance.get_physical_device_properties(physical_device) };
-        info!("Selected physical device: {:?}", unsafe {
-            CStr::from_ptr(props.device_name.as_ptr())
-        });
+        let selected = match index_override {
+            Some(index) => {
+                let selected = suitable_devices
+                    .into_iter()
+                    .find(|(suitable_index, ..)| *suitable_index == index);
+                if selected.is_none() {
+                    let candidate = candidates.swap_remove(index);
+                    return Err(DeviceSelectionError::UnsuitableOverride(candidate).into());
+                }
+                selected
+            }
+            None => suitable_devices
+                .into_iter()
+                .min_by_key(|(_, device_type, ..)| Self::device_type_rank(*device_type)),
+        };
+
+        let (index, _, physical_device, queue_family_index_set) =
+            selected.ok_or_else(|| DeviceSelectionError::NoSuitableDevice(candidates.clone()))?;
+        info!("Selected physical device: {}", candidates[index]);
+
+        Ok((physical_device, queue_family_index_set))
+    }
 
-        physical_device
+    fn device_type_rank(device_type: vk::PhysicalDeviceType) -> u32 {
+        match device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 3,
+            _ => 4,
+        }
     }
 
-    // TODO: Refactor this to use less parameters
-    fn is_physical_device_suitable(
+    // Returns the queue families to use, or why the device can't be used
+    fn suitability(
         instance: &ash::Instance,
+        physical_device: ash::vk::PhysicalDevice,
+        surface: Option<&Surface>,
+    ) -> std::result::Result<QueueFamilyIndexSet, String> {
+        let queue_family_index_set = QueueFamilyIndexSet::new(instance, physical_device, surface)?;
+
+        // A headless context has no swapchain to support.
+        // Anisotropic filtering is optional and disabled when unsupported.
+        if let Some(surface) = surface {
+            Self::check_swapchain_support(physical_device, surface)?;
+        }
+
+        //FIXME: check features.robust_buffer_access == vk::TRUE
+        Ok(queue_family_index_set)
+    }
+
+    fn check_swapchain_support(
         physical_device: ash::vk::PhysicalDevice,
         surface: &Surface,
-    ) -> bool {
+    ) -> std::result::Result<(), String> {
         // Get the supported surface formats
         let formats = unsafe {
             surface
                 .surface()
                 .get_physical_device_surface_formats(physical_device, surface.surface_khr())
-                .expect("Failed to get physical device surface formats")
+                .map_err(|error| format!("failed to query the surface formats: {}", error))?
         };
 
         // Get the supported present modes
@@ -96,18 +239,16 @@ impl PhysicalDevice {
             surface
                 .surface()
                 .get_physical_device_surface_present_modes(physical_device, surface.surface_khr())
-                .expect("Failed to get physical device surface present modes")
+                .map_err(|error| format!("failed to query the surface present modes: {}", error))?
         };
 
-        let features = unsafe { instance.get_physical_device_features(physical_device) };
-
-        let queue_family_index_set = QueueFamilyIndexSet::new(instance, physical_device, surface);
-        let swapchain_adequate = !formats.is_empty() && !present_modes.is_empty();
-
-        queue_family_index_set.is_some()
-            && swapchain_adequate
-            && features.sampler_anisotropy == vk::TRUE
-        //FIXME: && features.robust_buffer_access == vk::TRUE
+        if formats.is_empty() {
+            return Err("the surface has no supported formats".to_string());
+        }
+        if present_modes.is_empty() {
+            return Err("the surface has no supported present modes".to_string());
+        }
+        Ok(())
     }
 
     pub fn build_queue_creation_info_list(&self) -> Vec<vk::DeviceQueueCreateInfo> {
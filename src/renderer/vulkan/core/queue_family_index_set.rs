@@ -4,14 +4,17 @@ use ash::{version::InstanceV1_0, vk};
 pub struct QueueFamilyIndexSet {
     graphics_queue_family_index: u32,
     present_queue_family_index: u32,
+    // A family that supports transfers but not graphics, usually backed by a dma engine
+    transfer_queue_family_index: Option<u32>,
 }
 
 impl QueueFamilyIndexSet {
+    // Fails with the reason the device has no usable queue families
     pub fn new(
         instance: &ash::Instance,
         physical_device: ash::vk::PhysicalDevice,
-        surface: &Surface,
-    ) -> Option<Self> {
+        surface: Option<&Surface>,
+    ) -> Result<Self, String> {
         // According to the Vulkan spec, the present queue
         // and graphics queue are not guaranteed to have the same index
         let mut graphics_queue_family_index = None;
@@ -20,7 +23,12 @@ impl QueueFamilyIndexSet {
         let properties =
             unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
 
-        for (index, family) in properties.iter().filter(|f| f.queue_count > 0).enumerate() {
+        // Families are indexed by their position before filtering out the empty ones
+        for (index, family) in properties
+            .iter()
+            .enumerate()
+            .filter(|(_, family)| family.queue_count > 0)
+        {
             let index = index as u32;
 
             // Check for a graphics queue
@@ -30,16 +38,22 @@ impl QueueFamilyIndexSet {
                 graphics_queue_family_index = Some(index);
             }
 
-            // Check for a present queue
-            let present_support = unsafe {
-                surface
-                    .surface()
-                    .get_physical_device_surface_support(
-                        physical_device,
-                        index,
-                        surface.surface_khr(),
-                    )
-                    .expect("Failed to get physical device surface support!")
+            // Check for a present queue.
+            // Without a surface nothing is presented, so the graphics queue is used.
+            let present_support = match surface {
+                Some(surface) => unsafe {
+                    surface
+                        .surface()
+                        .get_physical_device_surface_support(
+                            physical_device,
+                            index,
+                            surface.surface_khr(),
+                        )
+                        .map_err(|error| {
+                            format!("failed to query the surface support: {}", error)
+                        })?
+                },
+                None => family.queue_flags.contains(vk::QueueFlags::GRAPHICS),
             };
 
             if present_support && present_queue_family_index.is_none() {
@@ -51,15 +65,27 @@ impl QueueFamilyIndexSet {
             }
         }
 
-        if graphics_queue_family_index.is_none() || present_queue_family_index.is_none() {
-            return None;
-        }
+        // Families that don't support compute either are the most likely to be dedicated
+        let transfer_queue_family_index = properties
+            .iter()
+            .enumerate()
+            .filter(|(_, family)| {
+                family.queue_count > 0
+                    && family.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .min_by_key(|(_, family)| family.queue_flags.contains(vk::QueueFlags::COMPUTE))
+            .map(|(index, _)| index as u32);
+
+        let graphics_queue_family_index = graphics_queue_family_index
+            .ok_or_else(|| "no queue family supports graphics".to_string())?;
+        let present_queue_family_index = present_queue_family_index
+            .ok_or_else(|| "no queue family can present to the surface".to_string())?;
 
-        Some(QueueFamilyIndexSet {
-            graphics_queue_family_index: graphics_queue_family_index
-                .expect("Failed to get graphics queue family index!"),
-            present_queue_family_index: present_queue_family_index
-                .expect("Failed to get present queue family index!"),
+        Ok(QueueFamilyIndexSet {
+            graphics_queue_family_index,
+            present_queue_family_index,
+            transfer_queue_family_index,
         })
     }
 
@@ -71,6 +97,10 @@ impl QueueFamilyIndexSet {
         self.present_queue_family_index
     }
 
+    pub fn transfer_queue_family_index(&self) -> Option<u32> {
+        self.transfer_queue_family_index
+    }
+
     pub fn indices(&self) -> Vec<u32> {
         // The queue family indices need to be deduplicated because
         // Vulkan does not allow passing an array containing duplicated family
@@ -80,6 +110,8 @@ impl QueueFamilyIndexSet {
             self.graphics_queue_family_index,
             self.present_queue_family_index,
         ];
+        queue_family_indices.extend(self.transfer_queue_family_index);
+        queue_family_indices.sort();
         queue_family_indices.dedup();
         queue_family_indices
     }
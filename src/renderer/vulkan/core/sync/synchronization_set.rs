@@ -1,15 +1,19 @@
 use crate::renderer::vulkan::core::{Fence, Semaphore, VulkanContext};
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use ash::vk;
 use std::sync::Arc;
 
 pub trait SynchronizationSetConstants {
-    // The maximum number of frames that can be rendered simultaneously
+    // The maximum number of frames that can be rendered simultaneously.
+    // Per-frame buffers and descriptor sets are created for the configured number of frames.
     const MAX_FRAMES_IN_FLIGHT: u32;
+
+    const DEFAULT_FRAMES_IN_FLIGHT: u32;
 }
 
 impl SynchronizationSetConstants for SynchronizationSet {
-    const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+    const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+    const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
 }
 
 pub struct SynchronizationSet {
@@ -19,11 +23,22 @@ pub struct SynchronizationSet {
 }
 
 impl SynchronizationSet {
-    pub fn new(context: Arc<VulkanContext>) -> Result<Self> {
+    // Each frame in flight lets the cpu record a frame while the gpu renders earlier ones.
+    // A single frame has the lowest latency, since the cpu waits for every frame to finish,
+    // which also makes captures and debugging easier to follow.
+    // More frames keep the gpu busy for higher throughput, at the cost of input latency.
+    pub fn new(context: Arc<VulkanContext>, frames_in_flight: u32) -> Result<Self> {
+        ensure!(
+            (1..=Self::MAX_FRAMES_IN_FLIGHT).contains(&frames_in_flight),
+            "Frames in flight must be between 1 and {}, but was {}!",
+            Self::MAX_FRAMES_IN_FLIGHT,
+            frames_in_flight
+        );
+
         let mut image_available_semaphores = Vec::new();
         let mut render_finished_semaphores = Vec::new();
         let mut in_flight_fences = Vec::new();
-        for _ in 0..SynchronizationSet::MAX_FRAMES_IN_FLIGHT {
+        for _ in 0..frames_in_flight {
             let image_available_semaphore = Semaphore::new(context.clone())?;
             image_available_semaphores.push(image_available_semaphore);
 
@@ -41,6 +56,10 @@ impl SynchronizationSet {
         })
     }
 
+    pub fn frames_in_flight(&self) -> usize {
+        self.in_flight_fences.len()
+    }
+
     pub fn current_frame_synchronization(
         &self,
         current_frame: usize,
@@ -7,13 +7,15 @@ use crate::renderer::{
             RenderPipelineSettingsBuilder,
         },
         resource::{
-            CommandPool, GeometryBuffer, ShaderCache, ShaderPathSetBuilder, TextureBundle,
-            TextureDescription,
+            image::{ImageView, Sampler},
+            CommandPool, DynamicGeometryBuffer, SamplerSettings, ShaderCache, ShaderPathSetBuilder,
+            TextureBundle, TextureDescription,
         },
     },
 };
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
-use imgui::{Context, DrawCmd, DrawCmdParams, DrawData};
+use imgui::{Context, DrawCmd, DrawCmdParams, DrawData, TextureId, Textures};
 use log::{debug, warn};
 use nalgebra_glm as glm;
 use std::{mem, sync::Arc};
@@ -24,28 +26,33 @@ pub struct PushConstantBlockGui {
 
 pub struct GuiRenderer {
     pub context: Arc<VulkanContext>,
-    pub descriptor_set: vk::DescriptorSet,
+    // The font atlas is the first texture, others can be registered to draw them as images
+    pub textures: Textures<vk::DescriptorSet>,
     pub descriptor_set_layout: Arc<DescriptorSetLayout>,
     pub descriptor_pool: DescriptorPool,
     pub font_texture: TextureBundle,
     pub pipeline: Option<RenderPipeline>,
-    pub geometry_buffer: Option<GeometryBuffer>,
+    pub geometry_buffer: DynamicGeometryBuffer,
 }
 
 impl GuiRenderer {
+    // Including the font atlas
+    pub const MAX_TEXTURES: u32 = 16;
+
     pub fn new(
         context: Arc<VulkanContext>,
         shader_cache: &mut ShaderCache,
         render_pass: Arc<RenderPass>,
         imgui: &mut Context,
         command_pool: &CommandPool,
-    ) -> Self {
+    ) -> Result<Self> {
         debug!("Creating gui renderer");
         let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
         let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
+        let font_descriptor_set =
+            descriptor_pool.allocate_descriptor_sets(descriptor_set_layout.layout(), 1)?[0];
+        let mut textures = Textures::new();
+        imgui.fonts().tex_id = textures.insert(font_descriptor_set);
 
         // TODO: Move texture loading out of this class
         let font_texture = {
@@ -57,35 +64,67 @@ impl GuiRenderer {
                 height: atlas_texture.height,
                 mip_levels: 1,
                 pixels: atlas_texture.data.to_vec(),
+                is_srgb: false,
+                mip_sizes: Vec::new(),
             };
 
-            TextureBundle::new(context.clone(), &command_pool, &atlas_texture_description).unwrap()
+            TextureBundle::new(
+                context.clone(),
+                command_pool,
+                &atlas_texture_description,
+                &SamplerSettings::default(),
+            )?
         };
 
-        Self::update_descriptor_set(context.clone(), descriptor_set, &font_texture);
+        let geometry_buffer = DynamicGeometryBuffer::new(context.clone())?;
 
         let mut gui_renderer = Self {
             context,
-            descriptor_set,
+            textures,
             descriptor_set_layout,
             descriptor_pool,
             font_texture,
             pipeline: None,
-            geometry_buffer: None,
+            geometry_buffer,
         };
-        gui_renderer.recreate_pipeline(shader_cache, render_pass);
-        gui_renderer
+        gui_renderer.update_descriptor_set(
+            font_descriptor_set,
+            &gui_renderer.font_texture.view,
+            &gui_renderer.font_texture.sampler,
+        );
+        gui_renderer.recreate_pipeline(shader_cache, render_pass)?;
+
+        Ok(gui_renderer)
+    }
+
+    // The image has to be in the shader read only layout whenever the gui is drawn
+    pub fn register_texture(&mut self, view: &ImageView, sampler: &Sampler) -> Result<TextureId> {
+        let descriptor_set = self
+            .descriptor_pool
+            .allocate_descriptor_sets(self.descriptor_set_layout.layout(), 1)?[0];
+        self.update_descriptor_set(descriptor_set, view, sampler);
+        Ok(self.textures.insert(descriptor_set))
+    }
+
+    // Points a registered texture at a new image, such as after its render target is recreated.
+    // The descriptor set must not be in use by a pending command buffer.
+    pub fn replace_texture(&mut self, texture_id: TextureId, view: &ImageView, sampler: &Sampler) {
+        match self.textures.get(texture_id) {
+            Some(descriptor_set) => self.update_descriptor_set(*descriptor_set, view, sampler),
+            None => warn!("Gui texture {} is not registered!", texture_id.id()),
+        }
     }
 
     fn update_descriptor_set(
-        context: Arc<VulkanContext>,
+        &self,
         descriptor_set: vk::DescriptorSet,
-        texture: &TextureBundle,
+        view: &ImageView,
+        sampler: &Sampler,
     ) {
         let image_info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(texture.view.view())
-            .sampler(texture.sampler.sampler())
+            .image_view(view.view())
+            .sampler(sampler.sampler())
             .build();
         let image_infos = [image_info];
 
@@ -100,7 +139,7 @@ impl GuiRenderer {
         let descriptor_writes = [sampler_descriptor_write];
 
         unsafe {
-            context
+            self.context
                 .logical_device()
                 .logical_device()
                 .update_descriptor_sets(&descriptor_writes, &[])
@@ -111,7 +150,7 @@ impl GuiRenderer {
         &mut self,
         shader_cache: &mut ShaderCache,
         render_pass: Arc<RenderPass>,
-    ) {
+    ) -> Result<()> {
         debug!("Recreating gui pipeline");
         let descriptions = Self::vertex_input_descriptions();
         let attributes = Self::vertex_attributes();
@@ -131,9 +170,7 @@ impl GuiRenderer {
             .build()
             .unwrap();
 
-        let shader_set = shader_cache
-            .create_shader_set(self.context.clone(), &shader_paths)
-            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
 
         let settings = RenderPipelineSettingsBuilder::default()
             .render_pass(render_pass.clone())
@@ -148,8 +185,11 @@ impl GuiRenderer {
             .build()
             .expect("Failed to create render pipeline settings");
 
-        let pipeline = RenderPipeline::new(self.context.clone(), settings.clone());
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Gui");
         self.pipeline = Some(pipeline);
+        Ok(())
     }
 
     pub fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
@@ -172,14 +212,14 @@ impl GuiRenderer {
     fn create_descriptor_pool(context: Arc<VulkanContext>) -> DescriptorPool {
         let sampler_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
+            descriptor_count: Self::MAX_TEXTURES,
         };
 
         let pool_sizes = [sampler_pool_size];
 
         let pool_info = vk::DescriptorPoolCreateInfo::builder()
             .pool_sizes(&pool_sizes)
-            .max_sets(1)
+            .max_sets(Self::MAX_TEXTURES)
             .build();
 
         DescriptorPool::new(context, pool_info).unwrap()
@@ -224,7 +264,7 @@ impl GuiRenderer {
         [vertex_input_binding_description]
     }
 
-    fn resize_geometry_buffer(command_pool: &CommandPool, draw_data: &DrawData) -> GeometryBuffer {
+    fn update_geometry_buffer(&mut self, draw_data: &DrawData) -> Result<()> {
         let vertices = draw_data
             .draw_lists()
             .flat_map(|draw_list| draw_list.vtx_buffer())
@@ -237,156 +277,135 @@ impl GuiRenderer {
             .map(|index| *index as u32)
             .collect::<Vec<_>>();
 
-        GeometryBuffer::new(&command_pool, &vertices, Some(&indices))
+        self.geometry_buffer.upload_vertices(&vertices)?;
+        self.geometry_buffer.upload_indices(&indices)?;
+        Ok(())
     }
 
-    pub fn issue_commands(
-        &mut self,
-        command_pool: &CommandPool,
-        command_buffer: vk::CommandBuffer,
-        draw_data: &DrawData,
-    ) {
+    pub fn issue_commands(&mut self, command_buffer: vk::CommandBuffer, draw_data: &DrawData) {
         if draw_data.total_vtx_count == 0 {
             return;
         }
 
+        self.update_geometry_buffer(draw_data)
+            .expect("Failed to update gui geometry buffer!");
+
         let device = self.context.logical_device();
 
-        // if self.geometry_buffer.is_none() {
-        self.geometry_buffer = None;
-        let resized_buffer = Self::resize_geometry_buffer(command_pool, draw_data);
-        self.geometry_buffer = Some(resized_buffer);
-        // }
-
-        // // FIXME: resize vertex and index buffers separately and append vertices
-        // if draw_data.total_vtx_count as u32
-        //     > self.geometry_buffer.as_ref().unwrap().number_of_vertices
-        // {
-        //     trace!("Resizing gui vertex buffer");
-        //     self.geometry_buffer = None;
-        //     let resized_buffer = Self::resize_geometry_buffer(command_pool, draw_data);
-        //     self.geometry_buffer = Some(resized_buffer);
-        // } else if draw_data.total_idx_count as u32
-        //     > self.geometry_buffer.as_ref().unwrap().number_of_indices
-        // {
-        //     trace!("Resizing gui index buffer");
-        //     self.geometry_buffer = None;
-        //     let resized_buffer = Self::resize_geometry_buffer(command_pool, draw_data);
-        //     self.geometry_buffer = Some(resized_buffer);
-        // }
-
-        if let Some(geometry_buffer) = self.geometry_buffer.as_mut() {
-            if let Some(pipeline) = self.pipeline.as_ref() {
-                pipeline.bind(device.logical_device(), command_buffer);
-
-                let framebuffer_width = draw_data.framebuffer_scale[0] * draw_data.display_size[0];
-                let framebuffer_height = draw_data.framebuffer_scale[1] * draw_data.display_size[1];
-
-                let projection =
-                    glm::ortho_zo(0.0, framebuffer_width, 0.0, framebuffer_height, -1.0, 1.0);
-
-                let viewport = vk::Viewport {
-                    width: framebuffer_width,
-                    height: framebuffer_height,
-                    max_depth: 1.0,
-                    ..Default::default()
-                };
-                let viewports = [viewport];
-
-                unsafe {
-                    device.logical_device().cmd_push_constants(
-                        command_buffer,
-                        pipeline.pipeline.layout(),
-                        vk::ShaderStageFlags::VERTEX,
-                        0,
-                        byte_slice_from(&PushConstantBlockGui { projection }),
-                    );
-                }
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) => pipeline,
+            None => {
+                warn!("No gui pipeline available");
+                return;
+            }
+        };
 
-                unsafe {
-                    device
-                        .logical_device()
-                        .cmd_set_viewport(command_buffer, 0, &viewports);
-                }
+        pipeline.bind(device.logical_device(), command_buffer);
+
+        let framebuffer_width = draw_data.framebuffer_scale[0] * draw_data.display_size[0];
+        let framebuffer_height = draw_data.framebuffer_scale[1] * draw_data.display_size[1];
+
+        let projection = glm::ortho_zo(0.0, framebuffer_width, 0.0, framebuffer_height, -1.0, 1.0);
+
+        let viewport = vk::Viewport {
+            width: framebuffer_width,
+            height: framebuffer_height,
+            max_depth: 1.0,
+            ..Default::default()
+        };
+        let viewports = [viewport];
+
+        unsafe {
+            device.logical_device().cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline.layout(),
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                byte_slice_from(&PushConstantBlockGui { projection }),
+            );
+        }
+
+        unsafe {
+            device
+                .logical_device()
+                .cmd_set_viewport(command_buffer, 0, &viewports);
+        }
 
-                geometry_buffer.bind(device.logical_device(), command_buffer);
-
-                // Render draw lists
-                // Adapted from: https://github.com/adrien-ben/imgui-rs-vulkan-renderer
-                let mut index_offset = 0;
-                let mut vertex_offset = 0;
-                let clip_offset = draw_data.display_pos;
-                let clip_scale = draw_data.framebuffer_scale;
-                for draw_list in draw_data.draw_lists() {
-                    for command in draw_list.commands() {
-                        match command {
-                            DrawCmd::Elements {
-                                count,
-                                cmd_params:
-                                    DrawCmdParams {
-                                        clip_rect,
-                                        texture_id: _texture_id,
-                                        vtx_offset,
-                                        idx_offset,
-                                    },
-                            } => {
-                                unsafe {
-                                    let clip_x = (clip_rect[0] - clip_offset[0]) * clip_scale[0];
-                                    let clip_y = (clip_rect[1] - clip_offset[1]) * clip_scale[1];
-                                    let clip_w =
-                                        (clip_rect[2] - clip_offset[0]) * clip_scale[0] - clip_x;
-                                    let clip_h =
-                                        (clip_rect[3] - clip_offset[1]) * clip_scale[1] - clip_y;
-                                    let scissors = [vk::Rect2D {
-                                        offset: vk::Offset2D {
-                                            x: clip_x as _,
-                                            y: clip_y as _,
-                                        },
-                                        extent: vk::Extent2D {
-                                            width: clip_w as _,
-                                            height: clip_h as _,
-                                        },
-                                    }];
-                                    device.logical_device().cmd_set_scissor(
-                                        command_buffer,
-                                        0,
-                                        &scissors,
-                                    );
-                                }
-
-                                // TODO: Create a map of texture ids to descriptor sets
-                                unsafe {
-                                    device.logical_device().cmd_bind_descriptor_sets(
-                                        command_buffer,
-                                        vk::PipelineBindPoint::GRAPHICS,
-                                        pipeline.pipeline.layout(),
-                                        0,
-                                        &[self.descriptor_set],
-                                        &[],
-                                    )
-                                };
-
-                                unsafe {
-                                    device.logical_device().cmd_draw_indexed(
-                                        command_buffer,
-                                        count as _,
-                                        1,
-                                        index_offset + idx_offset as u32,
-                                        vertex_offset + vtx_offset as i32,
-                                        0,
-                                    )
-                                };
-                            }
-                            _ => (),
+        self.geometry_buffer
+            .bind(device.logical_device(), command_buffer);
+
+        // Render draw lists
+        // Adapted from: https://github.com/adrien-ben/imgui-rs-vulkan-renderer
+        let mut index_offset = 0;
+        let mut vertex_offset = 0;
+        let clip_offset = draw_data.display_pos;
+        let clip_scale = draw_data.framebuffer_scale;
+        for draw_list in draw_data.draw_lists() {
+            for command in draw_list.commands() {
+                if let DrawCmd::Elements {
+                    count,
+                    cmd_params:
+                        DrawCmdParams {
+                            clip_rect,
+                            texture_id,
+                            vtx_offset,
+                            idx_offset,
+                        },
+                } = command
+                {
+                    let descriptor_set = match self.textures.get(texture_id) {
+                        Some(descriptor_set) => *descriptor_set,
+                        None => {
+                            warn!("Gui texture {} is not registered!", texture_id.id());
+                            continue;
                         }
+                    };
+
+                    unsafe {
+                        let clip_x = (clip_rect[0] - clip_offset[0]) * clip_scale[0];
+                        let clip_y = (clip_rect[1] - clip_offset[1]) * clip_scale[1];
+                        let clip_w = (clip_rect[2] - clip_offset[0]) * clip_scale[0] - clip_x;
+                        let clip_h = (clip_rect[3] - clip_offset[1]) * clip_scale[1] - clip_y;
+                        let scissors = [vk::Rect2D {
+                            offset: vk::Offset2D {
+                                x: clip_x as _,
+                                y: clip_y as _,
+                            },
+                            extent: vk::Extent2D {
+                                width: clip_w as _,
+                                height: clip_h as _,
+                            },
+                        }];
+                        device
+                            .logical_device()
+                            .cmd_set_scissor(command_buffer, 0, &scissors);
                     }
-                    index_offset += draw_list.idx_buffer().len() as u32;
-                    vertex_offset += draw_list.vtx_buffer().len() as i32;
+
+                    unsafe {
+                        device.logical_device().cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline.pipeline.layout(),
+                            0,
+                            &[descriptor_set],
+                            &[],
+                        )
+                    };
+
+                    unsafe {
+                        device.logical_device().cmd_draw_indexed(
+                            command_buffer,
+                            count as _,
+                            1,
+                            index_offset + idx_offset as u32,
+                            vertex_offset + vtx_offset as i32,
+                            0,
+                        )
+                    };
                 }
-            } else {
-                warn!("No gui pipeline available");
-                return;
             }
+            index_offset += draw_list.idx_buffer().len() as u32;
+            vertex_offset += draw_list.vtx_buffer().len() as i32;
         }
     }
 }
@@ -0,0 +1,357 @@
+use crate::renderer::{
+    byte_slice_from,
+    vulkan::{
+        core::VulkanContext,
+        render::{ComputePipeline, DescriptorPool, DescriptorSetLayout, PipelineLayout},
+        resource::{image::TextureBundle, Buffer, ShaderCache},
+    },
+    AutoExposureSettings,
+};
+use anyhow::Result;
+use ash::{version::DeviceV1_0, vk};
+use std::{mem, sync::Arc};
+
+// Written by the compute shader and read while post-processing.
+// The average luminance is zero until the first frame has been measured.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExposureState {
+    pub average_luminance: f32,
+    pub exposure: f32,
+}
+
+impl Default for ExposureState {
+    fn default() -> Self {
+        Self {
+            average_luminance: 0.0,
+            exposure: 1.0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExposureUniformBufferObject {
+    pub delta_time: f32,
+}
+
+#[allow(dead_code)]
+pub struct ExposurePushConstantBlock {
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    pub adaptation_speed: f32,
+    pub key_value: f32,
+}
+
+impl From<&AutoExposureSettings> for ExposurePushConstantBlock {
+    fn from(settings: &AutoExposureSettings) -> Self {
+        Self {
+            min_exposure: settings.min_exposure,
+            max_exposure: settings.max_exposure,
+            adaptation_speed: settings.adaptation_speed,
+            key_value: settings.key_value,
+        }
+    }
+}
+
+pub struct ExposureFrameData {
+    pub uniform_buffer: Buffer,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+// Measures the average log luminance of the offscreen color with a single compute workgroup,
+// and moves the exposure towards it a little every frame.
+// The exposure stays on the gpu, so the post-processing pass reads it from the state buffer.
+pub struct AutoExposure {
+    context: Arc<VulkanContext>,
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    _descriptor_pool: DescriptorPool,
+    state_buffer: Buffer,
+    frames: Vec<ExposureFrameData>,
+    pipeline: Option<ComputePipeline>,
+}
+
+impl AutoExposure {
+    pub fn new(
+        context: Arc<VulkanContext>,
+        color_texture: &TextureBundle,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone())?);
+        let descriptor_pool = Self::create_descriptor_pool(context.clone(), frames_in_flight)?;
+        let descriptor_sets = descriptor_pool
+            .allocate_descriptor_sets(descriptor_set_layout.layout(), frames_in_flight)?;
+
+        let state_buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            mem::size_of::<ExposureState>() as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )?;
+        state_buffer.upload_to_buffer(&[ExposureState::default()], 0)?;
+        context.set_debug_name(state_buffer.buffer(), "Exposure State");
+
+        let frames = descriptor_sets
+            .into_iter()
+            .map(|descriptor_set| {
+                let uniform_buffer = Buffer::new_mapped_basic(
+                    context.clone(),
+                    mem::size_of::<ExposureUniformBufferObject>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )?;
+                Self::update_descriptor_set(
+                    context.clone(),
+                    descriptor_set,
+                    color_texture,
+                    &state_buffer,
+                    &uniform_buffer,
+                );
+                Ok(ExposureFrameData {
+                    uniform_buffer,
+                    descriptor_set,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            context,
+            descriptor_set_layout,
+            _descriptor_pool: descriptor_pool,
+            state_buffer,
+            frames,
+            pipeline: None,
+        })
+    }
+
+    pub fn state_buffer(&self) -> &Buffer {
+        &self.state_buffer
+    }
+
+    fn descriptor_set_layout(context: Arc<VulkanContext>) -> Result<DescriptorSetLayout> {
+        let color_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let state_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(2)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let bindings = [color_binding, state_binding, ubo_binding];
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+        DescriptorSetLayout::new(context, layout_create_info)
+    }
+
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        frames_in_flight: u32,
+    ) -> Result<DescriptorPool> {
+        let sampler_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: frames_in_flight,
+        };
+        let storage_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: frames_in_flight,
+        };
+        let ubo_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: frames_in_flight,
+        };
+        let pool_sizes = [sampler_pool_size, storage_pool_size, ubo_pool_size];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight)
+            .build();
+
+        DescriptorPool::new(context, pool_info)
+    }
+
+    fn update_descriptor_set(
+        context: Arc<VulkanContext>,
+        descriptor_set: vk::DescriptorSet,
+        color_texture: &TextureBundle,
+        state_buffer: &Buffer,
+        uniform_buffer: &Buffer,
+    ) {
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(color_texture.view.view())
+            .sampler(color_texture.sampler.sampler())
+            .build();
+        let image_infos = [image_info];
+
+        let state_buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(state_buffer.buffer())
+            .offset(0)
+            .range(mem::size_of::<ExposureState>() as vk::DeviceSize)
+            .build();
+        let state_buffer_infos = [state_buffer_info];
+
+        let uniform_buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer())
+            .offset(0)
+            .range(mem::size_of::<ExposureUniformBufferObject>() as vk::DeviceSize)
+            .build();
+        let uniform_buffer_infos = [uniform_buffer_info];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&state_buffer_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&uniform_buffer_infos)
+                .build(),
+        ];
+
+        unsafe {
+            context
+                .logical_device()
+                .logical_device()
+                .update_descriptor_sets(&descriptor_writes, &[])
+        }
+    }
+
+    pub fn recreate_pipeline(&mut self, shader_cache: &mut ShaderCache) -> Result<()> {
+        let shader = shader_cache.add_shader(
+            self.context.clone(),
+            "assets/shaders/environment/auto_exposure.comp.spv",
+            vk::ShaderStageFlags::COMPUTE,
+        )?;
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<ExposurePushConstantBlock>() as u32)
+            .build();
+        let push_constant_ranges = [push_constant_range];
+        let descriptor_set_layouts = [self.descriptor_set_layout.layout()];
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .push_constant_ranges(&push_constant_ranges)
+            .set_layouts(&descriptor_set_layouts);
+        let pipeline_layout =
+            PipelineLayout::new(self.context.clone(), *pipeline_layout_create_info)?;
+
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(shader.state_info())
+            .layout(pipeline_layout.layout())
+            .build();
+
+        self.pipeline = None;
+        let pipeline = ComputePipeline::new(self.context.clone(), create_info, pipeline_layout)?;
+        self.context
+            .set_debug_name(pipeline.pipeline(), "Auto Exposure");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    // The delta time is uploaded every frame,
+    // since the commands are only recorded again when the scene changes
+    pub fn update(&self, frame: usize, delta_time: f32) -> Result<()> {
+        let ubo = ExposureUniformBufferObject { delta_time };
+        self.frames[frame]
+            .uniform_buffer
+            .upload_to_buffer(&[ubo], 0)
+    }
+
+    // Must be recorded outside of a render pass, after the offscreen color is rendered
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        settings: &AutoExposureSettings,
+        frame: usize,
+    ) {
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let device = self.context.logical_device().logical_device();
+        let push_constants = ExposurePushConstantBlock::from(settings);
+
+        // The previous frame may still be reading the state while post-processing
+        let measure_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::SHADER_WRITE,
+            )
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+        let exposure_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[measure_barrier],
+                &[],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout(),
+                0,
+                &[self.frames[frame].descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout(),
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                byte_slice_from(&push_constants),
+            );
+            device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[exposure_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+}
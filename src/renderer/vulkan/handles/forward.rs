@@ -1,22 +1,72 @@
-use crate::renderer::vulkan::{
-    core::VulkanContext,
-    handles::offscreen::Offscreen,
-    render::{
-        DescriptorPool, DescriptorSetLayout, Framebuffer, RenderPass, RenderPipeline,
-        RenderPipelineSettingsBuilder, Swapchain,
+use crate::{
+    camera::CameraProjection,
+    renderer::{
+        byte_slice_from,
+        vulkan::{
+            core::VulkanContext,
+            handles::{
+                exposure::{AutoExposure, ExposureState},
+                offscreen::Offscreen,
+            },
+            render::{
+                DescriptorPool, DescriptorSetLayout, Framebuffer, RenderPass, RenderPipeline,
+                RenderPipelineSettingsBuilder, Swapchain,
+            },
+            resource::{ShaderCache, ShaderPathSetBuilder},
+        },
+        RenderSettings,
     },
-    resource::{ShaderCache, ShaderPathSetBuilder},
 };
 use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
-use std::sync::Arc;
+use std::{mem, sync::Arc};
+
+#[allow(dead_code)]
+pub struct PostProcessPushConstantBlock {
+    pub fxaa_enabled: i32,
+    pub fxaa_subpixel: f32,
+    pub fxaa_edge_threshold: f32,
+    pub auto_exposure_enabled: i32,
+    pub exposure: f32,
+}
+
+impl From<&RenderSettings> for PostProcessPushConstantBlock {
+    fn from(render_settings: &RenderSettings) -> Self {
+        Self {
+            fxaa_enabled: render_settings.fxaa as i32,
+            fxaa_subpixel: render_settings.fxaa_subpixel,
+            fxaa_edge_threshold: render_settings.fxaa_edge_threshold,
+            auto_exposure_enabled: render_settings.auto_exposure as i32,
+            exposure: render_settings.exposure,
+        }
+    }
+}
+
+// Used to linearize the offscreen depth when it is displayed
+#[allow(dead_code)]
+pub struct DepthViewPushConstantBlock {
+    pub near: f32,
+    pub far: f32,
+}
+
+impl From<&CameraProjection> for DepthViewPushConstantBlock {
+    fn from(projection: &CameraProjection) -> Self {
+        Self {
+            near: projection.near,
+            far: projection.far,
+        }
+    }
+}
 
 // TODO: Rename to something related to post-processing
 pub struct ForwardRenderingHandles {
     pub offscreen: Offscreen,
+    pub auto_exposure: AutoExposure,
     pub render_pass: Arc<RenderPass>,
     pub framebuffers: Vec<Framebuffer>,
     pub pipeline: Option<RenderPipeline>, // TODO: Move some of the data to a separate struct
+    // Draws the offscreen depth instead of the color, for debugging
+    pub depth_pipeline: Option<RenderPipeline>,
     pub descriptor_set_layout: Arc<DescriptorSetLayout>,
     pub descriptor_set: vk::DescriptorSet,
     pub descriptor_pool: DescriptorPool,
@@ -24,27 +74,65 @@ pub struct ForwardRenderingHandles {
 }
 
 impl ForwardRenderingHandles {
-    pub fn new(context: Arc<VulkanContext>, swapchain: &Swapchain) -> Result<Self> {
+    // The sample count only applies to the offscreen scene pass,
+    // post-processing and the gui are always rendered single sampled
+    // The scene is rendered at the swapchain size scaled by the render scale
+    pub fn new(
+        context: Arc<VulkanContext>,
+        swapchain: &Swapchain,
+        samples: vk::SampleCountFlags,
+        render_scale: f32,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
         let format = swapchain.properties().format.format;
 
-        let render_pass = Arc::new(Self::create_render_pass(context.clone(), format));
+        let render_pass = Arc::new(Self::create_render_pass(
+            context.clone(),
+            format,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?);
+        context.set_debug_name(render_pass.render_pass(), "Present");
 
         let framebuffers = swapchain.create_framebuffers(context.clone(), render_pass.clone());
+        let offscreen_extent =
+            Offscreen::scaled_extent(&context, swapchain.properties().extent, render_scale);
 
-        let offscreen = Offscreen::new(context.clone())?;
+        Self::with_framebuffers(
+            context,
+            render_pass,
+            framebuffers,
+            offscreen_extent,
+            samples,
+            frames_in_flight,
+        )
+    }
 
-        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
-        let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
+    // The framebuffers must have been created with the given render pass
+    pub fn with_framebuffers(
+        context: Arc<VulkanContext>,
+        render_pass: Arc<RenderPass>,
+        framebuffers: Vec<Framebuffer>,
+        offscreen_extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let offscreen = Offscreen::new(context.clone(), offscreen_extent, samples)?;
+        let auto_exposure =
+            AutoExposure::new(context.clone(), &offscreen.color_texture, frames_in_flight)?;
+
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone())?);
+        let descriptor_pool = Self::create_descriptor_pool(context.clone())?;
+        let descriptor_set =
+            descriptor_pool.allocate_descriptor_sets(descriptor_set_layout.layout(), 1)?[0];
 
         let handles = Self {
             render_pass,
             offscreen,
+            auto_exposure,
             context,
             framebuffers,
             pipeline: None,
+            depth_pipeline: None,
             descriptor_set_layout,
             descriptor_set,
             descriptor_pool,
@@ -55,14 +143,19 @@ impl ForwardRenderingHandles {
         Ok(handles)
     }
 
-    fn create_render_pass(context: Arc<VulkanContext>, format: vk::Format) -> RenderPass {
+    // The final layout is the layout the color attachment is left in for presentation or readback
+    pub fn create_render_pass(
+        context: Arc<VulkanContext>,
+        format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> Result<RenderPass> {
         let color_attachment_description = vk::AttachmentDescription::builder()
             .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(final_layout)
             .build();
 
         let attachment_descriptions = [color_attachment_description];
@@ -110,61 +203,112 @@ impl ForwardRenderingHandles {
             .dependencies(&subpass_dependencies)
             .build();
 
-        RenderPass::new(context, &create_info).unwrap()
+        RenderPass::new(context, &create_info)
     }
 
-    pub fn recreate_pipeline(&mut self, shader_cache: &mut ShaderCache) {
+    pub fn recreate_pipeline(&mut self, shader_cache: &mut ShaderCache) -> Result<()> {
         let shader_paths = ShaderPathSetBuilder::default()
             .vertex("assets/shaders/environment/fullscreen_triangle.vert.spv")
             .fragment("assets/shaders/environment/post_process.frag.spv")
             .build()
             .unwrap();
-        let shader_set = shader_cache
-            .create_shader_set(self.context.clone(), &shader_paths)
-            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(mem::size_of::<PostProcessPushConstantBlock>() as u32)
+            .build();
 
         let settings = RenderPipelineSettingsBuilder::default()
             .render_pass(self.render_pass.clone())
             .vertex_state_info(vk::PipelineVertexInputStateCreateInfo::builder().build())
             .descriptor_set_layout(self.descriptor_set_layout.clone())
             .shader_set(shader_set)
+            .push_constant_range(push_constant_range)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        let depth_shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/environment/fullscreen_triangle.vert.spv")
+            .fragment("assets/shaders/environment/depth_view.frag.spv")
+            .build()
+            .unwrap();
+        let depth_shader_set =
+            shader_cache.create_shader_set(self.context.clone(), &depth_shader_paths)?;
+
+        let depth_push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(mem::size_of::<DepthViewPushConstantBlock>() as u32)
+            .build();
+
+        let depth_settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(self.render_pass.clone())
+            .vertex_state_info(vk::PipelineVertexInputStateCreateInfo::builder().build())
+            .descriptor_set_layout(self.descriptor_set_layout.clone())
+            .shader_set(depth_shader_set)
+            .push_constant_range(depth_push_constant_range)
             .build()
             .expect("Failed to create render pipeline settings");
 
         self.pipeline = None;
-        self.pipeline = Some(RenderPipeline::new(self.context.clone(), settings));
+        self.depth_pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Post Processing");
+        self.pipeline = Some(pipeline);
+        let depth_pipeline = RenderPipeline::new(self.context.clone(), depth_settings)?;
+        self.context
+            .set_debug_name(depth_pipeline.pipeline.pipeline(), "Depth View");
+        self.depth_pipeline = Some(depth_pipeline);
+
+        self.auto_exposure.recreate_pipeline(shader_cache)
     }
 
-    fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
+    fn descriptor_set_layout(context: Arc<VulkanContext>) -> Result<DescriptorSetLayout> {
         let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
             .binding(0)
             .descriptor_count(1)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build();
-        let bindings = [sampler_binding];
+        let depth_sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let exposure_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(2)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [sampler_binding, depth_sampler_binding, exposure_binding];
         let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
             .bindings(&bindings)
             .build();
-        let descriptor_set_layout =
-            DescriptorSetLayout::new(context.clone(), descriptor_set_layout_create_info).unwrap();
-        descriptor_set_layout
+        DescriptorSetLayout::new(context, descriptor_set_layout_create_info)
     }
 
-    fn create_descriptor_pool(context: Arc<VulkanContext>) -> DescriptorPool {
+    fn create_descriptor_pool(context: Arc<VulkanContext>) -> Result<DescriptorPool> {
         let sampler_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 2,
+        };
+
+        let storage_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
             descriptor_count: 1,
         };
 
-        let pool_sizes = [sampler_pool_size];
+        let pool_sizes = [sampler_pool_size, storage_pool_size];
 
         let pool_info = vk::DescriptorPoolCreateInfo::builder()
             .pool_sizes(&pool_sizes)
             .max_sets(1)
             .build();
 
-        DescriptorPool::new(context, pool_info).unwrap()
+        DescriptorPool::new(context, pool_info)
     }
 
     fn update_descriptor_set(&self) {
@@ -183,7 +327,46 @@ impl ForwardRenderingHandles {
             .image_info(&image_infos)
             .build();
 
-        let descriptor_writes = [sampler_descriptor_write];
+        let depth_image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(self.offscreen.depth_texture_view.view())
+            .sampler(self.offscreen.depth_sampler.sampler())
+            .build();
+        let depth_image_infos = [depth_image_info];
+
+        let depth_sampler_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&depth_image_infos)
+            .build();
+
+        let exposure_buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(self.auto_exposure.state_buffer().buffer())
+            .offset(0)
+            .range(mem::size_of::<ExposureState>() as vk::DeviceSize)
+            .build();
+        let exposure_buffer_infos = [exposure_buffer_info];
+
+        let exposure_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(2)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&exposure_buffer_infos)
+            .build();
+
+        // Multisampled depth isn't sampleable, so it is left unbound
+        let descriptor_writes = if self.offscreen.samples == vk::SampleCountFlags::TYPE_1 {
+            vec![
+                sampler_descriptor_write,
+                depth_sampler_descriptor_write,
+                exposure_descriptor_write,
+            ]
+        } else {
+            vec![sampler_descriptor_write, exposure_descriptor_write]
+        };
 
         unsafe {
             self.context
@@ -193,8 +376,20 @@ impl ForwardRenderingHandles {
         }
     }
 
-    pub fn issue_commands(&self, command_buffer: vk::CommandBuffer) {
+    // The depth is only shown when the offscreen targets are single sampled
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        render_settings: &RenderSettings,
+        projection: &CameraProjection,
+    ) {
+        if render_settings.show_depth && self.offscreen.samples == vk::SampleCountFlags::TYPE_1 {
+            self.issue_depth_commands(command_buffer, projection);
+            return;
+        }
+
         let device = self.context.logical_device().logical_device();
+        let push_constants = PostProcessPushConstantBlock::from(render_settings);
 
         if let Some(pipeline) = self.pipeline.as_ref() {
             pipeline.bind(device, command_buffer);
@@ -209,6 +404,48 @@ impl ForwardRenderingHandles {
                     &[],
                 );
 
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline.layout(),
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    byte_slice_from(&push_constants),
+                );
+
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            }
+        }
+    }
+
+    fn issue_depth_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        projection: &CameraProjection,
+    ) {
+        let device = self.context.logical_device().logical_device();
+        let push_constants = DepthViewPushConstantBlock::from(projection);
+
+        if let Some(pipeline) = self.depth_pipeline.as_ref() {
+            pipeline.bind(device, command_buffer);
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline.layout(),
+                    0,
+                    &[self.descriptor_set],
+                    &[],
+                );
+
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline.layout(),
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    byte_slice_from(&push_constants),
+                );
+
                 device.cmd_draw(command_buffer, 3, 1, 0, 0);
             }
         }
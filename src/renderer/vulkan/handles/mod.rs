@@ -1,4 +1,5 @@
 pub use self::{forward::*, offscreen::*};
 
+mod exposure;
 mod forward;
 mod offscreen;
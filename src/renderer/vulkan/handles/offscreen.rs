@@ -10,27 +10,53 @@ use std::sync::Arc;
 // TODO: Rename this to offscreen forward or something similar
 pub struct Offscreen {
     pub render_pass: Arc<RenderPass>,
+    // Continues rendering into the attachments left by the main render pass,
+    // used to draw transmissive primitives after the opaque scene color is copied
+    pub transmission_render_pass: Arc<RenderPass>,
     pub depth_texture: Texture,
+    // Only the depth aspect, for sampling
     pub depth_texture_view: ImageView,
+    // Both aspects, for the framebuffer. The stencil is used to outline the selection.
+    pub depth_stencil_view: ImageView,
+    // Nearest filtering, since linear filtering of depth formats isn't always supported
+    pub depth_sampler: Arc<Sampler>,
     pub framebuffer: Framebuffer,
+    // Single sampled, this is what the post-processing pass samples from
     pub color_texture: TextureBundle,
+    // Only present when multisampling, resolved into the color texture
+    pub multisample_texture: Option<Texture>,
+    pub multisample_texture_view: Option<ImageView>,
+    pub samples: vk::SampleCountFlags,
+    pub extent: vk::Extent2D,
 }
 
 impl Offscreen {
-    pub const DIMENSION: u32 = 2048;
-    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+    // High dynamic range, the exposure is applied while post-processing
+    pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
 
-    pub fn new(context: Arc<VulkanContext>) -> Result<Self> {
-        let texture = Self::create_texture(context.clone(), Self::DIMENSION, Self::FORMAT);
-        let view = Self::create_image_view(context.clone(), &texture, Self::FORMAT);
-        let sampler = Self::create_sampler(context.clone());
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+    pub fn new(
+        context: Arc<VulkanContext>,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let texture = Self::create_texture(
+            context.clone(),
+            extent,
+            Self::FORMAT,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+        let view = Self::create_image_view(context.clone(), &texture, Self::FORMAT)?;
+        let sampler = Self::create_sampler(context.clone())?;
         let color_texture = TextureBundle {
             texture,
             view,
             sampler,
         };
 
-        let depth_format = context.determine_depth_format(
+        let depth_format = context.determine_depth_stencil_format(
             vk::ImageTiling::OPTIMAL,
             vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
         );
@@ -39,73 +65,200 @@ impl Offscreen {
             context.clone(),
             Self::FORMAT,
             depth_format,
-        ));
+            samples,
+            false,
+        )?);
+        let transmission_render_pass = Arc::new(Self::create_render_pass(
+            context.clone(),
+            Self::FORMAT,
+            depth_format,
+            samples,
+            true,
+        )?);
+        context.set_debug_name(render_pass.render_pass(), "Offscreen");
+        context.set_debug_name(
+            transmission_render_pass.render_pass(),
+            "Offscreen Transmission",
+        );
 
-        let extent = vk::Extent2D::builder()
-            .width(Self::DIMENSION)
-            .height(Self::DIMENSION)
-            .build();
+        let depth_texture =
+            Self::create_depth_texture(context.clone(), extent, depth_format, samples)?;
+        let depth_texture_view = Self::create_depth_texture_view(
+            context.clone(),
+            &depth_texture,
+            depth_format,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+        let depth_stencil_view = Self::create_depth_texture_view(
+            context.clone(),
+            &depth_texture,
+            depth_format,
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        )?;
+        let depth_sampler = Self::create_depth_sampler(context.clone())?;
 
-        let depth_texture = Self::create_depth_texture(context.clone(), extent, depth_format);
-        let depth_texture_view =
-            Self::create_depth_texture_view(context.clone(), &depth_texture, depth_format);
+        let (multisample_texture, multisample_texture_view) =
+            if samples == vk::SampleCountFlags::TYPE_1 {
+                (None, None)
+            } else {
+                let texture = Self::create_texture(context.clone(), extent, Self::FORMAT, samples)?;
+                let view = Self::create_image_view(context.clone(), &texture, Self::FORMAT)?;
+                (Some(texture), Some(view))
+            };
 
-        let attachments = [color_texture.view.view(), depth_texture_view.view()];
+        let attachments = match multisample_texture_view.as_ref() {
+            Some(multisample_view) => vec![
+                multisample_view.view(),
+                depth_stencil_view.view(),
+                color_texture.view.view(),
+            ],
+            None => vec![color_texture.view.view(), depth_stencil_view.view()],
+        };
         let create_info = vk::FramebufferCreateInfo::builder()
             .render_pass(render_pass.render_pass())
             .attachments(&attachments)
-            .width(Self::DIMENSION)
-            .height(Self::DIMENSION)
+            .width(extent.width)
+            .height(extent.height)
             .layers(1)
             .build();
-        let framebuffer = Framebuffer::new(context.clone(), create_info).unwrap();
+        let framebuffer = Framebuffer::new(context.clone(), create_info)?;
 
         let handles = Offscreen {
             render_pass,
+            transmission_render_pass,
             depth_texture,
             depth_texture_view,
+            depth_stencil_view,
+            depth_sampler,
             framebuffer,
             color_texture,
+            multisample_texture,
+            multisample_texture_view,
+            samples,
+            extent,
         };
+        handles.set_debug_names(&context);
 
         Ok(handles)
     }
 
-    pub fn extent() -> vk::Extent2D {
+    fn set_debug_names(&self, context: &VulkanContext) {
+        context.set_debug_name(self.color_texture.texture.image(), "Offscreen Color");
+        context.set_debug_name(self.depth_texture.image(), "Offscreen Depth");
+        context.set_debug_name(
+            self.depth_stencil_view.view(),
+            "Offscreen Depth Stencil View",
+        );
+        if let (Some(texture), Some(view)) = (
+            self.multisample_texture.as_ref(),
+            self.multisample_texture_view.as_ref(),
+        ) {
+            context.set_debug_name(texture.image(), "Offscreen Multisample Color");
+            context.set_debug_name(view.view(), "Offscreen Multisample Color View");
+        }
+    }
+
+    // The size the scene is rendered at for a window, before post-processing rescales it.
+    // The render scale is clamped, as is the size to what the device can render to.
+    pub fn scaled_extent(
+        context: &VulkanContext,
+        extent: vk::Extent2D,
+        render_scale: f32,
+    ) -> vk::Extent2D {
+        let render_scale = render_scale.clamp(Self::MIN_RENDER_SCALE, Self::MAX_RENDER_SCALE);
+        let limits = context.physical_device_properties().limits;
+        let scale = |dimension: u32, max: u32| {
+            ((dimension as f32 * render_scale).round() as u32)
+                .max(1)
+                .min(max)
+        };
         vk::Extent2D {
-            width: Self::DIMENSION,
-            height: Self::DIMENSION,
+            width: scale(
+                extent.width,
+                limits
+                    .max_image_dimension2_d
+                    .min(limits.max_framebuffer_width),
+            ),
+            height: scale(
+                extent.height,
+                limits
+                    .max_image_dimension2_d
+                    .min(limits.max_framebuffer_height),
+            ),
         }
     }
 
+    // Both render passes are compatible, so they share the framebuffer and pipelines
     fn create_render_pass(
         context: Arc<VulkanContext>,
         format: vk::Format,
         depth_format: vk::Format,
-    ) -> RenderPass {
+        samples: vk::SampleCountFlags,
+        load_previous: bool,
+    ) -> Result<RenderPass> {
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+
+        // When multisampling, the color attachment is resolved
+        // into a single sampled attachment at the end of the subpass.
+        // The multisampled color and depth are always stored, since the transmission,
+        // outline and debug shapes passes each continue from the pass before them.
+        let color_layout = if multisampled {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        };
+
+        // Depth is left readable so it can be displayed for debugging
+        let depth_layout = vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL;
+
+        let (load_op, color_initial_layout, depth_initial_layout) = if load_previous {
+            (vk::AttachmentLoadOp::LOAD, color_layout, depth_layout)
+        } else {
+            (
+                vk::AttachmentLoadOp::CLEAR,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::UNDEFINED,
+            )
+        };
+
         let color_attachment_description = vk::AttachmentDescription::builder()
             .format(format)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .samples(samples)
+            .load_op(load_op)
             .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .initial_layout(color_initial_layout)
+            .final_layout(color_layout)
             .build();
 
         let depth_attachment_description = vk::AttachmentDescription::builder()
             .format(depth_format)
+            .samples(samples)
+            .load_op(load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(depth_initial_layout)
+            .final_layout(depth_layout)
+            .build();
+
+        let resolve_attachment_description = vk::AttachmentDescription::builder()
+            .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .build();
 
-        let attachment_descriptions = [color_attachment_description, depth_attachment_description];
+        let mut attachment_descriptions =
+            vec![color_attachment_description, depth_attachment_description];
+        if multisampled {
+            attachment_descriptions.push(resolve_attachment_description);
+        }
 
         let color_attachment_reference = vk::AttachmentReference::builder()
             .attachment(0)
@@ -118,14 +271,51 @@ impl Offscreen {
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .build();
 
-        let subpass_description = vk::SubpassDescription::builder()
+        let resolve_attachment_reference = vk::AttachmentReference::builder()
+            .attachment(2)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build();
+        let resolve_attachment_references = [resolve_attachment_reference];
+
+        let mut subpass_description_builder = vk::SubpassDescription::builder()
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
             .color_attachments(&color_attachment_references)
-            .depth_stencil_attachment(&depth_attachment_reference)
-            .build();
+            .depth_stencil_attachment(&depth_attachment_reference);
+        if multisampled {
+            subpass_description_builder =
+                subpass_description_builder.resolve_attachments(&resolve_attachment_references);
+        }
+        let subpass_description = subpass_description_builder.build();
         let subpass_descriptions = [subpass_description];
 
-        let subpass_dependencies = [
+        // The transmission pass waits for the color and depth writes of the main pass
+        // and for the copy of the scene color to finish
+        let external_dependency = if load_previous {
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                        | vk::AccessFlags::TRANSFER_READ,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build()
+        } else {
             vk::SubpassDependency::builder()
                 .src_subpass(vk::SUBPASS_EXTERNAL)
                 .dst_subpass(0)
@@ -136,17 +326,28 @@ impl Offscreen {
                     vk::AccessFlags::COLOR_ATTACHMENT_READ
                         | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
                 )
-                .build(),
+                .build()
+        };
+
+        let subpass_dependencies = [
+            external_dependency,
             vk::SubpassDependency::builder()
                 .src_subpass(0)
                 .dst_subpass(vk::SUBPASS_EXTERNAL)
-                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_stage_mask(vk::PipelineStageFlags::BOTTOM_OF_PIPE)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                )
                 .src_access_mask(
                     vk::AccessFlags::COLOR_ATTACHMENT_READ
-                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
                 )
-                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::MEMORY_READ)
                 .build(),
         ];
 
@@ -156,14 +357,22 @@ impl Offscreen {
             .dependencies(&subpass_dependencies)
             .build();
 
-        RenderPass::new(context, &create_info).unwrap()
+        RenderPass::new(context, &create_info)
     }
 
     fn create_depth_texture(
         context: Arc<VulkanContext>,
         swapchain_extent: vk::Extent2D,
         depth_format: vk::Format,
-    ) -> Texture {
+        samples: vk::SampleCountFlags,
+    ) -> Result<Texture> {
+        // Only single sampled depth can be displayed for debugging
+        let usage = if samples == vk::SampleCountFlags::TYPE_1 {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+        } else {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+        };
+
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .extent(vk::Extent3D {
@@ -176,9 +385,9 @@ impl Offscreen {
             .format(depth_format)
             .tiling(vk::ImageTiling::OPTIMAL)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .flags(vk::ImageCreateFlags::empty())
             .build();
 
@@ -186,14 +395,15 @@ impl Offscreen {
             usage: vk_mem::MemoryUsage::GpuOnly,
             ..Default::default()
         };
-        Texture::new(context, &image_allocation_create_info, &image_create_info).unwrap()
+        Texture::new(context, &image_allocation_create_info, &image_create_info)
     }
 
     fn create_depth_texture_view(
         context: Arc<VulkanContext>,
         depth_texture: &Texture,
         depth_format: vk::Format,
-    ) -> ImageView {
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<ImageView> {
         let create_info = vk::ImageViewCreateInfo::builder()
             .image(depth_texture.image())
             .view_type(vk::ImageViewType::TYPE_2D)
@@ -205,22 +415,38 @@ impl Offscreen {
                 a: vk::ComponentSwizzle::IDENTITY,
             })
             .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                aspect_mask,
                 base_mip_level: 0,
                 level_count: 1,
                 base_array_layer: 0,
                 layer_count: 1,
             })
             .build();
-        ImageView::new(context, create_info).unwrap()
+        ImageView::new(context, create_info)
     }
 
-    fn create_texture(context: Arc<VulkanContext>, dimension: u32, format: vk::Format) -> Texture {
+    fn create_texture(
+        context: Arc<VulkanContext>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Texture> {
+        // Multisampled color is only ever resolved, never sampled.
+        // It isn't transient because the transmission pass loads it again.
+        // Single sampled color is also copied into the transmission map.
+        let usage = if samples == vk::SampleCountFlags::TYPE_1 {
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+        };
+
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .extent(vk::Extent3D {
-                width: dimension,
-                height: dimension,
+                width: extent.width,
+                height: extent.height,
                 depth: 1,
             })
             .mip_levels(1)
@@ -228,9 +454,9 @@ impl Offscreen {
             .format(format)
             .tiling(vk::ImageTiling::OPTIMAL)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .flags(vk::ImageCreateFlags::empty())
             .build();
 
@@ -239,14 +465,14 @@ impl Offscreen {
             ..Default::default()
         };
 
-        Texture::new(context, &allocation_create_info, &image_create_info).unwrap()
+        Texture::new(context, &allocation_create_info, &image_create_info)
     }
 
     fn create_image_view(
         context: Arc<VulkanContext>,
         texture: &Texture,
         format: vk::Format,
-    ) -> ImageView {
+    ) -> Result<ImageView> {
         let create_info = vk::ImageViewCreateInfo::builder()
             .image(texture.image())
             .view_type(vk::ImageViewType::TYPE_2D)
@@ -265,10 +491,10 @@ impl Offscreen {
                 layer_count: 1,
             })
             .build();
-        ImageView::new(context, create_info).unwrap()
+        ImageView::new(context, create_info)
     }
 
-    fn create_sampler(context: Arc<VulkanContext>) -> Sampler {
+    fn create_sampler(context: Arc<VulkanContext>) -> Result<Arc<Sampler>> {
         let sampler_info = vk::SamplerCreateInfo::builder()
             .mag_filter(vk::Filter::LINEAR)
             .min_filter(vk::Filter::LINEAR)
@@ -286,6 +512,31 @@ impl Offscreen {
             .min_lod(0.0)
             .max_lod(1.0)
             .build();
-        Sampler::new(context, sampler_info).unwrap()
+        context
+            .sampler_cache()
+            .sampler(context.clone(), sampler_info)
+    }
+
+    fn create_depth_sampler(context: Arc<VulkanContext>) -> Result<Arc<Sampler>> {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .anisotropy_enable(false)
+            .max_anisotropy(1.0)
+            .border_color(vk::BorderColor::INT_OPAQUE_WHITE)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(1.0)
+            .build();
+        context
+            .sampler_cache()
+            .sampler(context.clone(), sampler_info)
     }
 }
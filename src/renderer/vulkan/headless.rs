@@ -0,0 +1,424 @@
+use crate::{
+    camera::CameraProjection,
+    renderer::{
+        vulkan::{
+            core::VulkanContext,
+            handles::ForwardRenderingHandles,
+            passes::{
+                present_target, AutoExposurePass, DebugShapesPass, OutlinePass, ParticlesPass,
+                PostProcessPass, ScenePass, ShadowPass, TransmissionCopyPass, TransmissionPass,
+            },
+            pbr::{PbrScene, ShadowMap},
+            render::{Framebuffer, RenderGraph, RenderPass},
+            resource::{
+                image::{ImageView, Texture},
+                Buffer, CommandPool, ShaderCache,
+            },
+        },
+        RenderScene, RenderSettings,
+    },
+};
+use anyhow::{Context, Result};
+use ash::{version::DeviceV1_0, vk};
+use std::sync::Arc;
+#[cfg(feature = "headless")]
+use std::{thread, time::Duration};
+
+// Renders the scene and post-processing into a fixed size image instead of a window,
+// so frames can be read back without a surface or swapchain.
+// Everything is rendered single sampled and the gui is not drawn.
+// It can also share the context of a windowed renderer, such as for rendering thumbnails.
+pub struct HeadlessRenderer {
+    scene: Option<PbrScene>,
+    handles: ForwardRenderingHandles,
+    _color_view: ImageView,
+    color_texture: Texture,
+    readback_buffer: Buffer,
+    command_pool: CommandPool,
+    shader_cache: ShaderCache,
+    render_settings: RenderSettings,
+    projection: CameraProjection,
+    extent: vk::Extent2D,
+    context: Arc<VulkanContext>,
+}
+
+impl HeadlessRenderer {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+    const SAMPLES: vk::SampleCountFlags = vk::SampleCountFlags::TYPE_1;
+    // Every frame is waited on before the next one is recorded
+    const FRAMES_IN_FLIGHT: u32 = 1;
+    #[cfg(feature = "headless")]
+    const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[cfg(feature = "headless")]
+    pub fn new(width: u32, height: u32, anisotropy: Option<f32>) -> Result<Self> {
+        let context = Arc::new(VulkanContext::headless(anisotropy)?);
+        Self::with_context(context, width, height)
+    }
+
+    pub fn with_context(context: Arc<VulkanContext>, width: u32, height: u32) -> Result<Self> {
+        let extent = vk::Extent2D { width, height };
+
+        let command_pool =
+            CommandPool::new(context.clone(), vk::CommandPoolCreateFlags::TRANSIENT)?;
+
+        let color_texture = Self::create_texture(context.clone(), extent)?;
+        let color_view = Self::create_image_view(context.clone(), &color_texture)?;
+
+        // The color target is left ready to be copied into the readback buffer
+        let render_pass = Arc::new(ForwardRenderingHandles::create_render_pass(
+            context.clone(),
+            Self::FORMAT,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )?);
+        let framebuffer =
+            Self::create_framebuffer(context.clone(), &render_pass, &color_view, extent)?;
+
+        let mut shader_cache = ShaderCache::default();
+        let mut handles = ForwardRenderingHandles::with_framebuffers(
+            context.clone(),
+            render_pass,
+            vec![framebuffer],
+            extent,
+            Self::SAMPLES,
+            Self::FRAMES_IN_FLIGHT,
+        )?;
+        handles.recreate_pipeline(&mut shader_cache)?;
+
+        let readback_buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            Self::image_size(extent) as _,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuToCpu,
+        )?;
+
+        Ok(Self {
+            scene: None,
+            handles,
+            _color_view: color_view,
+            color_texture,
+            readback_buffer,
+            command_pool,
+            shader_cache,
+            render_settings: RenderSettings::default(),
+            projection: CameraProjection::default(),
+            extent,
+            context,
+        })
+    }
+
+    pub fn initialize(&mut self, scene: &RenderScene) -> Result<()> {
+        let asset_names = &scene.asset_names();
+        let offscreen_render_pass = self.handles.offscreen.render_pass.clone();
+        self.scene = Some(PbrScene::new(
+            self.context.clone(),
+            &self.command_pool,
+            &mut self.shader_cache,
+            offscreen_render_pass,
+            asset_names,
+            Self::SAMPLES,
+            Self::FRAMES_IN_FLIGHT,
+            ShadowMap::DEFAULT_RESOLUTION,
+            self.extent,
+            self.extent,
+            &scene.environment,
+            &scene.ibl_settings,
+            scene.environment_cache.as_deref(),
+        )?);
+        Ok(())
+    }
+
+    // Blocks until every asset has either been uploaded or failed to load
+    #[cfg(feature = "headless")]
+    pub fn wait_for_assets(&mut self) -> Result<()> {
+        let scene = self
+            .scene
+            .as_mut()
+            .context("Failed to wait for assets before the headless renderer was initialized!")?;
+        loop {
+            let loaded = scene.load_progress().is_complete();
+            scene.receive_assets(&self.command_pool);
+            if loaded {
+                return Ok(());
+            }
+            thread::sleep(Self::LOAD_POLL_INTERVAL);
+        }
+    }
+
+    // Loads an asset on the calling thread, its instances are drawn from the next render on
+    pub fn load_asset(&mut self, asset_name: &str) -> Result<()> {
+        self.scene
+            .as_mut()
+            .context("Failed to load an asset before the headless renderer was initialized!")?
+            .load_asset_now(&self.command_pool, asset_name)
+    }
+
+    pub fn scene(&self) -> Option<&PbrScene> {
+        self.scene.as_ref()
+    }
+
+    // Renders a single frame and waits for it to finish,
+    // after which the frame can be read back with `read_pixels`
+    pub fn render(&mut self, scene: &RenderScene) -> Result<()> {
+        let aspect_ratio = self.extent.width as f32 / self.extent.height as f32;
+        self.render_settings = scene.render_settings;
+        self.projection = scene.projection;
+
+        let pbr_scene = self
+            .scene
+            .as_mut()
+            .context("Failed to render a frame before the headless renderer was initialized!")?;
+        pbr_scene.update_instances(&self.command_pool, &scene.asset_names());
+        pbr_scene.receive_assets(&self.command_pool);
+        pbr_scene.load_particle_textures(&self.command_pool, &scene.particle_emitters);
+        // Every frame is waited on, so only the first frame's buffers are used
+        pbr_scene.update(scene, aspect_ratio, 0)?;
+        self.handles.auto_exposure.update(0, scene.delta_time)?;
+
+        let context = &self.context;
+        let handles = &self.handles;
+        let render_settings = &self.render_settings;
+        let projection = &self.projection;
+        let extent = self.extent;
+        let color_image = self.color_texture.image();
+        let readback_buffer = self.readback_buffer.buffer();
+        self.command_pool
+            .execute_command_once(context.graphics_queue(), |command_buffer| {
+                Self::record_frame(
+                    context,
+                    handles,
+                    pbr_scene,
+                    render_settings,
+                    projection,
+                    extent,
+                    command_buffer,
+                );
+                Self::copy_to_buffer(
+                    context,
+                    command_buffer,
+                    color_image,
+                    readback_buffer,
+                    extent,
+                );
+            })
+    }
+
+    // Must only be called after a frame has been rendered
+    pub fn read_pixels(&self) -> Result<image::RgbaImage> {
+        let size = Self::image_size(self.extent);
+        self.readback_buffer.invalidate(0, size)?;
+        let data_pointer = self.readback_buffer.map_memory()?;
+        let pixels = unsafe { std::slice::from_raw_parts(data_pointer, size) }.to_vec();
+        self.readback_buffer.unmap_memory()?;
+        image::RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+            .context("Failed to create an image from the rendered pixels!")
+    }
+
+    // The same graph as the windowed renderer, without the gui
+    fn record_frame(
+        context: &Arc<VulkanContext>,
+        handles: &ForwardRenderingHandles,
+        scene: &PbrScene,
+        render_settings: &RenderSettings,
+        projection: &CameraProjection,
+        extent: vk::Extent2D,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let mut graph = RenderGraph::default();
+        graph
+            .add_pass(ShadowPass { scene, frame: 0 })
+            .add_pass(ScenePass::new(
+                context.clone(),
+                handles,
+                Some(scene),
+                render_settings,
+                &[],
+                0,
+            ));
+
+        if scene.has_transmissive_primitives() {
+            graph
+                .add_pass(TransmissionCopyPass::new(handles, scene))
+                .add_pass(TransmissionPass::new(handles, scene, render_settings, 0));
+        }
+
+        if scene.has_particles(0) {
+            graph.add_pass(ParticlesPass::new(handles, scene, 0));
+        }
+
+        if render_settings.outline_selection && scene.has_selection() {
+            graph.add_pass(OutlinePass::new(handles, scene, render_settings, 0));
+        }
+
+        if scene.has_debug_shapes(0) {
+            graph.add_pass(DebugShapesPass::new(handles, scene, 0));
+        }
+
+        if render_settings.auto_exposure {
+            graph.add_pass(AutoExposurePass {
+                handles,
+                render_settings,
+                frame: 0,
+            });
+        }
+
+        graph.add_pass(PostProcessPass {
+            context: context.clone(),
+            handles,
+            target: present_target(
+                handles,
+                handles.framebuffers[0].framebuffer(),
+                extent,
+                render_settings,
+            ),
+            render_settings,
+            projection,
+        });
+
+        graph.record(context.clone(), command_buffer);
+    }
+
+    // The image is expected to be in the transfer source layout
+    fn copy_to_buffer(
+        context: &VulkanContext,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        buffer: vk::Buffer,
+        extent: vk::Extent2D,
+    ) {
+        let device = context.logical_device().logical_device();
+
+        let before_copy = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .build();
+
+        let after_copy = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[before_copy],
+                &[],
+                &[],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[after_copy],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    // Four bytes per pixel
+    fn image_size(extent: vk::Extent2D) -> usize {
+        (extent.width * extent.height * 4) as usize
+    }
+
+    fn create_texture(context: Arc<VulkanContext>, extent: vk::Extent2D) -> Result<Texture> {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .format(Self::FORMAT)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .flags(vk::ImageCreateFlags::empty())
+            .build();
+
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+
+        Texture::new(context, &allocation_create_info, &image_create_info)
+    }
+
+    fn create_image_view(context: Arc<VulkanContext>, texture: &Texture) -> Result<ImageView> {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(texture.image())
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(Self::FORMAT)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY,
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        ImageView::new(context, create_info)
+    }
+
+    fn create_framebuffer(
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        color_view: &ImageView,
+        extent: vk::Extent2D,
+    ) -> Result<Framebuffer> {
+        let attachments = [color_view.view()];
+        let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass.render_pass())
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        Framebuffer::new(context, create_info)
+    }
+}
+
+impl Drop for HeadlessRenderer {
+    fn drop(&mut self) {
+        self.context.logical_device().wait_idle();
+    }
+}
@@ -1,9 +1,15 @@
+pub use self::core::DeviceSettings;
 pub use renderer::VulkanRenderer;
 
+#[cfg(feature = "headless")]
+pub use headless::HeadlessRenderer;
+
 mod asset;
 mod core;
 mod gui;
 mod handles;
+mod headless;
+mod passes;
 mod pbr;
 mod render;
 mod renderer;
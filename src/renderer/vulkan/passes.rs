@@ -0,0 +1,433 @@
+use crate::{
+    camera::CameraProjection,
+    renderer::{
+        vulkan::{
+            core::VulkanContext,
+            gui::GuiRenderer,
+            handles::ForwardRenderingHandles,
+            pbr::PbrScene,
+            render::{Pass, PassTarget},
+        },
+        RenderSettings,
+    },
+};
+use ash::{version::DeviceV1_0, vk};
+use imgui::DrawData;
+use log::warn;
+use std::sync::Arc;
+
+// The clear values shared by the offscreen and swapchain render passes
+pub fn clear_values(render_settings: &RenderSettings) -> [vk::ClearValue; 2] {
+    [
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: render_settings.clear_color,
+            },
+        },
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        },
+    ]
+}
+
+// Renders the shadow map, which begins its own render pass
+pub struct ShadowPass<'a> {
+    pub scene: &'a PbrScene,
+    pub frame: usize,
+}
+
+impl Pass for ShadowPass<'_> {
+    fn name(&self) -> &'static str {
+        "Shadow Map"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        None
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene.issue_shadow_commands(command_buffer, self.frame);
+    }
+}
+
+// Renders the scene into the offscreen target.
+// When secondary command buffers are given, the draws are recorded into them in parallel.
+pub struct ScenePass<'a> {
+    pub context: Arc<VulkanContext>,
+    pub scene: Option<&'a PbrScene>,
+    pub target: PassTarget,
+    pub render_settings: &'a RenderSettings,
+    pub secondary_command_buffers: &'a [vk::CommandBuffer],
+    pub frame: usize,
+}
+
+impl<'a> ScenePass<'a> {
+    pub fn new(
+        context: Arc<VulkanContext>,
+        handles: &ForwardRenderingHandles,
+        scene: Option<&'a PbrScene>,
+        render_settings: &'a RenderSettings,
+        secondary_command_buffers: &'a [vk::CommandBuffer],
+        frame: usize,
+    ) -> Self {
+        let contents = if secondary_command_buffers.is_empty() || scene.is_none() {
+            vk::SubpassContents::INLINE
+        } else {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        };
+        let target = PassTarget::new(
+            handles.offscreen.render_pass.render_pass(),
+            handles.offscreen.framebuffer.framebuffer(),
+            handles.offscreen.extent,
+        )
+        .with_clear_values(&clear_values(render_settings))
+        .with_contents(contents);
+
+        Self {
+            context,
+            scene,
+            target,
+            render_settings,
+            secondary_command_buffers,
+            frame,
+        }
+    }
+}
+
+impl Pass for ScenePass<'_> {
+    fn name(&self) -> &'static str {
+        "Scene"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        let scene = match self.scene {
+            Some(scene) => scene,
+            None => {
+                warn!("Scene not loaded!");
+                return;
+            }
+        };
+
+        if self.target.contents == vk::SubpassContents::INLINE {
+            scene
+                .issue_commands(
+                    command_buffer,
+                    self.target.extent,
+                    self.render_settings,
+                    self.frame,
+                )
+                .expect("Failed to issue scene commands!");
+            return;
+        }
+
+        let recorded_command_buffers = scene.issue_commands_parallel(
+            self.secondary_command_buffers,
+            self.target.render_pass,
+            self.target.framebuffer,
+            self.target.extent,
+            self.render_settings,
+            self.frame,
+        );
+        unsafe {
+            self.context
+                .logical_device()
+                .logical_device()
+                .cmd_execute_commands(command_buffer, &recorded_command_buffers);
+        }
+    }
+}
+
+// Copies the opaque scene color so transmissive primitives can sample what is behind them
+pub struct TransmissionCopyPass<'a> {
+    pub scene: &'a PbrScene,
+    pub source: vk::Image,
+}
+
+impl<'a> TransmissionCopyPass<'a> {
+    pub fn new(handles: &ForwardRenderingHandles, scene: &'a PbrScene) -> Self {
+        Self {
+            scene,
+            source: handles.offscreen.color_texture.texture.image(),
+        }
+    }
+}
+
+impl Pass for TransmissionCopyPass<'_> {
+    fn name(&self) -> &'static str {
+        "Transmission Copy"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        None
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene
+            .copy_transmission_source(command_buffer, self.source);
+    }
+}
+
+// Renders transmissive primitives over the opaque scene, after it has been copied
+pub struct TransmissionPass<'a> {
+    pub scene: &'a PbrScene,
+    pub target: PassTarget,
+    pub render_settings: &'a RenderSettings,
+    pub frame: usize,
+}
+
+impl<'a> TransmissionPass<'a> {
+    pub fn new(
+        handles: &ForwardRenderingHandles,
+        scene: &'a PbrScene,
+        render_settings: &'a RenderSettings,
+        frame: usize,
+    ) -> Self {
+        let target = PassTarget::new(
+            handles.offscreen.transmission_render_pass.render_pass(),
+            handles.offscreen.framebuffer.framebuffer(),
+            handles.offscreen.extent,
+        );
+        Self {
+            scene,
+            target,
+            render_settings,
+            frame,
+        }
+    }
+}
+
+impl Pass for TransmissionPass<'_> {
+    fn name(&self) -> &'static str {
+        "Transmission"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene.issue_transmission_commands(
+            command_buffer,
+            self.target.extent,
+            self.render_settings,
+            self.frame,
+        );
+    }
+}
+
+// Draws the debug shapes over the scene.
+// The render pass continues the offscreen targets, so it is shared with the transmission pass.
+pub struct DebugShapesPass<'a> {
+    pub scene: &'a PbrScene,
+    pub target: PassTarget,
+    pub frame: usize,
+}
+
+impl<'a> DebugShapesPass<'a> {
+    pub fn new(handles: &ForwardRenderingHandles, scene: &'a PbrScene, frame: usize) -> Self {
+        let target = PassTarget::new(
+            handles.offscreen.transmission_render_pass.render_pass(),
+            handles.offscreen.framebuffer.framebuffer(),
+            handles.offscreen.extent,
+        );
+        Self {
+            scene,
+            target,
+            frame,
+        }
+    }
+}
+
+impl Pass for DebugShapesPass<'_> {
+    fn name(&self) -> &'static str {
+        "Debug Shapes"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene
+            .issue_debug_shapes_commands(command_buffer, self.target.extent, self.frame);
+    }
+}
+
+// Draws the particles over the scene, continuing the offscreen targets like the debug shapes
+pub struct ParticlesPass<'a> {
+    pub scene: &'a PbrScene,
+    pub target: PassTarget,
+    pub frame: usize,
+}
+
+impl<'a> ParticlesPass<'a> {
+    pub fn new(handles: &ForwardRenderingHandles, scene: &'a PbrScene, frame: usize) -> Self {
+        let target = PassTarget::new(
+            handles.offscreen.transmission_render_pass.render_pass(),
+            handles.offscreen.framebuffer.framebuffer(),
+            handles.offscreen.extent,
+        );
+        Self {
+            scene,
+            target,
+            frame,
+        }
+    }
+}
+
+impl Pass for ParticlesPass<'_> {
+    fn name(&self) -> &'static str {
+        "Particles"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene
+            .issue_particle_commands(command_buffer, self.target.extent, self.frame);
+    }
+}
+
+// Outlines the selection over everything drawn into the offscreen targets before it
+pub struct OutlinePass<'a> {
+    pub scene: &'a PbrScene,
+    pub target: PassTarget,
+    pub render_settings: &'a RenderSettings,
+    pub frame: usize,
+}
+
+impl<'a> OutlinePass<'a> {
+    pub fn new(
+        handles: &ForwardRenderingHandles,
+        scene: &'a PbrScene,
+        render_settings: &'a RenderSettings,
+        frame: usize,
+    ) -> Self {
+        let target = PassTarget::new(
+            handles.offscreen.transmission_render_pass.render_pass(),
+            handles.offscreen.framebuffer.framebuffer(),
+            handles.offscreen.extent,
+        );
+        Self {
+            scene,
+            target,
+            render_settings,
+            frame,
+        }
+    }
+}
+
+impl Pass for OutlinePass<'_> {
+    fn name(&self) -> &'static str {
+        "Outline"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.scene.issue_outline_commands(
+            command_buffer,
+            self.target.extent,
+            self.render_settings,
+            self.frame,
+        );
+    }
+}
+
+// Measures the scene luminance and adapts the exposure the post-processing pass applies
+pub struct AutoExposurePass<'a> {
+    pub handles: &'a ForwardRenderingHandles,
+    pub render_settings: &'a RenderSettings,
+    pub frame: usize,
+}
+
+impl Pass for AutoExposurePass<'_> {
+    fn name(&self) -> &'static str {
+        "Auto Exposure"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        None
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.handles.auto_exposure.issue_commands(
+            command_buffer,
+            &self.render_settings.auto_exposure_settings,
+            self.frame,
+        );
+    }
+}
+
+// The target of the passes that draw into the final image
+pub fn present_target(
+    handles: &ForwardRenderingHandles,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    render_settings: &RenderSettings,
+) -> PassTarget {
+    PassTarget::new(handles.render_pass.render_pass(), framebuffer, extent)
+        .with_clear_values(&clear_values(render_settings))
+}
+
+// Applies the post-processing effects to the offscreen color and draws it to the final image
+pub struct PostProcessPass<'a> {
+    pub context: Arc<VulkanContext>,
+    pub handles: &'a ForwardRenderingHandles,
+    pub target: PassTarget,
+    pub render_settings: &'a RenderSettings,
+    pub projection: &'a CameraProjection,
+}
+
+impl Pass for PostProcessPass<'_> {
+    fn name(&self) -> &'static str {
+        "Post Processing"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        self.context
+            .logical_device()
+            .update_viewport(command_buffer, self.target.extent);
+        self.handles
+            .issue_commands(command_buffer, self.render_settings, self.projection);
+    }
+}
+
+// Draws the gui over the final image
+pub struct GuiPass<'a> {
+    pub gui_renderer: Option<&'a mut GuiRenderer>,
+    pub draw_data: &'a DrawData,
+    pub target: PassTarget,
+}
+
+impl Pass for GuiPass<'_> {
+    fn name(&self) -> &'static str {
+        "Gui"
+    }
+
+    fn target(&self) -> Option<PassTarget> {
+        Some(self.target.clone())
+    }
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        match self.gui_renderer.as_mut() {
+            Some(gui_renderer) => gui_renderer.issue_commands(command_buffer, self.draw_data),
+            None => warn!("No gui available!"),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::renderer::{
+    vulkan::{
+        core::VulkanContext,
+        pbr::PbrPipelineData,
+        render::{
+            DescriptorPool, DescriptorSetLayout, RenderPass, RenderPipeline,
+            RenderPipelineSettingsBuilder,
+        },
+        resource::{Buffer, DynamicGeometryBuffer, ShaderCache, ShaderPathSetBuilder},
+    },
+    DebugShapes, SceneCamera,
+};
+use anyhow::Result;
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::{mem, sync::Arc};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DebugVertex {
+    pub position: glm::Vec3,
+    pub color: glm::Vec4,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct DebugShapesUniformBufferObject {
+    pub view: glm::Mat4,
+    pub projection: glm::Mat4,
+}
+
+pub struct DebugShapesFrameData {
+    pub uniform_buffer: Buffer,
+    pub geometry_buffer: DynamicGeometryBuffer,
+    pub descriptor_set: vk::DescriptorSet,
+    // Nothing is drawn for the frame when this is zero
+    pub index_count: u32,
+}
+
+// Draws the debug shapes as lines over the scene, without lighting.
+// The shapes are rebuilt every frame into the geometry buffer of the frame in flight.
+pub struct DebugShapesRenderer {
+    context: Arc<VulkanContext>,
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    _descriptor_pool: DescriptorPool,
+    frames: Vec<DebugShapesFrameData>,
+    // The stride between the uniform buffer entries of each viewport
+    uniform_alignment: u64,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl DebugShapesRenderer {
+    // The corners of a unit cube centered on the origin
+    const BOX_CORNERS: [[f32; 3]; 8] = [
+        [-0.5, -0.5, -0.5],
+        [0.5, -0.5, -0.5],
+        [0.5, 0.5, -0.5],
+        [-0.5, 0.5, -0.5],
+        [-0.5, -0.5, 0.5],
+        [0.5, -0.5, 0.5],
+        [0.5, 0.5, 0.5],
+        [-0.5, 0.5, 0.5],
+    ];
+
+    // Pairs of corners that form the twelve edges of the cube
+    const BOX_EDGES: [u32; 24] = [
+        0, 1, 1, 2, 2, 3, 3, 0, 4, 5, 5, 6, 6, 7, 7, 4, 0, 4, 1, 5, 2, 6, 3, 7,
+    ];
+
+    pub fn new(context: Arc<VulkanContext>, frames_in_flight: u32) -> Result<Self> {
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
+        let descriptor_pool = Self::create_descriptor_pool(context.clone(), frames_in_flight);
+        let descriptor_sets = descriptor_pool
+            .allocate_descriptor_sets(descriptor_set_layout.layout(), frames_in_flight)?;
+
+        let uniform_alignment = context
+            .align_uniform_buffer_size(mem::size_of::<DebugShapesUniformBufferObject>() as _);
+        let frames = descriptor_sets
+            .into_iter()
+            .map(|descriptor_set| {
+                let uniform_buffer = Buffer::new_mapped_basic(
+                    context.clone(),
+                    (PbrPipelineData::MAX_VIEWPORTS as u64 * uniform_alignment) as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )?;
+                Self::update_descriptor_set(context.clone(), descriptor_set, &uniform_buffer);
+                Ok(DebugShapesFrameData {
+                    uniform_buffer,
+                    geometry_buffer: DynamicGeometryBuffer::new(context.clone())?,
+                    descriptor_set,
+                    index_count: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            context,
+            descriptor_set_layout,
+            _descriptor_pool: descriptor_pool,
+            frames,
+            uniform_alignment,
+            pipeline: None,
+        })
+    }
+
+    fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+        DescriptorSetLayout::new(context, layout_create_info).unwrap()
+    }
+
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        frames_in_flight: u32,
+    ) -> DescriptorPool {
+        let ubo_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: frames_in_flight,
+        };
+        let pool_sizes = [ubo_pool_size];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight)
+            .build();
+
+        DescriptorPool::new(context, pool_info).unwrap()
+    }
+
+    fn update_descriptor_set(
+        context: Arc<VulkanContext>,
+        descriptor_set: vk::DescriptorSet,
+        uniform_buffer: &Buffer,
+    ) {
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer())
+            .offset(0)
+            .range(mem::size_of::<DebugShapesUniformBufferObject>() as vk::DeviceSize)
+            .build();
+        let buffer_infos = [buffer_info];
+
+        let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos)
+            .build();
+
+        unsafe {
+            context
+                .logical_device()
+                .logical_device()
+                .update_descriptor_sets(&[ubo_descriptor_write], &[])
+        }
+    }
+
+    // The pipeline draws into the offscreen render pass, after the scene
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        render_pass: Arc<RenderPass>,
+        samples: vk::SampleCountFlags,
+    ) -> Result<()> {
+        let descriptions = Self::vertex_input_descriptions();
+        let attributes = Self::vertex_attributes();
+        let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&descriptions)
+            .vertex_attribute_descriptions(&attributes)
+            .build();
+
+        let shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/debug/debug_shapes.vert.spv")
+            .fragment("assets/shaders/debug/debug_shapes.frag.spv")
+            .build()
+            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        // Shapes are depth tested against the scene, but don't occlude each other
+        let settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(render_pass)
+            .vertex_state_info(vertex_state_info)
+            .descriptor_set_layout(self.descriptor_set_layout.clone())
+            .shader_set(shader_set)
+            .blended(true)
+            .depth_write_enabled(false)
+            .rasterization_samples(samples)
+            .topology(vk::PrimitiveTopology::LINE_LIST)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        self.pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Debug Shapes");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    fn vertex_attributes() -> [vk::VertexInputAttributeDescription; 2] {
+        let position_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+
+        let color_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((3 * mem::size_of::<f32>()) as _)
+            .build();
+
+        [position_description, color_description]
+    }
+
+    fn vertex_input_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        let vertex_input_binding_description = vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<DebugVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build();
+        [vertex_input_binding_description]
+    }
+
+    // Uploads the shapes and cameras for the frame in flight.
+    // Returns true if the draw recorded for the frame is out of date,
+    // which happens when the number of lines changes or the buffers are reallocated.
+    pub fn update(
+        &mut self,
+        frame: usize,
+        cameras: &[SceneCamera],
+        projections: &[glm::Mat4],
+        shapes: &DebugShapes,
+    ) -> Result<bool> {
+        let frame_data = &mut self.frames[frame];
+        let previous_draw = (
+            frame_data.geometry_buffer.vertex_buffer.buffer(),
+            frame_data.geometry_buffer.index_buffer.buffer(),
+            frame_data.index_count,
+        );
+
+        let (vertices, indices) = Self::build_geometry(shapes);
+        frame_data.index_count = indices.len() as u32;
+        if !indices.is_empty() {
+            let ubos = cameras
+                .iter()
+                .zip(projections.iter())
+                .map(|(camera, projection)| DebugShapesUniformBufferObject {
+                    view: camera.view,
+                    projection: *projection,
+                })
+                .collect::<Vec<_>>();
+            frame_data
+                .uniform_buffer
+                .upload_to_buffer_aligned(&ubos, 0, self.uniform_alignment)?;
+            frame_data.geometry_buffer.upload_vertices(&vertices)?;
+            frame_data.geometry_buffer.upload_indices(&indices)?;
+        }
+
+        let draw = (
+            frame_data.geometry_buffer.vertex_buffer.buffer(),
+            frame_data.geometry_buffer.index_buffer.buffer(),
+            frame_data.index_count,
+        );
+        Ok(draw != previous_draw)
+    }
+
+    fn build_geometry(shapes: &DebugShapes) -> (Vec<DebugVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for (transform, color) in shapes.boxes.iter() {
+            let matrix = transform.matrix();
+            let first_index = vertices.len() as u32;
+            vertices.extend(Self::BOX_CORNERS.iter().map(|corner| {
+                let position = matrix * glm::vec4(corner[0], corner[1], corner[2], 1.0);
+                DebugVertex {
+                    position: position.xyz(),
+                    color: *color,
+                }
+            }));
+            indices.extend(Self::BOX_EDGES.iter().map(|index| first_index + index));
+        }
+
+        for line in shapes.lines.iter() {
+            let first_index = vertices.len() as u32;
+            vertices.push(DebugVertex {
+                position: line.start,
+                color: line.color,
+            });
+            vertices.push(DebugVertex {
+                position: line.end,
+                color: line.color,
+            });
+            indices.extend_from_slice(&[first_index, first_index + 1]);
+        }
+
+        (vertices, indices)
+    }
+
+    pub fn has_shapes(&self, frame: usize) -> bool {
+        self.frames[frame].index_count > 0
+    }
+
+    // The shapes are drawn once per viewport, each in its own region
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        frame: usize,
+    ) {
+        let frame_data = &self.frames[frame];
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) if frame_data.index_count > 0 => pipeline,
+            _ => return,
+        };
+
+        let device = self.context.logical_device().logical_device();
+        pipeline.bind(device, command_buffer);
+        frame_data.geometry_buffer.bind(device, command_buffer);
+
+        for (viewport, region) in regions.iter().enumerate() {
+            self.context
+                .logical_device()
+                .set_viewport(command_buffer, *region);
+            let uniform_offset = (viewport as u64 * self.uniform_alignment) as u32;
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline.layout(),
+                    0,
+                    &[frame_data.descriptor_set],
+                    &[uniform_offset],
+                );
+                device.cmd_draw_indexed(command_buffer, frame_data.index_count, 1, 0, 0, 0);
+            }
+        }
+    }
+}
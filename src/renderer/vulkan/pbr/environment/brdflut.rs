@@ -8,6 +8,7 @@ use crate::renderer::vulkan::{
         CommandPool, ShaderCache, ShaderPathSetBuilder,
     },
 };
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use std::sync::Arc;
 
@@ -22,13 +23,13 @@ impl Brdflut {
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
         shader_cache: &mut ShaderCache,
-    ) -> Self {
+    ) -> Result<Self> {
         let dimension = 512;
         let format = vk::Format::R16G16_SFLOAT;
         let texture = Self::create_texture(context.clone(), dimension, format);
         let view = Self::create_image_view(context.clone(), &texture, format);
         let sampler = Self::create_sampler(context.clone());
-        let render_pass = Arc::new(Self::create_render_pass(context.clone(), format));
+        let render_pass = Arc::new(Self::create_render_pass(context.clone(), format)?);
 
         let attachments = [view.view()];
         let create_info = vk::FramebufferCreateInfo::builder()
@@ -38,7 +39,7 @@ impl Brdflut {
             .height(dimension)
             .layers(1)
             .build();
-        let framebuffer = Framebuffer::new(context.clone(), create_info).unwrap();
+        let framebuffer = Framebuffer::new(context.clone(), create_info)?;
 
         let clear_values = [vk::ClearValue {
             color: vk::ClearColorValue {
@@ -63,50 +64,48 @@ impl Brdflut {
 
         let device = context.logical_device().logical_device();
 
-        let pipeline = Self::create_pipeline(context.clone(), shader_cache, render_pass.clone());
+        let pipeline = Self::create_pipeline(context.clone(), shader_cache, render_pass.clone())?;
 
-        command_pool
-            .execute_command_once(context.graphics_queue(), |command_buffer| unsafe {
-                let viewport = vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: dimension as _,
-                    height: dimension as _,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                };
-                let viewports = [viewport];
+        command_pool.execute_command_once(context.graphics_queue(), |command_buffer| unsafe {
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: dimension as _,
+                height: dimension as _,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            let viewports = [viewport];
 
-                let scissor = vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent,
-                };
-                let scissors = [scissor];
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            };
+            let scissors = [scissor];
 
-                device.cmd_set_viewport(command_buffer, 0, &viewports);
-                device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
 
-                RenderPass::record(
-                    context.clone(),
-                    command_buffer,
-                    &render_pass_begin_info,
-                    || {
-                        device.cmd_bind_pipeline(
-                            command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline.pipeline.pipeline(),
-                        );
-                        device.cmd_draw(command_buffer, 3, 1, 0, 0);
-                    },
-                );
-            })
-            .unwrap();
+            RenderPass::record(
+                context.clone(),
+                command_buffer,
+                &render_pass_begin_info,
+                || {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.pipeline.pipeline(),
+                    );
+                    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                },
+            );
+        })?;
 
-        Self {
+        Ok(Self {
             texture,
             view,
             sampler,
-        }
+        })
     }
 
     fn create_texture(context: Arc<VulkanContext>, dimension: u32, format: vk::Format) -> Texture {
@@ -183,7 +182,7 @@ impl Brdflut {
         Sampler::new(context, sampler_info).unwrap()
     }
 
-    fn create_render_pass(context: Arc<VulkanContext>, format: vk::Format) -> RenderPass {
+    fn create_render_pass(context: Arc<VulkanContext>, format: vk::Format) -> Result<RenderPass> {
         let color_attachment_description = vk::AttachmentDescription::builder()
             .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
@@ -239,28 +238,26 @@ impl Brdflut {
             .dependencies(&subpass_dependencies)
             .build();
 
-        RenderPass::new(context, &create_info).unwrap()
+        RenderPass::new(context, &create_info)
     }
 
     fn create_pipeline(
         context: Arc<VulkanContext>,
         shader_cache: &mut ShaderCache,
         render_pass: Arc<RenderPass>,
-    ) -> RenderPipeline {
+    ) -> Result<RenderPipeline> {
         let shader_paths = ShaderPathSetBuilder::default()
             .vertex("assets/shaders/environment/fullscreen_triangle.vert.spv")
             .fragment("assets/shaders/environment/genbrdflut.frag.spv")
             .build()
             .unwrap();
-        let shader_set = shader_cache
-            .create_shader_set(context.clone(), &shader_paths)
-            .unwrap();
+        let shader_set = shader_cache.create_shader_set(context.clone(), &shader_paths)?;
 
         let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
             .bindings(&[])
             .build();
         let descriptor_set_layout =
-            DescriptorSetLayout::new(context.clone(), descriptor_set_layout_create_info).unwrap();
+            DescriptorSetLayout::new(context.clone(), descriptor_set_layout_create_info)?;
         let descriptor_set_layout = Arc::new(descriptor_set_layout);
 
         let settings = RenderPipelineSettingsBuilder::default()
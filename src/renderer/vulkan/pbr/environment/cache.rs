@@ -0,0 +1,138 @@
+use crate::renderer::{
+    vulkan::{
+        core::VulkanContext,
+        resource::{image::Cubemap, CommandPool},
+    },
+    Environment, IblSettings,
+};
+use anyhow::{Context, Result};
+use ash::vk;
+use log::{debug, warn};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Stores the cubemaps computed from an environment on disk, so they
+// don't have to be computed again the next time the environment is loaded.
+// Entries are keyed by a hash of the source images and bake settings along with the
+// cubemap dimension and format, so a changed source or cubemap layout is recomputed
+// instead of loading a stale entry.
+pub struct EnvironmentCache {
+    context: Arc<VulkanContext>,
+    directory: PathBuf,
+    key_hash: u64,
+}
+
+impl EnvironmentCache {
+    // Bumped whenever the bake shaders change, so entries baked by older shaders are recomputed
+    const BAKE_VERSION: u32 = 1;
+
+    pub fn new(
+        context: Arc<VulkanContext>,
+        directory: &str,
+        environment: &Environment,
+        ibl_settings: &IblSettings,
+    ) -> Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        Self::BAKE_VERSION.hash(&mut hasher);
+        // The lod bias only changes how the baked maps are sampled
+        (
+            ibl_settings.irradiance_dim,
+            ibl_settings.prefilter_dim,
+            ibl_settings.prefilter_mips,
+            ibl_settings.sample_count,
+        )
+            .hash(&mut hasher);
+        for path in Self::source_paths(environment) {
+            fs::read(path)
+                .with_context(|| format!("path: {}", path))?
+                .hash(&mut hasher);
+        }
+
+        Ok(Self {
+            context,
+            directory: PathBuf::from(directory),
+            key_hash: hasher.finish(),
+        })
+    }
+
+    // Failing to read or write an entry isn't an error, the cubemap is computed instead
+    pub fn load_or_compute<T>(
+        &self,
+        command_pool: &CommandPool,
+        name: &str,
+        dimension: u32,
+        mip_levels: u32,
+        format: vk::Format,
+        compute: T,
+    ) -> Result<Cubemap>
+    where
+        T: FnOnce() -> Result<Cubemap>,
+    {
+        let path = self.entry_path(name, dimension, format);
+
+        if let Ok(pixels) = fs::read(&path) {
+            let cubemap =
+                Cubemap::with_mip_levels(self.context.clone(), dimension, format, mip_levels)?;
+            match cubemap.upload_mip_chain(command_pool, &pixels) {
+                Ok(()) => {
+                    debug!("Loaded {} from {}", name, path.display());
+                    return Ok(cubemap);
+                }
+                Err(error) => warn!(
+                    "Ignoring invalid environment cache entry {}: {}",
+                    path.display(),
+                    error
+                ),
+            }
+        }
+
+        let cubemap = compute()?;
+        match Self::store(&path, &cubemap, command_pool) {
+            Ok(()) => debug!("Cached {} at {}", name, path.display()),
+            Err(error) => warn!(
+                "Failed to write environment cache entry {}: {}",
+                path.display(),
+                error
+            ),
+        }
+        Ok(cubemap)
+    }
+
+    fn store(path: &Path, cubemap: &Cubemap, command_pool: &CommandPool) -> Result<()> {
+        let pixels = cubemap.download(command_pool)?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, pixels)?;
+        Ok(())
+    }
+
+    fn entry_path(&self, name: &str, dimension: u32, format: vk::Format) -> PathBuf {
+        self.directory.join(format!(
+            "{}_{:016x}_{}_{}.bin",
+            name,
+            self.key_hash,
+            dimension,
+            format.as_raw()
+        ))
+    }
+
+    fn source_paths(environment: &Environment) -> Vec<&str> {
+        match environment {
+            Environment::Hdr(path) | Environment::Cubemap(path) => vec![path],
+            Environment::Faces {
+                right,
+                left,
+                top,
+                bottom,
+                front,
+                back,
+            } => vec![right, left, top, bottom, front, back],
+        }
+    }
+}
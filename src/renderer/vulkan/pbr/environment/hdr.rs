@@ -8,7 +8,9 @@ use crate::renderer::{
             RenderPipelineSettingsBuilder,
         },
         resource::{
-            image::{Cubemap, ImageLayoutTransition, TextureBundle, TextureDescription},
+            image::{
+                Cubemap, ImageLayoutTransition, SamplerSettings, TextureBundle, TextureDescription,
+            },
             CommandPool, ShaderCache, ShaderPathSetBuilder,
         },
     },
@@ -34,9 +36,19 @@ impl HdrCubemap {
         path: &str,
         shader_cache: &mut ShaderCache,
     ) -> Result<Self> {
-        let description = TextureDescription::from_hdr(path).unwrap();
-        let hdr_texture_bundle =
-            TextureBundle::new(context.clone(), &command_pool, &description).unwrap();
+        let description = TextureDescription::from_hdr(path)?;
+        // Only the longitude wraps around. Repeating the latitude would blend
+        // the two poles together, most visibly in the small mips.
+        let sampler_settings = SamplerSettings {
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let hdr_texture_bundle = TextureBundle::new(
+            context.clone(),
+            command_pool,
+            &description,
+            &sampler_settings,
+        )?;
 
         let dimension = description.width;
         let format = vk::Format::R32G32B32A32_SFLOAT;
@@ -67,7 +79,7 @@ impl HdrCubemap {
 
         offscreen
             .texture
-            .transition(&command_pool, &transition, 1)
+            .transition(command_pool, &transition, 1)
             .unwrap();
 
         let descriptor_set_layout = Arc::new(Self::create_descriptor_set_layout(context.clone()));
@@ -95,9 +107,7 @@ impl HdrCubemap {
             .fragment("assets/shaders/environment/equirectangular_to_cubemap.frag.spv")
             .build()
             .unwrap();
-        let shader_set = shader_cache
-            .create_shader_set(context.clone(), &shader_paths)
-            .unwrap();
+        let shader_set = shader_cache.create_shader_set(context.clone(), &shader_paths)?;
 
         let settings = RenderPipelineSettingsBuilder::default()
             .render_pass(render_pass.clone())
@@ -109,7 +119,7 @@ impl HdrCubemap {
             .build()
             .expect("Failed to create render pipeline settings!");
 
-        let render_pipeline = RenderPipeline::new(context.clone(), settings);
+        let render_pipeline = RenderPipeline::new(context.clone(), settings)?;
 
         let clear_values = [vk::ClearValue {
             color: vk::ClearColorValue {
@@ -178,7 +188,7 @@ impl HdrCubemap {
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
         output_cubemap
-            .transition(&command_pool, &transition)
+            .transition(command_pool, &transition)
             .unwrap();
 
         let mut viewport = vk::Viewport {
@@ -258,7 +268,7 @@ impl HdrCubemap {
                 };
                 offscreen
                     .texture
-                    .transition(&command_pool, &transition, 1)
+                    .transition(command_pool, &transition, 1)
                     .unwrap();
 
                 let src_subresource = vk::ImageSubresourceLayers::builder()
@@ -309,7 +319,7 @@ impl HdrCubemap {
 
                 offscreen
                     .texture
-                    .transition(&command_pool, &transition, 1)
+                    .transition(command_pool, &transition, 1)
                     .unwrap();
             }
         }
@@ -324,7 +334,7 @@ impl HdrCubemap {
         };
 
         output_cubemap
-            .transition(&command_pool, &transition)
+            .transition(command_pool, &transition)
             .unwrap();
 
         let hdr = Self {
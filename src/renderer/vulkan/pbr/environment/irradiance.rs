@@ -13,6 +13,7 @@ use crate::renderer::{
         },
     },
 };
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use nalgebra_glm as glm;
 use std::{ffi::CString, sync::Arc};
@@ -29,10 +30,16 @@ pub struct IrradianceMap {
 }
 
 impl IrradianceMap {
-    pub fn new(context: Arc<VulkanContext>, command_pool: &CommandPool, cubemap: &Cubemap) -> Self {
-        let dimension = 64;
-        let format = vk::Format::R32G32B32A32_SFLOAT;
-        let output_cubemap = Cubemap::new(context.clone(), dimension, format).unwrap();
+    pub const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+    pub fn new(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        cubemap: &Cubemap,
+        dimension: u32,
+    ) -> Result<Self> {
+        let format = Self::FORMAT;
+        let output_cubemap = Cubemap::new(context.clone(), dimension, format)?;
 
         let render_pass = Self::create_render_pass(context.clone(), format);
 
@@ -46,7 +53,7 @@ impl IrradianceMap {
             .height(dimension)
             .layers(1)
             .build();
-        let framebuffer = Framebuffer::new(context.clone(), create_info).unwrap();
+        let framebuffer = Framebuffer::new(context.clone(), create_info)?;
 
         let transition = ImageLayoutTransition {
             old_layout: vk::ImageLayout::UNDEFINED,
@@ -57,23 +64,19 @@ impl IrradianceMap {
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
 
-        offscreen
-            .texture
-            .transition(&command_pool, &transition, 1)
-            .unwrap();
+        offscreen.texture.transition(command_pool, &transition, 1)?;
 
         let descriptor_set_layout = Self::create_descriptor_set_layout(context.clone());
         let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
+        let descriptor_set =
+            descriptor_pool.allocate_descriptor_sets(descriptor_set_layout.layout(), 1)?[0];
 
         Self::update_descriptor_set(context.clone(), descriptor_set, &cubemap);
 
         let pipeline_layout =
             Self::create_pipeline_layout(context.clone(), descriptor_set_layout.layout());
 
-        let pipeline = Self::create_pipeline(context.clone(), pipeline_layout, &render_pass);
+        let pipeline = Self::create_pipeline(context.clone(), pipeline_layout, &render_pass)?;
 
         let clear_values = [vk::ClearValue {
             color: vk::ClearColorValue {
@@ -139,9 +142,7 @@ impl IrradianceMap {
             src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
-        output_cubemap
-            .transition(&command_pool, &transition)
-            .unwrap();
+        output_cubemap.transition(command_pool, &transition)?;
 
         let mut viewport = vk::Viewport {
             x: 0.0,
@@ -167,8 +168,9 @@ impl IrradianceMap {
                 viewport.height = current_dimension;
                 let viewports = [viewport];
 
-                command_pool
-                    .execute_command_once(context.graphics_queue(), |command_buffer| unsafe {
+                command_pool.execute_command_once(
+                    context.graphics_queue(),
+                    |command_buffer| unsafe {
                         device.cmd_set_viewport(command_buffer, 0, &viewports);
                         device.cmd_set_scissor(command_buffer, 0, &scissors);
 
@@ -211,8 +213,8 @@ impl IrradianceMap {
                                 unit_cube.draw(device, command_buffer);
                             },
                         );
-                    })
-                    .unwrap();
+                    },
+                )?;
 
                 let transition = ImageLayoutTransition {
                     old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -223,10 +225,7 @@ impl IrradianceMap {
                     dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
                 };
 
-                offscreen
-                    .texture
-                    .transition(&command_pool, &transition, 1)
-                    .unwrap();
+                offscreen.texture.transition(command_pool, &transition, 1)?;
 
                 let src_subresource = vk::ImageSubresourceLayers::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -255,15 +254,13 @@ impl IrradianceMap {
                     .build();
                 let regions = [region];
 
-                command_pool
-                    .copy_image_to_image(
-                        offscreen.texture.image(),
-                        output_cubemap.texture.image(),
-                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &regions,
-                    )
-                    .unwrap();
+                command_pool.copy_image_to_image(
+                    offscreen.texture.image(),
+                    output_cubemap.texture.image(),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                )?;
 
                 let transition = ImageLayoutTransition {
                     old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
@@ -274,10 +271,7 @@ impl IrradianceMap {
                     dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
                 };
 
-                offscreen
-                    .texture
-                    .transition(&command_pool, &transition, 1)
-                    .unwrap();
+                offscreen.texture.transition(command_pool, &transition, 1)?;
             }
         }
 
@@ -290,13 +284,11 @@ impl IrradianceMap {
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
 
-        output_cubemap
-            .transition(&command_pool, &transition)
-            .unwrap();
+        output_cubemap.transition(command_pool, &transition)?;
 
-        Self {
+        Ok(Self {
             cubemap: output_cubemap,
-        }
+        })
     }
 
     fn create_render_pass(context: Arc<VulkanContext>, format: vk::Format) -> RenderPass {
@@ -442,7 +434,7 @@ impl IrradianceMap {
         context: Arc<VulkanContext>,
         pipeline_layout: PipelineLayout,
         render_pass: &RenderPass,
-    ) -> GraphicsPipeline {
+    ) -> Result<GraphicsPipeline> {
         let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
             .build();
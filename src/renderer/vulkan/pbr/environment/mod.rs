@@ -1,6 +1,9 @@
-pub use self::{brdflut::*, cube::*, hdr::*, irradiance::*, offscreen::*, prefilter::*, skybox::*};
+pub use self::{
+    brdflut::*, cache::*, cube::*, hdr::*, irradiance::*, offscreen::*, prefilter::*, skybox::*,
+};
 
 pub mod brdflut;
+pub mod cache;
 pub mod cube;
 pub mod hdr;
 pub mod irradiance;
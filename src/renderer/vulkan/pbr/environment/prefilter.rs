@@ -13,6 +13,7 @@ use crate::renderer::{
         },
     },
 };
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use nalgebra_glm as glm;
 use std::{ffi::CString, sync::Arc};
@@ -29,11 +30,22 @@ pub struct PrefilterMap {
 }
 
 impl PrefilterMap {
-    pub fn new(context: Arc<VulkanContext>, command_pool: &CommandPool, cubemap: &Cubemap) -> Self {
-        let dimension = 512;
-        let format = vk::Format::R16G16B16A16_SFLOAT;
+    pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
 
-        let output_cubemap = Cubemap::new(context.clone(), dimension, format).unwrap();
+    // Each mip level is prefiltered for a higher roughness,
+    // so the mip count must not exceed the full mip chain of the dimension
+    pub fn new(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        cubemap: &Cubemap,
+        dimension: u32,
+        mip_levels: u32,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let format = Self::FORMAT;
+
+        let output_cubemap =
+            Cubemap::with_mip_levels(context.clone(), dimension, format, mip_levels)?;
 
         let render_pass = Self::create_render_pass(context.clone(), format);
 
@@ -47,7 +59,7 @@ impl PrefilterMap {
             .height(dimension)
             .layers(1)
             .build();
-        let framebuffer = Framebuffer::new(context.clone(), create_info).unwrap();
+        let framebuffer = Framebuffer::new(context.clone(), create_info)?;
 
         let transition = ImageLayoutTransition {
             old_layout: vk::ImageLayout::UNDEFINED,
@@ -58,23 +70,19 @@ impl PrefilterMap {
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
 
-        offscreen
-            .texture
-            .transition(&command_pool, &transition, 1)
-            .unwrap();
+        offscreen.texture.transition(command_pool, &transition, 1)?;
 
         let descriptor_set_layout = Self::create_descriptor_set_layout(context.clone());
         let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
+        let descriptor_set =
+            descriptor_pool.allocate_descriptor_sets(descriptor_set_layout.layout(), 1)?[0];
 
         Self::update_descriptor_set(context.clone(), descriptor_set, &cubemap);
 
         let pipeline_layout =
             Self::create_pipeline_layout(context.clone(), descriptor_set_layout.layout());
 
-        let pipeline = Self::create_pipeline(context.clone(), pipeline_layout, &render_pass);
+        let pipeline = Self::create_pipeline(context.clone(), pipeline_layout, &render_pass)?;
 
         let clear_values = [vk::ClearValue {
             color: vk::ClearColorValue {
@@ -140,9 +148,7 @@ impl PrefilterMap {
             src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
-        output_cubemap
-            .transition(&command_pool, &transition)
-            .unwrap();
+        output_cubemap.transition(command_pool, &transition)?;
 
         let mut viewport = vk::Viewport {
             x: 0.0,
@@ -168,8 +174,9 @@ impl PrefilterMap {
                 viewport.height = current_dimension;
                 let viewports = [viewport];
 
-                command_pool
-                    .execute_command_once(context.graphics_queue(), |command_buffer| unsafe {
+                command_pool.execute_command_once(
+                    context.graphics_queue(),
+                    |command_buffer| unsafe {
                         device.cmd_set_viewport(command_buffer, 0, &viewports);
                         device.cmd_set_scissor(command_buffer, 0, &scissors);
 
@@ -183,8 +190,8 @@ impl PrefilterMap {
                                     mvp: glm::perspective_zo(1.0, 90_f32.to_radians(), 0.1, 512.0)
                                         * matrix,
                                     roughness: mip_level as f32
-                                        / (output_cubemap.description.mip_levels - 1) as f32,
-                                    num_samples: 32,
+                                        / (output_cubemap.description.mip_levels - 1).max(1) as f32,
+                                    num_samples: sample_count,
                                 };
 
                                 device.cmd_push_constants(
@@ -213,8 +220,8 @@ impl PrefilterMap {
                                 unit_cube.draw(device, command_buffer);
                             },
                         );
-                    })
-                    .unwrap();
+                    },
+                )?;
 
                 let transition = ImageLayoutTransition {
                     old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -224,10 +231,7 @@ impl PrefilterMap {
                     src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
                     dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
                 };
-                offscreen
-                    .texture
-                    .transition(&command_pool, &transition, 1)
-                    .unwrap();
+                offscreen.texture.transition(command_pool, &transition, 1)?;
 
                 let src_subresource = vk::ImageSubresourceLayers::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -256,15 +260,13 @@ impl PrefilterMap {
                     .build();
                 let regions = [region];
 
-                command_pool
-                    .copy_image_to_image(
-                        offscreen.texture.image(),
-                        output_cubemap.texture.image(),
-                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &regions,
-                    )
-                    .unwrap();
+                command_pool.copy_image_to_image(
+                    offscreen.texture.image(),
+                    output_cubemap.texture.image(),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                )?;
 
                 let transition = ImageLayoutTransition {
                     old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
@@ -275,10 +277,7 @@ impl PrefilterMap {
                     dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
                 };
 
-                offscreen
-                    .texture
-                    .transition(&command_pool, &transition, 1)
-                    .unwrap();
+                offscreen.texture.transition(command_pool, &transition, 1)?;
             }
         }
 
@@ -291,13 +290,11 @@ impl PrefilterMap {
             dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
         };
 
-        output_cubemap
-            .transition(&command_pool, &transition)
-            .unwrap();
+        output_cubemap.transition(command_pool, &transition)?;
 
-        Self {
+        Ok(Self {
             cubemap: output_cubemap,
-        }
+        })
     }
 
     fn create_render_pass(context: Arc<VulkanContext>, format: vk::Format) -> RenderPass {
@@ -443,7 +440,7 @@ impl PrefilterMap {
         context: Arc<VulkanContext>,
         pipeline_layout: PipelineLayout,
         render_pass: &RenderPass,
-    ) -> GraphicsPipeline {
+    ) -> Result<GraphicsPipeline> {
         let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
             .build();
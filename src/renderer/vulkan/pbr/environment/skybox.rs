@@ -1,12 +1,16 @@
 use crate::renderer::vulkan::{
     core::VulkanContext,
-    pbr::environment::UnitCube,
+    pbr::{environment::UnitCube, PbrPipelineData},
     render::{
         DescriptorPool, DescriptorSetLayout, RenderPass, RenderPipeline,
         RenderPipelineSettingsBuilder,
     },
-    resource::{image::Cubemap, Buffer, CommandPool, ShaderCache, ShaderPathSetBuilder},
+    resource::{
+        image::{Cubemap, CubemapFaces, TextureDescription},
+        Buffer, CommandPool, ShaderCache, ShaderPathSetBuilder,
+    },
 };
+use anyhow::{ensure, Context, Result};
 use ash::{version::DeviceV1_0, vk};
 use nalgebra_glm as glm;
 use std::{mem, sync::Arc};
@@ -16,7 +20,7 @@ pub fn create_skybox_pipeline(
     shader_cache: &mut ShaderCache,
     render_pass: Arc<RenderPass>,
     samples: vk::SampleCountFlags,
-) -> RenderPipeline {
+) -> Result<RenderPipeline> {
     let descriptions = UnitCube::vertex_input_descriptions();
     let attributes = UnitCube::vertex_attributes();
     let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
@@ -29,9 +33,7 @@ pub fn create_skybox_pipeline(
         .fragment("assets/shaders/environment/skybox.frag.spv")
         .build()
         .unwrap();
-    let shader_set = shader_cache
-        .create_shader_set(context.clone(), &shader_paths)
-        .unwrap();
+    let shader_set = shader_cache.create_shader_set(context.clone(), &shader_paths)?;
 
     let descriptor_set_layout = SkyboxPipelineData::descriptor_set_layout(context.clone());
     let settings = RenderPipelineSettingsBuilder::default()
@@ -47,7 +49,68 @@ pub fn create_skybox_pipeline(
         .build()
         .expect("Failed to create render pipeline settings!");
 
-    RenderPipeline::new(context, settings)
+    let pipeline = RenderPipeline::new(context.clone(), settings)?;
+    context.set_debug_name(pipeline.pipeline.pipeline(), "Skybox");
+    Ok(pipeline)
+}
+
+pub struct SkyboxCubemap {
+    pub cubemap: Cubemap,
+}
+
+impl SkyboxCubemap {
+    pub fn from_faces(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        faces: &CubemapFaces,
+    ) -> Result<Self> {
+        let descriptions = faces
+            .create_descriptions()
+            .into_iter()
+            .collect::<Result<Vec<TextureDescription>>>()?;
+        Self::from_descriptions(context, command_pool, &descriptions)
+    }
+
+    // A DDS file holding all six faces
+    pub fn from_dds(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        path: &str,
+    ) -> Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("path: {}", path))?;
+        let descriptions = TextureDescription::from_dds_cubemap(&bytes)
+            .with_context(|| format!("path: {}", path))?;
+        Self::from_descriptions(context, command_pool, &descriptions)
+    }
+
+    fn from_descriptions(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        descriptions: &[TextureDescription],
+    ) -> Result<Self> {
+        let first = &descriptions[0];
+        ensure!(
+            first.width == first.height,
+            "Cubemap faces must be square, found {}x{}",
+            first.width,
+            first.height
+        );
+        ensure!(
+            descriptions.iter().all(|description| {
+                description.width == first.width
+                    && description.height == first.height
+                    && description.format == first.format
+                    && description.mip_levels == first.mip_levels
+            }),
+            "Cubemap faces must all share the same dimensions, format and mip levels"
+        );
+
+        let cubemap =
+            Cubemap::with_mip_levels(context, first.width, first.format, first.mip_levels)?;
+        cubemap.upload_texture_data(command_pool, descriptions)?;
+
+        Ok(Self { cubemap })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,33 +121,49 @@ pub struct SkyboxUniformBufferObject {
 
 pub struct SkyboxPipelineData {
     pub descriptor_pool: DescriptorPool,
-    pub descriptor_set: vk::DescriptorSet,
-    pub uniform_buffer: Buffer,
+    // One descriptor set and uniform buffer per frame in flight
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub uniform_buffers: Vec<Buffer>,
+    // The stride between the uniform buffer entries of each viewport
+    pub uniform_alignment: u64,
     pub cube: UnitCube,
 }
 
 impl SkyboxPipelineData {
-    pub fn new(context: Arc<VulkanContext>, command_pool: &CommandPool, cubemap: &Cubemap) -> Self {
+    pub fn new(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        cubemap: &Cubemap,
+        frames_in_flight: u32,
+    ) -> Self {
         let descriptor_set_layout = Self::descriptor_set_layout(context.clone());
-        let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
-
-        let uniform_buffer = Buffer::new_mapped_basic(
-            context.clone(),
-            mem::size_of::<SkyboxUniformBufferObject>() as _,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk_mem::MemoryUsage::CpuToGpu,
-        )
-        .unwrap();
+        let descriptor_pool = Self::create_descriptor_pool(context.clone(), frames_in_flight);
+        let descriptor_sets = descriptor_pool
+            .allocate_descriptor_sets(descriptor_set_layout.layout(), frames_in_flight)
+            .unwrap();
+
+        let uniform_alignment =
+            context.align_uniform_buffer_size(mem::size_of::<SkyboxUniformBufferObject>() as _);
+        let uniform_buffers = descriptor_sets
+            .iter()
+            .map(|_| {
+                Buffer::new_mapped_basic(
+                    context.clone(),
+                    (PbrPipelineData::MAX_VIEWPORTS as u64 * uniform_alignment) as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
 
         let cube = UnitCube::new(command_pool);
 
         let data = SkyboxPipelineData {
             descriptor_pool,
-            uniform_buffer,
-            descriptor_set,
+            uniform_buffers,
+            uniform_alignment,
+            descriptor_sets,
             cube,
         };
 
@@ -95,7 +174,7 @@ impl SkyboxPipelineData {
     pub fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
         let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
             .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .build();
@@ -113,66 +192,73 @@ impl SkyboxPipelineData {
         DescriptorSetLayout::new(context, layout_create_info).unwrap()
     }
 
-    fn create_descriptor_pool(context: Arc<VulkanContext>) -> DescriptorPool {
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        frames_in_flight: u32,
+    ) -> DescriptorPool {
         let ubo_pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: frames_in_flight,
         };
 
         let sampler_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
+            descriptor_count: frames_in_flight,
         };
 
         let pool_sizes = [ubo_pool_size, sampler_pool_size];
 
         let pool_info = vk::DescriptorPoolCreateInfo::builder()
             .pool_sizes(&pool_sizes)
-            .max_sets(1)
+            .max_sets(frames_in_flight)
             .build();
 
         DescriptorPool::new(context, pool_info).unwrap()
     }
 
     fn update_descriptor_set(&self, context: Arc<VulkanContext>, cubemap: &Cubemap) {
-        let uniform_buffer_size = mem::size_of::<SkyboxUniformBufferObject>() as vk::DeviceSize;
-        let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(self.uniform_buffer.buffer())
-            .offset(0)
-            .range(uniform_buffer_size)
-            .build();
-        let buffer_infos = [buffer_info];
-
-        let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(&buffer_infos)
-            .build();
+        for (descriptor_set, uniform_buffer) in
+            self.descriptor_sets.iter().zip(self.uniform_buffers.iter())
+        {
+            let uniform_buffer_size = mem::size_of::<SkyboxUniformBufferObject>() as vk::DeviceSize;
+            let buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(uniform_buffer.buffer())
+                .offset(0)
+                .range(uniform_buffer_size)
+                .build();
+            let buffer_infos = [buffer_info];
 
-        let image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(cubemap.view.view())
-            .sampler(cubemap.sampler.sampler())
-            .build();
-        let image_infos = [image_info];
+            let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&buffer_infos)
+                .build();
 
-        let sampler_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&image_infos)
-            .build();
+            let image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(cubemap.view.view())
+                .sampler(cubemap.sampler.sampler())
+                .build();
+            let image_infos = [image_info];
 
-        let descriptor_writes = vec![ubo_descriptor_write, sampler_descriptor_write];
+            let sampler_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build();
 
-        unsafe {
-            context
-                .logical_device()
-                .logical_device()
-                .update_descriptor_sets(&descriptor_writes, &[])
+            let descriptor_writes = vec![ubo_descriptor_write, sampler_descriptor_write];
+
+            unsafe {
+                context
+                    .logical_device()
+                    .logical_device()
+                    .update_descriptor_sets(&descriptor_writes, &[])
+            }
         }
     }
 }
@@ -181,6 +267,7 @@ pub struct SkyboxRenderer {
     command_buffer: vk::CommandBuffer,
     pipeline_layout: vk::PipelineLayout,
     descriptor_set: vk::DescriptorSet,
+    uniform_alignment: u64,
 }
 
 impl SkyboxRenderer {
@@ -188,15 +275,18 @@ impl SkyboxRenderer {
         command_buffer: vk::CommandBuffer,
         pipeline: &RenderPipeline,
         pipeline_data: &SkyboxPipelineData,
+        frame: usize,
     ) -> Self {
         Self {
             command_buffer,
             pipeline_layout: pipeline.pipeline.layout(),
-            descriptor_set: pipeline_data.descriptor_set,
+            descriptor_set: pipeline_data.descriptor_sets[frame],
+            uniform_alignment: pipeline_data.uniform_alignment,
         }
     }
 
-    pub fn draw(&self, device: &ash::Device, cube: &UnitCube) {
+    pub fn draw(&self, device: &ash::Device, cube: &UnitCube, viewport: usize) {
+        let uniform_offset = (viewport as u64 * self.uniform_alignment) as u32;
         unsafe {
             device.cmd_bind_descriptor_sets(
                 self.command_buffer,
@@ -204,7 +294,7 @@ impl SkyboxRenderer {
                 self.pipeline_layout,
                 0,
                 &[self.descriptor_set],
-                &[],
+                &[uniform_offset],
             );
         }
 
@@ -0,0 +1,240 @@
+use crate::renderer::{
+    vulkan::{
+        core::VulkanContext,
+        pbr::PbrPipelineData,
+        render::{
+            DescriptorPool, DescriptorSetLayout, RenderPass, RenderPipeline,
+            RenderPipelineSettingsBuilder,
+        },
+        resource::{Buffer, ShaderCache, ShaderPathSetBuilder},
+    },
+    GridSettings, SceneCamera,
+};
+use anyhow::Result;
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::{mem, sync::Arc};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct GridUniformBufferObject {
+    pub view_projection: glm::Mat4,
+    pub inverse_view_projection: glm::Mat4,
+    pub color: glm::Vec4,
+    pub spacing: f32,
+    pub fade_distance: f32,
+}
+
+pub struct GridFrameData {
+    pub uniform_buffer: Buffer,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+// Draws an infinite grid on the ground plane with a fullscreen triangle.
+// Each pixel finds where its ray meets the plane and writes that depth,
+// so the grid is occluded by the scene without being drawn as geometry.
+pub struct GridRenderer {
+    context: Arc<VulkanContext>,
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    _descriptor_pool: DescriptorPool,
+    frames: Vec<GridFrameData>,
+    // The stride between the uniform buffer entries of each viewport
+    uniform_alignment: u64,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl GridRenderer {
+    pub fn new(context: Arc<VulkanContext>, frames_in_flight: u32) -> Result<Self> {
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
+        let descriptor_pool = Self::create_descriptor_pool(context.clone(), frames_in_flight);
+        let descriptor_sets = descriptor_pool
+            .allocate_descriptor_sets(descriptor_set_layout.layout(), frames_in_flight)?;
+
+        let uniform_alignment =
+            context.align_uniform_buffer_size(mem::size_of::<GridUniformBufferObject>() as _);
+        let frames = descriptor_sets
+            .into_iter()
+            .map(|descriptor_set| {
+                let uniform_buffer = Buffer::new_mapped_basic(
+                    context.clone(),
+                    (PbrPipelineData::MAX_VIEWPORTS as u64 * uniform_alignment) as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )?;
+                Self::update_descriptor_set(context.clone(), descriptor_set, &uniform_buffer);
+                Ok(GridFrameData {
+                    uniform_buffer,
+                    descriptor_set,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            context,
+            descriptor_set_layout,
+            _descriptor_pool: descriptor_pool,
+            frames,
+            uniform_alignment,
+            pipeline: None,
+        })
+    }
+
+    fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+        DescriptorSetLayout::new(context, layout_create_info).unwrap()
+    }
+
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        frames_in_flight: u32,
+    ) -> DescriptorPool {
+        let ubo_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: frames_in_flight,
+        };
+        let pool_sizes = [ubo_pool_size];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight)
+            .build();
+
+        DescriptorPool::new(context, pool_info).unwrap()
+    }
+
+    fn update_descriptor_set(
+        context: Arc<VulkanContext>,
+        descriptor_set: vk::DescriptorSet,
+        uniform_buffer: &Buffer,
+    ) {
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer())
+            .offset(0)
+            .range(mem::size_of::<GridUniformBufferObject>() as vk::DeviceSize)
+            .build();
+        let buffer_infos = [buffer_info];
+
+        let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos)
+            .build();
+
+        unsafe {
+            context
+                .logical_device()
+                .logical_device()
+                .update_descriptor_sets(&[ubo_descriptor_write], &[])
+        }
+    }
+
+    // The pipeline draws into the offscreen render pass, between the opaque and blended assets
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        render_pass: Arc<RenderPass>,
+        samples: vk::SampleCountFlags,
+    ) -> Result<()> {
+        let shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/environment/fullscreen_triangle.vert.spv")
+            .fragment("assets/shaders/debug/grid.frag.spv")
+            .build()
+            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        // The grid is depth tested against the opaque scene,
+        // but doesn't hide the blended primitives drawn after it
+        let settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(render_pass)
+            .vertex_state_info(vk::PipelineVertexInputStateCreateInfo::builder().build())
+            .descriptor_set_layout(self.descriptor_set_layout.clone())
+            .shader_set(shader_set)
+            .blended(true)
+            .depth_write_enabled(false)
+            .rasterization_samples(samples)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        self.pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Grid");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    // Uploads the grid settings and cameras for the frame in flight
+    pub fn update(
+        &mut self,
+        frame: usize,
+        cameras: &[SceneCamera],
+        projections: &[glm::Mat4],
+        settings: &GridSettings,
+    ) -> Result<()> {
+        let ubos = cameras
+            .iter()
+            .zip(projections.iter())
+            .map(|(camera, projection)| {
+                let view_projection = projection * camera.view;
+                GridUniformBufferObject {
+                    view_projection,
+                    inverse_view_projection: glm::inverse(&view_projection),
+                    color: glm::make_vec4(&settings.color),
+                    spacing: settings.spacing,
+                    fade_distance: settings.fade_distance,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.frames[frame]
+            .uniform_buffer
+            .upload_to_buffer_aligned(&ubos, 0, self.uniform_alignment)
+    }
+
+    // The grid is drawn once per viewport, each in its own region
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        frame: usize,
+    ) {
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        self.context.begin_debug_label(command_buffer, "Grid");
+        let device = self.context.logical_device().logical_device();
+        pipeline.bind(device, command_buffer);
+
+        for (viewport, region) in regions.iter().enumerate() {
+            self.context
+                .logical_device()
+                .set_viewport(command_buffer, *region);
+            let uniform_offset = (viewport as u64 * self.uniform_alignment) as u32;
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline.layout(),
+                    0,
+                    &[self.frames[frame].descriptor_set],
+                    &[uniform_offset],
+                );
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            }
+        }
+        self.context.end_debug_label(command_buffer);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::renderer::vulkan::{
+    core::VulkanContext, pbr::PushConstantBlockMaterial, resource::Buffer,
+};
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::{mem, sync::Arc};
+
+// The data of each slot of an instance buffer.
+// This needs to match the per-instance attributes of the pbr vertex shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstanceVertex {
+    pub model: glm::Mat4,
+    // X value is the draw's index into the indirect material buffer, or -1 for direct draws.
+    // Y value is the offset of the mesh's dynamic uniform buffer entry, in vec4s.
+    pub indirect_draw: glm::Vec2,
+}
+
+impl InstanceVertex {
+    pub fn direct(model: glm::Mat4) -> Self {
+        Self {
+            model,
+            indirect_draw: glm::vec2(-1.0, 0.0),
+        }
+    }
+}
+
+// The material struct of the pbr fragment shader,
+// which already fills its std430 array stride of 128 bytes
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IndirectMaterial {
+    pub material: PushConstantBlockMaterial,
+}
+
+// A primitive draw that doesn't need its own push constants or dynamic offset
+#[derive(Clone, Copy)]
+pub struct IndirectDraw {
+    // The first instance is the draw's offset into the scene's instance buffer
+    pub command: vk::DrawIndexedIndirectCommand,
+    pub material: PushConstantBlockMaterial,
+    // The offset of the mesh's entry in the dynamic uniform buffer
+    pub dynamic_offset: u32,
+    pub double_sided: bool,
+}
+
+// A range of commands recorded with a single indirect draw
+struct IndirectBatch {
+    double_sided: bool,
+    first_command: usize,
+    number_of_commands: usize,
+}
+
+// Opaque primitives drawn with one indirect draw per asset and cull mode.
+// Indirect draws can't vary push constants or dynamic offsets, so each draw
+// gets its own range of instance slots that tell the shaders where its material and mesh are.
+pub struct IndirectDraws {
+    command_buffer: Buffer,
+    instance_buffer: Buffer,
+    batches: Vec<IndirectBatch>,
+    // The scene instance each slot copies its model matrix from, along with its indirect draw
+    slots: Vec<(usize, glm::Vec2)>,
+    materials: Vec<IndirectMaterial>,
+}
+
+impl IndirectDraws {
+    // Returns None when there are no draws to batch
+    pub fn new(context: Arc<VulkanContext>, asset_draws: &[Vec<IndirectDraw>]) -> Option<Self> {
+        let mut commands = Vec::new();
+        let mut batches = Vec::new();
+        let mut slots = Vec::new();
+        let mut materials = Vec::new();
+        for draws in asset_draws.iter() {
+            for double_sided in [false, true].iter() {
+                let first_command = commands.len();
+                for draw in draws
+                    .iter()
+                    .filter(|draw| draw.double_sided == *double_sided)
+                {
+                    let indirect_draw = glm::vec2(
+                        materials.len() as f32,
+                        (draw.dynamic_offset / mem::size_of::<glm::Vec4>() as u32) as f32,
+                    );
+                    let first_slot = slots.len();
+                    slots.extend((0..draw.command.instance_count as usize).map(|instance| {
+                        (
+                            draw.command.first_instance as usize + instance,
+                            indirect_draw,
+                        )
+                    }));
+                    commands.push(vk::DrawIndexedIndirectCommand {
+                        first_instance: first_slot as _,
+                        ..draw.command
+                    });
+                    materials.push(IndirectMaterial {
+                        material: draw.material,
+                    });
+                }
+
+                if commands.len() > first_command {
+                    batches.push(IndirectBatch {
+                        double_sided: *double_sided,
+                        first_command,
+                        number_of_commands: commands.len() - first_command,
+                    });
+                }
+            }
+        }
+
+        if commands.is_empty() {
+            return None;
+        }
+
+        let command_buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            (commands.len() * mem::size_of::<vk::DrawIndexedIndirectCommand>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap();
+        command_buffer.upload_to_buffer(&commands, 0).unwrap();
+        context.set_debug_name(command_buffer.buffer(), "Indirect Draw Commands");
+
+        let instance_buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            (slots.len() * mem::size_of::<InstanceVertex>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap();
+        context.set_debug_name(instance_buffer.buffer(), "Indirect Instance Transforms");
+
+        Some(Self {
+            command_buffer,
+            instance_buffer,
+            batches,
+            slots,
+            materials,
+        })
+    }
+
+    pub fn materials(&self) -> &[IndirectMaterial] {
+        &self.materials
+    }
+
+    pub fn number_of_draw_calls(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn number_of_instances(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Copies the model matrix of every scene instance into the slots of its draws
+    pub fn update(&self, instance_models: &[glm::Mat4]) {
+        let instances = self
+            .slots
+            .iter()
+            .map(|(instance, indirect_draw)| InstanceVertex {
+                model: instance_models[*instance],
+                indirect_draw: *indirect_draw,
+            })
+            .collect::<Vec<_>>();
+        self.instance_buffer
+            .upload_to_buffer(&instances, 0)
+            .unwrap();
+    }
+
+    // The vertex and index buffers, descriptor set and push constants must already be bound.
+    // This replaces the instance buffer binding, so it must be rebound for any direct draws.
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline: impl Fn(bool) -> vk::Pipeline,
+    ) {
+        let stride = mem::size_of::<vk::DrawIndexedIndirectCommand>();
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                1,
+                &[self.instance_buffer.buffer()],
+                &[0],
+            );
+            for batch in self.batches.iter() {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline(batch.double_sided),
+                );
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    self.command_buffer.buffer(),
+                    (batch.first_command * stride) as vk::DeviceSize,
+                    batch.number_of_commands as u32,
+                    stride as u32,
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,11 @@
-pub use self::{environment::*, scene::*};
+pub use self::{environment::*, scene::*, shadow::*};
 
+pub mod debug_shapes;
 pub mod environment;
+pub mod grid;
+pub mod indirect;
+pub mod particles;
+pub mod picking;
 pub mod scene;
+pub mod shadow;
+pub mod transmission;
@@ -0,0 +1,543 @@
+use crate::{
+    particles::ParticleEmitter,
+    renderer::{
+        byte_slice_from,
+        vulkan::{
+            core::VulkanContext,
+            pbr::PbrPipelineData,
+            render::{
+                DescriptorPool, DescriptorSetLayout, RenderPass, RenderPipeline,
+                RenderPipelineSettingsBuilder,
+            },
+            resource::{
+                Buffer, CommandPool, DynamicGeometryBuffer, SamplerSettings, ShaderCache,
+                ShaderPathSetBuilder, TextureBundle, TextureDescription,
+            },
+        },
+        SceneCamera,
+    },
+};
+use anyhow::Result;
+use ash::{version::DeviceV1_0, vk};
+use log::warn;
+use nalgebra_glm as glm;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Arc,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParticleVertex {
+    pub position: glm::Vec3,
+    // Vertices with no size are culled by the vertex shader
+    pub size: f32,
+    pub color: glm::Vec4,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct ParticleUniformBufferObject {
+    pub view: glm::Mat4,
+    pub projection: glm::Mat4,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ParticlePushConstantBlock {
+    pub viewport_height: f32,
+    pub max_point_size: f32,
+}
+
+// A sprite along with the descriptor set it is drawn with in each frame in flight
+struct ParticleTexture {
+    _texture: TextureBundle,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+// The vertices of an emitter, which has a slot for each particle it can hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ParticleDraw {
+    texture: usize,
+    first_vertex: u32,
+    vertex_count: u32,
+}
+
+pub struct ParticleFrameData {
+    pub uniform_buffer: Buffer,
+    pub geometry_buffer: DynamicGeometryBuffer,
+    draws: Vec<ParticleDraw>,
+}
+
+// Draws the particles of every emitter as additive point sprites over the scene.
+// The particles are simulated on the cpu and copied into the vertex buffer of the frame in flight.
+pub struct ParticleRenderer {
+    context: Arc<VulkanContext>,
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    descriptor_pool: DescriptorPool,
+    frames: Vec<ParticleFrameData>,
+    // The stride between the uniform buffer entries of each viewport
+    uniform_alignment: u64,
+    // The default sprite comes first, followed by the textures of the emitters
+    textures: Vec<ParticleTexture>,
+    texture_indices: HashMap<String, usize>,
+    // Textures that failed to load aren't loaded again
+    failed_textures: HashSet<String>,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl ParticleRenderer {
+    // Including the default sprite
+    const MAX_TEXTURES: u32 = 16;
+
+    const SPRITE_DIMENSION: u32 = 64;
+
+    pub fn new(
+        context: Arc<VulkanContext>,
+        command_pool: &CommandPool,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
+        let descriptor_pool = Self::create_descriptor_pool(context.clone(), frames_in_flight);
+
+        let uniform_alignment =
+            context.align_uniform_buffer_size(mem::size_of::<ParticleUniformBufferObject>() as _);
+        let frames = (0..frames_in_flight)
+            .map(|_| {
+                let uniform_buffer = Buffer::new_mapped_basic(
+                    context.clone(),
+                    (PbrPipelineData::MAX_VIEWPORTS as u64 * uniform_alignment) as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )?;
+                Ok(ParticleFrameData {
+                    uniform_buffer,
+                    geometry_buffer: DynamicGeometryBuffer::new(context.clone())?,
+                    draws: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut renderer = Self {
+            context,
+            descriptor_set_layout,
+            descriptor_pool,
+            frames,
+            uniform_alignment,
+            textures: Vec::new(),
+            texture_indices: HashMap::new(),
+            failed_textures: HashSet::new(),
+            pipeline: None,
+        };
+        renderer.add_texture(command_pool, &Self::sprite_description())?;
+        Ok(renderer)
+    }
+
+    fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding, sampler_binding];
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+        DescriptorSetLayout::new(context, layout_create_info).unwrap()
+    }
+
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        frames_in_flight: u32,
+    ) -> DescriptorPool {
+        let number_of_sets = frames_in_flight * Self::MAX_TEXTURES;
+        let ubo_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: number_of_sets,
+        };
+        let sampler_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: number_of_sets,
+        };
+        let pool_sizes = [ubo_pool_size, sampler_pool_size];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(number_of_sets)
+            .build();
+
+        DescriptorPool::new(context, pool_info).unwrap()
+    }
+
+    fn update_descriptor_set(
+        context: Arc<VulkanContext>,
+        descriptor_set: vk::DescriptorSet,
+        uniform_buffer: &Buffer,
+        texture: &TextureBundle,
+    ) {
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer())
+            .offset(0)
+            .range(mem::size_of::<ParticleUniformBufferObject>() as vk::DeviceSize)
+            .build();
+        let buffer_infos = [buffer_info];
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view.view())
+            .sampler(texture.sampler.sampler())
+            .build();
+        let image_infos = [image_info];
+
+        let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos)
+            .build();
+
+        let sampler_descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+
+        unsafe {
+            context
+                .logical_device()
+                .logical_device()
+                .update_descriptor_sets(&[ubo_descriptor_write, sampler_descriptor_write], &[])
+        }
+    }
+
+    // A white disc that fades out towards its edge
+    fn sprite_description() -> TextureDescription {
+        let dimension = Self::SPRITE_DIMENSION;
+        let pixels = (0..dimension * dimension)
+            .flat_map(|index| {
+                let pixel = glm::vec2((index % dimension) as f32, (index / dimension) as f32);
+                let offset =
+                    (pixel + glm::vec2(0.5, 0.5)) / dimension as f32 * 2.0 - glm::vec2(1.0, 1.0);
+                let falloff = (1.0 - glm::length(&offset)).max(0.0);
+                let alpha = (falloff * falloff * 255.0) as u8;
+                vec![255, 255, 255, alpha]
+            })
+            .collect::<Vec<_>>();
+
+        TextureDescription {
+            format: vk::Format::R8G8B8A8_UNORM,
+            width: dimension,
+            height: dimension,
+            pixels,
+            mip_levels: TextureDescription::calculate_mip_levels(dimension, dimension),
+            is_srgb: false,
+            mip_sizes: Vec::new(),
+        }
+    }
+
+    fn add_texture(
+        &mut self,
+        command_pool: &CommandPool,
+        description: &TextureDescription,
+    ) -> Result<usize> {
+        // Sprites are sampled across the whole point, so they shouldn't wrap at the edges
+        let sampler_settings = SamplerSettings {
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let texture = TextureBundle::new(
+            self.context.clone(),
+            command_pool,
+            description,
+            &sampler_settings,
+        )?;
+
+        let descriptor_sets = self.descriptor_pool.allocate_descriptor_sets(
+            self.descriptor_set_layout.layout(),
+            self.frames.len() as _,
+        )?;
+        for (frame_data, descriptor_set) in self.frames.iter().zip(descriptor_sets.iter()) {
+            Self::update_descriptor_set(
+                self.context.clone(),
+                *descriptor_set,
+                &frame_data.uniform_buffer,
+                &texture,
+            );
+        }
+
+        self.textures.push(ParticleTexture {
+            _texture: texture,
+            descriptor_sets,
+        });
+        Ok(self.textures.len() - 1)
+    }
+
+    // Emitters are drawn with the default sprite until their texture is loaded,
+    // or if it can't be loaded
+    pub fn load_textures(&mut self, command_pool: &CommandPool, emitters: &[ParticleEmitter]) {
+        for path in emitters
+            .iter()
+            .filter_map(|emitter| emitter.texture.as_ref())
+        {
+            if self.texture_indices.contains_key(path) || self.failed_textures.contains(path) {
+                continue;
+            }
+
+            if self.textures.len() as u32 >= Self::MAX_TEXTURES {
+                warn!(
+                    "Particle texture '{}' exceeds the limit of {} textures, using the default sprite",
+                    path,
+                    Self::MAX_TEXTURES
+                );
+                self.failed_textures.insert(path.to_string());
+                continue;
+            }
+
+            let index = TextureDescription::from_file(path)
+                .and_then(|description| self.add_texture(command_pool, &description));
+            match index {
+                Ok(index) => {
+                    self.texture_indices.insert(path.to_string(), index);
+                }
+                Err(error) => {
+                    warn!("Failed to load particle texture '{}': {}", path, error);
+                    self.failed_textures.insert(path.to_string());
+                }
+            }
+        }
+    }
+
+    // The pipeline draws into the offscreen render pass, after the scene
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        render_pass: Arc<RenderPass>,
+        samples: vk::SampleCountFlags,
+    ) -> Result<()> {
+        let descriptions = Self::vertex_input_descriptions();
+        let attributes = Self::vertex_attributes();
+        let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&descriptions)
+            .vertex_attribute_descriptions(&attributes)
+            .build();
+
+        let shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/particles/particle.vert.spv")
+            .fragment("assets/shaders/particles/particle.frag.spv")
+            .build()
+            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(mem::size_of::<ParticlePushConstantBlock>() as u32)
+            .build();
+
+        // Additive particles don't need sorting, since the order they are drawn in doesn't matter
+        let settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(render_pass)
+            .vertex_state_info(vertex_state_info)
+            .descriptor_set_layout(self.descriptor_set_layout.clone())
+            .shader_set(shader_set)
+            .additive(true)
+            .depth_write_enabled(false)
+            .rasterization_samples(samples)
+            .topology(vk::PrimitiveTopology::POINT_LIST)
+            .push_constant_range(push_constant_range)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        self.pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Particles");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    fn vertex_attributes() -> [vk::VertexInputAttributeDescription; 3] {
+        let position_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+
+        let size_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32_SFLOAT)
+            .offset((3 * mem::size_of::<f32>()) as _)
+            .build();
+
+        let color_description = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((4 * mem::size_of::<f32>()) as _)
+            .build();
+
+        [position_description, size_description, color_description]
+    }
+
+    fn vertex_input_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        let vertex_input_binding_description = vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<ParticleVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build();
+        [vertex_input_binding_description]
+    }
+
+    // Uploads the particles and cameras for the frame in flight.
+    // Returns true if the draws recorded for the frame are out of date,
+    // which only happens when the emitters change or the buffers are reallocated.
+    pub fn update(
+        &mut self,
+        frame: usize,
+        cameras: &[SceneCamera],
+        projections: &[glm::Mat4],
+        emitters: &[ParticleEmitter],
+    ) -> Result<bool> {
+        let texture_indices = &self.texture_indices;
+        let frame_data = &mut self.frames[frame];
+        let previous_draws = (
+            frame_data.geometry_buffer.vertex_buffer.buffer(),
+            frame_data.draws.clone(),
+        );
+
+        // Every emitter gets a slot for each particle it can hold,
+        // so the draws stay the same while particles spawn and die
+        let mut vertices = Vec::new();
+        frame_data.draws.clear();
+        for emitter in emitters.iter().filter(|emitter| emitter.max_particles > 0) {
+            let first_vertex = vertices.len();
+            vertices.extend(
+                emitter
+                    .particles
+                    .iter()
+                    .take(emitter.max_particles)
+                    .map(|particle| ParticleVertex {
+                        position: particle.position,
+                        size: emitter.size,
+                        color: emitter.particle_color(particle),
+                    }),
+            );
+            vertices.resize(
+                first_vertex + emitter.max_particles,
+                ParticleVertex {
+                    position: glm::Vec3::zeros(),
+                    size: 0.0,
+                    color: glm::Vec4::zeros(),
+                },
+            );
+
+            let texture = emitter
+                .texture
+                .as_ref()
+                .and_then(|path| texture_indices.get(path))
+                .copied()
+                .unwrap_or(0);
+            frame_data.draws.push(ParticleDraw {
+                texture,
+                first_vertex: first_vertex as u32,
+                vertex_count: emitter.max_particles as u32,
+            });
+        }
+
+        if !vertices.is_empty() {
+            let ubos = cameras
+                .iter()
+                .zip(projections.iter())
+                .map(|(camera, projection)| ParticleUniformBufferObject {
+                    view: camera.view,
+                    projection: *projection,
+                })
+                .collect::<Vec<_>>();
+            frame_data
+                .uniform_buffer
+                .upload_to_buffer_aligned(&ubos, 0, self.uniform_alignment)?;
+            frame_data.geometry_buffer.upload_vertices(&vertices)?;
+        }
+
+        let draws = (
+            frame_data.geometry_buffer.vertex_buffer.buffer(),
+            frame_data.draws.clone(),
+        );
+        Ok(draws != previous_draws)
+    }
+
+    pub fn has_particles(&self, frame: usize) -> bool {
+        !self.frames[frame].draws.is_empty()
+    }
+
+    // The particles are drawn once per viewport, each in its own region
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        frame: usize,
+    ) {
+        let frame_data = &self.frames[frame];
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) if !frame_data.draws.is_empty() => pipeline,
+            _ => return,
+        };
+
+        let device = self.context.logical_device().logical_device();
+        pipeline.bind(device, command_buffer);
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[frame_data.geometry_buffer.vertex_buffer.buffer()],
+                &[0],
+            );
+        }
+
+        let max_point_size = self.context.max_point_size();
+        for (viewport, region) in regions.iter().enumerate() {
+            self.context
+                .logical_device()
+                .set_viewport(command_buffer, *region);
+            let push_constants = ParticlePushConstantBlock {
+                viewport_height: region.extent.height as f32,
+                max_point_size,
+            };
+            let uniform_offset = (viewport as u64 * self.uniform_alignment) as u32;
+            unsafe {
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline.layout(),
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    byte_slice_from(&push_constants),
+                );
+                for draw in frame_data.draws.iter() {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.pipeline.layout(),
+                        0,
+                        &[self.textures[draw.texture].descriptor_sets[frame]],
+                        &[uniform_offset],
+                    );
+                    device.cmd_draw(command_buffer, draw.vertex_count, 1, draw.first_vertex, 0);
+                }
+            }
+        }
+    }
+}
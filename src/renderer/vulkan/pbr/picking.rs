@@ -0,0 +1,443 @@
+use crate::renderer::vulkan::{
+    asset::GltfAsset,
+    core::VulkanContext,
+    render::{
+        DescriptorSetLayout, Framebuffer, RenderPass, RenderPipeline, RenderPipelineSettingsBuilder,
+    },
+    resource::{
+        image::{ImageView, Texture},
+        Buffer, ShaderCache, ShaderPathSetBuilder,
+    },
+};
+use anyhow::{ensure, Result};
+use ash::{version::DeviceV1_0, vk};
+use std::{mem, sync::Arc};
+
+#[allow(dead_code)]
+pub struct PickingPushConstantBlock {
+    // Zero is reserved for pixels that aren't covered by any instance
+    pub id: u32,
+}
+
+// Renders the id of each asset instance so the one under the cursor can be read back
+pub struct PickingPass {
+    pub render_pass: Arc<RenderPass>,
+    pub id_texture: Texture,
+    pub id_view: ImageView,
+    pub depth_texture: Texture,
+    pub depth_view: ImageView,
+    pub framebuffer: Framebuffer,
+    pub pipeline: Option<RenderPipeline>,
+    readback_buffer: Buffer,
+    depth_format: vk::Format,
+    extent: vk::Extent2D,
+    context: Arc<VulkanContext>,
+}
+
+impl PickingPass {
+    pub const FORMAT: vk::Format = vk::Format::R32_UINT;
+
+    pub fn new(context: Arc<VulkanContext>, extent: vk::Extent2D) -> Self {
+        let depth_format = context.determine_depth_format(
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+
+        let render_pass = Arc::new(Self::create_render_pass(context.clone(), depth_format));
+
+        let id_texture = Self::create_texture(
+            context.clone(),
+            extent,
+            Self::FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+        let id_view = Self::create_image_view(
+            context.clone(),
+            &id_texture,
+            Self::FORMAT,
+            vk::ImageAspectFlags::COLOR,
+        );
+        let depth_texture = Self::create_texture(
+            context.clone(),
+            extent,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+        let depth_view = Self::create_image_view(
+            context.clone(),
+            &depth_texture,
+            depth_format,
+            vk::ImageAspectFlags::DEPTH,
+        );
+        let framebuffer =
+            Self::create_framebuffer(context.clone(), &render_pass, &id_view, &depth_view, extent);
+
+        let readback_buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            mem::size_of::<u32>() as _,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuToCpu,
+        )
+        .unwrap();
+
+        Self {
+            render_pass,
+            id_texture,
+            id_view,
+            depth_texture,
+            depth_view,
+            framebuffer,
+            pipeline: None,
+            readback_buffer,
+            depth_format,
+            extent,
+            context,
+        }
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    // The render pass is kept, so the pipeline remains valid
+    pub fn resize(&mut self, extent: vk::Extent2D) {
+        if extent == self.extent {
+            return;
+        }
+
+        self.id_texture = Self::create_texture(
+            self.context.clone(),
+            extent,
+            Self::FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+        self.id_view = Self::create_image_view(
+            self.context.clone(),
+            &self.id_texture,
+            Self::FORMAT,
+            vk::ImageAspectFlags::COLOR,
+        );
+        self.depth_texture = Self::create_texture(
+            self.context.clone(),
+            extent,
+            self.depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+        self.depth_view = Self::create_image_view(
+            self.context.clone(),
+            &self.depth_texture,
+            self.depth_format,
+            vk::ImageAspectFlags::DEPTH,
+        );
+        self.framebuffer = Self::create_framebuffer(
+            self.context.clone(),
+            &self.render_pass,
+            &self.id_view,
+            &self.depth_view,
+            extent,
+        );
+        self.extent = extent;
+    }
+
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        descriptor_set_layout: Arc<DescriptorSetLayout>,
+    ) -> Result<()> {
+        let descriptions = GltfAsset::create_vertex_input_descriptions();
+        let attributes = GltfAsset::create_vertex_attributes();
+        let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&descriptions)
+            .vertex_attribute_descriptions(&attributes)
+            .build();
+
+        let shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/pbr/pbr.vert.spv")
+            .fragment("assets/shaders/pbr/picking.frag.spv")
+            .build()
+            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(mem::size_of::<PickingPushConstantBlock>() as u32)
+            .build();
+
+        let settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(self.render_pass.clone())
+            .vertex_state_info(vertex_state_info)
+            .descriptor_set_layout(descriptor_set_layout)
+            .shader_set(shader_set)
+            .push_constant_range(push_constant_range)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        self.pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Picking");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    // Records the id pass followed by a copy of the pixel at (x, y) into the readback buffer
+    pub fn record<T>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        x: u32,
+        y: u32,
+        mut action: T,
+    ) -> Result<()>
+    where
+        T: FnMut(&RenderPipeline),
+    {
+        ensure!(
+            x < self.extent.width && y < self.extent.height,
+            "Failed to pick pixel ({}, {}) outside of the {}x{} picking pass!",
+            x,
+            y,
+            self.extent.width,
+            self.extent.height
+        );
+
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass.render_pass())
+            .framebuffer(self.framebuffer.framebuffer())
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        RenderPass::record(
+            self.context.clone(),
+            command_buffer,
+            &render_pass_begin_info,
+            || {
+                self.context
+                    .logical_device()
+                    .update_viewport(command_buffer, self.extent);
+                pipeline.bind(
+                    self.context.logical_device().logical_device(),
+                    command_buffer,
+                );
+                action(pipeline);
+            },
+        );
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D {
+                x: x as _,
+                y: y as _,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build();
+
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build();
+
+        let device = self.context.logical_device().logical_device();
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.id_texture.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback_buffer.buffer(),
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        Ok(())
+    }
+
+    // Must only be called after the recorded commands have finished executing
+    pub fn read_id(&self) -> Result<u32> {
+        self.readback_buffer.invalidate(0, mem::size_of::<u32>())?;
+        let data_pointer = self.readback_buffer.map_memory()?;
+        let id = unsafe { (data_pointer as *const u32).read_unaligned() };
+        self.readback_buffer.unmap_memory()?;
+        Ok(id)
+    }
+
+    fn create_texture(
+        context: Arc<VulkanContext>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Texture {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .flags(vk::ImageCreateFlags::empty())
+            .build();
+
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+
+        Texture::new(context, &allocation_create_info, &image_create_info).unwrap()
+    }
+
+    fn create_image_view(
+        context: Arc<VulkanContext>,
+        texture: &Texture,
+        format: vk::Format,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> ImageView {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(texture.image())
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY,
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        ImageView::new(context, create_info).unwrap()
+    }
+
+    fn create_framebuffer(
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        id_view: &ImageView,
+        depth_view: &ImageView,
+        extent: vk::Extent2D,
+    ) -> Framebuffer {
+        let attachments = [id_view.view(), depth_view.view()];
+        let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass.render_pass())
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        Framebuffer::new(context, create_info).unwrap()
+    }
+
+    fn create_render_pass(context: Arc<VulkanContext>, depth_format: vk::Format) -> RenderPass {
+        let id_attachment_description = vk::AttachmentDescription::builder()
+            .format(Self::FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .build();
+
+        let depth_attachment_description = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let attachment_descriptions = [id_attachment_description, depth_attachment_description];
+
+        let id_attachment_reference = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build();
+        let id_attachment_references = [id_attachment_reference];
+
+        let depth_attachment_reference = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let subpass_description = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&id_attachment_references)
+            .depth_stencil_attachment(&depth_attachment_reference)
+            .build();
+        let subpass_descriptions = [subpass_description];
+
+        // The id attachment is copied to the readback buffer after the pass
+        let subpass_dependencies = [vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build()];
+
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descriptions)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&subpass_dependencies)
+            .build();
+
+        RenderPass::new(context, &create_info).unwrap()
+    }
+}
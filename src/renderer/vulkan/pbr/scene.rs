@@ -1,35 +1,53 @@
 use crate::{
-    camera::OrbitalCamera,
+    camera::CameraProjection,
+    particles::ParticleEmitter,
     renderer::{
         byte_slice_from,
         vulkan::{
-            asset::{GltfAsset, Primitive},
+            asset::{AssetLoader, GltfAsset, Primitive},
             core::VulkanContext,
-            pbr::environment::{
-                create_skybox_pipeline, Brdflut, HdrCubemap, IrradianceMap, PrefilterMap,
-                SkyboxPipelineData, SkyboxRenderer, SkyboxUniformBufferObject,
+            pbr::{
+                debug_shapes::DebugShapesRenderer,
+                environment::{
+                    create_skybox_pipeline, Brdflut, EnvironmentCache, HdrCubemap, IrradianceMap,
+                    PrefilterMap, SkyboxCubemap, SkyboxPipelineData, SkyboxRenderer,
+                    SkyboxUniformBufferObject,
+                },
+                grid::GridRenderer,
+                indirect::{IndirectDraw, IndirectDraws, IndirectMaterial, InstanceVertex},
+                particles::ParticleRenderer,
+                picking::{PickingPass, PickingPushConstantBlock},
+                shadow::ShadowMap,
+                transmission::TransmissionMap,
             },
             render::{
                 DescriptorPool, DescriptorSetLayout, GraphicsPipeline, RenderPass, RenderPipeline,
-                RenderPipelineSettingsBuilder,
+                RenderPipelineSettings, RenderPipelineSettingsBuilder,
             },
             resource::{
-                image::{DummyImage, TextureBundle},
+                image::{Cubemap, CubemapFaces, DummyImage, TextureBundle, TextureDescription},
                 Buffer, CommandPool, GeometryBuffer, ShaderCache, ShaderPathSetBuilder,
             },
         },
-        AssetName, Transform,
+        Aabb, AnimationInfo, AssetCamera, DebugShapes, Environment, IblSettings, Light, LightKind,
+        LoadProgress, RenderScene, RenderSettings, RenderStats, SceneInfo,
     },
-    system::System,
 };
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use ash::{version::DeviceV1_0, vk};
 use gltf::material::AlphaMode;
-use legion::prelude::*;
-use log::debug;
+use legion::prelude::Entity;
+use log::{debug, error, warn};
 use nalgebra_glm as glm;
-use std::{collections::HashMap, mem, sync::Arc};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::Path,
+    sync::Arc,
+};
 
+#[derive(Clone, Copy)]
 pub struct PushConstantBlockMaterial {
     pub base_color_factor: glm::Vec4,
     pub emissive_factor: glm::Vec3,
@@ -42,19 +60,145 @@ pub struct PushConstantBlockMaterial {
     pub roughness_factor: f32,
     pub alpha_mode: i32,
     pub alpha_cutoff: f32,
+    // One bit per texture, set when the texture is sampled with the second UV set
+    pub uv_sets: i32,
+    // KHR_materials_transmission, zero for materials that aren't transmissive
+    pub transmission_factor: f32,
+    pub transmission_texture_set: i32, // R channel - transmission values
+    // KHR_materials_clearcoat, zero for materials without a clearcoat layer
+    pub clearcoat_factor: f32,
+    pub clearcoat_roughness_factor: f32,
+    pub clearcoat_texture_set: i32, // R channel - clearcoat values
+    pub clearcoat_roughness_texture_set: i32, // G channel - clearcoat roughness values
+    // Scales the X and Y of the sampled normals
+    pub normal_scale: f32,
+    // How much of the sampled occlusion is applied
+    pub occlusion_strength: f32,
+    // KHR_materials_ior, the default of 1.5 gives the usual dielectric F0 of 0.04
+    pub ior: f32,
+    // KHR_materials_specular
+    pub specular_factor: f32,
+    pub specular_texture_set: i32, // A channel - specular values
+    pub specular_color_factor: glm::Vec3,
+    pub specular_color_texture_set: i32,
+}
+
+impl Default for PushConstantBlockMaterial {
+    fn default() -> Self {
+        Self {
+            base_color_factor: glm::vec4(0.0, 0.0, 0.0, 1.0),
+            emissive_factor: glm::Vec3::zeros(),
+            color_texture_set: -1,
+            metallic_roughness_texture_set: -1,
+            normal_texture_set: -1,
+            occlusion_texture_set: -1,
+            emissive_texture_set: -1,
+            metallic_factor: 0.0,
+            roughness_factor: 0.0,
+            alpha_mode: gltf::material::AlphaMode::Opaque as i32,
+            alpha_cutoff: 0.0,
+            uv_sets: 0,
+            transmission_factor: 0.0,
+            transmission_texture_set: -1,
+            clearcoat_factor: 0.0,
+            clearcoat_roughness_factor: 0.0,
+            clearcoat_texture_set: -1,
+            clearcoat_roughness_texture_set: -1,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            ior: 1.5,
+            specular_factor: 1.0,
+            specular_texture_set: -1,
+            specular_color_factor: glm::vec3(1.0, 1.0, 1.0),
+            specular_color_texture_set: -1,
+        }
+    }
+}
+
+impl PushConstantBlockMaterial {
+    pub fn is_transmissive(&self) -> bool {
+        self.transmission_factor > 0.0
+    }
+
+    // Only the first two UV sets are supported, any other set samples the first one
+    fn set_uv_set(&mut self, texture: MaterialTexture, uv_set: u32) {
+        if uv_set == 1 {
+            self.uv_sets |= 1 << texture as i32;
+        }
+    }
+}
+
+// The bit of each texture in the uv set mask of a material.
+// These need to match the defines in the pbr fragment shader.
+#[derive(Debug, Clone, Copy)]
+enum MaterialTexture {
+    Color,
+    MetallicRoughness,
+    Normal,
+    Occlusion,
+    Emissive,
+    Transmission,
+    Clearcoat,
+    ClearcoatRoughness,
+    Specular,
+    SpecularColor,
+}
+
+// This needs to match the light struct in the pbr fragment shader
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LightUniform {
+    pub direction: glm::Vec3,
+    pub range: f32,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    pub position: glm::Vec3,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+    pub kind: i32,
+    pub padding: glm::Vec2,
+}
+
+impl From<&Light> for LightUniform {
+    fn from(light: &Light) -> Self {
+        let kind = match light.kind {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot => 2,
+        };
+        Self {
+            direction: light.direction,
+            range: light.range,
+            color: light.color,
+            intensity: light.intensity,
+            position: light.position,
+            inner_cone_cos: light.inner_cone_cos,
+            outer_cone_cos: light.outer_cone_cos,
+            kind,
+            padding: glm::Vec2::zeros(),
+        }
+    }
 }
 
+#[repr(C)]
 #[derive(Clone, Copy)]
 pub struct UniformBufferObject {
     pub view: glm::Mat4,
     pub projection: glm::Mat4,
     pub camera_position: glm::Vec4,
+    pub light_space_matrix: glm::Mat4,
     pub joint_matrices: [glm::Mat4; UniformBufferObject::MAX_NUM_JOINTS],
+    // X value is the light count.
+    // Y value is the image based lighting intensity.
+    // A vec4 is necessary for proper alignment
+    pub light_info: glm::Vec4,
+    pub lights: [LightUniform; UniformBufferObject::MAX_NUM_LIGHTS],
 }
 
 impl UniformBufferObject {
-    // This needs to match the defined value in the shaders
+    // These need to match the defined values in the shaders
     pub const MAX_NUM_JOINTS: usize = 128;
+    pub const MAX_NUM_LIGHTS: usize = 4;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -62,88 +206,282 @@ pub struct DynamicUniformBufferObject {
     pub model: glm::Mat4,
     // X value is the joint count.
     // Y value is the joint matrix offset.
+    // Z value is the morph target count.
+    // W value is the asset's offset into the morph target buffer.
     // A vec4 is necessary for proper alignment
     pub joint_info: glm::Vec4,
+    pub morph_weights: [glm::Vec4; GltfAsset::MAX_NUM_MORPH_TARGETS / 4],
 }
 
-pub struct PbrPipelineData {
-    pub descriptor_pool: DescriptorPool,
+// The buffers written by the cpu every frame, along with the descriptor set that binds them.
+// Each frame in flight has its own copy so the next frame can be written while one is rendered.
+pub struct PbrFrameData {
     pub uniform_buffer: Buffer,
     pub dynamic_uniform_buffer: Buffer,
-    pub dynamic_alignment: u64,
     pub descriptor_set: vk::DescriptorSet,
+}
+
+pub struct PbrPipelineData {
+    pub descriptor_pool: DescriptorPool,
+    pub frames: Vec<PbrFrameData>,
+    // Morph target deltas of every asset
+    pub morph_target_buffer: Buffer,
+    // Materials of the draws recorded indirectly
+    pub indirect_material_buffer: Buffer,
+    pub dynamic_alignment: u64,
+    // The stride between the uniform buffer entries of each viewport
+    pub uniform_alignment: u64,
+    pub mesh_capacity: usize,
     pub dummy: DummyImage,
     pub descriptor_set_layout: Arc<DescriptorSetLayout>,
+    // The number of textures the texture array can hold
+    pub texture_capacity: usize,
+    // With descriptor indexing the texture array is partially bound,
+    // otherwise its unused slots are filled with the dummy image
+    pub bindless: bool,
 }
 
 impl PbrPipelineData {
-    pub const MAX_NUMBER_OF_MESHES: usize = 100;
+    pub const MIN_MESH_CAPACITY: usize = 100;
 
-    // This should match the number of textures defined in the shader
+    // The size of the texture array without descriptor indexing
     pub const MAX_TEXTURES: usize = 100;
 
+    // The texture array never grows past this, even if the device allows more
+    pub const MAX_BINDLESS_TEXTURES: usize = 4096;
+
+    // The environment, shadow and transmission maps count against the same sampler limits
+    const RESERVED_SAMPLERS: usize = 5;
+
+    // These need to match the texture array in the pbr fragment shader.
+    // The binding is last because only the last binding can have a variable descriptor count.
+    pub const TEXTURE_BINDING: u32 = 11;
+    pub const TEXTURE_CAPACITY_CONSTANT_ID: u32 = 0;
+
+    // The most cameras the screen can be split between
+    pub const MAX_VIEWPORTS: usize = 4;
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
         textures: &[&TextureBundle],
         environment_maps: &EnvironmentMapSet,
-    ) -> Self {
-        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(context.clone()));
-        let descriptor_pool = Self::create_descriptor_pool(context.clone());
-        let descriptor_set = descriptor_pool
-            .allocate_descriptor_sets(descriptor_set_layout.layout(), 1)
-            .unwrap()[0];
-
-        let uniform_buffer = Buffer::new_mapped_basic(
+        shadow_map: &ShadowMap,
+        transmission_map: &TransmissionMap,
+        number_of_meshes: usize,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let bindless = context.features().descriptor_indexing;
+        let texture_capacity =
+            Self::texture_capacity(bindless, &context.physical_device_properties().limits);
+        let descriptor_set_layout = Arc::new(Self::descriptor_set_layout(
             context.clone(),
-            mem::size_of::<UniformBufferObject>() as _,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk_mem::MemoryUsage::CpuToGpu,
-        )
+            texture_capacity,
+            bindless,
+        ));
+        let descriptor_pool =
+            Self::create_descriptor_pool(context.clone(), texture_capacity, frames_in_flight);
+        let descriptor_sets = if bindless {
+            descriptor_pool.allocate_variable_descriptor_sets(
+                descriptor_set_layout.layout(),
+                frames_in_flight,
+                texture_capacity as _,
+            )
+        } else {
+            descriptor_pool
+                .allocate_descriptor_sets(descriptor_set_layout.layout(), frames_in_flight)
+        }
         .unwrap();
 
-        let dynamic_alignment = Self::calculate_dynamic_alignment(context.clone());
+        // The uniform buffer holds one aligned entry per viewport
+        let uniform_alignment =
+            context.align_uniform_buffer_size(mem::size_of::<UniformBufferObject>() as _);
+
+        let dynamic_alignment =
+            context.align_uniform_buffer_size(mem::size_of::<DynamicUniformBufferObject>() as _);
+
+        // The dynamic uniform buffer holds one aligned entry per mesh instance
+        let mesh_capacity = number_of_meshes.max(Self::MIN_MESH_CAPACITY);
+
+        let frames = descriptor_sets
+            .into_iter()
+            .enumerate()
+            .map(|(frame, descriptor_set)| {
+                let uniform_buffer = Buffer::new_mapped_basic(
+                    context.clone(),
+                    (Self::MAX_VIEWPORTS as u64 * uniform_alignment) as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk_mem::MemoryUsage::CpuToGpu,
+                )
+                .unwrap();
+                context.set_debug_name(
+                    uniform_buffer.buffer(),
+                    &format!("Pbr Uniforms (Frame {})", frame),
+                );
+                PbrFrameData {
+                    uniform_buffer,
+                    dynamic_uniform_buffer: Self::create_dynamic_uniform_buffer(
+                        context.clone(),
+                        mesh_capacity,
+                        dynamic_alignment,
+                    ),
+                    descriptor_set,
+                }
+            })
+            .collect::<Vec<_>>();
 
-        let dynamic_uniform_buffer = Buffer::new_mapped_basic(
-            context.clone(),
-            (Self::MAX_NUMBER_OF_MESHES as u64 * dynamic_alignment) as vk::DeviceSize,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk_mem::MemoryUsage::CpuToGpu,
-        )
-        .unwrap();
+        let morph_target_buffer = Self::create_morph_target_buffer(context.clone(), &[]);
+        let indirect_material_buffer = Self::create_indirect_material_buffer(context.clone(), &[]);
 
         let data = PbrPipelineData {
             descriptor_pool,
-            uniform_buffer,
-            dynamic_uniform_buffer,
-            descriptor_set,
+            frames,
+            morph_target_buffer,
+            indirect_material_buffer,
             dynamic_alignment,
-            dummy: DummyImage::new(context.clone(), &command_pool),
+            uniform_alignment,
+            mesh_capacity,
+            dummy: DummyImage::new(context.clone(), command_pool),
             descriptor_set_layout,
+            texture_capacity,
+            bindless,
         };
 
-        data.update_descriptor_set(context, textures, environment_maps);
+        data.update_descriptor_set(
+            context,
+            textures,
+            environment_maps,
+            shadow_map,
+            transmission_map,
+        )?;
+
+        Ok(data)
+    }
+
+    // The descriptor sets must be updated after the dynamic uniform buffers are reallocated
+    pub fn reserve_meshes(&mut self, context: Arc<VulkanContext>, number_of_meshes: usize) {
+        if number_of_meshes <= self.mesh_capacity {
+            return;
+        }
 
-        data
+        let mesh_capacity = Self::grown_mesh_capacity(self.mesh_capacity, number_of_meshes);
+        for frame in self.frames.iter_mut() {
+            frame.dynamic_uniform_buffer = Self::create_dynamic_uniform_buffer(
+                context.clone(),
+                mesh_capacity,
+                self.dynamic_alignment,
+            );
+        }
+        self.mesh_capacity = mesh_capacity;
     }
 
-    fn calculate_dynamic_alignment(context: Arc<VulkanContext>) -> u64 {
-        let minimum_ubo_alignment = context
-            .physical_device_properties()
-            .limits
-            .min_uniform_buffer_offset_alignment;
-        let dynamic_alignment = std::mem::size_of::<DynamicUniformBufferObject>() as u64;
-        if minimum_ubo_alignment > 0 {
-            (dynamic_alignment + minimum_ubo_alignment - 1) & !(minimum_ubo_alignment - 1)
-        } else {
-            dynamic_alignment
+    // The capacity at least doubles, so growing one mesh at a time doesn't reallocate every time
+    fn grown_mesh_capacity(mesh_capacity: usize, number_of_meshes: usize) -> usize {
+        if number_of_meshes <= mesh_capacity {
+            return mesh_capacity;
+        }
+        number_of_meshes.max(mesh_capacity * 2)
+    }
+
+    fn dynamic_uniform_buffer_size(mesh_capacity: usize, dynamic_alignment: u64) -> vk::DeviceSize {
+        (mesh_capacity as u64 * dynamic_alignment) as vk::DeviceSize
+    }
+
+    // The dynamic descriptor only covers a single entry, which the dynamic offset selects
+    fn dynamic_uniform_range() -> vk::DeviceSize {
+        mem::size_of::<DynamicUniformBufferObject>() as vk::DeviceSize
+    }
+
+    fn create_dynamic_uniform_buffer(
+        context: Arc<VulkanContext>,
+        mesh_capacity: usize,
+        dynamic_alignment: u64,
+    ) -> Buffer {
+        Buffer::new_mapped_basic(
+            context,
+            Self::dynamic_uniform_buffer_size(mesh_capacity, dynamic_alignment),
+            // Indirect draws read the mesh entries through a storage buffer binding
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap()
+    }
+
+    // The descriptor set must be updated after the morph targets are uploaded
+    pub fn upload_morph_targets(&mut self, context: Arc<VulkanContext>, deltas: &[glm::Vec4]) {
+        self.morph_target_buffer = Self::create_morph_target_buffer(context, deltas);
+    }
+
+    fn create_morph_target_buffer(context: Arc<VulkanContext>, deltas: &[glm::Vec4]) -> Buffer {
+        let buffer = Buffer::new_mapped_basic(
+            context,
+            (deltas.len().max(1) * mem::size_of::<glm::Vec4>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap();
+        if !deltas.is_empty() {
+            buffer.upload_to_buffer(deltas, 0).unwrap();
+        }
+        buffer
+    }
+
+    // The descriptor set must be updated after the indirect materials are uploaded
+    pub fn upload_indirect_materials(
+        &mut self,
+        context: Arc<VulkanContext>,
+        materials: &[IndirectMaterial],
+    ) {
+        self.indirect_material_buffer = Self::create_indirect_material_buffer(context, materials);
+    }
+
+    fn create_indirect_material_buffer(
+        context: Arc<VulkanContext>,
+        materials: &[IndirectMaterial],
+    ) -> Buffer {
+        let buffer = Buffer::new_mapped_basic(
+            context,
+            (materials.len().max(1) * mem::size_of::<IndirectMaterial>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap();
+        if !materials.is_empty() {
+            buffer.upload_to_buffer(materials, 0).unwrap();
+        }
+        buffer
+    }
+
+    // Without descriptor indexing the texture array has a fixed size.
+    // With it, the array is as large as the sampler limits of the device allow.
+    pub fn texture_capacity(descriptor_indexing: bool, limits: &vk::PhysicalDeviceLimits) -> usize {
+        if !descriptor_indexing {
+            return Self::MAX_TEXTURES;
         }
+        let device_limit = [
+            limits.max_per_stage_descriptor_samplers,
+            limits.max_per_stage_descriptor_sampled_images,
+            limits.max_descriptor_set_samplers,
+            limits.max_descriptor_set_sampled_images,
+        ]
+        .iter()
+        .min()
+        .copied()
+        .unwrap_or_default() as usize;
+        device_limit
+            .saturating_sub(Self::RESERVED_SAMPLERS)
+            .clamp(Self::MAX_TEXTURES, Self::MAX_BINDLESS_TEXTURES)
     }
 
-    pub fn descriptor_set_layout(context: Arc<VulkanContext>) -> DescriptorSetLayout {
+    pub fn descriptor_set_layout(
+        context: Arc<VulkanContext>,
+        texture_capacity: usize,
+        bindless: bool,
+    ) -> DescriptorSetLayout {
         let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
             .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
             .build();
@@ -154,8 +492,8 @@ impl PbrPipelineData {
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .build();
         let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-            .binding(2)
-            .descriptor_count(Self::MAX_TEXTURES as _)
+            .binding(Self::TEXTURE_BINDING)
+            .descriptor_count(texture_capacity as _)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build();
@@ -177,51 +515,125 @@ impl PbrPipelineData {
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build();
+        let shadow_map_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(6)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let morph_target_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(7)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let transmission_map_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(8)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let mesh_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(9)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let indirect_material_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(10)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
 
         let bindings = [
             ubo_binding,
             dynamic_ubo_binding,
-            sampler_binding,
             irradiance_cubemap_binding,
             prefilter_cubemap_binding,
             brdflut_binding,
+            shadow_map_binding,
+            morph_target_binding,
+            transmission_map_binding,
+            mesh_binding,
+            indirect_material_binding,
+            sampler_binding,
         ];
 
-        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&bindings)
+        // Only the loaded textures are written to the texture array
+        let mut binding_flags = vec![vk::DescriptorBindingFlags::empty(); bindings.len()];
+        binding_flags[bindings.len() - 1] = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT;
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags)
             .build();
-        DescriptorSetLayout::new(context, layout_create_info).unwrap()
+
+        let mut layout_create_info =
+            vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        if bindless {
+            layout_create_info = layout_create_info.push_next(&mut binding_flags_info);
+        }
+        DescriptorSetLayout::new(context, layout_create_info.build()).unwrap()
     }
 
-    fn create_descriptor_pool(context: Arc<VulkanContext>) -> DescriptorPool {
+    fn create_descriptor_pool(
+        context: Arc<VulkanContext>,
+        texture_capacity: usize,
+        frames_in_flight: u32,
+    ) -> DescriptorPool {
         let ubo_pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: frames_in_flight,
         };
 
         let dynamic_ubo_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
-            descriptor_count: 1,
+            descriptor_count: frames_in_flight,
         };
 
         let sampler_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: Self::MAX_TEXTURES as _,
+            descriptor_count: texture_capacity as u32 * frames_in_flight,
         };
 
         let irradiance_cubemap_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
+            descriptor_count: frames_in_flight,
         };
 
         let prefilter_cubemap_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
+            descriptor_count: frames_in_flight,
         };
 
         let brdflut_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
+            descriptor_count: frames_in_flight,
+        };
+
+        let shadow_map_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: frames_in_flight,
+        };
+
+        let morph_target_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: frames_in_flight,
+        };
+
+        let transmission_map_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: frames_in_flight,
+        };
+
+        let mesh_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: frames_in_flight,
+        };
+
+        let indirect_material_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: frames_in_flight,
         };
 
         let pool_sizes = [
@@ -231,157 +643,376 @@ impl PbrPipelineData {
             irradiance_cubemap_pool_size,
             prefilter_cubemap_pool_size,
             brdflut_pool_size,
+            shadow_map_pool_size,
+            morph_target_pool_size,
+            transmission_map_pool_size,
+            mesh_pool_size,
+            indirect_material_pool_size,
         ];
 
         let pool_info = vk::DescriptorPoolCreateInfo::builder()
             .pool_sizes(&pool_sizes)
-            .max_sets(1)
+            .max_sets(frames_in_flight)
             .build();
 
         DescriptorPool::new(context, pool_info).unwrap()
     }
 
+    // Updates the descriptor set of every frame in flight.
+    // Fails if there are more textures than the texture array in the shader can hold.
     fn update_descriptor_set(
         &self,
         context: Arc<VulkanContext>, // TODO: This struct can store a clone of the context Arc
         textures: &[&TextureBundle],
         environment_maps: &EnvironmentMapSet,
-    ) {
-        let uniform_buffer_size = mem::size_of::<UniformBufferObject>() as vk::DeviceSize;
-        let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(self.uniform_buffer.buffer())
-            .offset(0)
-            .range(uniform_buffer_size)
-            .build();
-        let buffer_infos = [buffer_info];
-
-        let dynamic_uniform_buffer_size =
-            (Self::MAX_NUMBER_OF_MESHES as u64 * self.dynamic_alignment) as vk::DeviceSize;
-        let dynamic_buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(self.dynamic_uniform_buffer.buffer())
-            .offset(0)
-            .range(dynamic_uniform_buffer_size)
-            .build();
-        let dynamic_buffer_infos = [dynamic_buffer_info];
-
-        let mut image_infos = textures
-            .iter()
-            .map(|texture| {
-                vk::DescriptorImageInfo::builder()
-                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .image_view(texture.view.view())
-                    .sampler(texture.sampler.sampler())
-                    .build()
-            })
-            .collect::<Vec<_>>();
+        shadow_map: &ShadowMap,
+        transmission_map: &TransmissionMap,
+    ) -> Result<()> {
+        ensure!(
+            textures.len() <= self.texture_capacity,
+            "The loaded assets use {} textures but at most {} are supported!",
+            textures.len(),
+            self.texture_capacity
+        );
 
-        let number_of_images = image_infos.len();
-        let required_images = Self::MAX_TEXTURES;
-        if number_of_images < required_images {
-            let remaining = required_images - number_of_images;
-            for _ in 0..remaining {
-                image_infos.push(
+        for frame in self.frames.iter() {
+            let uniform_buffer_size = mem::size_of::<UniformBufferObject>() as vk::DeviceSize;
+            let buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(frame.uniform_buffer.buffer())
+                .offset(0)
+                .range(uniform_buffer_size)
+                .build();
+            let buffer_infos = [buffer_info];
+
+            let dynamic_buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(frame.dynamic_uniform_buffer.buffer())
+                .offset(0)
+                .range(Self::dynamic_uniform_range())
+                .build();
+            let dynamic_buffer_infos = [dynamic_buffer_info];
+
+            let mesh_buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(frame.dynamic_uniform_buffer.buffer())
+                .offset(0)
+                .range(Self::dynamic_uniform_buffer_size(
+                    self.mesh_capacity,
+                    self.dynamic_alignment,
+                ))
+                .build();
+            let mesh_buffer_infos = [mesh_buffer_info];
+
+            let morph_target_buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(self.morph_target_buffer.buffer())
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build();
+            let morph_target_buffer_infos = [morph_target_buffer_info];
+
+            let indirect_material_buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(self.indirect_material_buffer.buffer())
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build();
+            let indirect_material_buffer_infos = [indirect_material_buffer_info];
+
+            let mut image_infos = textures
+                .iter()
+                .map(|texture| {
                     vk::DescriptorImageInfo::builder()
                         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                        .image_view(self.dummy.view().view())
-                        .sampler(self.dummy.sampler().sampler())
-                        .build(),
-                );
+                        .image_view(texture.view.view())
+                        .sampler(texture.sampler.sampler())
+                        .build()
+                })
+                .collect::<Vec<_>>();
+
+            let number_of_images = image_infos.len();
+            let required_images = if self.bindless {
+                number_of_images
+            } else {
+                self.texture_capacity
+            };
+            if number_of_images < required_images {
+                let remaining = required_images - number_of_images;
+                for _ in 0..remaining {
+                    image_infos.push(
+                        vk::DescriptorImageInfo::builder()
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                            .image_view(self.dummy.view().view())
+                            .sampler(self.dummy.sampler().sampler())
+                            .build(),
+                    );
+                }
             }
-        }
-
-        let irradiance_cubemap_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(environment_maps.irradiance.cubemap.view.view())
-            .sampler(environment_maps.irradiance.cubemap.sampler.sampler())
-            .build();
-        let irradiance_cubemap_image_infos = [irradiance_cubemap_image_info];
 
-        let prefilter_cubemap_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(environment_maps.prefilter.cubemap.view.view())
-            .sampler(environment_maps.prefilter.cubemap.sampler.sampler())
-            .build();
-        let prefilter_cubemap_image_infos = [prefilter_cubemap_image_info];
+            let irradiance_cubemap_image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(environment_maps.irradiance.cubemap.view.view())
+                .sampler(environment_maps.irradiance.cubemap.sampler.sampler())
+                .build();
+            let irradiance_cubemap_image_infos = [irradiance_cubemap_image_info];
+
+            let prefilter_cubemap_image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(environment_maps.prefilter.cubemap.view.view())
+                .sampler(environment_maps.prefilter.cubemap.sampler.sampler())
+                .build();
+            let prefilter_cubemap_image_infos = [prefilter_cubemap_image_info];
+
+            let brdflut_image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(environment_maps.brdflut.view.view())
+                .sampler(environment_maps.brdflut.sampler.sampler())
+                .build();
+            let brdflut_image_infos = [brdflut_image_info];
+
+            let shadow_map_image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .image_view(shadow_map.texture.view.view())
+                .sampler(shadow_map.texture.sampler.sampler())
+                .build();
+            let shadow_map_image_infos = [shadow_map_image_info];
+
+            let transmission_map_image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(transmission_map.view.view())
+                .sampler(transmission_map.sampler.sampler())
+                .build();
+            let transmission_map_image_infos = [transmission_map_image_info];
+
+            let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&buffer_infos)
+                .build();
+
+            let dynamic_ubo_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&dynamic_buffer_infos)
+                .build();
+
+            let sampler_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(Self::TEXTURE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build();
+
+            let irradiance_cubemap_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(3)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&irradiance_cubemap_image_infos)
+                .build();
+
+            let prefilter_cubemap_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(4)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&prefilter_cubemap_image_infos)
+                .build();
+
+            let brdflut_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(5)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&brdflut_image_infos)
+                .build();
+
+            let shadow_map_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(6)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&shadow_map_image_infos)
+                .build();
+
+            let morph_target_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(7)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&morph_target_buffer_infos)
+                .build();
+
+            let transmission_map_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(8)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&transmission_map_image_infos)
+                .build();
+
+            // The dynamic uniform buffer is bound again for indirect draws,
+            // which can't select their mesh entry with a dynamic offset
+            let mesh_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(9)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&mesh_buffer_infos)
+                .build();
+
+            let indirect_material_descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame.descriptor_set)
+                .dst_binding(10)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&indirect_material_buffer_infos)
+                .build();
+
+            // TODO: This probably doesn't need to be a vec, just a regular slice
+            let mut descriptor_writes = vec![
+                ubo_descriptor_write,
+                dynamic_ubo_descriptor_write,
+                irradiance_cubemap_descriptor_write,
+                prefilter_cubemap_descriptor_write,
+                brdflut_descriptor_write,
+                shadow_map_descriptor_write,
+                morph_target_descriptor_write,
+                transmission_map_descriptor_write,
+                mesh_descriptor_write,
+                indirect_material_descriptor_write,
+            ];
+            // A partially bound texture array is left empty until textures are loaded
+            if !image_infos.is_empty() {
+                descriptor_writes.push(sampler_descriptor_write);
+            }
 
-        let brdflut_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(environment_maps.brdflut.view.view())
-            .sampler(environment_maps.brdflut.sampler.sampler())
-            .build();
-        let brdflut_image_infos = [brdflut_image_info];
-
-        let ubo_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(&buffer_infos)
-            .build();
+            unsafe {
+                context
+                    .logical_device()
+                    .logical_device()
+                    .update_descriptor_sets(&descriptor_writes, &[])
+            }
+        }
+        Ok(())
+    }
+}
 
-        let dynamic_ubo_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-            .buffer_info(&dynamic_buffer_infos)
-            .build();
+// A primitive draw that can be recorded without access to the scene
+#[derive(Clone, Copy)]
+pub struct PbrDraw {
+    alpha_mode: AlphaMode,
+    dynamic_offset: u32,
+    material: PushConstantBlockMaterial,
+    indexed: bool,
+    number_of_indices: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    number_of_vertices: u32,
+    first_vertex: u32,
+    number_of_instances: u32,
+    first_instance: u32,
+    // Center of the primitive in asset space, used to sort blended draws
+    centroid: glm::Vec3,
+    topology: vk::PrimitiveTopology,
+    // Back faces are only drawn for double-sided materials
+    double_sided: bool,
+    // Opaque indexed triangles of meshes that aren't skinned or morphed can be drawn indirectly
+    indirect: bool,
+}
 
-        let sampler_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(2)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&image_infos)
-            .build();
+impl PbrDraw {
+    // The shadow, depth pre-pass and picking pipelines only draw triangle lists
+    pub fn is_triangle_list(&self) -> bool {
+        self.topology == vk::PrimitiveTopology::TRIANGLE_LIST
+    }
 
-        let irradiance_cubemap_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(3)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&irradiance_cubemap_image_infos)
-            .build();
+    pub fn indirect_draw(&self) -> IndirectDraw {
+        IndirectDraw {
+            command: vk::DrawIndexedIndirectCommand {
+                index_count: self.number_of_indices,
+                instance_count: self.number_of_instances,
+                first_index: self.first_index,
+                vertex_offset: self.vertex_offset,
+                first_instance: self.first_instance,
+            },
+            material: self.material,
+            dynamic_offset: self.dynamic_offset,
+            double_sided: self.double_sided,
+        }
+    }
 
-        let prefilter_cubemap_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(4)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&prefilter_cubemap_image_infos)
-            .build();
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            if self.indexed {
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    self.number_of_indices,
+                    self.number_of_instances,
+                    self.first_index,
+                    self.vertex_offset,
+                    self.first_instance,
+                );
+            } else {
+                device.cmd_draw(
+                    command_buffer,
+                    self.number_of_vertices,
+                    self.number_of_instances,
+                    self.first_vertex,
+                    self.first_instance,
+                );
+            }
+        }
+    }
+}
 
-        let brdflut_descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.descriptor_set)
-            .dst_binding(5)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&brdflut_image_infos)
-            .build();
+// A pbr pipeline that culls back faces, along with a double-sided copy of it
+pub struct PbrPipelineVariants {
+    pub culled: RenderPipeline,
+    pub double_sided: RenderPipeline,
+}
 
-        // TODO: This probably doesn't need to be a vec, just a regular slice
-        let descriptor_writes = vec![
-            ubo_descriptor_write,
-            dynamic_ubo_descriptor_write,
-            sampler_descriptor_write,
-            irradiance_cubemap_descriptor_write,
-            prefilter_cubemap_descriptor_write,
-            brdflut_descriptor_write,
-        ];
+impl PbrPipelineVariants {
+    pub fn new(
+        context: Arc<VulkanContext>,
+        mut settings: RenderPipelineSettings,
+        name: &str,
+    ) -> Result<Self> {
+        settings.cull_mode = vk::CullModeFlags::BACK;
+        let culled = RenderPipeline::new(context.clone(), settings.clone())?;
+        settings.cull_mode = vk::CullModeFlags::NONE;
+        let double_sided = RenderPipeline::new(context.clone(), settings)?;
+        context.set_debug_name(culled.pipeline.pipeline(), name);
+        context.set_debug_name(
+            double_sided.pipeline.pipeline(),
+            &format!("{} (Double Sided)", name),
+        );
+        Ok(Self {
+            culled,
+            double_sided,
+        })
+    }
 
-        unsafe {
-            context
-                .logical_device()
-                .logical_device()
-                .update_descriptor_sets(&descriptor_writes, &[])
+    pub fn pipeline(&self, double_sided: bool) -> vk::Pipeline {
+        if double_sided {
+            self.double_sided.pipeline.pipeline()
+        } else {
+            self.culled.pipeline.pipeline()
         }
     }
 }
 
+// A draw along with the pipeline it is recorded with
+type PipelineDraw = (vk::Pipeline, PbrDraw);
+
 pub struct PbrRenderer {
     command_buffer: vk::CommandBuffer,
     pipeline_layout: vk::PipelineLayout,
     dynamic_alignment: u64,
+    uniform_alignment: u64,
+    // The offset of the uniform buffer entry for the viewport being drawn
+    uniform_offset: u32,
     descriptor_set: vk::DescriptorSet,
 }
 
@@ -390,40 +1021,127 @@ impl PbrRenderer {
         command_buffer: vk::CommandBuffer,
         pipeline: &GraphicsPipeline,
         pipeline_data: &PbrPipelineData,
+        frame: usize,
     ) -> Self {
         Self {
             command_buffer,
             pipeline_layout: pipeline.layout(),
             dynamic_alignment: pipeline_data.dynamic_alignment,
-            descriptor_set: pipeline_data.descriptor_set,
+            uniform_alignment: pipeline_data.uniform_alignment,
+            uniform_offset: 0,
+            descriptor_set: pipeline_data.frames[frame].descriptor_set,
         }
     }
 
+    pub fn set_viewport(&mut self, viewport: usize) {
+        self.uniform_offset = (viewport as u64 * self.uniform_alignment) as u32;
+    }
+
     pub fn draw_asset(
         &self,
         device: &ash::Device,
         asset: &GltfAsset,
         asset_metadata: &AssetMetadata,
+        alpha_mode: AlphaMode,
+    ) {
+        let mut draws = Vec::new();
+        self.collect_draws(asset, asset_metadata, alpha_mode, &mut draws);
+        draws
+            .iter()
+            .filter(|draw| draw.is_triangle_list())
+            .for_each(|draw| self.draw(device, draw));
+    }
+
+    pub fn draw(&self, device: &ash::Device, draw: &PbrDraw) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[self.uniform_offset, draw.dynamic_offset],
+            );
+
+            device.cmd_push_constants(
+                self.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::ALL_GRAPHICS,
+                0,
+                byte_slice_from(&draw.material),
+            );
+        }
+        draw.record(device, self.command_buffer);
+    }
+
+    // Indirect draws read their materials and mesh entries from storage buffers,
+    // but the push constants must still be set for the shaders that declare them
+    pub fn bind_indirect(&self, device: &ash::Device) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[self.uniform_offset, 0],
+            );
+
+            device.cmd_push_constants(
+                self.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::ALL_GRAPHICS,
+                0,
+                byte_slice_from(&PushConstantBlockMaterial::default()),
+            );
+        }
+    }
+
+    pub fn collect_draws(
+        &self,
+        asset: &GltfAsset,
+        asset_metadata: &AssetMetadata,
+        alpha_mode: AlphaMode,
+        draws: &mut Vec<PbrDraw>,
+    ) {
+        // Skinned assets are drawn one instance at a time
+        // because each instance has its own joint offset.
+        // Blended primitives are also drawn per instance so they can be sorted.
+        if asset.is_skinned() || alpha_mode == AlphaMode::Blend {
+            for instance in 0..asset_metadata.instances.len() {
+                self.collect_instance_draws(asset, asset_metadata, instance, 1, alpha_mode, draws);
+            }
+        } else if !asset_metadata.instances.is_empty() {
+            self.collect_instance_draws(
+                asset,
+                asset_metadata,
+                0,
+                asset_metadata.instances.len(),
+                alpha_mode,
+                draws,
+            );
+        }
+    }
+
+    fn collect_instance_draws(
+        &self,
+        asset: &GltfAsset,
+        asset_metadata: &AssetMetadata,
         instance: usize,
+        number_of_instances: usize,
         alpha_mode: AlphaMode,
+        draws: &mut Vec<PbrDraw>,
     ) {
         let instance_metadata = &asset_metadata.instances[instance];
-        asset.walk(|node_index, graph| {
+        asset.walk_mut(|node_index, graph| {
             if let Some(mesh) = graph[node_index].mesh.as_ref() {
-                unsafe {
-                    device.cmd_bind_descriptor_sets(
-                        self.command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.pipeline_layout,
-                        0,
-                        &[self.descriptor_set],
-                        &[((instance_metadata.mesh_offset + mesh.mesh_id) as u64
-                            * self.dynamic_alignment) as _],
-                    );
-                }
+                let dynamic_offset = ((instance_metadata.mesh_offset + mesh.mesh_id) as u64
+                    * self.dynamic_alignment) as u32;
+                let global_transform = GltfAsset::calculate_global_transform(node_index, graph);
 
                 for primitive in mesh.primitives.iter() {
                     let mut primitive_alpha_mode = AlphaMode::Opaque;
+                    let mut double_sided = false;
                     if let Some(material_index) = primitive.material_index {
                         let primitive_material = asset
                             .gltf
@@ -431,6 +1149,7 @@ impl PbrRenderer {
                             .nth(material_index)
                             .expect("Failed to retrieve material!");
                         primitive_alpha_mode = primitive_material.alpha_mode();
+                        double_sided = primitive_material.double_sided();
                     }
 
                     if primitive_alpha_mode != alpha_mode {
@@ -442,24 +1161,30 @@ impl PbrRenderer {
                         &primitive,
                         asset_metadata.texture_offset as i32,
                     );
-                    unsafe {
-                        device.cmd_push_constants(
-                            self.command_buffer,
-                            self.pipeline_layout,
-                            vk::ShaderStageFlags::ALL_GRAPHICS,
-                            0,
-                            byte_slice_from(&material),
-                        );
-
-                        device.cmd_draw_indexed(
-                            self.command_buffer,
-                            primitive.number_of_indices,
-                            1,
-                            asset_metadata.index_offset as u32 + primitive.first_index,
-                            asset_metadata.vertex_offset as _,
-                            0,
-                        );
-                    }
+                    let indirect = alpha_mode == AlphaMode::Opaque
+                        && primitive.indexed
+                        && primitive.topology == vk::PrimitiveTopology::TRIANGLE_LIST
+                        && !material.is_transmissive()
+                        && !asset.is_skinned()
+                        && mesh.morph_target_count == 0;
+
+                    draws.push(PbrDraw {
+                        alpha_mode,
+                        dynamic_offset,
+                        material,
+                        indexed: primitive.indexed,
+                        number_of_indices: primitive.number_of_indices,
+                        first_index: asset_metadata.index_offset as u32 + primitive.first_index,
+                        vertex_offset: asset_metadata.vertex_offset as _,
+                        number_of_vertices: primitive.number_of_vertices,
+                        first_vertex: asset_metadata.vertex_offset as u32 + primitive.first_vertex,
+                        number_of_instances: number_of_instances as _,
+                        first_instance: (asset_metadata.instance_offset + instance) as _,
+                        centroid: (global_transform * primitive.centroid.push(1.0)).xyz(),
+                        topology: primitive.topology,
+                        double_sided,
+                        indirect,
+                    });
                 }
             }
         });
@@ -470,19 +1195,7 @@ impl PbrRenderer {
         primitive: &Primitive,
         texture_offset: i32,
     ) -> PushConstantBlockMaterial {
-        let mut material = PushConstantBlockMaterial {
-            base_color_factor: glm::vec4(0.0, 0.0, 0.0, 1.0),
-            emissive_factor: glm::Vec3::identity(),
-            color_texture_set: -1,
-            metallic_roughness_texture_set: -1,
-            normal_texture_set: -1,
-            occlusion_texture_set: -1,
-            emissive_texture_set: -1,
-            metallic_factor: 0.0,
-            roughness_factor: 0.0,
-            alpha_mode: gltf::material::AlphaMode::Opaque as i32,
-            alpha_cutoff: 0.0,
-        };
+        let mut material = PushConstantBlockMaterial::default();
 
         if let Some(material_index) = primitive.material_index {
             let primitive_material = asset
@@ -495,43 +1208,101 @@ impl PbrRenderer {
             material.base_color_factor = glm::Vec4::from(pbr.base_color_factor());
             material.metallic_factor = pbr.metallic_factor();
             material.roughness_factor = pbr.roughness_factor();
-            material.emissive_factor = glm::Vec3::from(primitive_material.emissive_factor());
+            // Strengths above 1.0 stay bright until the exposure is applied while post-processing
+            material.emissive_factor = glm::Vec3::from(primitive_material.emissive_factor())
+                * asset.material_extensions[material_index].emissive_strength;
             material.alpha_mode = primitive_material.alpha_mode() as i32;
             material.alpha_cutoff = primitive_material.alpha_cutoff();
 
             if let Some(base_color_texture) = pbr.base_color_texture() {
                 material.color_texture_set =
                     texture_offset + base_color_texture.texture().index() as i32;
+                material.set_uv_set(MaterialTexture::Color, base_color_texture.tex_coord());
             }
 
             if let Some(metallic_roughness_texture) = pbr.metallic_roughness_texture() {
                 material.metallic_roughness_texture_set =
                     texture_offset + metallic_roughness_texture.texture().index() as i32;
+                material.set_uv_set(
+                    MaterialTexture::MetallicRoughness,
+                    metallic_roughness_texture.tex_coord(),
+                );
             }
 
             if let Some(normal_texture) = primitive_material.normal_texture() {
                 material.normal_texture_set =
                     texture_offset + normal_texture.texture().index() as i32;
+                material.set_uv_set(MaterialTexture::Normal, normal_texture.tex_coord());
+                material.normal_scale = normal_texture.scale();
             }
 
             if let Some(occlusion_texture) = primitive_material.occlusion_texture() {
                 material.occlusion_texture_set =
                     texture_offset + occlusion_texture.texture().index() as i32;
+                material.set_uv_set(MaterialTexture::Occlusion, occlusion_texture.tex_coord());
+                material.occlusion_strength = occlusion_texture.strength();
             }
 
             if let Some(emissive_texture) = primitive_material.emissive_texture() {
                 material.emissive_texture_set =
                     texture_offset + emissive_texture.texture().index() as i32;
+                material.set_uv_set(MaterialTexture::Emissive, emissive_texture.tex_coord());
             }
-        }
 
-        material
-    }
+            let extensions = &asset.material_extensions[material_index];
+            material.transmission_factor = extensions.transmission_factor;
+            if let Some(transmission_texture) = extensions.transmission_texture {
+                material.transmission_texture_set =
+                    texture_offset + transmission_texture.index as i32;
+                material.set_uv_set(
+                    MaterialTexture::Transmission,
+                    transmission_texture.tex_coord as u32,
+                );
+            }
+
+            material.clearcoat_factor = extensions.clearcoat_factor;
+            material.clearcoat_roughness_factor = extensions.clearcoat_roughness_factor;
+            if let Some(clearcoat_texture) = extensions.clearcoat_texture {
+                material.clearcoat_texture_set = texture_offset + clearcoat_texture.index as i32;
+                material.set_uv_set(
+                    MaterialTexture::Clearcoat,
+                    clearcoat_texture.tex_coord as u32,
+                );
+            }
+            if let Some(clearcoat_roughness_texture) = extensions.clearcoat_roughness_texture {
+                material.clearcoat_roughness_texture_set =
+                    texture_offset + clearcoat_roughness_texture.index as i32;
+                material.set_uv_set(
+                    MaterialTexture::ClearcoatRoughness,
+                    clearcoat_roughness_texture.tex_coord as u32,
+                );
+            }
+
+            material.ior = extensions.ior;
+            material.specular_factor = extensions.specular_factor;
+            material.specular_color_factor = glm::Vec3::from(extensions.specular_color_factor);
+            if let Some(specular_texture) = extensions.specular_texture {
+                material.specular_texture_set = texture_offset + specular_texture.index as i32;
+                material.set_uv_set(MaterialTexture::Specular, specular_texture.tex_coord as u32);
+            }
+            if let Some(specular_color_texture) = extensions.specular_color_texture {
+                material.specular_color_texture_set =
+                    texture_offset + specular_color_texture.index as i32;
+                material.set_uv_set(
+                    MaterialTexture::SpecularColor,
+                    specular_color_texture.tex_coord as u32,
+                );
+            }
+        }
+
+        material
+    }
 }
 
 pub struct EnvironmentMapSet {
     brdflut: Brdflut,
-    hdr: HdrCubemap,
+    // The skybox, which the irradiance and prefilter maps are computed from
+    cubemap: Cubemap,
     irradiance: IrradianceMap,
     prefilter: PrefilterMap,
 }
@@ -541,35 +1312,131 @@ impl EnvironmentMapSet {
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
         shader_cache: &mut ShaderCache,
-    ) -> Self {
+        environment: &Environment,
+        ibl_settings: &IblSettings,
+        cache_directory: Option<&str>,
+    ) -> Result<Self> {
+        let max_prefilter_mips = TextureDescription::calculate_mip_levels(
+            ibl_settings.prefilter_dim,
+            ibl_settings.prefilter_dim,
+        );
+        ensure!(
+            ibl_settings.irradiance_dim > 0 && ibl_settings.prefilter_dim > 0,
+            "Failed to create environment maps with a dimension of zero!"
+        );
+        ensure!(
+            (1..=max_prefilter_mips).contains(&ibl_settings.prefilter_mips),
+            "Failed to create a {}px prefilter map with {} mip levels, it supports 1 to {}!",
+            ibl_settings.prefilter_dim,
+            ibl_settings.prefilter_mips,
+            max_prefilter_mips
+        );
+        ensure!(
+            ibl_settings.sample_count > 0,
+            "Failed to prefilter the environment with zero samples!"
+        );
+
         debug!("Creating Brdflut");
-        let brdflut = Brdflut::new(context.clone(), command_pool, shader_cache);
+        let brdflut = Brdflut::new(context.clone(), command_pool, shader_cache)?;
 
-        let cubemap_path = "assets/skyboxes/walk_of_fame/walk_of_fame.hdr";
+        let cubemap = match environment {
+            Environment::Hdr(path) => {
+                debug!("Creating HDR cubemap from {}", path);
+                HdrCubemap::new(context.clone(), command_pool, path, shader_cache)?.cubemap
+            }
+            Environment::Cubemap(path) => {
+                debug!("Creating cubemap from {}", path);
+                SkyboxCubemap::from_dds(context.clone(), command_pool, path)?.cubemap
+            }
+            Environment::Faces {
+                right,
+                left,
+                top,
+                bottom,
+                front,
+                back,
+            } => {
+                debug!("Creating cubemap from faces");
+                let faces = CubemapFaces {
+                    right: right.to_string(),
+                    left: left.to_string(),
+                    top: top.to_string(),
+                    bottom: bottom.to_string(),
+                    front: front.to_string(),
+                    back: back.to_string(),
+                };
+                SkyboxCubemap::from_faces(context.clone(), command_pool, &faces)?.cubemap
+            }
+        };
 
-        debug!("Creating HDR cubemap");
-        let hdr = HdrCubemap::new(context.clone(), command_pool, &cubemap_path, shader_cache);
+        let cache = cache_directory
+            .map(|directory| {
+                EnvironmentCache::new(context.clone(), directory, environment, ibl_settings)
+            })
+            .transpose()?;
 
         debug!("Creating Irradiance cubemap");
-        let irradiance = IrradianceMap::new(
-            context.clone(),
-            &command_pool,
-            &hdr.as_ref().expect("Failed to lookup hdr cubemap!").cubemap,
-        );
+        let compute_irradiance = || {
+            IrradianceMap::new(
+                context.clone(),
+                command_pool,
+                &cubemap,
+                ibl_settings.irradiance_dim,
+            )
+            .map(|irradiance| irradiance.cubemap)
+        };
+        let irradiance = IrradianceMap {
+            cubemap: match cache.as_ref() {
+                Some(cache) => cache.load_or_compute(
+                    command_pool,
+                    "irradiance",
+                    ibl_settings.irradiance_dim,
+                    TextureDescription::calculate_mip_levels(
+                        ibl_settings.irradiance_dim,
+                        ibl_settings.irradiance_dim,
+                    ),
+                    IrradianceMap::FORMAT,
+                    compute_irradiance,
+                )?,
+                None => compute_irradiance()?,
+            },
+        };
 
         debug!("Creating Prefilter cubemap");
-        let prefilter = PrefilterMap::new(
-            context,
-            &command_pool,
-            &hdr.as_ref().expect("Failed to lookup hdr cubemap!").cubemap,
-        );
+        let compute_prefilter = || {
+            PrefilterMap::new(
+                context.clone(),
+                command_pool,
+                &cubemap,
+                ibl_settings.prefilter_dim,
+                ibl_settings.prefilter_mips,
+                ibl_settings.sample_count,
+            )
+            .map(|prefilter| prefilter.cubemap)
+        };
+        let mut prefilter = PrefilterMap {
+            cubemap: match cache.as_ref() {
+                Some(cache) => cache.load_or_compute(
+                    command_pool,
+                    "prefilter",
+                    ibl_settings.prefilter_dim,
+                    ibl_settings.prefilter_mips,
+                    PrefilterMap::FORMAT,
+                    compute_prefilter,
+                )?,
+                None => compute_prefilter()?,
+            },
+        };
+        prefilter
+            .cubemap
+            .set_mip_lod_bias(ibl_settings.prefilter_lod_bias)?;
 
-        Self {
+        Ok(Self {
             brdflut,
-            hdr: hdr.unwrap(),
+            cubemap,
             irradiance,
             prefilter,
-        }
+        })
     }
 }
 
@@ -585,40 +1452,107 @@ pub struct AssetMetadata {
     texture_offset: usize,
     vertex_offset: usize,
     index_offset: usize,
+    morph_target_offset: usize,
+    // Instances of an asset occupy a contiguous range of the instance buffer
+    instance_offset: usize,
     instances: Vec<InstanceMetadata>,
 }
 
 pub struct AssetCache {
     pub assets: Vec<GltfAsset>,
     pub metadata: HashMap<String, AssetMetadata>,
-    context: Arc<VulkanContext>,
+    asset_names: Vec<String>,
+    // Assets are stored in the order they finish loading
+    asset_indices: HashMap<String, usize>,
 }
 
 impl AssetCache {
-    pub fn new(
-        context: Arc<VulkanContext>,
-        asset_names: &[String],
-        command_pool: &CommandPool,
-    ) -> Self {
-        let mut asset_cache = Self {
+    pub fn new(asset_names: &[String]) -> Self {
+        Self {
             assets: Vec::new(),
             metadata: HashMap::new(),
-            context,
+            asset_names: asset_names.to_vec(),
+            asset_indices: HashMap::new(),
+        }
+    }
+
+    pub fn insert_asset(&mut self, asset_name: &str, asset: GltfAsset) {
+        self.asset_indices
+            .insert(asset_name.to_string(), self.assets.len());
+        self.assets.push(asset);
+        self.generate_metadata();
+    }
+
+    // Returns false if the asset wasn't loaded
+    pub fn remove_asset(&mut self, asset_name: &str) -> bool {
+        let removed_index = match self.asset_indices.remove(asset_name) {
+            Some(index) => index,
+            None => return false,
         };
-        asset_cache.generate_metadata(asset_names, command_pool);
-        asset_cache
+        self.assets.remove(removed_index);
+        self.asset_indices
+            .values_mut()
+            .filter(|index| **index > removed_index)
+            .for_each(|index| *index -= 1);
+        self.generate_metadata();
+        true
+    }
+
+    pub fn contains_asset(&self, asset_name: &str) -> bool {
+        self.asset_indices.contains_key(asset_name)
+    }
+
+    // In the order the assets finished loading
+    pub fn loaded_assets(&self) -> Vec<String> {
+        let mut loaded_assets = self.asset_indices.iter().collect::<Vec<_>>();
+        loaded_assets.sort_by_key(|(_, index)| **index);
+        loaded_assets
+            .into_iter()
+            .map(|(asset_name, _)| asset_name.to_string())
+            .collect()
     }
 
-    pub fn generate_metadata(&mut self, asset_names: &[String], command_pool: &CommandPool) {
+    // One name per instance. Returns false if the instances are unchanged.
+    pub fn set_asset_names(&mut self, asset_names: &[String]) -> bool {
+        if self.asset_names == asset_names {
+            return false;
+        }
+        self.asset_names = asset_names.to_vec();
+        self.generate_metadata();
+        true
+    }
+
+    // Only assets that have finished loading have metadata
+    pub fn generate_metadata(&mut self) {
         let mut metadata = HashMap::new();
         let mut mesh_offset = 0;
         let mut joint_offset = 0;
+
+        // Offsets into the combined geometry buffer and texture array follow the asset order
+        let mut asset_offsets = Vec::new();
         let mut texture_offset = 0;
         let mut vertex_offset = 0;
         let mut index_offset = 0;
-        let mut asset_index = 0;
+        let mut morph_target_offset = 0;
+        for asset in self.assets.iter() {
+            asset_offsets.push((
+                texture_offset,
+                vertex_offset,
+                index_offset,
+                morph_target_offset,
+            ));
+            texture_offset += asset.textures.len();
+            vertex_offset += asset.vertices.len() / GltfAsset::vertex_stride();
+            index_offset += asset.indices.len();
+            morph_target_offset += asset.morph_targets.len();
+        }
+
+        for asset_name in self.asset_names.iter() {
+            let asset_index = match self.asset_indices.get(asset_name) {
+                Some(asset_index) => *asset_index,
+                None => continue,
+            };
 
-        for asset_name in asset_names.iter() {
             // First occurrence of asset
             let first_visit = !metadata.contains_key(&asset_name.to_string());
 
@@ -628,23 +1562,13 @@ impl AssetCache {
                 .or_insert(AssetMetadata::default());
 
             if first_visit {
-                // Update the metadata
+                let (texture_offset, vertex_offset, index_offset, morph_target_offset) =
+                    asset_offsets[asset_index];
                 asset_metadata.index = asset_index;
                 asset_metadata.texture_offset = texture_offset;
                 asset_metadata.vertex_offset = vertex_offset;
                 asset_metadata.index_offset = index_offset;
-
-                // Load the asset
-                let asset = GltfAsset::new(self.context.clone(), &command_pool, &asset_name);
-
-                // Asset metadata is only updated on the first visit
-                asset_index += 1;
-                texture_offset += asset.textures.len();
-                vertex_offset += asset.vertices.len() / GltfAsset::vertex_stride();
-                index_offset += asset.indices.len();
-
-                // Store the asset
-                self.assets.push(asset);
+                asset_metadata.morph_target_offset = morph_target_offset;
             }
 
             // Create the instance
@@ -665,7 +1589,21 @@ impl AssetCache {
             asset_metadata.instances.push(instance_metadata);
         }
 
-        println!("Metadata: {:#?}", metadata);
+        if joint_offset > UniformBufferObject::MAX_NUM_JOINTS {
+            warn!(
+                "The loaded assets need {} joint matrices but only {} are supported, skins past the limit will not be animated!",
+                joint_offset,
+                UniformBufferObject::MAX_NUM_JOINTS
+            );
+        }
+
+        let mut instance_offset = 0;
+        for asset_metadata in metadata.values_mut() {
+            asset_metadata.instance_offset = instance_offset;
+            instance_offset += asset_metadata.instances.len();
+        }
+
+        debug!("Metadata: {:#?}", metadata);
 
         self.metadata = metadata;
     }
@@ -684,7 +1622,40 @@ impl AssetCache {
             .flat_map(|asset| asset.indices.iter().copied())
             .collect::<Vec<_>>();
 
-        GeometryBuffer::new(&command_pool, &vertices, Some(&indices))
+        // Assets made only of non-indexed primitives have no indices to upload
+        let indices = if indices.is_empty() {
+            None
+        } else {
+            Some(indices.as_slice())
+        };
+
+        GeometryBuffer::new(command_pool, &vertices, indices)
+    }
+
+    pub fn morph_targets(&self) -> Vec<glm::Vec4> {
+        self.assets
+            .iter()
+            .flat_map(|asset| asset.morph_targets.iter().copied())
+            .collect::<Vec<_>>()
+    }
+
+    // Total number of meshes across every instance of every asset
+    pub fn number_of_meshes(&self) -> usize {
+        self.metadata
+            .values()
+            .map(|metadata| self.assets[metadata.index].number_of_meshes * metadata.instances.len())
+            .sum()
+    }
+
+    pub fn number_of_instances(&self) -> usize {
+        self.metadata
+            .values()
+            .map(|metadata| metadata.instances.len())
+            .sum()
+    }
+
+    pub fn number_of_textures(&self) -> usize {
+        self.assets.iter().map(|asset| asset.textures.len()).sum()
     }
 
     pub fn textures(&self) -> Vec<&TextureBundle> {
@@ -697,17 +1668,80 @@ impl AssetCache {
 
 pub struct PbrScene {
     context: Arc<VulkanContext>,
-    asset_geometry_buffer: GeometryBuffer,
-    _environment_maps: EnvironmentMapSet,
+    // There is no geometry until the first asset finishes loading
+    asset_geometry_buffer: Option<GeometryBuffer>,
+    // Holds the model matrix of every asset instance
+    instance_buffer: Buffer,
+    // The selected instances scaled about their centers, the outline is drawn with these
+    outline_instance_buffer: Buffer,
+    // Only batched when the device supports multi draw indirect
+    indirect_draws: Option<IndirectDraws>,
+    environment_maps: EnvironmentMapSet,
     skybox_pipeline: Option<RenderPipeline>,
     skybox_pipeline_data: SkyboxPipelineData,
-    pbr_pipeline: Option<RenderPipeline>,
-    pbr_pipeline_blend: Option<RenderPipeline>,
+    pbr_pipeline: Option<PbrPipelineVariants>,
+    pbr_pipeline_blend: Option<PbrPipelineVariants>,
+    // Masked primitives use alpha to coverage when multisampling, for smooth cutout edges
+    pbr_pipeline_mask: Option<PbrPipelineVariants>,
+    // Used together when the depth pre-pass is enabled
+    depth_prepass_pipeline: Option<PbrPipelineVariants>,
+    pbr_pipeline_depth_equal: Option<PbrPipelineVariants>,
+    // Variants of the pbr pipelines for primitives that aren't triangle lists,
+    // keyed by topology and whether they are blended
+    topology_pipelines: HashMap<(vk::PrimitiveTopology, bool), PbrPipelineVariants>,
+    // Adds a constant color on top of the selected instances
+    highlight_pipeline: Option<PbrPipelineVariants>,
+    // Mark the stencil where the selected instances are,
+    // then draw their scaled copies everywhere else
+    outline_mask_pipeline: Option<PbrPipelineVariants>,
+    outline_pipeline: Option<PbrPipelineVariants>,
     pbr_pipeline_data: PbrPipelineData,
+    shadow_map: ShadowMap,
+    transmission_map: TransmissionMap,
+    picking_pass: PickingPass,
     asset_cache: AssetCache,
+    asset_loader: AssetLoader,
+    // Assets that failed to load aren't requested again
+    failed_assets: HashSet<String>,
+    // Kept from the last update to sort blended primitives back to front
+    instance_models: Vec<glm::Mat4>,
+    // The entity that owns each slot of the instance buffer
+    instance_entities: Vec<Option<Entity>>,
+    // The instance buffer slots of the selected entities
+    selected_instances: Vec<usize>,
+    // Set by an update that changed the selected instances
+    selection_changed: bool,
+    // The blended draws from the last update, back to front,
+    // identified by their first instance, index and vertex
+    blended_draw_order: Vec<(u32, u32, u32)>,
+    // Set by an update that changed the order of the blended draws
+    blended_draw_order_changed: bool,
+    // The position of the main camera
+    camera_position: glm::Vec3,
+    // The number of cameras the screen is split between
+    viewport_count: usize,
+    // The frame in flight written by the last update, which picking reads from
+    frame: usize,
+    debug_shapes_renderer: DebugShapesRenderer,
+    // Set by an update that changed the debug shape draw of its frame
+    debug_shapes_changed: bool,
+    particle_renderer: ParticleRenderer,
+    // Set by an update that changed the particle draws of its frame
+    particles_changed: bool,
+    grid_renderer: GridRenderer,
 }
 
 impl PbrScene {
+    // glTF primitive modes besides triangles, line loops are loaded as strips
+    const EXTRA_TOPOLOGIES: [vk::PrimitiveTopology; 5] = [
+        vk::PrimitiveTopology::POINT_LIST,
+        vk::PrimitiveTopology::LINE_LIST,
+        vk::PrimitiveTopology::LINE_STRIP,
+        vk::PrimitiveTopology::TRIANGLE_STRIP,
+        vk::PrimitiveTopology::TRIANGLE_FAN,
+    ];
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
@@ -715,41 +1749,407 @@ impl PbrScene {
         render_pass: Arc<RenderPass>,
         asset_names: &[String],
         samples: vk::SampleCountFlags,
-    ) -> Self {
+        frames_in_flight: u32,
+        shadow_map_resolution: u32,
+        offscreen_extent: vk::Extent2D,
+        picking_extent: vk::Extent2D,
+        environment: &Environment,
+        ibl_settings: &IblSettings,
+        environment_cache: Option<&str>,
+    ) -> Result<Self> {
         // FIXME: This will need to allow dynamic entity addition and removal
         // FIXME: Cache loaded assets, can be manually cleared whenever necessary
-        let environment_maps = EnvironmentMapSet::new(context.clone(), command_pool, shader_cache);
-
-        let asset_cache = AssetCache::new(context.clone(), asset_names, command_pool);
-        let asset_geometry_buffer = asset_cache.create_geometry_buffer(&command_pool);
+        let environment_maps = EnvironmentMapSet::new(
+            context.clone(),
+            command_pool,
+            shader_cache,
+            environment,
+            ibl_settings,
+            environment_cache,
+        )?;
+
+        // Assets are streamed in by the loader
+        let asset_cache = AssetCache::new(asset_names);
+        let asset_loader = AssetLoader::new(asset_names);
+
+        let instance_buffer = Self::create_instance_buffer(context.clone(), 0);
+        let outline_instance_buffer = Self::create_instance_buffer(context.clone(), 0);
+
+        let shadow_map = ShadowMap::new(context.clone(), shadow_map_resolution);
+        let transmission_map =
+            TransmissionMap::new(context.clone(), command_pool, offscreen_extent);
+        let picking_pass = PickingPass::new(context.clone(), picking_extent);
+        let debug_shapes_renderer = DebugShapesRenderer::new(context.clone(), frames_in_flight)?;
+        let particle_renderer =
+            ParticleRenderer::new(context.clone(), command_pool, frames_in_flight)?;
+        let grid_renderer = GridRenderer::new(context.clone(), frames_in_flight)?;
 
         let pbr_pipeline_data = PbrPipelineData::new(
             context.clone(),
-            &command_pool,
+            command_pool,
             &asset_cache.textures(),
             &environment_maps,
-        );
+            &shadow_map,
+            &transmission_map,
+            asset_cache.number_of_meshes(),
+            frames_in_flight,
+        )?;
 
         let skybox_pipeline_data = SkyboxPipelineData::new(
             context.clone(),
-            &command_pool,
-            &environment_maps.hdr.cubemap,
+            command_pool,
+            &environment_maps.cubemap,
+            frames_in_flight,
         );
 
         let mut pbr_scene_data = Self {
             context,
-            asset_geometry_buffer,
-            _environment_maps: environment_maps,
+            asset_geometry_buffer: None,
+            instance_buffer,
+            outline_instance_buffer,
+            indirect_draws: None,
+            environment_maps,
             skybox_pipeline: None,
             skybox_pipeline_data,
             pbr_pipeline: None,
             pbr_pipeline_blend: None,
+            pbr_pipeline_mask: None,
+            depth_prepass_pipeline: None,
+            pbr_pipeline_depth_equal: None,
+            topology_pipelines: HashMap::new(),
+            highlight_pipeline: None,
+            outline_mask_pipeline: None,
+            outline_pipeline: None,
             pbr_pipeline_data,
+            shadow_map,
+            transmission_map,
+            picking_pass,
             asset_cache,
+            asset_loader,
+            failed_assets: HashSet::new(),
+            instance_models: Vec::new(),
+            instance_entities: Vec::new(),
+            selected_instances: Vec::new(),
+            selection_changed: false,
+            blended_draw_order: Vec::new(),
+            blended_draw_order_changed: false,
+            camera_position: glm::Vec3::zeros(),
+            viewport_count: 1,
+            frame: 0,
+            debug_shapes_renderer,
+            debug_shapes_changed: false,
+            particle_renderer,
+            particles_changed: false,
+            grid_renderer,
+        };
+
+        pbr_scene_data.recreate_pipelines(shader_cache, render_pass, samples)?;
+        Ok(pbr_scene_data)
+    }
+
+    fn create_instance_buffer(context: Arc<VulkanContext>, number_of_instances: usize) -> Buffer {
+        let buffer = Buffer::new_mapped_basic(
+            context.clone(),
+            (number_of_instances.max(1) * mem::size_of::<InstanceVertex>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )
+        .unwrap();
+        context.set_debug_name(buffer.buffer(), "Instance Transforms");
+        buffer
+    }
+
+    pub fn load_progress(&self) -> LoadProgress {
+        self.asset_loader.progress()
+    }
+
+    // Only available once the asset has been received
+    pub fn asset_bounding_box(&self, asset_name: &str) -> Option<Aabb> {
+        let metadata = self.asset_cache.metadata.get(asset_name)?;
+        self.asset_cache.assets[metadata.index].bounding_box()
+    }
+
+    // Only available once the asset has been received
+    pub fn asset_scenes(&self, asset_name: &str) -> Vec<SceneInfo> {
+        let asset = match self.asset_cache.metadata.get(asset_name) {
+            Some(metadata) => &self.asset_cache.assets[metadata.index],
+            None => return Vec::new(),
+        };
+        asset
+            .scenes()
+            .iter()
+            .enumerate()
+            .map(|(index, scene)| SceneInfo {
+                name: scene.name.clone(),
+                active: index == asset.active_scene(),
+            })
+            .collect()
+    }
+
+    // Returns true if the active scene changed and command buffers need to be re-recorded
+    pub fn set_asset_scene(
+        &mut self,
+        command_pool: &CommandPool,
+        asset_name: &str,
+        index: usize,
+    ) -> Result<bool> {
+        let metadata = self.asset_cache.metadata.get(asset_name).with_context(|| {
+            format!(
+                "Failed to select a scene of '{}' before it loaded!",
+                asset_name
+            )
+        })?;
+        let asset = &mut self.asset_cache.assets[metadata.index];
+        if asset.active_scene() == index {
+            return Ok(false);
+        }
+        asset.set_active_scene(index)?;
+
+        // The instances and joints are laid out from the nodes of the active scene
+        self.context.logical_device().wait_idle();
+        self.upload_assets(command_pool);
+        Ok(true)
+    }
+
+    pub fn asset_primitive_bounding_boxes(&self, asset_name: &str) -> Vec<Aabb> {
+        match self.asset_cache.metadata.get(asset_name) {
+            Some(metadata) => self.asset_cache.assets[metadata.index]
+                .primitive_bounding_boxes()
+                .to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    // Only available once the asset has been received
+    pub fn asset_cameras(&self, asset_name: &str) -> Vec<AssetCamera> {
+        match self.asset_cache.metadata.get(asset_name) {
+            Some(metadata) => self.asset_cache.assets[metadata.index].cameras(),
+            None => Vec::new(),
+        }
+    }
+
+    // Only available once the asset has been received
+    pub fn asset_animations(&self, asset_name: &str) -> Vec<AnimationInfo> {
+        let metadata = match self.asset_cache.metadata.get(asset_name) {
+            Some(metadata) => metadata,
+            None => return Vec::new(),
+        };
+        self.asset_cache.assets[metadata.index]
+            .animations
+            .iter()
+            .map(|animation| AnimationInfo {
+                name: animation.name.clone(),
+                duration: animation.duration(),
+            })
+            .collect()
+    }
+
+    // The assets that have been received, which excludes any that are still loading
+    pub fn loaded_assets(&self) -> Vec<String> {
+        self.asset_cache.loaded_assets()
+    }
+
+    // The asset is streamed in and received like the assets the scene was created with
+    pub fn load_asset(&mut self, asset_name: &str) {
+        if self.asset_cache.contains_asset(asset_name)
+            || self.asset_loader.is_pending(asset_name)
+            || self.failed_assets.contains(asset_name)
+        {
+            return;
+        }
+        self.asset_loader.load(asset_name);
+    }
+
+    // Returns true if the asset was loaded and command buffers need to be re-recorded
+    pub fn unload_asset(&mut self, command_pool: &CommandPool, asset_name: &str) -> bool {
+        if !self.asset_cache.contains_asset(asset_name) {
+            return false;
+        }
+
+        // The asset's buffers and textures may still be in use
+        self.context.logical_device().wait_idle();
+        self.asset_cache.remove_asset(asset_name);
+        self.upload_assets(command_pool);
+        true
+    }
+
+    // Matches the instances to the assets of the scene, one name per instance.
+    // Returns true if they changed and command buffers need to be re-recorded.
+    pub fn update_instances(&mut self, command_pool: &CommandPool, asset_names: &[String]) -> bool {
+        if !self.asset_cache.set_asset_names(asset_names) {
+            return false;
+        }
+
+        self.context.logical_device().wait_idle();
+        self.upload_assets(command_pool);
+        true
+    }
+
+    // Uploads any assets that finished loading since the last call.
+    // Returns true if the scene changed and command buffers need to be re-recorded.
+    pub fn receive_assets(&mut self, command_pool: &CommandPool) -> bool {
+        let received = self.asset_loader.receive();
+        if received.is_empty() {
+            return false;
+        }
+
+        // Buffers and descriptors that are replaced below may still be in use
+        self.context.logical_device().wait_idle();
+
+        for (asset_name, data) in received.into_iter() {
+            let asset = data
+                .and_then(|data| GltfAsset::from_data(self.context.clone(), command_pool, data))
+                .and_then(|asset| {
+                    self.check_texture_capacity(&asset)?;
+                    Ok(asset)
+                });
+            match asset {
+                Ok(asset) => self.asset_cache.insert_asset(&asset_name, asset),
+                Err(error) => {
+                    error!("Failed to load asset '{}': {}", asset_name, error);
+                    self.failed_assets.insert(asset_name);
+                }
+            }
+        }
+
+        if self.asset_cache.assets.is_empty() {
+            return false;
+        }
+
+        self.upload_assets(command_pool);
+        true
+    }
+
+    // Uploads an asset that is already in memory, such as one read from an archive or downloaded.
+    // Its instances are drawn like those of streamed assets.
+    pub fn load_asset_from_bytes(
+        &mut self,
+        command_pool: &CommandPool,
+        asset_name: &str,
+        bytes: &[u8],
+        base_dir: &Path,
+    ) -> Result<()> {
+        self.insert_asset_now(command_pool, asset_name, |context| {
+            GltfAsset::from_bytes(context, command_pool, bytes, base_dir)
+        })
+    }
+
+    // Loads an asset on the calling thread instead of streaming it in
+    pub fn load_asset_now(&mut self, command_pool: &CommandPool, asset_name: &str) -> Result<()> {
+        self.insert_asset_now(command_pool, asset_name, |context| {
+            GltfAsset::new(context, command_pool, asset_name)
+        })
+    }
+
+    fn insert_asset_now<T>(
+        &mut self,
+        command_pool: &CommandPool,
+        asset_name: &str,
+        load: T,
+    ) -> Result<()>
+    where
+        T: FnOnce(Arc<VulkanContext>) -> Result<GltfAsset>,
+    {
+        ensure!(
+            !self.asset_cache.contains_asset(asset_name)
+                && !self.asset_loader.is_pending(asset_name),
+            "The asset '{}' is already loaded!",
+            asset_name
+        );
+
+        // Buffers and descriptors that are replaced below may still be in use
+        self.context.logical_device().wait_idle();
+
+        let asset = load(self.context.clone())?;
+        self.check_texture_capacity(&asset)?;
+        self.failed_assets.remove(asset_name);
+        self.asset_cache.insert_asset(asset_name, asset);
+        self.upload_assets(command_pool);
+        Ok(())
+    }
+
+    // Assets that would overflow the texture array are left out of the scene
+    fn check_texture_capacity(&self, asset: &GltfAsset) -> Result<()> {
+        let number_of_textures = self.asset_cache.number_of_textures();
+        let texture_capacity = self.pbr_pipeline_data.texture_capacity;
+        ensure!(
+            number_of_textures + asset.textures.len() <= texture_capacity,
+            "The asset's {} textures would exceed the limit of {} textures, {} of which are in use!",
+            asset.textures.len(),
+            texture_capacity,
+            number_of_textures
+        );
+        Ok(())
+    }
+
+    // Rebuilds everything that depends on the loaded assets and their instances.
+    // The gpu must be finished with the previous resources.
+    fn upload_assets(&mut self, command_pool: &CommandPool) {
+        self.asset_geometry_buffer = if self.asset_cache.assets.is_empty() {
+            None
+        } else {
+            Some(self.asset_cache.create_geometry_buffer(command_pool))
         };
+        self.instance_buffer = Self::create_instance_buffer(
+            self.context.clone(),
+            self.asset_cache.number_of_instances(),
+        );
+        self.outline_instance_buffer = Self::create_instance_buffer(
+            self.context.clone(),
+            self.asset_cache.number_of_instances(),
+        );
+        self.pbr_pipeline_data
+            .reserve_meshes(self.context.clone(), self.asset_cache.number_of_meshes());
+        self.pbr_pipeline_data
+            .upload_morph_targets(self.context.clone(), &self.asset_cache.morph_targets());
+        self.indirect_draws = self.create_indirect_draws();
+        let indirect_materials = match self.indirect_draws.as_ref() {
+            Some(indirect_draws) => indirect_draws.materials().to_vec(),
+            None => Vec::new(),
+        };
+        self.pbr_pipeline_data
+            .upload_indirect_materials(self.context.clone(), &indirect_materials);
+        self.pbr_pipeline_data
+            .update_descriptor_set(
+                self.context.clone(),
+                &self.asset_cache.textures(),
+                &self.environment_maps,
+                &self.shadow_map,
+                &self.transmission_map,
+            )
+            .expect("Failed to update the pbr descriptor set!");
+    }
+
+    // Without multi draw indirect, or indirect draws with a nonzero first instance,
+    // every draw is recorded directly
+    fn create_indirect_draws(&self) -> Option<IndirectDraws> {
+        let features = self.context.features();
+        if !features.multi_draw_indirect || !features.draw_indirect_first_instance {
+            return None;
+        }
 
-        pbr_scene_data.recreate_pipelines(shader_cache, render_pass, samples);
-        pbr_scene_data
+        let pbr_renderer = PbrRenderer::new(
+            vk::CommandBuffer::null(),
+            &self.pbr_pipeline.as_ref().unwrap().culled.pipeline,
+            &self.pbr_pipeline_data,
+            0,
+        );
+        let asset_draws = self
+            .asset_cache
+            .metadata
+            .values()
+            .map(|metadata| {
+                let asset = &self.asset_cache.assets[metadata.index];
+                let mut draws = Vec::new();
+                pbr_renderer.collect_draws(asset, metadata, AlphaMode::Opaque, &mut draws);
+                draws
+                    .iter()
+                    .filter(|draw| draw.indirect)
+                    .map(PbrDraw::indirect_draw)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        IndirectDraws::new(self.context.clone(), &asset_draws)
     }
 
     pub fn recreate_pipelines(
@@ -757,7 +2157,7 @@ impl PbrScene {
         shader_cache: &mut ShaderCache,
         render_pass: Arc<RenderPass>,
         samples: vk::SampleCountFlags,
-    ) {
+    ) -> Result<()> {
         let descriptions = GltfAsset::create_vertex_input_descriptions();
         let attributes = GltfAsset::create_vertex_attributes();
         let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
@@ -775,9 +2175,34 @@ impl PbrScene {
             .fragment("assets/shaders/pbr/pbr.frag.spv")
             .build()
             .unwrap();
-        let shader_set = shader_cache
-            .create_shader_set(self.context.clone(), &shader_paths)
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        // The pre-pass only needs the depth, so the fragment shader is omitted
+        let depth_prepass_shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/pbr/pbr.vert.spv")
+            .build()
+            .unwrap();
+        let depth_prepass_shader_set =
+            shader_cache.create_shader_set(self.context.clone(), &depth_prepass_shader_paths)?;
+
+        let highlight_shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/pbr/pbr.vert.spv")
+            .fragment("assets/shaders/pbr/highlight.frag.spv")
+            .build()
             .unwrap();
+        let highlight_shader_set =
+            shader_cache.create_shader_set(self.context.clone(), &highlight_shader_paths)?;
+
+        let depth_prepass_settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(render_pass.clone())
+            .vertex_state_info(vertex_state_info)
+            .descriptor_set_layout(self.pbr_pipeline_data.descriptor_set_layout.clone())
+            .shader_set(depth_prepass_shader_set)
+            .color_write_enabled(false)
+            .rasterization_samples(samples)
+            .push_constant_range(push_constant_range)
+            .build()
+            .expect("Failed to create render pipeline settings");
 
         let mut settings = RenderPipelineSettingsBuilder::default()
             .render_pass(render_pass.clone())
@@ -786,155 +2211,1244 @@ impl PbrScene {
             .shader_set(shader_set)
             .rasterization_samples(samples)
             .sample_shading_enabled(true)
-            .cull_mode(vk::CullModeFlags::NONE)
             .push_constant_range(push_constant_range)
+            .fragment_constants(vec![(
+                PbrPipelineData::TEXTURE_CAPACITY_CONSTANT_ID,
+                self.pbr_pipeline_data.texture_capacity as u32,
+            )])
             .build()
             .expect("Failed to create render pipeline settings");
 
+        self.shadow_map.recreate_pipeline(
+            shader_cache,
+            self.pbr_pipeline_data.descriptor_set_layout.clone(),
+            push_constant_range,
+        )?;
+        self.picking_pass.recreate_pipeline(
+            shader_cache,
+            self.pbr_pipeline_data.descriptor_set_layout.clone(),
+        )?;
+        self.debug_shapes_renderer
+            .recreate_pipeline(shader_cache, render_pass.clone(), samples)?;
+        self.particle_renderer
+            .recreate_pipeline(shader_cache, render_pass.clone(), samples)?;
+        self.grid_renderer
+            .recreate_pipeline(shader_cache, render_pass.clone(), samples)?;
+
         self.pbr_pipeline = None;
         self.pbr_pipeline_blend = None;
-        self.pbr_pipeline = Some(RenderPipeline::new(self.context.clone(), settings.clone()));
+        self.pbr_pipeline_mask = None;
+        self.depth_prepass_pipeline = None;
+        self.pbr_pipeline_depth_equal = None;
+        self.topology_pipelines.clear();
+        self.highlight_pipeline = None;
+        self.outline_mask_pipeline = None;
+        self.outline_pipeline = None;
+        self.pbr_pipeline = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            settings.clone(),
+            "Pbr",
+        )?);
+
+        for topology in Self::EXTRA_TOPOLOGIES.iter() {
+            for blended in [false, true].iter() {
+                let mut topology_settings = settings.clone();
+                topology_settings.topology = *topology;
+                topology_settings.blended = *blended;
+                self.topology_pipelines.insert(
+                    (*topology, *blended),
+                    PbrPipelineVariants::new(
+                        self.context.clone(),
+                        topology_settings,
+                        &format!("Pbr {:?} (Blended: {})", topology, blended),
+                    )?,
+                );
+            }
+        }
+
+        // Opaque geometry is shaded only where it matches the pre-pass depth
+        let mut depth_equal_settings = settings.clone();
+        depth_equal_settings.depth_write_enabled = false;
+        depth_equal_settings.depth_compare_op = vk::CompareOp::EQUAL;
+        self.pbr_pipeline_depth_equal = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            depth_equal_settings,
+            "Pbr Depth Equal",
+        )?);
+        self.depth_prepass_pipeline = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            depth_prepass_settings.clone(),
+            "Depth Pre-Pass",
+        )?);
+
+        // Selected instances are drawn over themselves, so the depth is only tested
+        let mut highlight_settings = settings.clone();
+        highlight_settings.shader_set = highlight_shader_set;
+        highlight_settings.additive = true;
+        highlight_settings.depth_write_enabled = false;
+        highlight_settings.sample_shading_enabled = false;
+        self.highlight_pipeline = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            highlight_settings.clone(),
+            "Highlight",
+        )?);
+
+        // The outline is drawn over everything, so neither of its pipelines test the depth
+        let outline_stencil_state = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::REPLACE,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference: 1,
+        };
+        let mut outline_mask_settings = depth_prepass_settings.clone();
+        outline_mask_settings.depth_test_enabled = false;
+        outline_mask_settings.depth_write_enabled = false;
+        outline_mask_settings.stencil_test_enabled = true;
+        outline_mask_settings.stencil_front_state = outline_stencil_state;
+        outline_mask_settings.stencil_back_state = outline_stencil_state;
+        self.outline_mask_pipeline = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            outline_mask_settings,
+            "Outline Mask",
+        )?);
+
+        let outline_stencil_state = vk::StencilOpState {
+            pass_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            write_mask: 0,
+            ..outline_stencil_state
+        };
+        let mut outline_settings = highlight_settings.clone();
+        outline_settings.additive = false;
+        outline_settings.blended = true;
+        outline_settings.depth_test_enabled = false;
+        outline_settings.stencil_test_enabled = true;
+        outline_settings.stencil_front_state = outline_stencil_state;
+        outline_settings.stencil_back_state = outline_stencil_state;
+        self.outline_pipeline = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            outline_settings,
+            "Outline",
+        )?);
+
+        let mut mask_settings = settings.clone();
+        mask_settings.alpha_to_coverage_enabled = samples != vk::SampleCountFlags::TYPE_1;
+        self.pbr_pipeline_mask = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            mask_settings,
+            "Pbr Masked",
+        )?);
+
         settings.blended = true;
-        self.pbr_pipeline_blend = Some(RenderPipeline::new(self.context.clone(), settings));
+        self.pbr_pipeline_blend = Some(PbrPipelineVariants::new(
+            self.context.clone(),
+            settings,
+            "Pbr Blended",
+        )?);
 
         self.skybox_pipeline = None;
         self.skybox_pipeline = Some(create_skybox_pipeline(
             self.context.clone(),
             shader_cache,
             render_pass,
-            vk::SampleCountFlags::TYPE_1,
-        ));
+            samples,
+        )?);
+        Ok(())
     }
 
+    // The scene is drawn once per viewport, each in its own region of the extent
     pub fn issue_commands(
-        &mut self,
+        &self,
         command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        render_settings: &RenderSettings,
+        frame: usize,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.render_skybox(command_buffer);
-        self.render_pbr_assets(command_buffer);
+        let regions = self.viewport_regions(extent);
+        if render_settings.draw_skybox {
+            self.render_skybox(command_buffer, &regions, frame);
+        }
+        self.render_pbr_assets(command_buffer, &regions, render_settings, false, frame);
         Ok(())
     }
 
-    fn render_skybox(&mut self, command_buffer: vk::CommandBuffer) {
-        let skybox_pipeline = self.skybox_pipeline.as_ref().unwrap();
-        skybox_pipeline.bind(
-            self.context.logical_device().logical_device(),
-            command_buffer,
-        );
-
-        let skybox_renderer =
-            SkyboxRenderer::new(command_buffer, &skybox_pipeline, &self.skybox_pipeline_data);
+    // Transmissive primitives are drawn in their own render pass,
+    // after the opaque scene color has been copied into the transmission map
+    pub fn issue_transmission_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        render_settings: &RenderSettings,
+        frame: usize,
+    ) {
+        let regions = self.viewport_regions(extent);
+        self.render_pbr_assets(command_buffer, &regions, render_settings, true, frame);
+    }
 
-        skybox_renderer.draw(
-            self.context.logical_device().logical_device(),
-            &self.skybox_pipeline_data.cube,
-        );
+    // The source is the single sampled offscreen color target
+    pub fn copy_transmission_source(&self, command_buffer: vk::CommandBuffer, source: vk::Image) {
+        self.transmission_map.copy_from(command_buffer, source);
     }
 
-    fn render_pbr_assets(&mut self, command_buffer: vk::CommandBuffer) {
-        let pbr_pipeline = self.pbr_pipeline.as_ref().unwrap();
-        let pbr_pipeline_blended = self.pbr_pipeline_blend.as_ref().unwrap();
-        let pbr_renderer = PbrRenderer::new(
-            command_buffer,
-            &pbr_pipeline.pipeline,
-            &self.pbr_pipeline_data,
-        );
-        let pbr_renderer_blended = PbrRenderer::new(
-            command_buffer,
-            &pbr_pipeline.pipeline,
-            &self.pbr_pipeline_data,
+    // Records the skybox and indirect draws into the first command buffer and splits the asset draws
+    // across the remaining secondary command buffers, which are recorded in parallel.
+    // When the grid is drawn, the last command buffer is kept for the grid
+    // and the blended draws that follow it, which also keeps them in back to front order.
+    // Returns the command buffers that were recorded, in the order they should be executed.
+    pub fn issue_commands_parallel(
+        &self,
+        command_buffers: &[vk::CommandBuffer],
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        render_settings: &RenderSettings,
+        frame: usize,
+    ) -> Vec<vk::CommandBuffer> {
+        let (skybox_command_buffer, draw_command_buffers) = command_buffers
+            .split_first()
+            .expect("Failed to get a command buffer for the skybox!");
+
+        let regions = self.viewport_regions(extent);
+        let logical_device = self.context.logical_device();
+        logical_device.record_secondary_command_buffer(
+            *skybox_command_buffer,
+            render_pass,
+            framebuffer,
+            || {
+                if render_settings.draw_skybox {
+                    self.render_skybox(*skybox_command_buffer, &regions, frame);
+                }
+                self.render_indirect_draws(
+                    *skybox_command_buffer,
+                    &regions,
+                    render_settings,
+                    frame,
+                );
+                // The grid is still drawn before any assets have loaded
+                if render_settings.draw_grid && self.asset_geometry_buffer.is_none() {
+                    self.grid_renderer
+                        .issue_commands(*skybox_command_buffer, &regions, frame);
+                }
+            },
         );
 
-        let offsets = [0];
-        let vertex_buffers = [self.asset_geometry_buffer.vertex_buffer.buffer()];
+        let mut recorded_command_buffers = vec![*skybox_command_buffer];
+        let (grid_command_buffer, draw_command_buffers) = if render_settings.draw_grid {
+            match draw_command_buffers.split_last() {
+                Some((grid_command_buffer, draw_command_buffers)) => {
+                    (Some(*grid_command_buffer), draw_command_buffers)
+                }
+                None => (None, draw_command_buffers),
+            }
+        } else {
+            (None, draw_command_buffers)
+        };
+        let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+            Some(geometry_buffer) if !draw_command_buffers.is_empty() => geometry_buffer,
+            _ => return recorded_command_buffers,
+        };
 
-        unsafe {
-            self.context
-                .logical_device()
-                .logical_device()
-                .cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-            self.context
-                .logical_device()
-                .logical_device()
-                .cmd_bind_index_buffer(
-                    command_buffer,
-                    self.asset_geometry_buffer
-                        .index_buffer
-                        .as_ref()
-                        .expect("Failed to get an index buffer!")
-                        .buffer(),
-                    0,
-                    vk::IndexType::UINT32,
+        let all_draws = self.collect_draws(render_settings, false);
+        let (draws, blended_draws) = match grid_command_buffer {
+            Some(_) => Self::split_blended_draws(&all_draws),
+            None => (&all_draws[..], &all_draws[..0]),
+        };
+        let chunk_size = draws.len().div_ceil(draw_command_buffers.len()).max(1);
+        let chunks = draws.chunks(chunk_size).collect::<Vec<_>>();
+
+        let pipeline_layout = self.pbr_pipeline.as_ref().unwrap().culled.pipeline.layout();
+        let pipeline_data = &self.pbr_pipeline_data;
+        let vertex_buffers = [
+            geometry_buffer.vertex_buffer.buffer(),
+            self.instance_buffer.buffer(),
+        ];
+        let index_buffer = geometry_buffer
+            .index_buffer
+            .as_ref()
+            .expect("Failed to get an index buffer!")
+            .buffer();
+
+        chunks
+            .par_iter()
+            .zip(draw_command_buffers.par_iter())
+            .for_each(|(draws, command_buffer)| {
+                let device = logical_device.logical_device();
+                let mut pbr_renderer = PbrRenderer {
+                    command_buffer: *command_buffer,
+                    pipeline_layout,
+                    dynamic_alignment: pipeline_data.dynamic_alignment,
+                    uniform_alignment: pipeline_data.uniform_alignment,
+                    uniform_offset: 0,
+                    descriptor_set: pipeline_data.frames[frame].descriptor_set,
+                };
+
+                logical_device.record_secondary_command_buffer(
+                    *command_buffer,
+                    render_pass,
+                    framebuffer,
+                    || {
+                        // Secondary command buffers don't inherit any state
+                        unsafe {
+                            device.cmd_bind_vertex_buffers(
+                                *command_buffer,
+                                0,
+                                &vertex_buffers,
+                                &[0, 0],
+                            );
+                            device.cmd_bind_index_buffer(
+                                *command_buffer,
+                                index_buffer,
+                                0,
+                                vk::IndexType::UINT32,
+                            );
+                        }
+
+                        for (viewport, region) in regions.iter().enumerate() {
+                            logical_device.set_viewport(*command_buffer, *region);
+                            pbr_renderer.set_viewport(viewport);
+                            Self::record_draws(device, &pbr_renderer, draws);
+                        }
+                    },
                 );
+            });
+
+        recorded_command_buffers.extend(draw_command_buffers.iter().take(chunks.len()));
+
+        if let Some(command_buffer) = grid_command_buffer {
+            let device = logical_device.logical_device();
+            let mut pbr_renderer = PbrRenderer {
+                command_buffer,
+                pipeline_layout,
+                dynamic_alignment: pipeline_data.dynamic_alignment,
+                uniform_alignment: pipeline_data.uniform_alignment,
+                uniform_offset: 0,
+                descriptor_set: pipeline_data.frames[frame].descriptor_set,
+            };
+            logical_device.record_secondary_command_buffer(
+                command_buffer,
+                render_pass,
+                framebuffer,
+                || {
+                    self.grid_renderer
+                        .issue_commands(command_buffer, &regions, frame);
+                    unsafe {
+                        device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &[0, 0]);
+                        device.cmd_bind_index_buffer(
+                            command_buffer,
+                            index_buffer,
+                            0,
+                            vk::IndexType::UINT32,
+                        );
+                    }
+                    for (viewport, region) in regions.iter().enumerate() {
+                        logical_device.set_viewport(command_buffer, *region);
+                        pbr_renderer.set_viewport(viewport);
+                        Self::record_draws(device, &pbr_renderer, blended_draws);
+                    }
+                },
+            );
+            recorded_command_buffers.push(command_buffer);
+        }
+
+        recorded_command_buffers
+    }
+
+    // Counts the draws recorded by issue_commands with the given settings
+    pub fn stats(&self, render_settings: &RenderSettings) -> RenderStats {
+        let mut stats = RenderStats::default();
+        if render_settings.draw_skybox {
+            stats.draw_calls += 1;
+            stats.instances += 1;
+        }
+        if render_settings.draw_grid {
+            stats.draw_calls += 1;
+            stats.instances += 1;
+        }
+
+        if self.asset_geometry_buffer.is_some() {
+            let mut draws = self.collect_draws(render_settings, false);
+            if self.has_transmissive_primitives() {
+                draws.extend(self.collect_draws(render_settings, true));
+            }
+            stats.draw_calls += draws.len();
+            stats.instances += draws
+                .iter()
+                .map(|(_, draw)| draw.number_of_instances as usize)
+                .sum::<usize>();
+            // Indirect draws are recorded twice with the depth pre-pass, like other opaque draws
+            if let Some(indirect_draws) = self.indirect_draws.as_ref() {
+                let passes = if render_settings.depth_prepass { 2 } else { 1 };
+                stats.draw_calls += passes * indirect_draws.number_of_draw_calls();
+                stats.instances += passes * indirect_draws.number_of_instances();
+            }
+        }
+
+        stats
+    }
+
+    // Draws are ordered by alpha mode so blended primitives are drawn last.
+    // With the depth pre-pass, opaque primitives are drawn twice:
+    // once to write depth and once to shade them with an equal depth test.
+    // Only the draws of transmissive materials are collected when transmissive is set.
+    fn collect_draws(
+        &self,
+        render_settings: &RenderSettings,
+        transmissive: bool,
+    ) -> Vec<(vk::Pipeline, PbrDraw)> {
+        let pbr_pipeline = self.pbr_pipeline.as_ref().unwrap();
+        let pbr_renderer = PbrRenderer::new(
+            vk::CommandBuffer::null(),
+            &pbr_pipeline.culled.pipeline,
+            &self.pbr_pipeline_data,
+            0,
+        );
+
+        let mut draws = Vec::new();
+        for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend].iter() {
+            let first_draw = draws.len();
+            for metadata in self.asset_cache.metadata.values() {
+                let asset = &self.asset_cache.assets[metadata.index];
+                pbr_renderer.collect_draws(asset, metadata, *alpha_mode, &mut draws);
+            }
+
+            if *alpha_mode == AlphaMode::Blend {
+                self.sort_back_to_front(&mut draws[first_draw..]);
+            }
+        }
+        // Draws batched indirectly are recorded separately, before the direct draws
+        let indirect = self.indirect_draws.is_some();
+        draws.retain(|draw| {
+            draw.material.is_transmissive() == transmissive && !(indirect && draw.indirect)
+        });
+
+        // The debug override draws every primitive without culling back faces
+        let double_sided = |draw: &PbrDraw| render_settings.double_sided || draw.double_sided;
+        let pipeline = |pipeline: &Option<PbrPipelineVariants>, draw: &PbrDraw| {
+            pipeline
+                .as_ref()
+                .expect("Failed to get pbr pipeline!")
+                .pipeline(double_sided(draw))
+        };
+
+        let mut pipeline_draws = Vec::new();
+        // Transmissive primitives are drawn after the pre-pass depth has been used
+        let depth_prepass = render_settings.depth_prepass && !transmissive;
+        if depth_prepass {
+            pipeline_draws.extend(
+                draws
+                    .iter()
+                    .filter(|draw| draw.alpha_mode == AlphaMode::Opaque && draw.is_triangle_list())
+                    .map(|draw| (pipeline(&self.depth_prepass_pipeline, draw), *draw)),
+            );
+        }
+
+        for draw in draws.into_iter() {
+            if !draw.is_triangle_list() {
+                let blended = draw.alpha_mode == AlphaMode::Blend;
+                let topology_pipeline = self
+                    .topology_pipelines
+                    .get(&(draw.topology, blended))
+                    .expect("Failed to get pbr topology pipeline!");
+                pipeline_draws.push((topology_pipeline.pipeline(double_sided(&draw)), draw));
+                continue;
+            }
+
+            let draw_pipeline = match draw.alpha_mode {
+                AlphaMode::Opaque if depth_prepass => {
+                    pipeline(&self.pbr_pipeline_depth_equal, &draw)
+                }
+                AlphaMode::Mask => pipeline(&self.pbr_pipeline_mask, &draw),
+                AlphaMode::Blend => pipeline(&self.pbr_pipeline_blend, &draw),
+                _ => pipeline(&self.pbr_pipeline, &draw),
+            };
+            pipeline_draws.push((draw_pipeline, draw));
+        }
+
+        if render_settings.highlight_selection && !transmissive {
+            pipeline_draws.extend(self.collect_highlight_draws(render_settings));
         }
+        pipeline_draws
+    }
+
+    // The highlight color is pushed in place of the base color factor
+    fn collect_highlight_draws(&self, render_settings: &RenderSettings) -> Vec<PipelineDraw> {
+        let highlight_pipeline = self
+            .highlight_pipeline
+            .as_ref()
+            .expect("Failed to get highlight pipeline!");
+        let color = glm::make_vec4(&render_settings.highlight_color);
+        self.collect_selected_draws()
+            .into_iter()
+            .map(|mut draw| {
+                draw.material.base_color_factor = color;
+                let double_sided = render_settings.double_sided || draw.double_sided;
+                (highlight_pipeline.pipeline(double_sided), draw)
+            })
+            .collect()
+    }
+
+    // The triangle list draws of every primitive of the selected instances
+    fn collect_selected_draws(&self) -> Vec<PbrDraw> {
+        let pbr_renderer = PbrRenderer::new(
+            vk::CommandBuffer::null(),
+            &self.pbr_pipeline.as_ref().unwrap().culled.pipeline,
+            &self.pbr_pipeline_data,
+            0,
+        );
 
-        [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend]
+        let mut draws = Vec::new();
+        for metadata in self.asset_cache.metadata.values() {
+            let asset = &self.asset_cache.assets[metadata.index];
+            for instance in 0..metadata.instances.len() {
+                if !self
+                    .selected_instances
+                    .contains(&(metadata.instance_offset + instance))
+                {
+                    continue;
+                }
+                for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend].iter() {
+                    pbr_renderer.collect_instance_draws(
+                        asset,
+                        metadata,
+                        instance,
+                        1,
+                        *alpha_mode,
+                        &mut draws,
+                    );
+                }
+            }
+        }
+        draws.retain(|draw| draw.is_triangle_list());
+        draws
+    }
+
+    fn sort_back_to_front(&self, draws: &mut [PbrDraw]) {
+        let distance = |draw: &PbrDraw| {
+            let model = self
+                .instance_models
+                .get(draw.first_instance as usize)
+                .copied()
+                .unwrap_or_else(glm::Mat4::identity);
+            let position = (model * draw.centroid.push(1.0)).xyz();
+            glm::distance2(&position, &self.camera_position)
+        };
+        draws.sort_by(|a, b| {
+            distance(b)
+                .partial_cmp(&distance(a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    // The blended draw order depends on the camera,
+    // so command buffers are recorded again when moving it changes the order
+    pub fn blended_draw_order_changed(&self) -> bool {
+        self.blended_draw_order_changed
+    }
+
+    fn update_blended_draw_order(&mut self) {
+        if !self.has_blended_primitives() {
+            self.blended_draw_order_changed = false;
+            return;
+        }
+
+        let pbr_pipeline = self.pbr_pipeline.as_ref().unwrap();
+        let pbr_renderer = PbrRenderer::new(
+            vk::CommandBuffer::null(),
+            &pbr_pipeline.culled.pipeline,
+            &self.pbr_pipeline_data,
+            0,
+        );
+        let mut draws = Vec::new();
+        for metadata in self.asset_cache.metadata.values() {
+            let asset = &self.asset_cache.assets[metadata.index];
+            pbr_renderer.collect_draws(asset, metadata, AlphaMode::Blend, &mut draws);
+        }
+        self.sort_back_to_front(&mut draws);
+
+        let order = draws
             .iter()
-            .for_each(|alpha_mode| {
-                match alpha_mode {
-                    AlphaMode::Opaque => pbr_pipeline.bind(
-                        self.context.logical_device().logical_device(),
-                        command_buffer,
-                    ),
-                    AlphaMode::Blend => pbr_pipeline_blended.bind(
-                        self.context.logical_device().logical_device(),
-                        command_buffer,
-                    ),
-                    _ => {}
+            .map(|draw| (draw.first_instance, draw.first_index, draw.first_vertex))
+            .collect::<Vec<_>>();
+        self.blended_draw_order_changed = order != self.blended_draw_order;
+        self.blended_draw_order = order;
+    }
+
+    fn has_blended_primitives(&self) -> bool {
+        self.asset_cache.assets.iter().any(|asset| {
+            asset
+                .gltf
+                .materials()
+                .any(|material| material.alpha_mode() == AlphaMode::Blend)
+        })
+    }
+
+    pub fn has_transmissive_primitives(&self) -> bool {
+        self.asset_cache.assets.iter().any(|asset| {
+            asset
+                .material_extensions
+                .iter()
+                .any(|extensions| extensions.transmission_factor > 0.0)
+        })
+    }
+
+    pub fn viewport_count(&self) -> usize {
+        self.viewport_count
+    }
+
+    pub fn has_debug_shapes(&self, frame: usize) -> bool {
+        self.debug_shapes_renderer.has_shapes(frame)
+    }
+
+    // True if the last update changed the debug shape draw, which must be recorded again
+    pub fn debug_shapes_changed(&self) -> bool {
+        self.debug_shapes_changed
+    }
+
+    pub fn has_particles(&self, frame: usize) -> bool {
+        self.particle_renderer.has_particles(frame)
+    }
+
+    // True if the last update changed the particle draws, which must be recorded again
+    pub fn particles_changed(&self) -> bool {
+        self.particles_changed
+    }
+
+    // Loads the sprites of emitters that haven't been seen before
+    pub fn load_particle_textures(
+        &mut self,
+        command_pool: &CommandPool,
+        emitters: &[ParticleEmitter],
+    ) {
+        self.particle_renderer.load_textures(command_pool, emitters);
+    }
+
+    pub fn selection_changed(&self) -> bool {
+        self.selection_changed
+    }
+
+    pub fn has_selection(&self) -> bool {
+        !self.selected_instances.is_empty()
+    }
+
+    // Drawn after the scene in a render pass that continues the offscreen targets.
+    // The stencil is cleared first, since the passes before this one don't keep it.
+    pub fn issue_outline_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        render_settings: &RenderSettings,
+        frame: usize,
+    ) {
+        let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+            Some(geometry_buffer) => geometry_buffer,
+            None => return,
+        };
+
+        let device = self.context.logical_device().logical_device();
+        self.context.begin_debug_label(command_buffer, "Outline");
+        let clear_attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::STENCIL,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        };
+        let clear_rect = vk::ClearRect {
+            rect: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        unsafe {
+            device.cmd_clear_attachments(command_buffer, &[clear_attachment], &[clear_rect]);
+        }
+
+        let mut pbr_renderer = PbrRenderer::new(
+            command_buffer,
+            &self.pbr_pipeline.as_ref().unwrap().culled.pipeline,
+            &self.pbr_pipeline_data,
+            frame,
+        );
+        let double_sided = |draw: &PbrDraw| render_settings.double_sided || draw.double_sided;
+        let pipeline = |pipeline: &Option<PbrPipelineVariants>, draw: &PbrDraw| {
+            pipeline
+                .as_ref()
+                .expect("Failed to get outline pipeline!")
+                .pipeline(double_sided(draw))
+        };
+        let color = glm::make_vec4(&render_settings.outline_color);
+        let mut draws = self.collect_selected_draws();
+        draws
+            .iter_mut()
+            .for_each(|draw| draw.material.base_color_factor = color);
+        let mask_draws = draws
+            .iter()
+            .map(|draw| (pipeline(&self.outline_mask_pipeline, draw), *draw))
+            .collect::<Vec<_>>();
+        let outline_draws = draws
+            .iter()
+            .map(|draw| (pipeline(&self.outline_pipeline, draw), *draw))
+            .collect::<Vec<_>>();
+
+        geometry_buffer.bind(device, command_buffer);
+        let regions = self.viewport_regions(extent);
+        for (instance_buffer, draws) in [
+            (&self.instance_buffer, &mask_draws),
+            (&self.outline_instance_buffer, &outline_draws),
+        ]
+        .iter()
+        {
+            unsafe {
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    1,
+                    &[instance_buffer.buffer()],
+                    &[0],
+                );
+            }
+            for (viewport, region) in regions.iter().enumerate() {
+                self.context
+                    .logical_device()
+                    .set_viewport(command_buffer, *region);
+                pbr_renderer.set_viewport(viewport);
+                Self::record_draws(device, &pbr_renderer, draws);
+            }
+        }
+        self.context.end_debug_label(command_buffer);
+    }
+
+    // The selected instances scaled about the center of their asset
+    fn update_outline_instances(&self, scale: f32) {
+        for metadata in self.asset_cache.metadata.values() {
+            let center = match self.asset_cache.assets[metadata.index].bounding_box() {
+                Some(bounding_box) => bounding_box.center(),
+                None => continue,
+            };
+            let scaling = glm::translation(&center)
+                * glm::scaling(&glm::vec3(scale, scale, scale))
+                * glm::translation(&-center);
+            for instance in 0..metadata.instances.len() {
+                let slot = metadata.instance_offset + instance;
+                if !self.selected_instances.contains(&slot) {
+                    continue;
+                }
+                let instance_vertex = InstanceVertex::direct(self.instance_models[slot] * scaling);
+                self.outline_instance_buffer
+                    .upload_to_buffer(&[instance_vertex], slot * mem::size_of::<InstanceVertex>())
+                    .unwrap();
+            }
+        }
+    }
+
+    // Drawn after the scene in a render pass that continues the offscreen targets
+    pub fn issue_debug_shapes_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+    ) {
+        let regions = self.viewport_regions(extent);
+        self.debug_shapes_renderer
+            .issue_commands(command_buffer, &regions, frame);
+    }
+
+    // Drawn after the scene in a render pass that continues the offscreen targets
+    pub fn issue_particle_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+    ) {
+        let regions = self.viewport_regions(extent);
+        self.particle_renderer
+            .issue_commands(command_buffer, &regions, frame);
+    }
+
+    // Splits the extent into equal columns, one per viewport from left to right.
+    // The last column takes the pixels left over by the division.
+    pub fn viewport_regions(&self, extent: vk::Extent2D) -> Vec<vk::Rect2D> {
+        let column_width = extent.width / self.viewport_count as u32;
+        (0..self.viewport_count as u32)
+            .map(|index| {
+                let x = index * column_width;
+                let width = if index + 1 == self.viewport_count as u32 {
+                    extent.width - x
+                } else {
+                    column_width
+                };
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: x as _, y: 0 },
+                    extent: vk::Extent2D {
+                        width,
+                        height: extent.height,
+                    },
                 }
+            })
+            .collect()
+    }
+
+    // All pbr pipelines share a compatible layout, so only the pipeline needs to be rebound
+    fn record_draws(
+        device: &ash::Device,
+        pbr_renderer: &PbrRenderer,
+        draws: &[(vk::Pipeline, PbrDraw)],
+    ) {
+        let mut bound_pipeline = None;
+        for (pipeline, draw) in draws.iter() {
+            if bound_pipeline != Some(*pipeline) {
+                unsafe {
+                    device.cmd_bind_pipeline(
+                        pbr_renderer.command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        *pipeline,
+                    );
+                }
+                bound_pipeline = Some(*pipeline);
+            }
+            pbr_renderer.draw(device, draw);
+        }
+    }
 
+    pub fn issue_shadow_commands(&self, command_buffer: vk::CommandBuffer, frame: usize) {
+        let device = self.context.logical_device().logical_device();
+        self.shadow_map.record(command_buffer, |pipeline| {
+            let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+                Some(geometry_buffer) => geometry_buffer,
+                None => return,
+            };
+
+            let shadow_renderer = PbrRenderer::new(
+                command_buffer,
+                &pipeline.pipeline,
+                &self.pbr_pipeline_data,
+                frame,
+            );
+
+            geometry_buffer.bind(device, command_buffer);
+            unsafe {
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    1,
+                    &[self.instance_buffer.buffer()],
+                    &[0],
+                );
+            }
+
+            // Blended geometry does not cast shadows
+            for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask].iter() {
                 for metadata in self.asset_cache.metadata.values() {
                     let asset = &self.asset_cache.assets[metadata.index];
-                    for instance in 0..metadata.instances.len() {
-                        if *alpha_mode == AlphaMode::Blend {
-                            pbr_renderer_blended.draw_asset(
-                                self.context.logical_device().logical_device(),
-                                asset,
-                                &metadata,
-                                instance,
-                                *alpha_mode,
-                            );
-                        } else {
-                            pbr_renderer.draw_asset(
-                                self.context.logical_device().logical_device(),
-                                &asset,
-                                &metadata,
-                                instance,
-                                *alpha_mode,
-                            );
-                        }
+                    shadow_renderer.draw_asset(device, asset, metadata, *alpha_mode);
+                }
+            }
+        });
+    }
+
+    // The picking pass matches the swapchain so cursor coordinates map directly to its pixels
+    pub fn resize_picking_pass(&mut self, extent: vk::Extent2D) {
+        self.picking_pass.resize(extent);
+    }
+
+    // The transmission map copies the offscreen color target, so it has to match its size.
+    // The gpu must be idle, since the descriptor sets are rewritten.
+    pub fn resize_offscreen(&mut self, command_pool: &CommandPool, extent: vk::Extent2D) {
+        if self.transmission_map.extent == extent {
+            return;
+        }
+
+        self.transmission_map = TransmissionMap::new(self.context.clone(), command_pool, extent);
+        self.pbr_pipeline_data
+            .update_descriptor_set(
+                self.context.clone(),
+                &self.asset_cache.textures(),
+                &self.environment_maps,
+                &self.shadow_map,
+                &self.transmission_map,
+            )
+            .expect("Failed to update the pbr descriptor set!");
+    }
+
+    // Renders the id of every instance and reads back the one at (x, y).
+    // This waits for the gpu, so it should only be called in response to user input.
+    pub fn pick(&self, command_pool: &CommandPool, x: u32, y: u32) -> Result<Option<Entity>> {
+        let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+            Some(geometry_buffer) => geometry_buffer,
+            None => return Ok(None),
+        };
+
+        let extent = self.picking_pass.extent();
+        if x >= extent.width || y >= extent.height {
+            return Ok(None);
+        }
+
+        // Only the viewport under the cursor needs to be drawn
+        let (viewport, region) = self
+            .viewport_regions(extent)
+            .into_iter()
+            .enumerate()
+            .find(|(_, region)| x < region.offset.x as u32 + region.extent.width)
+            .expect("Failed to find the viewport under the cursor!");
+        let uniform_offset = (viewport as u64 * self.pbr_pipeline_data.uniform_alignment) as u32;
+
+        let draws = self.collect_picking_draws();
+        let device = self.context.logical_device().logical_device();
+        let mut result = Ok(());
+        command_pool.execute_command_once(self.context.graphics_queue(), |command_buffer| {
+            result = self.picking_pass.record(command_buffer, x, y, |pipeline| {
+                let pipeline_layout = pipeline.pipeline.layout();
+                self.context
+                    .logical_device()
+                    .set_viewport(command_buffer, region);
+                geometry_buffer.bind(device, command_buffer);
+                unsafe {
+                    device.cmd_bind_vertex_buffers(
+                        command_buffer,
+                        1,
+                        &[self.instance_buffer.buffer()],
+                        &[0],
+                    );
+                }
+
+                for draw in draws.iter() {
+                    // Zero is left for the background
+                    let push_constants = PickingPushConstantBlock {
+                        id: draw.first_instance + 1,
+                    };
+                    unsafe {
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            0,
+                            &[self.pbr_pipeline_data.frames[self.frame].descriptor_set],
+                            &[uniform_offset, draw.dynamic_offset],
+                        );
+                        device.cmd_push_constants(
+                            command_buffer,
+                            pipeline_layout,
+                            vk::ShaderStageFlags::FRAGMENT,
+                            0,
+                            byte_slice_from(&push_constants),
+                        );
                     }
+                    draw.record(device, command_buffer);
                 }
             });
+        })?;
+        result?;
+
+        let id = self.picking_pass.read_id()?;
+        let entity = match id {
+            0 => None,
+            id => self
+                .instance_entities
+                .get(id as usize - 1)
+                .copied()
+                .flatten(),
+        };
+        Ok(entity)
+    }
+
+    // Every instance is drawn on its own so it can write its own id
+    fn collect_picking_draws(&self) -> Vec<PbrDraw> {
+        let pbr_renderer = PbrRenderer::new(
+            vk::CommandBuffer::null(),
+            &self.pbr_pipeline.as_ref().unwrap().culled.pipeline,
+            &self.pbr_pipeline_data,
+            0,
+        );
+
+        let mut draws = Vec::new();
+        for metadata in self.asset_cache.metadata.values() {
+            let asset = &self.asset_cache.assets[metadata.index];
+            for instance in 0..metadata.instances.len() {
+                for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend].iter() {
+                    pbr_renderer.collect_instance_draws(
+                        asset,
+                        metadata,
+                        instance,
+                        1,
+                        *alpha_mode,
+                        &mut draws,
+                    );
+                }
+            }
+        }
+        draws.retain(|draw| draw.is_triangle_list());
+        draws
+    }
+
+    fn render_skybox(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        frame: usize,
+    ) {
+        self.context.begin_debug_label(command_buffer, "Skybox");
+        let skybox_pipeline = self.skybox_pipeline.as_ref().unwrap();
+        skybox_pipeline.bind(
+            self.context.logical_device().logical_device(),
+            command_buffer,
+        );
+
+        let skybox_renderer = SkyboxRenderer::new(
+            command_buffer,
+            &skybox_pipeline,
+            &self.skybox_pipeline_data,
+            frame,
+        );
+
+        for (viewport, region) in regions.iter().enumerate() {
+            self.context
+                .logical_device()
+                .set_viewport(command_buffer, *region);
+            skybox_renderer.draw(
+                self.context.logical_device().logical_device(),
+                &self.skybox_pipeline_data.cube,
+                viewport,
+            );
+        }
+        self.context.end_debug_label(command_buffer);
     }
 
-    pub fn update(&mut self, world: &World, resources: &Resources, projection: glm::Mat4) {
-        let camera = &<Read<OrbitalCamera>>::query()
-            .iter(world)
-            .collect::<Vec<_>>()[0];
+    fn render_pbr_assets(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        render_settings: &RenderSettings,
+        transmissive: bool,
+        frame: usize,
+    ) {
+        let draw_grid = render_settings.draw_grid && !transmissive;
+        let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+            Some(geometry_buffer) => geometry_buffer,
+            None => {
+                // The grid is still drawn before any assets have loaded
+                if draw_grid {
+                    self.grid_renderer
+                        .issue_commands(command_buffer, regions, frame);
+                }
+                return;
+            }
+        };
+
+        let pbr_pipeline = self.pbr_pipeline.as_ref().unwrap();
+        let mut pbr_renderer = PbrRenderer::new(
+            command_buffer,
+            &pbr_pipeline.culled.pipeline,
+            &self.pbr_pipeline_data,
+            frame,
+        );
 
-        let camera_position = camera.position();
-        let view = camera.view_matrix();
+        let offsets = [0, 0];
+        let vertex_buffers = [
+            geometry_buffer.vertex_buffer.buffer(),
+            self.instance_buffer.buffer(),
+        ];
 
-        let system = resources
-            .get::<System>()
-            .expect("Failed to get system resource!");
+        let device = self.context.logical_device().logical_device();
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                geometry_buffer
+                    .index_buffer
+                    .as_ref()
+                    .expect("Failed to get an index buffer!")
+                    .buffer(),
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+
+        let draws = self.collect_draws(render_settings, transmissive);
+        let draw_groups = if draw_grid {
+            let (opaque_draws, blended_draws) = Self::split_blended_draws(&draws);
+            vec![opaque_draws, blended_draws]
+        } else {
+            vec![&draws[..]]
+        };
+
+        for (group, draws) in draw_groups.iter().enumerate() {
+            if group > 0 {
+                self.grid_renderer
+                    .issue_commands(command_buffer, regions, frame);
+            }
+            for (viewport, region) in regions.iter().enumerate() {
+                self.context
+                    .logical_device()
+                    .set_viewport(command_buffer, *region);
+                pbr_renderer.set_viewport(viewport);
+                if group == 0 && !transmissive {
+                    self.record_indirect_draws(device, &pbr_renderer, render_settings);
+                }
+                Self::record_draws(device, &pbr_renderer, draws);
+            }
+        }
+    }
+
+    // Secondary command buffers don't inherit any state, so the geometry is bound here
+    fn render_indirect_draws(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[vk::Rect2D],
+        render_settings: &RenderSettings,
+        frame: usize,
+    ) {
+        let geometry_buffer = match self.asset_geometry_buffer.as_ref() {
+            Some(geometry_buffer) if self.indirect_draws.is_some() => geometry_buffer,
+            _ => return,
+        };
+
+        let device = self.context.logical_device().logical_device();
+        geometry_buffer.bind(device, command_buffer);
+        let mut pbr_renderer = PbrRenderer::new(
+            command_buffer,
+            &self.pbr_pipeline.as_ref().unwrap().culled.pipeline,
+            &self.pbr_pipeline_data,
+            frame,
+        );
+        for (viewport, region) in regions.iter().enumerate() {
+            self.context
+                .logical_device()
+                .set_viewport(command_buffer, *region);
+            pbr_renderer.set_viewport(viewport);
+            self.record_indirect_draws(device, &pbr_renderer, render_settings);
+        }
+    }
+
+    // Indirect draws are recorded before the direct draws, with their own depth pre-pass.
+    // Opaque direct draws shade over them wherever they are closer.
+    // The scene's instance buffer is rebound afterwards for the direct draws.
+    fn record_indirect_draws(
+        &self,
+        device: &ash::Device,
+        pbr_renderer: &PbrRenderer,
+        render_settings: &RenderSettings,
+    ) {
+        let indirect_draws = match self.indirect_draws.as_ref() {
+            Some(indirect_draws) => indirect_draws,
+            None => return,
+        };
+
+        let pipelines = if render_settings.depth_prepass {
+            vec![&self.depth_prepass_pipeline, &self.pbr_pipeline_depth_equal]
+        } else {
+            vec![&self.pbr_pipeline]
+        };
+
+        pbr_renderer.bind_indirect(device);
+        for pipeline in pipelines.into_iter() {
+            let pipeline = pipeline.as_ref().expect("Failed to get pbr pipeline!");
+            indirect_draws.record(device, pbr_renderer.command_buffer, |double_sided| {
+                pipeline.pipeline(render_settings.double_sided || double_sided)
+            });
+        }
+
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                pbr_renderer.command_buffer,
+                1,
+                &[self.instance_buffer.buffer()],
+                &[0],
+            );
+        }
+    }
+
+    // Blended draws are collected last, so the grid can be drawn between them and the rest
+    fn split_blended_draws(draws: &[PipelineDraw]) -> (&[PipelineDraw], &[PipelineDraw]) {
+        let first_blended_draw = draws
+            .iter()
+            .position(|(_, draw)| draw.alpha_mode == AlphaMode::Blend)
+            .unwrap_or(draws.len());
+        draws.split_at(first_blended_draw)
+    }
+
+    // The aspect ratio is that of the whole render target, which is shared between the viewports.
+    // Only the buffers of the given frame in flight are written,
+    // so its previous submission must have finished before this is called.
+    pub fn update(&mut self, scene: &RenderScene, aspect_ratio: f32, frame: usize) -> Result<()> {
+        self.frame = frame;
+        let mut cameras = scene.cameras();
+        if cameras.len() > PbrPipelineData::MAX_VIEWPORTS {
+            warn!(
+                "Scene camera count {} is greater than the maximum viewport limit of {}!",
+                cameras.len(),
+                PbrPipelineData::MAX_VIEWPORTS
+            );
+            cameras.truncate(PbrPipelineData::MAX_VIEWPORTS);
+        }
+        self.viewport_count = cameras.len();
+        let viewport_aspect_ratio = aspect_ratio / cameras.len() as f32;
+        let projections = cameras
+            .iter()
+            .map(|camera| Self::projection_matrix(&camera.projection, viewport_aspect_ratio))
+            .collect::<Vec<_>>();
+
+        let camera_position = scene.camera_position;
+        self.camera_position = camera_position;
+
+        // The first directional light casts the shadows
+        if let Some(light) = scene
+            .lights
+            .iter()
+            .find(|light| light.kind == LightKind::Directional)
+        {
+            self.shadow_map.light_direction = light.direction;
+        }
 
         // TODO: Move this logic to systems and state into components
-        let skybox_ubo = SkyboxUniformBufferObject { view, projection };
-        let skybox_ubos = [skybox_ubo];
-        self.skybox_pipeline_data
-            .uniform_buffer
-            .upload_to_buffer(&skybox_ubos, 0)
+        let skybox_ubos = cameras
+            .iter()
+            .zip(projections.iter())
+            .map(|(camera, projection)| SkyboxUniformBufferObject {
+                view: camera.view,
+                projection: *projection,
+            })
+            .collect::<Vec<_>>();
+        self.skybox_pipeline_data.uniform_buffers[frame]
+            .upload_to_buffer_aligned(&skybox_ubos, 0, self.skybox_pipeline_data.uniform_alignment)
             .unwrap();
 
+        let no_debug_shapes = DebugShapes::default();
+        let debug_shapes = if scene.render_settings.draw_debug_shapes {
+            &scene.debug_shapes
+        } else {
+            &no_debug_shapes
+        };
+        self.debug_shapes_changed =
+            self.debug_shapes_renderer
+                .update(frame, &cameras, &projections, debug_shapes)?;
+
+        self.particles_changed = self.particle_renderer.update(
+            frame,
+            &cameras,
+            &projections,
+            &scene.particle_emitters,
+        )?;
+
+        if scene.render_settings.draw_grid {
+            self.grid_renderer.update(
+                frame,
+                &cameras,
+                &projections,
+                &scene.render_settings.grid,
+            )?;
+        }
+
+        // Instances without a selected animation share this clock
         for asset in self.asset_cache.assets.iter_mut() {
             for animation in asset.animations.iter_mut() {
-                animation.time += 0.75 * system.delta_time as f32;
+                animation.time += scene.delta_time;
             }
-
-            // Only animate first animation
-            asset.animate(0);
         }
 
         let mut ubo = UniformBufferObject {
@@ -944,42 +3458,120 @@ impl PbrScene {
                 camera_position.z,
                 1.0,
             ),
-            view,
-            projection,
+            view: scene.view,
+            projection: projections[0],
+            light_space_matrix: self.shadow_map.light_space_matrix(),
             joint_matrices: [glm::Mat4::identity(); UniformBufferObject::MAX_NUM_JOINTS],
+            light_info: glm::vec4(0.0, scene.render_settings.ibl_intensity.max(0.0), 0.0, 0.0),
+            lights: [LightUniform::default(); UniformBufferObject::MAX_NUM_LIGHTS],
         };
 
+        if scene.lights.len() > UniformBufferObject::MAX_NUM_LIGHTS {
+            warn!(
+                "Scene light count {} is greater than the maximum light limit of {}!",
+                scene.lights.len(),
+                UniformBufferObject::MAX_NUM_LIGHTS
+            );
+        }
+        let lights = scene
+            .lights
+            .iter()
+            .take(UniformBufferObject::MAX_NUM_LIGHTS);
+        for (index, light) in lights.enumerate() {
+            ubo.lights[index] = LightUniform::from(light);
+            ubo.light_info.x = (index + 1) as f32;
+        }
+
+        let mut instance_models =
+            vec![glm::Mat4::identity(); self.asset_cache.number_of_instances().max(1)];
+        let mut instance_entities = vec![None; instance_models.len()];
+
         let mut instances = HashMap::new();
-        for (name, transform) in <(Read<AssetName>, Read<Transform>)>::query().iter(world) {
+        for (asset_index, (name, world_matrix)) in scene.assets.iter().enumerate() {
             *instances.entry(name.0.to_string()).or_insert(0) += 1;
             let instance_count = instances[&name.0];
 
-            let metadata = &self.asset_cache.metadata[&name.0];
+            // Assets that are still loading aren't drawn yet
+            let metadata = match self.asset_cache.metadata.get(&name.0) {
+                Some(metadata) => metadata,
+                None => continue,
+            };
             let instance_metadata = &metadata.instances[instance_count - 1];
             let mesh_offset = instance_metadata.mesh_offset;
-            let joint_offset = instance_metadata.joint_offset;
-
+            let mut skin_joint_offset = instance_metadata.joint_offset;
+            let morph_target_offset = metadata.morph_target_offset;
+
+            // Instances of an asset share its node graphs,
+            // so each one is posed right before its transforms are gathered
+            let asset = &mut self.asset_cache.assets[metadata.index];
+            match scene.animations.get(asset_index).and_then(Option::as_ref) {
+                Some(state) => {
+                    let index = match state.name.as_ref() {
+                        Some(name) => asset.animation_index(name),
+                        None => Some(0),
+                    };
+                    let blend = state.blend.as_ref().map(|blend| {
+                        (
+                            asset.animation_index(&blend.name),
+                            &blend.name,
+                            blend.weight,
+                        )
+                    });
+                    match (index, blend) {
+                        (Some(index), Some((Some(blend_index), _, weight))) => asset
+                            .blend_animations_at(
+                                (index, state.time),
+                                (blend_index, state.time),
+                                weight,
+                                state.looping,
+                            ),
+                        (Some(_), Some((None, blend_name, _))) => warn!(
+                            "Failed to find animation '{}' in asset '{}'!",
+                            blend_name, name.0
+                        ),
+                        (Some(index), None) => asset.animate_at(index, state.time, state.looping),
+                        (None, _) => warn!(
+                            "Failed to find animation {:?} in asset '{}'!",
+                            state.name, name.0
+                        ),
+                    }
+                }
+                None => asset.animate(0),
+            }
             let asset = &self.asset_cache.assets[metadata.index];
 
+            let instance_slot = metadata.instance_offset + instance_count - 1;
+            instance_models[instance_slot] = *world_matrix;
+            instance_entities[instance_slot] = scene.entities.get(asset_index).copied();
+
+            ensure!(
+                mesh_offset + asset.number_of_meshes <= self.pbr_pipeline_data.mesh_capacity,
+                "Mesh offset {} with {} meshes exceeds the dynamic uniform buffer capacity of {}!",
+                mesh_offset,
+                asset.number_of_meshes,
+                self.pbr_pipeline_data.mesh_capacity
+            );
+
             asset.walk_mut(|node_index, graph| {
-                let global_transform =
-                    GltfAsset::calculate_global_transform(node_index, graph);
+                let global_transform = GltfAsset::calculate_global_transform(node_index, graph);
                 if let Some(mesh) = graph[node_index].mesh.as_ref() {
-                        let mut dynamic_ubo = DynamicUniformBufferObject {
-                            model: (*transform).matrix() * global_transform,
-                            joint_info: glm::vec4(0.0, 0.0, 0.0, 0.0),
-                        };
-
-                        if let Some(skin) = graph[node_index].skin.as_ref() {
-                            let joint_count = skin.joints.len();
-                            dynamic_ubo.joint_info = glm::vec4(joint_count as f32, joint_offset as f32, 0.0, 0.0);
+                    let mut dynamic_ubo = DynamicUniformBufferObject {
+                        model: global_transform,
+                        joint_info: glm::vec4(0.0, 0.0, 0.0, 0.0),
+                        morph_weights: [glm::Vec4::zeros(); GltfAsset::MAX_NUM_MORPH_TARGETS / 4],
+                    };
+
+                    if let Some(skin) = graph[node_index].skin.as_ref() {
+                        // Skins that don't fit in the joint matrix array are drawn unskinned.
+                        // The overflow is reported once when the metadata is generated.
+                        let joint_count = skin.joints.len();
+                        if skin_joint_offset + joint_count <= UniformBufferObject::MAX_NUM_JOINTS {
+                            dynamic_ubo.joint_info =
+                                glm::vec4(joint_count as f32, skin_joint_offset as f32, 0.0, 0.0);
                             for (index, joint) in skin.joints.iter().enumerate() {
-                                if index > UniformBufferObject::MAX_NUM_JOINTS {
-                                    eprintln!("Skin joint count {} is greater than the maximum joint limit of {}!", dynamic_ubo.joint_info, UniformBufferObject::MAX_NUM_JOINTS);
-                                }
-
-                                let joint_node_index = GltfAsset::matching_node_index(joint.target_gltf_index, &graph)
-                                    .expect("Failed to find joint target node index!");
+                                let joint_node_index =
+                                    GltfAsset::matching_node_index(joint.target_gltf_index, &graph)
+                                        .expect("Failed to find joint target node index!");
 
                                 let joint_global_transform =
                                     GltfAsset::calculate_global_transform(joint_node_index, &graph);
@@ -988,36 +3580,184 @@ impl PbrScene {
                                     * joint_global_transform
                                     * joint.inverse_bind_matrix;
 
-                                ubo.joint_matrices[joint_offset + index] = joint_matrix;
+                                ubo.joint_matrices[skin_joint_offset + index] = joint_matrix;
                             }
                         }
 
-                        let dynamic_ubos = [dynamic_ubo];
-                        let buffer = &self.pbr_pipeline_data.dynamic_uniform_buffer;
-                        let offset = (self.pbr_pipeline_data.dynamic_alignment
-                                      * (mesh_offset + mesh.mesh_id) as u64)
-                            as usize;
+                        // Every skin of an instance gets its own range of joint matrices
+                        skin_joint_offset += joint_count;
+                    }
 
-                        buffer.upload_to_buffer_aligned(
+                    if mesh.morph_target_count > 0 {
+                        dynamic_ubo.joint_info.z = mesh.morph_target_count as f32;
+                        dynamic_ubo.joint_info.w = morph_target_offset as f32;
+                        for (index, weight) in graph[node_index].morph_weights.iter().enumerate() {
+                            dynamic_ubo.morph_weights[index / 4][index % 4] = *weight;
+                        }
+                    }
+
+                    let dynamic_ubos = [dynamic_ubo];
+                    let buffer = &self.pbr_pipeline_data.frames[frame].dynamic_uniform_buffer;
+                    let offset = (self.pbr_pipeline_data.dynamic_alignment
+                        * (mesh_offset + mesh.mesh_id) as u64)
+                        as usize;
+
+                    buffer
+                        .upload_to_buffer_aligned(
                             &dynamic_ubos,
                             offset,
                             self.pbr_pipeline_data.dynamic_alignment,
-                        ).unwrap();
-
-                        let dynamic_ubo_size = (asset.number_of_meshes as u64
-                                                * self.pbr_pipeline_data.dynamic_alignment)
-                            as u64;
-                        buffer
-                            .flush(offset, dynamic_ubo_size as _)
-                            .expect("Failed to flush buffer!");
+                        )
+                        .unwrap();
                 }
             });
         }
 
-        let ubos = [ubo];
-        self.pbr_pipeline_data
+        let instances = instance_models
+            .iter()
+            .map(|model| InstanceVertex::direct(*model))
+            .collect::<Vec<_>>();
+        self.instance_buffer
+            .upload_to_buffer(&instances, 0)
+            .unwrap();
+        if let Some(indirect_draws) = self.indirect_draws.as_ref() {
+            indirect_draws.update(&instance_models);
+        }
+        let selected_instances = instance_entities
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, entity)| match entity {
+                Some(entity) if scene.selected_entities.contains(entity) => Some(slot),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.selection_changed = selected_instances != self.selected_instances;
+        self.selected_instances = selected_instances;
+        self.instance_models = instance_models;
+        self.instance_entities = instance_entities;
+        self.update_blended_draw_order();
+        if scene.render_settings.outline_selection {
+            self.update_outline_instances(scene.render_settings.outline_scale);
+        }
+
+        // Each viewport gets a copy of the uniform buffer with its own camera
+        let ubos = cameras
+            .iter()
+            .zip(projections.iter())
+            .map(|(camera, projection)| UniformBufferObject {
+                view: camera.view,
+                projection: *projection,
+                camera_position: glm::vec4(
+                    camera.position.x,
+                    camera.position.y,
+                    camera.position.z,
+                    1.0,
+                ),
+                ..ubo
+            })
+            .collect::<Vec<_>>();
+        self.pbr_pipeline_data.frames[frame]
             .uniform_buffer
-            .upload_to_buffer(&ubos, 0)
+            .upload_to_buffer_aligned(&ubos, 0, self.pbr_pipeline_data.uniform_alignment)
             .unwrap();
+
+        Ok(())
+    }
+
+    fn projection_matrix(projection: &CameraProjection, aspect_ratio: f32) -> glm::Mat4 {
+        let projection = match projection.validate() {
+            Ok(_) => *projection,
+            Err(error) => {
+                warn!(
+                    "Invalid camera projection, using the default instead: {}",
+                    error
+                );
+                CameraProjection::default()
+            }
+        };
+        projection.matrix(aspect_ratio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A common minUniformBufferOffsetAlignment and maxUniformBufferRange
+    const ALIGNMENT: u64 = 256;
+    const MAX_UNIFORM_BUFFER_RANGE: vk::DeviceSize = 65536;
+
+    #[test]
+    fn mesh_capacity_grows_past_the_initial_capacity() {
+        let initial = PbrPipelineData::MIN_MESH_CAPACITY;
+        assert_eq!(
+            PbrPipelineData::grown_mesh_capacity(initial, initial),
+            initial
+        );
+        assert_eq!(
+            PbrPipelineData::grown_mesh_capacity(initial, initial + 1),
+            initial * 2
+        );
+        assert_eq!(
+            PbrPipelineData::grown_mesh_capacity(initial, initial * 5),
+            initial * 5
+        );
+    }
+
+    #[test]
+    fn dynamic_range_stays_inside_the_grown_buffer() {
+        let mut capacity = PbrPipelineData::MIN_MESH_CAPACITY;
+        for number_of_meshes in [150, 300, 1000].iter() {
+            capacity = PbrPipelineData::grown_mesh_capacity(capacity, *number_of_meshes);
+            assert!(capacity >= *number_of_meshes);
+
+            let buffer_size = PbrPipelineData::dynamic_uniform_buffer_size(capacity, ALIGNMENT);
+            let range = PbrPipelineData::dynamic_uniform_range();
+            let last_offset = (*number_of_meshes as u64 - 1) * ALIGNMENT;
+            assert!(range <= ALIGNMENT);
+            assert!(last_offset + range <= buffer_size);
+            assert!(range <= MAX_UNIFORM_BUFFER_RANGE);
+        }
+    }
+
+    fn sampler_limits(per_stage_samplers: u32, per_set_samplers: u32) -> vk::PhysicalDeviceLimits {
+        vk::PhysicalDeviceLimits {
+            max_per_stage_descriptor_samplers: per_stage_samplers,
+            max_per_stage_descriptor_sampled_images: per_stage_samplers,
+            max_descriptor_set_samplers: per_set_samplers,
+            max_descriptor_set_sampled_images: per_set_samplers,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn texture_capacity_is_fixed_without_descriptor_indexing() {
+        let limits = sampler_limits(1_000_000, 1_000_000);
+        assert_eq!(
+            PbrPipelineData::texture_capacity(false, &limits),
+            PbrPipelineData::MAX_TEXTURES
+        );
+    }
+
+    #[test]
+    fn texture_capacity_follows_the_sampler_limits() {
+        // The other samplers in the set are left room
+        let limits = sampler_limits(1000, 1_000_000);
+        assert_eq!(PbrPipelineData::texture_capacity(true, &limits), 995);
+        let limits = sampler_limits(1_000_000, 500);
+        assert_eq!(PbrPipelineData::texture_capacity(true, &limits), 495);
+
+        let limits = sampler_limits(1_000_000, 1_000_000);
+        assert_eq!(
+            PbrPipelineData::texture_capacity(true, &limits),
+            PbrPipelineData::MAX_BINDLESS_TEXTURES
+        );
+
+        // Never smaller than the array without descriptor indexing
+        let limits = sampler_limits(16, 96);
+        assert_eq!(
+            PbrPipelineData::texture_capacity(true, &limits),
+            PbrPipelineData::MAX_TEXTURES
+        );
     }
 }
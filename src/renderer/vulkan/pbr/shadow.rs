@@ -0,0 +1,307 @@
+use crate::renderer::vulkan::{
+    asset::GltfAsset,
+    core::VulkanContext,
+    render::{
+        DescriptorSetLayout, Framebuffer, RenderPass, RenderPipeline, RenderPipelineSettingsBuilder,
+    },
+    resource::{
+        image::{ImageView, Sampler, Texture, TextureBundle},
+        ShaderCache, ShaderPathSetBuilder,
+    },
+};
+use anyhow::Result;
+use ash::vk;
+use nalgebra_glm as glm;
+use std::sync::Arc;
+
+pub struct ShadowMap {
+    pub render_pass: Arc<RenderPass>,
+    pub texture: TextureBundle,
+    pub framebuffer: Framebuffer,
+    pub pipeline: Option<RenderPipeline>,
+    // Follows the first directional light in the scene
+    pub light_direction: glm::Vec3,
+    resolution: u32,
+    context: Arc<VulkanContext>,
+}
+
+impl ShadowMap {
+    pub const DEFAULT_RESOLUTION: u32 = 2048;
+    pub const FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+    // Half the width of the orthographic volume rendered from the light
+    pub const COVERAGE: f32 = 10.0;
+
+    // Distance the light is placed from the origin along its direction
+    pub const DISTANCE: f32 = 20.0;
+
+    pub fn new(context: Arc<VulkanContext>, resolution: u32) -> Self {
+        let texture = Self::create_texture(context.clone(), resolution);
+        let view = Self::create_image_view(context.clone(), &texture);
+        let sampler = Arc::new(Self::create_sampler(context.clone()));
+        let render_pass = Arc::new(Self::create_render_pass(context.clone()));
+
+        let texture = TextureBundle {
+            texture,
+            view,
+            sampler,
+        };
+
+        let attachments = [texture.view.view()];
+        let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass.render_pass())
+            .attachments(&attachments)
+            .width(resolution)
+            .height(resolution)
+            .layers(1)
+            .build();
+        let framebuffer = Framebuffer::new(context.clone(), create_info).unwrap();
+
+        Self {
+            render_pass,
+            texture,
+            framebuffer,
+            pipeline: None,
+            light_direction: glm::vec3(0.0, -10.0, 0.0),
+            resolution,
+            context,
+        }
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.resolution,
+            height: self.resolution,
+        }
+    }
+
+    pub fn light_space_matrix(&self) -> glm::Mat4 {
+        let direction = glm::normalize(&self.light_direction);
+        let eye = -direction * Self::DISTANCE;
+
+        // The up vector can't be parallel to the light direction
+        let up = if direction.y.abs() > 0.99 {
+            glm::vec3(0.0, 0.0, 1.0)
+        } else {
+            glm::vec3(0.0, 1.0, 0.0)
+        };
+
+        let view = glm::look_at(&eye, &glm::Vec3::zeros(), &up);
+        let projection = glm::ortho_zo(
+            -Self::COVERAGE,
+            Self::COVERAGE,
+            -Self::COVERAGE,
+            Self::COVERAGE,
+            0.1,
+            Self::DISTANCE * 2.0,
+        );
+
+        projection * view
+    }
+
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        descriptor_set_layout: Arc<DescriptorSetLayout>,
+        push_constant_range: vk::PushConstantRange,
+    ) -> Result<()> {
+        let descriptions = GltfAsset::create_vertex_input_descriptions();
+        let attributes = GltfAsset::create_vertex_attributes();
+        let vertex_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&descriptions)
+            .vertex_attribute_descriptions(&attributes)
+            .build();
+
+        let shader_paths = ShaderPathSetBuilder::default()
+            .vertex("assets/shaders/pbr/shadow.vert.spv")
+            .build()
+            .unwrap();
+        let shader_set = shader_cache.create_shader_set(self.context.clone(), &shader_paths)?;
+
+        let settings = RenderPipelineSettingsBuilder::default()
+            .render_pass(self.render_pass.clone())
+            .vertex_state_info(vertex_state_info)
+            .descriptor_set_layout(descriptor_set_layout)
+            .shader_set(shader_set)
+            .depth_only(true)
+            .push_constant_range(push_constant_range)
+            .build()
+            .expect("Failed to create render pipeline settings");
+
+        self.pipeline = None;
+        let pipeline = RenderPipeline::new(self.context.clone(), settings)?;
+        self.context
+            .set_debug_name(pipeline.pipeline.pipeline(), "Shadow Map");
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    pub fn record<T>(&self, command_buffer: vk::CommandBuffer, mut action: T)
+    where
+        T: FnMut(&RenderPipeline),
+    {
+        let pipeline = match self.pipeline.as_ref() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass.render_pass())
+            .framebuffer(self.framebuffer.framebuffer())
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent(),
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        RenderPass::record(
+            self.context.clone(),
+            command_buffer,
+            &render_pass_begin_info,
+            || {
+                self.context
+                    .logical_device()
+                    .update_viewport(command_buffer, self.extent());
+                pipeline.bind(
+                    self.context.logical_device().logical_device(),
+                    command_buffer,
+                );
+                action(pipeline);
+            },
+        );
+    }
+
+    fn create_texture(context: Arc<VulkanContext>, resolution: u32) -> Texture {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: resolution,
+                height: resolution,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .format(Self::FORMAT)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .flags(vk::ImageCreateFlags::empty())
+            .build();
+
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+
+        Texture::new(context, &allocation_create_info, &image_create_info).unwrap()
+    }
+
+    fn create_image_view(context: Arc<VulkanContext>, texture: &Texture) -> ImageView {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(texture.image())
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(Self::FORMAT)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY,
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        ImageView::new(context, create_info).unwrap()
+    }
+
+    fn create_sampler(context: Arc<VulkanContext>) -> Sampler {
+        // Samples outside of the shadow map are treated as lit
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .anisotropy_enable(false)
+            .max_anisotropy(1.0)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(1.0)
+            .build();
+        Sampler::new(context, sampler_info).unwrap()
+    }
+
+    fn create_render_pass(context: Arc<VulkanContext>) -> RenderPass {
+        let depth_attachment_description = vk::AttachmentDescription::builder()
+            .format(Self::FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .build();
+
+        let attachment_descriptions = [depth_attachment_description];
+
+        let depth_attachment_reference = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let subpass_description = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_reference)
+            .build();
+        let subpass_descriptions = [subpass_description];
+
+        let subpass_dependencies = [
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dependency_flags(vk::DependencyFlags::BY_REGION)
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .dependency_flags(vk::DependencyFlags::BY_REGION)
+                .build(),
+        ];
+
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descriptions)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&subpass_dependencies)
+            .build();
+
+        RenderPass::new(context, &create_info).unwrap()
+    }
+}
@@ -43,6 +43,33 @@ impl DescriptorPool {
         };
         Ok(descriptor_sets)
     }
+
+    // For layouts whose last binding has a variable descriptor count
+    pub fn allocate_variable_descriptor_sets(
+        &self,
+        layout: vk::DescriptorSetLayout,
+        number_of_sets: u32,
+        descriptor_count: u32,
+    ) -> Result<Vec<vk::DescriptorSet>> {
+        let layouts = (0..number_of_sets).map(|_| layout).collect::<Vec<_>>();
+        let descriptor_counts = vec![descriptor_count; layouts.len()];
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                .descriptor_counts(&descriptor_counts)
+                .build();
+        let allocation_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&layouts)
+            .push_next(&mut variable_count_info)
+            .build();
+        let descriptor_sets = unsafe {
+            self.context
+                .logical_device()
+                .logical_device()
+                .allocate_descriptor_sets(&allocation_info)?
+        };
+        Ok(descriptor_sets)
+    }
 }
 
 impl Drop for DescriptorPool {
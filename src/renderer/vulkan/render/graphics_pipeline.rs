@@ -1,4 +1,5 @@
 use crate::renderer::vulkan::{core::VulkanContext, render::PipelineLayout};
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use std::sync::Arc;
 
@@ -13,7 +14,7 @@ impl GraphicsPipeline {
         context: Arc<VulkanContext>,
         create_info: vk::GraphicsPipelineCreateInfo,
         pipeline_layout: PipelineLayout,
-    ) -> Self {
+    ) -> Result<Self> {
         let pipeline_create_info_arr = [create_info];
         let pipeline = unsafe {
             context
@@ -24,14 +25,14 @@ impl GraphicsPipeline {
                     &pipeline_create_info_arr,
                     None,
                 )
-                .expect("Failed to create graphics pipelines!")[0]
+                .map_err(|(_, result)| result)?[0]
         };
 
-        Self {
+        Ok(Self {
             pipeline,
             pipeline_layout,
             context,
-        }
+        })
     }
 
     pub fn pipeline(&self) -> vk::Pipeline {
@@ -1,6 +1,7 @@
 pub use self::{
     compute_pipeline::*, descriptor_pool::*, descriptor_set_layout::*, framebuffer::*,
-    graphics_pipeline::*, pipeline_layout::*, render_pipeline::*, renderpass::*, swapchain::*,
+    graphics_pipeline::*, pipeline_layout::*, render_graph::*, render_pipeline::*, renderpass::*,
+    swapchain::*,
 };
 
 pub mod compute_pipeline;
@@ -9,6 +10,7 @@ pub mod descriptor_set_layout;
 pub mod framebuffer;
 pub mod graphics_pipeline;
 pub mod pipeline_layout;
+pub mod render_graph;
 pub mod render_pipeline;
 pub mod renderpass;
 pub mod swapchain;
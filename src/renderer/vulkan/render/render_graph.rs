@@ -0,0 +1,127 @@
+use crate::renderer::vulkan::core::VulkanContext;
+use ash::{version::DeviceV1_0, vk};
+use std::sync::Arc;
+
+// The render pass instance a pass records its commands in
+#[derive(Clone)]
+pub struct PassTarget {
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+    // One per attachment, can be empty if the render pass doesn't clear
+    pub clear_values: Vec<vk::ClearValue>,
+    pub contents: vk::SubpassContents,
+}
+
+impl PassTarget {
+    pub fn new(
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+    ) -> Self {
+        Self {
+            render_pass,
+            framebuffer,
+            extent,
+            clear_values: Vec::new(),
+            contents: vk::SubpassContents::INLINE,
+        }
+    }
+
+    pub fn with_clear_values(mut self, clear_values: &[vk::ClearValue]) -> Self {
+        self.clear_values = clear_values.to_vec();
+        self
+    }
+
+    pub fn with_contents(mut self, contents: vk::SubpassContents) -> Self {
+        self.contents = contents;
+        self
+    }
+
+    // Passes that draw into the same target share a single render pass instance
+    fn is_shared_with(&self, other: &Self) -> bool {
+        self.render_pass == other.render_pass
+            && self.framebuffer == other.framebuffer
+            && self.contents == other.contents
+    }
+
+    fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&self.clear_values)
+            .build();
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, self.contents)
+        };
+    }
+}
+
+pub trait Pass {
+    // Labels the commands of the pass in graphics debuggers
+    fn name(&self) -> &'static str;
+
+    // Passes without a target record outside of a render pass,
+    // such as transfers or passes that manage their own render pass
+    fn target(&self) -> Option<PassTarget>;
+
+    fn record(&mut self, command_buffer: vk::CommandBuffer);
+}
+
+// Records a list of passes in order, beginning and ending the render pass of each target.
+// Consecutive passes with the same target are recorded in the same render pass.
+// Each pass is wrapped in a debug label, which starts before its render pass begins.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    passes: Vec<Box<dyn Pass + 'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn add_pass<T: Pass + 'a>(&mut self, pass: T) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn record(&mut self, context: Arc<VulkanContext>, command_buffer: vk::CommandBuffer) {
+        let device = context.logical_device().logical_device();
+        let mut current_target: Option<PassTarget> = None;
+        let mut labeled = false;
+        for pass in self.passes.iter_mut() {
+            let target = pass.target();
+            let shared = match (current_target.as_ref(), target.as_ref()) {
+                (Some(current), Some(target)) => current.is_shared_with(target),
+                _ => false,
+            };
+
+            if !shared && current_target.take().is_some() {
+                unsafe { device.cmd_end_render_pass(command_buffer) };
+            }
+
+            if labeled {
+                context.end_debug_label(command_buffer);
+            }
+            context.begin_debug_label(command_buffer, pass.name());
+            labeled = true;
+
+            if !shared {
+                if let Some(target) = target.as_ref() {
+                    target.begin(device, command_buffer);
+                }
+                current_target = target;
+            }
+
+            pass.record(command_buffer);
+        }
+
+        if current_target.is_some() {
+            unsafe { device.cmd_end_render_pass(command_buffer) };
+        }
+        if labeled {
+            context.end_debug_label(command_buffer);
+        }
+    }
+}
@@ -3,9 +3,10 @@ use crate::renderer::vulkan::{
     render::{DescriptorSetLayout, GraphicsPipeline, PipelineLayout, RenderPass},
     resource::ShaderSet,
 };
+use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use derive_builder::Builder;
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 #[derive(Builder, Clone)]
 #[builder(setter(into))]
@@ -18,12 +19,28 @@ pub struct RenderPipelineSettings {
     #[builder(default)]
     pub blended: bool,
 
+    // Adds the fragment color, scaled by its alpha, to the color attachment
+    #[builder(default)]
+    pub additive: bool,
+
+    // Depth only pipelines have no color attachments to blend
+    #[builder(default)]
+    pub depth_only: bool,
+
     #[builder(default = "true")]
     pub depth_test_enabled: bool,
 
     #[builder(default = "true")]
     pub depth_write_enabled: bool,
 
+    #[builder(default = "vk::CompareOp::LESS_OR_EQUAL")]
+    pub depth_compare_op: vk::CompareOp,
+
+    // Keeps the color attachments but masks off all writes to them,
+    // for depth only pipelines used within a color subpass
+    #[builder(default = "true")]
+    pub color_write_enabled: bool,
+
     #[builder(default)]
     pub stencil_test_enabled: bool,
 
@@ -42,11 +59,26 @@ pub struct RenderPipelineSettings {
     #[builder(default)]
     pub sample_shading_enabled: bool,
 
+    // Turns the fragment alpha into sample coverage, only meaningful when multisampling
+    #[builder(default)]
+    pub alpha_to_coverage_enabled: bool,
+
     #[builder(default = "vk::CullModeFlags::NONE")]
     pub cull_mode: vk::CullModeFlags,
 
     #[builder(default = "vk::FrontFace::COUNTER_CLOCKWISE")]
     pub front_face: vk::FrontFace,
+
+    #[builder(default = "vk::PrimitiveTopology::TRIANGLE_LIST")]
+    pub topology: vk::PrimitiveTopology,
+
+    // Clamped to what the device supports, widths other than 1.0 need the wideLines feature
+    #[builder(default = "1.0")]
+    pub line_width: f32,
+
+    // Specialization constants of the fragment shader, as pairs of constant id and value
+    #[builder(default)]
+    pub fragment_constants: Vec<(u32, u32)>,
 }
 
 pub struct RenderPipeline {
@@ -55,26 +87,46 @@ pub struct RenderPipeline {
 }
 
 impl RenderPipeline {
-    pub fn new(context: Arc<VulkanContext>, settings: RenderPipelineSettings) -> Self {
-        let shader_state_info = [
-            settings.shader_set.vertex_shader.state_info(),
-            settings
-                .shader_set
-                .fragment_shader
-                .as_ref()
-                .expect("Failed to lookup fragment shader!")
-                .state_info(),
-        ];
+    pub fn new(context: Arc<VulkanContext>, settings: RenderPipelineSettings) -> Result<Self> {
+        // The specialization data has to outlive the pipeline creation
+        let specialization_entries = settings
+            .fragment_constants
+            .iter()
+            .enumerate()
+            .map(|(index, (constant_id, _))| vk::SpecializationMapEntry {
+                constant_id: *constant_id,
+                offset: (index * mem::size_of::<u32>()) as u32,
+                size: mem::size_of::<u32>(),
+            })
+            .collect::<Vec<_>>();
+        let specialization_data = settings
+            .fragment_constants
+            .iter()
+            .flat_map(|(_, value)| value.to_ne_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_entries)
+            .data(&specialization_data)
+            .build();
+
+        let mut shader_state_info = vec![settings.shader_set.vertex_shader.state_info()];
+        if let Some(fragment_shader) = settings.shader_set.fragment_shader.as_ref() {
+            let mut fragment_state_info = fragment_shader.state_info();
+            if !settings.fragment_constants.is_empty() {
+                fragment_state_info.p_specialization_info = &specialization_info;
+            }
+            shader_state_info.push(fragment_state_info);
+        }
 
         let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(settings.topology)
             .primitive_restart_enable(false);
 
         let rasterizer_create_info = vk::PipelineRasterizationStateCreateInfo::builder()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
             .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
+            .line_width(context.clamp_line_width(settings.line_width))
             .cull_mode(settings.cull_mode)
             .front_face(settings.front_face)
             .depth_bias_enable(false)
@@ -86,13 +138,13 @@ impl RenderPipeline {
             .sample_shading_enable(settings.sample_shading_enabled)
             .rasterization_samples(settings.rasterization_samples)
             .min_sample_shading(0.2)
-            .alpha_to_coverage_enable(false)
+            .alpha_to_coverage_enable(settings.alpha_to_coverage_enabled)
             .alpha_to_one_enable(false);
 
         let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(settings.depth_test_enabled)
             .depth_write_enable(settings.depth_write_enabled)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_compare_op(settings.depth_compare_op)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
             .max_depth_bounds(1.0)
@@ -100,11 +152,20 @@ impl RenderPipeline {
             .front(settings.stencil_front_state)
             .back(settings.stencil_back_state);
 
-        let color_blend_attachments = if settings.blended {
-            Self::create_color_blend_attachments_blended()
+        let mut color_blend_attachments = if settings.depth_only {
+            Vec::new()
+        } else if settings.additive {
+            Self::create_color_blend_attachments_additive().to_vec()
+        } else if settings.blended {
+            Self::create_color_blend_attachments_blended().to_vec()
         } else {
-            Self::create_color_blend_attachments_opaque()
+            Self::create_color_blend_attachments_opaque().to_vec()
         };
+        if !settings.color_write_enabled {
+            color_blend_attachments.iter_mut().for_each(|attachment| {
+                attachment.color_write_mask = vk::ColorComponentFlags::empty()
+            });
+        }
 
         let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
@@ -112,7 +173,7 @@ impl RenderPipeline {
             .attachments(&color_blend_attachments)
             .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-        let pipeline_layout = Self::create_pipeline_layout(context.clone(), &settings);
+        let pipeline_layout = Self::create_pipeline_layout(context.clone(), &settings)?;
 
         let mut viewport_create_info = vk::PipelineViewportStateCreateInfo::default();
         viewport_create_info.viewport_count = 1;
@@ -137,9 +198,9 @@ impl RenderPipeline {
             .render_pass(settings.render_pass.render_pass())
             .subpass(0);
 
-        let pipeline = GraphicsPipeline::new(context, *pipeline_create_info, pipeline_layout);
+        let pipeline = GraphicsPipeline::new(context, *pipeline_create_info, pipeline_layout)?;
 
-        Self { pipeline, settings }
+        Ok(Self { pipeline, settings })
     }
 
     pub fn create_color_blend_attachments_opaque() -> [vk::PipelineColorBlendAttachmentState; 1] {
@@ -168,10 +229,23 @@ impl RenderPipeline {
         [*color_blend_attachment]
     }
 
+    pub fn create_color_blend_attachments_additive() -> [vk::PipelineColorBlendAttachmentState; 1] {
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::all())
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD);
+        [*color_blend_attachment]
+    }
+
     pub fn create_pipeline_layout(
         context: Arc<VulkanContext>,
         settings: &RenderPipelineSettings,
-    ) -> PipelineLayout {
+    ) -> Result<PipelineLayout> {
         let descriptor_set_layouts = [settings.descriptor_set_layout.layout()];
 
         if let Some(push_constant_range) = settings.push_constant_range.as_ref() {
@@ -179,11 +253,11 @@ impl RenderPipeline {
             let pipeline_layout_create_info_builder = vk::PipelineLayoutCreateInfo::builder()
                 .push_constant_ranges(&push_constant_ranges)
                 .set_layouts(&descriptor_set_layouts);
-            PipelineLayout::new(context, *pipeline_layout_create_info_builder).unwrap()
+            PipelineLayout::new(context, *pipeline_layout_create_info_builder)
         } else {
             let pipeline_layout_create_info_builder =
                 vk::PipelineLayoutCreateInfo::builder().set_layouts(&descriptor_set_layouts);
-            PipelineLayout::new(context, *pipeline_layout_create_info_builder).unwrap()
+            PipelineLayout::new(context, *pipeline_layout_create_info_builder)
         }
     }
 
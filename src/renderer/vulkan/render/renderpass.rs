@@ -36,18 +36,30 @@ impl RenderPass {
         context: Arc<VulkanContext>,
         command_buffer: vk::CommandBuffer,
         render_pass_begin_info: &vk::RenderPassBeginInfo,
+        action: T,
+    ) where
+        T: FnMut(),
+    {
+        Self::record_with_contents(
+            context,
+            command_buffer,
+            render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+            action,
+        );
+    }
+
+    pub fn record_with_contents<T>(
+        context: Arc<VulkanContext>,
+        command_buffer: vk::CommandBuffer,
+        render_pass_begin_info: &vk::RenderPassBeginInfo,
+        contents: vk::SubpassContents,
         mut action: T,
     ) where
         T: FnMut(),
     {
         let device = context.logical_device().logical_device();
-        unsafe {
-            device.cmd_begin_render_pass(
-                command_buffer,
-                render_pass_begin_info,
-                vk::SubpassContents::INLINE,
-            )
-        };
+        unsafe { device.cmd_begin_render_pass(command_buffer, render_pass_begin_info, contents) };
 
         action();
 
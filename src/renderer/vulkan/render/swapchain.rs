@@ -5,7 +5,7 @@ use crate::renderer::vulkan::{
 };
 use anyhow::Result;
 use ash::{extensions::khr::Swapchain as AshSwapchain, vk};
-use log::info;
+use log::{info, warn};
 use std::sync::Arc;
 
 #[derive(Clone, Copy, Debug)]
@@ -16,6 +16,16 @@ pub struct SwapchainProperties {
 }
 
 impl SwapchainProperties {
+    // Whether the presentation engine encodes the output to sRGB on write
+    pub fn is_srgb(&self) -> bool {
+        matches!(
+            self.format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
         let height = if self.extent.height == 0 {
             0
@@ -78,33 +88,34 @@ impl SwapchainSupportDetails {
         }
     }
 
+    // UNORM formats are chosen on purpose, sRGB formats are avoided.
+    // The scene shaders already gamma correct when tonemapping and the gui colors are sRGB,
+    // and post-processing copies them to the swapchain unchanged.
+    // An sRGB swapchain would encode the output a second time, washing it out.
+    // Moving to an sRGB swapchain means dropping the manual gamma from the shaders at the same time.
     fn choose_surface_format(available_formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
-        // Specify a default format and color space
-        let (default_format, default_color_space) = (
-            vk::Format::R8G8B8A8_UNORM,
-            vk::ColorSpaceKHR::SRGB_NONLINEAR,
-        );
+        let color_space = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+        let preferred_formats = [vk::Format::R8G8B8A8_UNORM, vk::Format::B8G8R8A8_UNORM];
 
-        // Choose the default format if available or choose the first available format
+        // A single undefined format means the surface has no preference
         if available_formats.len() == 1 && available_formats[0].format == vk::Format::UNDEFINED {
-            // If only one format is available
-            // but it is undefined, assign a default
-            vk::SurfaceFormatKHR {
-                format: default_format,
-                color_space: default_color_space,
-            }
-        } else {
-            *available_formats
-                .iter()
-                .find(|format| {
-                    format.format == default_format && format.color_space == default_color_space
-                })
-                .unwrap_or_else(|| {
-                    available_formats
-                        .first()
-                        .expect("Failed to get first surface format")
-                })
+            return vk::SurfaceFormatKHR {
+                format: preferred_formats[0],
+                color_space,
+            };
         }
+
+        let preferred_format = preferred_formats.iter().find_map(|preferred_format| {
+            available_formats.iter().find(|format| {
+                format.format == *preferred_format && format.color_space == color_space
+            })
+        });
+
+        *preferred_format.unwrap_or_else(|| {
+            available_formats
+                .first()
+                .expect("Failed to get first surface format")
+        })
     }
 
     fn choose_surface_present_mode(
@@ -144,7 +155,15 @@ pub struct Swapchain {
 }
 
 impl Swapchain {
-    pub fn new(context: Arc<VulkanContext>, dimensions: [u32; 2]) -> Result<Swapchain> {
+    // The requested image count is clamped to what the surface supports,
+    // and the presentation engine may still create more images than requested.
+    // Without a request, one image more than the minimum is used so that
+    // the application doesn't have to wait on the driver to acquire the next image.
+    pub fn new(
+        context: Arc<VulkanContext>,
+        dimensions: [u32; 2],
+        requested_image_count: Option<u32>,
+    ) -> Result<Swapchain> {
         let swapchain_support_details = SwapchainSupportDetails::new(&context)?;
         let capabilities = &swapchain_support_details.capabilities;
 
@@ -153,14 +172,13 @@ impl Swapchain {
         let present_mode = swapchain_properties.present_mode;
         let extent = swapchain_properties.extent;
 
-        let image_count = {
-            let max = capabilities.max_image_count;
-            let mut preferred = capabilities.min_image_count + 1;
-            if max > 0 && preferred > max {
-                preferred = max;
-            }
-            preferred
-        };
+        let image_count = Self::choose_image_count(capabilities, requested_image_count);
+        if present_mode == vk::PresentModeKHR::MAILBOX && image_count < 3 {
+            warn!(
+                "Mailbox presentation with {} swapchain images may block on the display",
+                image_count
+            );
+        }
 
         let swapchain_create_info = {
             let mut builder = vk::SwapchainCreateInfoKHR::builder()
@@ -216,12 +234,18 @@ Creating swapchain.
 "#,
             surface_format.format, surface_format.color_space, present_mode, extent, image_count
         );
+        if swapchain_properties.is_srgb() {
+            warn!("No UNORM surface format is available, colors will be gamma corrected twice");
+        }
 
         let images = unsafe {
             swapchain
                 .get_swapchain_images(swapchain_khr)
                 .expect("Failed to get swapchain khr!")
         };
+        if images.len() != image_count as usize {
+            info!("Swapchain created with {} images", images.len());
+        }
         let image_views = images
             .iter()
             .map(|image| {
@@ -259,6 +283,21 @@ Creating swapchain.
         Ok(swapchain)
     }
 
+    // A max image count of zero means the surface has no upper limit
+    fn choose_image_count(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        requested_image_count: Option<u32>,
+    ) -> u32 {
+        let min = capabilities.min_image_count;
+        let max = capabilities.max_image_count;
+        let image_count = requested_image_count.unwrap_or(min + 1).max(min);
+        if max > 0 {
+            image_count.min(max)
+        } else {
+            image_count
+        }
+    }
+
     pub fn properties(&self) -> &SwapchainProperties {
         &self.swapchain_properties
     }
@@ -328,3 +367,60 @@ impl Drop for Swapchain {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_format(format: vk::Format) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    fn chosen_format(formats: &[vk::Format]) -> vk::Format {
+        let available_formats = formats
+            .iter()
+            .map(|format| surface_format(*format))
+            .collect::<Vec<_>>();
+        SwapchainSupportDetails::choose_surface_format(&available_formats).format
+    }
+
+    #[test]
+    fn unorm_formats_are_preferred_over_srgb() {
+        assert_eq!(
+            chosen_format(&[vk::Format::B8G8R8A8_SRGB, vk::Format::B8G8R8A8_UNORM]),
+            vk::Format::B8G8R8A8_UNORM
+        );
+        assert_eq!(
+            chosen_format(&[
+                vk::Format::R8G8B8A8_SRGB,
+                vk::Format::B8G8R8A8_UNORM,
+                vk::Format::R8G8B8A8_UNORM
+            ]),
+            vk::Format::R8G8B8A8_UNORM
+        );
+    }
+
+    #[test]
+    fn an_undefined_format_falls_back_to_unorm() {
+        assert_eq!(
+            chosen_format(&[vk::Format::UNDEFINED]),
+            vk::Format::R8G8B8A8_UNORM
+        );
+    }
+
+    #[test]
+    fn srgb_is_only_chosen_when_nothing_else_is_available() {
+        let format = chosen_format(&[vk::Format::B8G8R8A8_SRGB]);
+        assert_eq!(format, vk::Format::B8G8R8A8_SRGB);
+
+        let properties = SwapchainProperties {
+            format: surface_format(format),
+            present_mode: vk::PresentModeKHR::FIFO,
+            extent: vk::Extent2D::default(),
+        };
+        assert!(properties.is_srgb());
+    }
+}
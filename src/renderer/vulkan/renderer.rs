@@ -1,234 +1,483 @@
 use crate::{
+    camera::{CameraProjection, OrbitalCamera},
     renderer::{
         vulkan::{
             core::{
                 sync::synchronization_set::{SynchronizationSet, SynchronizationSetConstants},
-                VulkanContext,
+                DeviceSettings, VulkanContext, VulkanContextBuilder,
             },
             gui::GuiRenderer,
-            handles::{ForwardRenderingHandles, Offscreen},
-            pbr::PbrScene,
-            render::{RenderPass, Swapchain},
-            resource::{CommandPool, ShaderCache},
+            handles::ForwardRenderingHandles,
+            headless::HeadlessRenderer,
+            passes::{
+                present_target, AutoExposurePass, DebugShapesPass, GuiPass, OutlinePass,
+                ParticlesPass, PostProcessPass, ScenePass, ShadowPass, TransmissionCopyPass,
+                TransmissionPass,
+            },
+            pbr::{PbrScene, ShadowMap},
+            render::{RenderGraph, Swapchain},
+            resource::{CommandPool, ShaderCache, SharedShaderCache},
         },
-        AssetName, Renderer,
+        Aabb, AnimationInfo, AssetCamera, AssetName, DeviceLost, Environment, IblSettings,
+        LoadProgress, RenderScene, RenderSettings, RenderStats, Renderer, SceneInfo,
     },
-    system::System,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Context as _, Result};
 use ash::vk;
-use imgui::{Context, DrawData};
-use legion::prelude::*;
+use imgui::{Context, DrawData, TextureId};
+use legion::prelude::Entity;
 use log::warn;
 use nalgebra_glm as glm;
-use std::sync::Arc;
+use std::{collections::HashMap, path::Path, sync::Arc};
 use winit::window::Window;
+#[cfg(feature = "shader-hot-reload")]
+use {crate::renderer::vulkan::resource::ShaderWatcher, log::info};
 
 pub struct VulkanRenderer {
     context: Arc<VulkanContext>,
     synchronization_set: SynchronizationSet,
     command_pool: CommandPool,
     transient_command_pool: CommandPool,
+    // One pool per recording thread, since command pools can't be used concurrently
+    secondary_command_pools: Vec<CommandPool>,
     swapchain: Option<Swapchain>,
+    // Requested again whenever the swapchain is recreated
+    swapchain_image_count: Option<u32>,
     handles: Option<ForwardRenderingHandles>,
     current_frame: usize,
     scene: Option<PbrScene>,
-    shader_cache: ShaderCache,
+    shader_cache: SharedShaderCache,
     gui_renderer: Option<GuiRenderer>,
+    // The offscreen color registered with the gui renderer
+    scene_texture: Option<TextureId>,
+    // The scene is drawn by the gui instead of the post-processing pass while set
+    scene_windowed: bool,
+    // Set when something baked into the command buffers changes
+    command_buffers_dirty: bool,
+    // Indexed like the command buffers. A command buffer is only recorded again
+    // when its frame in flight comes around, since the others may still be pending.
+    stale_command_buffers: Vec<bool>,
+    gui_recorded: bool,
+    render_settings: RenderSettings,
+    // Baked into the command buffers while the depth is displayed
+    projection: CameraProjection,
+    samples: vk::SampleCountFlags,
+    // Updated by resize events, zero while the window is minimized
+    window_dimensions: glm::Vec2,
+    swapchain_dirty: bool,
+    // The offscreen targets are sized to the swapchain extent scaled by this
+    render_scale: f32,
+    // Refreshed whenever the scene commands are recorded
+    stats: RenderStats,
+    // Kept from initialization so thumbnails are lit the same way as the scene
+    environment: Environment,
+    environment_cache: Option<String>,
+    // Keyed by asset name and size
+    thumbnails: HashMap<(String, u32), image::RgbaImage>,
+    #[cfg(feature = "shader-hot-reload")]
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl VulkanRenderer {
-    pub fn new(window: &mut Window) -> Result<Self> {
-        let context = Arc::new(VulkanContext::new(&window)?);
+    #[cfg(feature = "shader-hot-reload")]
+    const SHADER_DIRECTORY: &'static str = "assets/shaders";
+
+    // Thumbnails are small, so the environment maps are baked at a lower quality
+    const THUMBNAIL_IBL_SETTINGS: IblSettings = IblSettings {
+        irradiance_dim: 32,
+        prefilter_dim: 128,
+        prefilter_mips: 8,
+        sample_count: 16,
+        prefilter_lod_bias: 0.0,
+    };
+
+    pub fn new(
+        window: &mut Window,
+        anisotropy: Option<f32>,
+        device_settings: &DeviceSettings,
+        frames_in_flight: Option<u32>,
+        swapchain_image_count: Option<u32>,
+    ) -> Result<Self> {
+        let context = Arc::new(
+            VulkanContextBuilder::default()
+                .anisotropy(anisotropy)
+                .settings(device_settings)?
+                .build(&window)?,
+        );
 
-        let synchronization_set = SynchronizationSet::new(context.clone())?;
+        let frames_in_flight =
+            frames_in_flight.unwrap_or(SynchronizationSet::DEFAULT_FRAMES_IN_FLIGHT);
+        let synchronization_set = SynchronizationSet::new(context.clone(), frames_in_flight)?;
 
         let command_pool = CommandPool::new(
             context.clone(),
             vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
         )?;
 
-        let transient_command_pool =
-            CommandPool::new(context.clone(), vk::CommandPoolCreateFlags::TRANSIENT)?;
+        // Assets are uploaded through this, so it uploads on the transfer queue when there is one
+        let transient_command_pool = CommandPool::with_transfer_queue(
+            context.clone(),
+            vk::CommandPoolCreateFlags::TRANSIENT,
+        )?;
+
+        // The extra pools are for the skybox, which is recorded before the asset draws,
+        // and for the grid, which is recorded before the blended draws
+        let secondary_command_pools = (0..rayon::current_num_threads() + 2)
+            .map(|_| {
+                CommandPool::new(
+                    context.clone(),
+                    vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let logical_size = window.inner_size();
         let dimensions = [logical_size.width as u32, logical_size.height as u32];
+        let window_dimensions = glm::vec2(logical_size.width as f32, logical_size.height as f32);
 
-        let swapchain = Swapchain::new(context.clone(), dimensions)?;
+        let swapchain = Swapchain::new(context.clone(), dimensions, swapchain_image_count)?;
 
         let mut shader_cache = ShaderCache::default();
 
-        let mut handles = ForwardRenderingHandles::new(context.clone(), &swapchain).unwrap();
-        handles.recreate_pipeline(&mut shader_cache);
+        let samples = vk::SampleCountFlags::TYPE_1;
+        let render_scale = 1.0;
+        let mut handles = ForwardRenderingHandles::new(
+            context.clone(),
+            &swapchain,
+            samples,
+            render_scale,
+            frames_in_flight,
+        )?;
+        handles.recreate_pipeline(&mut shader_cache)?;
 
         let renderer = Self {
             context,
             synchronization_set,
             command_pool,
             transient_command_pool,
+            secondary_command_pools,
             swapchain: Some(swapchain),
+            swapchain_image_count,
             handles: Some(handles),
             current_frame: 0,
             scene: None,
-            shader_cache,
+            shader_cache: shader_cache.into(),
             gui_renderer: None,
+            scene_texture: None,
+            scene_windowed: false,
+            command_buffers_dirty: true,
+            stale_command_buffers: Vec::new(),
+            gui_recorded: false,
+            render_settings: RenderSettings::default(),
+            projection: CameraProjection::default(),
+            samples,
+            render_scale,
+            window_dimensions,
+            swapchain_dirty: false,
+            stats: RenderStats::default(),
+            environment: Environment::default(),
+            environment_cache: None,
+            thumbnails: HashMap::new(),
+            #[cfg(feature = "shader-hot-reload")]
+            shader_watcher: Self::create_shader_watcher(),
         };
 
         Ok(renderer)
     }
 
-    fn recreate_swapchain(
-        &mut self,
-        window_dimensions: &glm::Vec2,
-        draw_data: &DrawData,
-    ) -> Result<()> {
+    fn recreate_swapchain(&mut self) -> Result<()> {
         self.context.logical_device().wait_idle();
 
         self.swapchain = None;
 
         let swapchain = Swapchain::new(
             self.context.clone(),
-            [window_dimensions.x as _, window_dimensions.y as _],
+            [self.window_dimensions.x as _, self.window_dimensions.y as _],
+            self.swapchain_image_count,
         )?;
+        self.swapchain_dirty = false;
         self.swapchain = Some(swapchain);
 
         self.handles = None;
-        let mut handles = ForwardRenderingHandles::new(self.context.clone(), self.swapchain())
-            .expect("Failed to create strategy handles");
-        handles.recreate_pipeline(&mut self.shader_cache);
+        let mut handles = ForwardRenderingHandles::new(
+            self.context.clone(),
+            self.swapchain(),
+            self.samples,
+            self.render_scale,
+            self.frames_in_flight(),
+        )?;
+        handles.recreate_pipeline(&mut self.shader_cache.lock())?;
+        if let (Some(gui_renderer), Some(scene_texture)) =
+            (self.gui_renderer.as_mut(), self.scene_texture)
+        {
+            let color_texture = &handles.offscreen.color_texture;
+            gui_renderer.replace_texture(
+                scene_texture,
+                &color_texture.view,
+                &color_texture.sampler,
+            );
+        }
         self.handles = Some(handles);
 
+        // The surface may hand out a different number of images than before
+        if self.scene.is_some() {
+            self.allocate_command_buffers();
+        }
+
+        // The offscreen target is recreated at the new size along with its render pass.
+        // The scene pipelines are rebuilt against the new render pass instead of relying on
+        // render pass compatibility, which would also keep the old render pass alive.
+        let offscreen = &self.handles.as_ref().unwrap().offscreen;
+        let offscreen_render_pass = offscreen.render_pass.clone();
+        let offscreen_extent = offscreen.extent;
         let extent = self.swapchain().properties().extent;
-        self.record_all_command_buffers(&extent, draw_data);
+        if let Some(scene) = self.scene.as_mut() {
+            scene.resize_picking_pass(extent);
+            scene.resize_offscreen(&self.transient_command_pool, offscreen_extent);
+            scene.recreate_pipelines(
+                &mut self.shader_cache.lock(),
+                offscreen_render_pass,
+                self.samples,
+            )?;
+        }
+
+        self.command_buffers_dirty = true;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "shader-hot-reload")]
+    fn create_shader_watcher() -> Option<ShaderWatcher> {
+        match ShaderWatcher::new(Self::SHADER_DIRECTORY) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                warn!("Shader hot reloading is disabled: {}", error);
+                None
+            }
+        }
+    }
+
+    // Recreates every pipeline when a loaded shader changes,
+    // since pipelines don't keep track of the shaders they were built from
+    #[cfg(feature = "shader-hot-reload")]
+    fn reload_changed_shaders(&mut self) -> Result<()> {
+        let changed_files = match self.shader_watcher.as_ref() {
+            Some(watcher) => watcher.changed_files(),
+            None => return Ok(()),
+        };
+
+        let mut reload = false;
+        for path in changed_files.iter() {
+            if self.shader_cache.lock().evict(path) {
+                info!("Reloading shader '{}'", path.display());
+                reload = true;
+            }
+        }
+        if !reload {
+            return Ok(());
+        }
+
+        // The old pipelines may still be in use by frames in flight
+        self.context.logical_device().wait_idle();
+
+        if let Some(handles) = self.handles.as_mut() {
+            handles.recreate_pipeline(&mut self.shader_cache.lock())?;
+        }
+
+        let offscreen_render_pass = self.handles.as_ref().unwrap().offscreen.render_pass.clone();
+        if let Some(scene) = self.scene.as_mut() {
+            scene.recreate_pipelines(
+                &mut self.shader_cache.lock(),
+                offscreen_render_pass,
+                self.samples,
+            )?;
+        }
+
+        let render_pass = self.handles.as_ref().unwrap().render_pass.clone();
+        if let Some(gui_renderer) = self.gui_renderer.as_mut() {
+            gui_renderer.recreate_pipeline(&mut self.shader_cache.lock(), render_pass)?;
+        }
 
+        self.command_buffers_dirty = true;
         Ok(())
     }
 
+    fn frames_in_flight(&self) -> u32 {
+        self.synchronization_set.frames_in_flight() as _
+    }
+
     fn swapchain(&self) -> &Swapchain {
         // FIXME: Use a result here
         self.swapchain.as_ref().expect("Failed to get swapchain!")
     }
 
-    fn record_all_command_buffers(&mut self, extent: &vk::Extent2D, draw_data: &DrawData) {
-        let command_buffers = self
-            .command_pool
-            .command_buffers()
-            .iter()
-            .copied()
-            .enumerate()
-            .collect::<Vec<_>>();
+    // Every command buffer is recorded again the next time its frame in flight is rendered
+    fn mark_command_buffers_stale(&mut self) {
+        self.stats = self
+            .scene
+            .as_ref()
+            .map(|scene| scene.stats(&self.render_settings))
+            .unwrap_or_default();
+        let number_of_command_buffers = self.handles.as_ref().unwrap().framebuffers.len()
+            * self.synchronization_set.frames_in_flight();
+        self.stale_command_buffers = vec![true; number_of_command_buffers];
+    }
 
-        for (index, command_buffer) in command_buffers {
-            let framebuffer = self.handles.as_ref().unwrap().framebuffers[index].framebuffer();
-            self.record_single_command_buffer(extent, framebuffer, command_buffer, draw_data);
+    // There is a command buffer for every pair of frame in flight and swapchain image,
+    // since each frame in flight binds its own uniform buffers
+    fn allocate_command_buffers(&mut self) {
+        let number_of_command_buffers = self.handles.as_ref().unwrap().framebuffers.len()
+            * self.synchronization_set.frames_in_flight();
+        if self.command_pool.command_buffers().len() == number_of_command_buffers {
+            return;
         }
+
+        self.command_pool.clear_command_buffers();
+        self.command_pool
+            .allocate_command_buffers(
+                number_of_command_buffers as _,
+                vk::CommandBufferLevel::PRIMARY,
+            )
+            .unwrap();
+        for pool in self.secondary_command_pools.iter_mut() {
+            pool.clear_command_buffers();
+            pool.allocate_command_buffers(
+                number_of_command_buffers as _,
+                vk::CommandBufferLevel::SECONDARY,
+            )
+            .unwrap();
+        }
+    }
+
+    fn command_buffer_index(&self, frame: usize, image: usize) -> usize {
+        frame * self.handles.as_ref().unwrap().framebuffers.len() + image
+    }
+
+    fn record_command_buffer_at(
+        &mut self,
+        frame: usize,
+        image: usize,
+        extent: &vk::Extent2D,
+        draw_data: &DrawData,
+    ) {
+        let index = self.command_buffer_index(frame, image);
+        let command_buffer = self.command_pool.command_buffers()[index];
+        let framebuffer = self.handles.as_ref().unwrap().framebuffers[image].framebuffer();
+        let secondary_command_buffers = self
+            .secondary_command_pools
+            .iter()
+            .map(|pool| pool.command_buffers()[index])
+            .collect::<Vec<_>>();
+        self.record_single_command_buffer(
+            extent,
+            framebuffer,
+            command_buffer,
+            &secondary_command_buffers,
+            draw_data,
+            frame,
+        );
     }
 
+    // The frame is recorded as a render graph:
+    // shadows -> scene -> transmission (if needed) -> outline (if enabled) -> debug shapes (if any)
+    // -> auto exposure (if enabled) -> post-processing (unless the scene is in a gui window) -> gui
     fn record_single_command_buffer(
         &mut self,
         extent: &vk::Extent2D,
         framebuffer: vk::Framebuffer,
         command_buffer: vk::CommandBuffer,
+        secondary_command_buffers: &[vk::CommandBuffer],
         draw_data: &DrawData,
+        frame: usize,
     ) {
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.39, 0.58, 0.93, 1.0],
-                },
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            },
-        ];
-
         let context = self.context.clone();
-        let render_pass = self.handles.as_ref().unwrap().render_pass.render_pass();
+        let handles = self.handles.as_ref().unwrap();
+        let scene = self.scene.as_ref();
+        let render_settings = &self.render_settings;
+        let secondary_command_buffers = if render_settings.parallel_recording {
+            secondary_command_buffers
+        } else {
+            &[]
+        };
 
-        let (offscreen_framebuffer, offscreen_render_pass) = {
-            let offscreen = &self.handles.as_ref().unwrap().offscreen;
+        let mut graph = RenderGraph::default();
+        if let Some(scene) = scene {
+            graph.add_pass(ShadowPass { scene, frame });
+        }
 
-            (
-                offscreen.framebuffer.framebuffer(),
-                offscreen.render_pass.render_pass(),
-            )
-        };
+        graph.add_pass(ScenePass::new(
+            context.clone(),
+            handles,
+            scene,
+            render_settings,
+            secondary_command_buffers,
+            frame,
+        ));
+
+        if let Some(scene) = scene.filter(|scene| scene.has_transmissive_primitives()) {
+            graph
+                .add_pass(TransmissionCopyPass::new(handles, scene))
+                .add_pass(TransmissionPass::new(
+                    handles,
+                    scene,
+                    render_settings,
+                    frame,
+                ));
+        }
+
+        if let Some(scene) = scene.filter(|scene| scene.has_particles(frame)) {
+            graph.add_pass(ParticlesPass::new(handles, scene, frame));
+        }
+
+        if let Some(scene) =
+            scene.filter(|scene| render_settings.outline_selection && scene.has_selection())
+        {
+            graph.add_pass(OutlinePass::new(handles, scene, render_settings, frame));
+        }
+
+        if let Some(scene) = scene.filter(|scene| scene.has_debug_shapes(frame)) {
+            graph.add_pass(DebugShapesPass::new(handles, scene, frame));
+        }
+
+        let target = present_target(handles, framebuffer, *extent, render_settings);
+        if !self.scene_windowed {
+            if render_settings.auto_exposure {
+                graph.add_pass(AutoExposurePass {
+                    handles,
+                    render_settings,
+                    frame,
+                });
+            }
+            graph.add_pass(PostProcessPass {
+                context: context.clone(),
+                handles,
+                target: target.clone(),
+                render_settings,
+                projection: &self.projection,
+            });
+        }
+        graph.add_pass(GuiPass {
+            gui_renderer: self.gui_renderer.as_mut(),
+            draw_data,
+            target,
+        });
 
         context.logical_device().record_command_buffer(
             command_buffer,
             vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
-            || {
-                // Render the scene
-                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(offscreen_render_pass)
-                    .framebuffer(offscreen_framebuffer)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: Offscreen::extent(),
-                    })
-                    .clear_values(&clear_values)
-                    .build();
-
-                RenderPass::record(
-                    context.clone(),
-                    command_buffer,
-                    &render_pass_begin_info,
-                    || {
-                        context
-                            .logical_device()
-                            .update_viewport(command_buffer, Offscreen::extent());
-
-                        if let Some(scene) = self.scene.as_mut() {
-                            scene.issue_commands(command_buffer).unwrap();
-                        } else {
-                            warn!("Scene not loaded!");
-                        }
-                    },
-                );
-
-                // Post-Processing and Gui
-                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(render_pass)
-                    .framebuffer(framebuffer)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: *extent,
-                    })
-                    .clear_values(&clear_values)
-                    .build();
-
-                RenderPass::record(
-                    context.clone(),
-                    command_buffer,
-                    &render_pass_begin_info,
-                    || {
-                        context
-                            .logical_device()
-                            .update_viewport(command_buffer, *extent);
-
-                        if let Some(handles) = self.handles.as_ref() {
-                            handles.issue_commands(command_buffer);
-                        }
-
-                        if let Some(gui_renderer) = self.gui_renderer.as_mut() {
-                            gui_renderer.issue_commands(
-                                &self.transient_command_pool,
-                                command_buffer,
-                                draw_data,
-                            );
-                        } else {
-                            warn!("No gui available!");
-                        }
-                    },
-                );
-            },
+            || graph.record(context.clone(), command_buffer),
         );
     }
 }
 
+// Device loss is reported as its own error so the caller can recreate the renderer
+fn device_error(error: vk::Result, message: &str) -> anyhow::Error {
+    match error {
+        vk::Result::ERROR_DEVICE_LOST => DeviceLost.into(),
+        error => anyhow!("{} Cause: {}", message, error),
+    }
+}
+
 impl Drop for VulkanRenderer {
     fn drop(&mut self) {
         self.context.logical_device().wait_idle();
@@ -236,64 +485,304 @@ impl Drop for VulkanRenderer {
 }
 
 impl Renderer for VulkanRenderer {
-    fn initialize(&mut self, world: &World, mut imgui: &mut Context) {
-        let asset_names = &<Read<AssetName>>::query()
-            .iter(world)
-            .map(|asset_name| asset_name.0.to_string())
-            .collect::<Vec<_>>();
+    fn load_progress(&self) -> LoadProgress {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.load_progress())
+            .unwrap_or_default()
+    }
 
-        let offscreen_render_pass = self.handles.as_ref().unwrap().offscreen.render_pass.clone();
+    fn loaded_assets(&self) -> Vec<String> {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.loaded_assets())
+            .unwrap_or_default()
+    }
+
+    fn load_asset(&mut self, asset_name: &str) {
+        if let Some(scene) = self.scene.as_mut() {
+            scene.load_asset(asset_name);
+        }
+    }
+
+    fn load_asset_from_bytes(
+        &mut self,
+        asset_name: &str,
+        bytes: &[u8],
+        base_dir: &Path,
+    ) -> Result<()> {
+        let scene = self
+            .scene
+            .as_mut()
+            .context("Failed to load an asset before the renderer was initialized!")?;
+        scene.load_asset_from_bytes(&self.transient_command_pool, asset_name, bytes, base_dir)?;
+        self.command_buffers_dirty = true;
+        Ok(())
+    }
+
+    fn unload_asset(&mut self, asset_name: &str) {
+        if let Some(scene) = self.scene.as_mut() {
+            if scene.unload_asset(&self.transient_command_pool, asset_name) {
+                self.command_buffers_dirty = true;
+            }
+        }
+    }
+
+    fn pick(&mut self, x: u32, y: u32) -> Option<Entity> {
+        let scene = self.scene.as_ref()?;
+        match scene.pick(&self.transient_command_pool, x, y) {
+            Ok(entity) => entity,
+            Err(error) => {
+                warn!("Failed to pick entity: {}", error);
+                None
+            }
+        }
+    }
+
+    fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn scene_texture(&self) -> Option<TextureId> {
+        self.scene_texture
+    }
+
+    // The asset is loaded into its own scene and rendered into a transient target,
+    // so the swapchain and the main scene are left untouched
+    fn render_thumbnail(&mut self, asset_name: &str, size: u32) -> Result<image::RgbaImage> {
+        let key = (asset_name.to_string(), size);
+        if let Some(thumbnail) = self.thumbnails.get(&key) {
+            return Ok(thumbnail.clone());
+        }
+
+        let mut thumbnail_renderer =
+            HeadlessRenderer::with_context(self.context.clone(), size, size)?;
+        let mut render_scene = RenderScene {
+            environment: self.environment.clone(),
+            environment_cache: self.environment_cache.clone(),
+            ibl_settings: Self::THUMBNAIL_IBL_SETTINGS,
+            ..Default::default()
+        };
+        thumbnail_renderer.initialize(&render_scene)?;
+        thumbnail_renderer.load_asset(asset_name)?;
+        render_scene.assets = vec![(AssetName(asset_name.to_string()), glm::Mat4::identity())];
+
+        let bounding_box = thumbnail_renderer
+            .scene()
+            .and_then(|scene| scene.asset_bounding_box(asset_name))
+            .with_context(|| format!("Failed to load '{}' for its thumbnail!", asset_name))?;
+        let mut camera = OrbitalCamera::default();
+        camera.set_target(bounding_box.center());
+        camera.focus_on(&bounding_box);
+        render_scene.view = camera.view_matrix();
+        render_scene.camera_position = camera.position();
+
+        thumbnail_renderer.render(&render_scene)?;
+        let thumbnail = thumbnail_renderer.read_pixels()?;
+        self.thumbnails.insert(key, thumbnail.clone());
+        Ok(thumbnail)
+    }
+
+    fn asset_cameras(&self, asset_name: &str) -> Vec<AssetCamera> {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.asset_cameras(asset_name))
+            .unwrap_or_default()
+    }
+
+    fn asset_animations(&self, asset_name: &str) -> Vec<AnimationInfo> {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.asset_animations(asset_name))
+            .unwrap_or_default()
+    }
+
+    fn asset_bounding_box(&self, asset_name: &str) -> Option<Aabb> {
+        self.scene
+            .as_ref()
+            .and_then(|scene| scene.asset_bounding_box(asset_name))
+    }
+
+    fn asset_primitive_bounding_boxes(&self, asset_name: &str) -> Vec<Aabb> {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.asset_primitive_bounding_boxes(asset_name))
+            .unwrap_or_default()
+    }
+
+    fn asset_scenes(&self, asset_name: &str) -> Vec<SceneInfo> {
+        self.scene
+            .as_ref()
+            .map(|scene| scene.asset_scenes(asset_name))
+            .unwrap_or_default()
+    }
+
+    fn set_asset_scene(&mut self, asset_name: &str, index: usize) -> Result<()> {
+        let scene = self
+            .scene
+            .as_mut()
+            .context("Failed to select a scene before the renderer was initialized!")?;
+        if scene.set_asset_scene(&self.transient_command_pool, asset_name, index)? {
+            self.command_buffers_dirty = true;
+        }
+        Ok(())
+    }
+
+    fn initialize(&mut self, scene: &RenderScene, mut imgui: &mut Context) -> Result<()> {
+        let asset_names = &scene.asset_names();
+        self.environment = scene.environment.clone();
+        self.environment_cache = scene.environment_cache.clone();
+
+        let offscreen = &self.handles.as_ref().unwrap().offscreen;
+        let offscreen_render_pass = offscreen.render_pass.clone();
+        let offscreen_extent = offscreen.extent;
+        let extent = self.swapchain().properties().extent;
         let scene_data = PbrScene::new(
             self.context.clone(),
             &self.transient_command_pool,
-            &mut self.shader_cache,
+            &mut self.shader_cache.lock(),
             offscreen_render_pass,
             asset_names,
-            vk::SampleCountFlags::TYPE_1,
-        );
+            self.samples,
+            self.frames_in_flight(),
+            ShadowMap::DEFAULT_RESOLUTION,
+            offscreen_extent,
+            extent,
+            &scene.environment,
+            &scene.ibl_settings,
+            scene.environment_cache.as_deref(),
+        )?;
 
-        self.command_pool
-            .allocate_command_buffers(self.handles.as_ref().unwrap().framebuffers.len() as _)
-            .unwrap();
+        self.allocate_command_buffers();
         self.scene = Some(scene_data);
+        self.command_buffers_dirty = true;
 
         let render_pass = self.handles.as_ref().unwrap().render_pass.clone();
 
-        let gui_renderer = GuiRenderer::new(
+        let mut gui_renderer = GuiRenderer::new(
             self.context.clone(),
-            &mut self.shader_cache,
+            &mut self.shader_cache.lock(),
             render_pass,
             &mut imgui,
             &self.transient_command_pool,
-        );
+        )?;
+        let color_texture = &self.handles.as_ref().unwrap().offscreen.color_texture;
+        self.scene_texture =
+            Some(gui_renderer.register_texture(&color_texture.view, &color_texture.sampler)?);
         self.gui_renderer = Some(gui_renderer);
+
+        Ok(())
     }
 
-    fn render(&mut self, world: &World, resources: &Resources, draw_data: &DrawData) {
-        let projection = glm::perspective_zo(
-            self.swapchain().properties().aspect_ratio(),
-            70_f32.to_radians(),
-            0.1_f32,
-            1000_f32,
-        );
+    fn resize(&mut self, width: u32, height: u32) {
+        self.window_dimensions = glm::vec2(width as f32, height as f32);
+        self.swapchain_dirty = true;
+    }
 
-        // FIXME: Move this to the system struct
-        self.scene
-            .as_mut()
-            .unwrap()
-            .update(world, resources, projection);
+    fn render(&mut self, scene: &RenderScene, draw_data: &DrawData) -> Result<()> {
+        // A minimized window has no surface area to render to
+        if self.window_dimensions.x < 1.0 || self.window_dimensions.y < 1.0 {
+            return Ok(());
+        }
+
+        if self.swapchain_dirty {
+            self.recreate_swapchain()
+                .context("Failed to recreate swapchain!")?;
+        }
+
+        #[cfg(feature = "shader-hot-reload")]
+        if let Err(error) = self.reload_changed_shaders() {
+            warn!("Failed to reload shaders: {}", error);
+        }
+
+        let aspect_ratio = match scene.scene_window_dimensions {
+            Some(dimensions) if dimensions.x >= 1.0 && dimensions.y >= 1.0 => {
+                dimensions.x / dimensions.y
+            }
+            _ => self.swapchain().properties().aspect_ratio(),
+        };
 
-        let system = resources
-            .get::<System>()
-            .expect("Failed to get system resource!");
+        let scene_windowed = scene.scene_window_dimensions.is_some();
+        if scene_windowed != self.scene_windowed {
+            self.scene_windowed = scene_windowed;
+            self.command_buffers_dirty = true;
+        }
 
+        if let Some(pbr_scene) = self.scene.as_mut() {
+            if pbr_scene.update_instances(&self.transient_command_pool, &scene.asset_names()) {
+                self.command_buffers_dirty = true;
+            }
+            if pbr_scene.receive_assets(&self.transient_command_pool) {
+                self.command_buffers_dirty = true;
+            }
+            pbr_scene
+                .load_particle_textures(&self.transient_command_pool, &scene.particle_emitters);
+        }
+
+        // Waiting for the previous submission of this frame
+        // makes its uniform buffers safe to write
         let current_frame_synchronization = self
             .synchronization_set
             .current_frame_synchronization(self.current_frame);
 
         self.context
             .logical_device()
-            .wait_for_fence(&current_frame_synchronization);
+            .wait_for_fence(&current_frame_synchronization)
+            .map_err(|error| device_error(error, "Failed to wait for fences!"))?;
+
+        let pbr_scene = self.scene.as_mut().unwrap();
+        let previous_viewport_count = pbr_scene.viewport_count();
+        pbr_scene
+            .update(scene, aspect_ratio, self.current_frame)
+            .context("Failed to update the pbr scene!")?;
+        if pbr_scene.blended_draw_order_changed() {
+            self.command_buffers_dirty = true;
+        }
+        if pbr_scene.viewport_count() != previous_viewport_count {
+            self.command_buffers_dirty = true;
+        }
+        if pbr_scene.debug_shapes_changed() || pbr_scene.particles_changed() {
+            self.command_buffers_dirty = true;
+        }
+        if pbr_scene.selection_changed()
+            && (scene.render_settings.highlight_selection
+                || scene.render_settings.outline_selection)
+        {
+            self.command_buffers_dirty = true;
+        }
+        self.handles
+            .as_ref()
+            .unwrap()
+            .auto_exposure
+            .update(self.current_frame, scene.delta_time)
+            .context("Failed to update the auto exposure!")?;
+
+        let render_settings = scene.render_settings;
+        if render_settings != self.render_settings {
+            self.render_settings = render_settings;
+            self.command_buffers_dirty = true;
+        }
+
+        if scene.projection != self.projection {
+            self.projection = scene.projection;
+            if render_settings.show_depth {
+                self.command_buffers_dirty = true;
+            }
+        }
+
+        // Changing the sample count rebuilds the offscreen targets the same way a resize does.
+        // Multisampled depth can't be displayed, so showing it renders single sampled.
+        let samples = if render_settings.show_depth {
+            vk::SampleCountFlags::TYPE_1
+        } else {
+            self.context.clamp_samples(render_settings.msaa_samples)
+        };
+        if samples != self.samples || render_settings.render_scale != self.render_scale {
+            self.samples = samples;
+            self.render_scale = render_settings.render_scale;
+            self.recreate_swapchain()
+                .context("Failed to recreate swapchain!")?;
+        }
 
         let image_index_result = self.swapchain().acquire_next_image(
             current_frame_synchronization.image_available(),
@@ -303,11 +792,11 @@ impl Renderer for VulkanRenderer {
         let image_index = match image_index_result {
             Ok((image_index, _)) => image_index,
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.recreate_swapchain(&system.window_dimensions, draw_data)
-                    .expect("Failed to recreate swapchain!");
-                return;
+                return self
+                    .recreate_swapchain()
+                    .context("Failed to recreate swapchain!");
             }
-            Err(error) => panic!("Error while acquiring next image. Cause: {}", error),
+            Err(error) => return Err(device_error(error, "Error while acquiring next image.")),
         };
         let image_indices = [image_index];
 
@@ -317,17 +806,41 @@ impl Renderer for VulkanRenderer {
 
         let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
 
+        // The scene commands only need to be recorded again when something changes,
+        // but the gui geometry is rebuilt every frame so the command buffer
+        // for the acquired image is re-recorded while there is gui data to draw.
+        // Only the command buffer about to be submitted is recorded, since the fence
+        // of this frame in flight guarantees it is no longer pending.
         let extent = self.swapchain().properties().extent;
-        self.record_all_command_buffers(&extent, draw_data);
+        let gui_visible = draw_data.total_vtx_count > 0;
+        if self.command_buffers_dirty || (self.gui_recorded && !gui_visible) {
+            self.mark_command_buffers_stale();
+            self.command_buffers_dirty = false;
+        }
+        self.gui_recorded = gui_visible;
+
+        let command_buffer_index = self.command_buffer_index(self.current_frame, image_index as _);
+        if self.stale_command_buffers[command_buffer_index] || gui_visible {
+            self.record_command_buffer_at(
+                self.current_frame,
+                image_index as usize,
+                &extent,
+                draw_data,
+            );
+            self.stale_command_buffers[command_buffer_index] = false;
+        }
 
         self.command_pool
             .submit_command_buffer(
-                image_index as usize,
+                command_buffer_index,
                 self.context.graphics_queue(),
                 &wait_stages,
                 &current_frame_synchronization,
             )
-            .unwrap();
+            .map_err(|error| match error.downcast::<vk::Result>() {
+                Ok(error) => device_error(error, "Failed to submit the command buffer!"),
+                Err(error) => error,
+            })?;
 
         let swapchain_presentation_result = self.swapchain().present_rendered_image(
             &current_frame_synchronization,
@@ -337,18 +850,20 @@ impl Renderer for VulkanRenderer {
 
         match swapchain_presentation_result {
             Ok(is_suboptimal) if is_suboptimal => {
-                self.recreate_swapchain(&system.window_dimensions, draw_data)
-                    .expect("Failed to recreate swapchain!");
+                self.recreate_swapchain()
+                    .context("Failed to recreate swapchain!")?;
             }
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.recreate_swapchain(&system.window_dimensions, draw_data)
-                    .expect("Failed to recreate swapchain!");
+                self.recreate_swapchain()
+                    .context("Failed to recreate swapchain!")?;
             }
-            Err(error) => panic!("Failed to present queue. Cause: {}", error),
+            Err(error) => return Err(device_error(error, "Failed to present queue.")),
             _ => {}
         }
 
-        self.current_frame +=
-            (1 + self.current_frame) % SynchronizationSet::MAX_FRAMES_IN_FLIGHT as usize;
+        self.current_frame = (self.current_frame + 1) % self.synchronization_set.frames_in_flight();
+        debug_assert!(self.current_frame < self.synchronization_set.frames_in_flight());
+
+        Ok(())
     }
 }
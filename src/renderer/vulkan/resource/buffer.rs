@@ -1,12 +1,15 @@
 use crate::renderer::vulkan::{core::VulkanContext, resource::CommandPool};
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use ash::{version::DeviceV1_0, vk};
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 pub struct Buffer {
     buffer: vk::Buffer,
     allocation: vk_mem::Allocation,
     allocation_info: vk_mem::AllocationInfo,
+    // Uploads to memory that isn't host coherent are flushed so the device sees them
+    host_coherent: bool,
+    non_coherent_atom_size: usize,
     context: Arc<VulkanContext>,
 }
 
@@ -20,10 +23,21 @@ impl Buffer {
             .allocator()
             .create_buffer(&buffer_create_info, &allocation_create_info)?;
 
+        let memory_properties = context
+            .allocator()
+            .get_memory_type_properties(allocation_info.get_memory_type())?;
+        let host_coherent = memory_properties.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        let non_coherent_atom_size = context
+            .physical_device_properties()
+            .limits
+            .non_coherent_atom_size as usize;
+
         let buffer = Self {
             buffer,
             allocation,
             allocation_info,
+            host_coherent,
+            non_coherent_atom_size,
             context,
         };
 
@@ -50,14 +64,48 @@ impl Buffer {
         Buffer::new(context, &allocation_create_info, &buffer_create_info)
     }
 
+    // The offset is in bytes from the start of the buffer.
+    // The written range is flushed if the memory isn't host coherent.
     pub fn upload_to_buffer<T>(&self, data: &[T], offset: usize) -> Result<()> {
-        // TODO: Add checks for size of data being written
-        let data_pointer = self.map_memory()?;
-        unsafe {
-            data_pointer.add(offset);
-            (data_pointer as *mut T).copy_from_nonoverlapping(data.as_ptr(), data.len());
+        let data_size = mem::size_of_val(data);
+        let buffer_size = self.allocation_info.get_size();
+        Self::check_upload_range(data_size, offset, buffer_size)?;
+
+        // Persistently mapped buffers don't need to be mapped for every upload
+        let mapped_data = self.allocation_info.get_mapped_data();
+        let data_pointer = if mapped_data.is_null() {
+            self.map_memory()?
+        } else {
+            mapped_data
+        };
+
+        let memory = unsafe { std::slice::from_raw_parts_mut(data_pointer, buffer_size) };
+        Self::copy_bytes(memory, data, offset)?;
+        self.flush_if_not_coherent(offset, data_size)?;
+
+        if mapped_data.is_null() {
+            self.unmap_memory()?;
         }
-        self.unmap_memory()?;
+        Ok(())
+    }
+
+    fn check_upload_range(data_size: usize, offset: usize, buffer_size: usize) -> Result<()> {
+        ensure!(
+            data_size <= buffer_size && offset <= buffer_size - data_size,
+            "Failed to upload {} bytes at offset {} to a buffer of {} bytes!",
+            data_size,
+            offset,
+            buffer_size
+        );
+        Ok(())
+    }
+
+    // Copied as bytes since the offset isn't necessarily aligned for T
+    fn copy_bytes<T>(memory: &mut [u8], data: &[T], offset: usize) -> Result<()> {
+        let data_size = mem::size_of_val(data);
+        Self::check_upload_range(data_size, offset, memory.len())?;
+        let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data_size) };
+        memory[offset..offset + data_size].copy_from_slice(bytes);
         Ok(())
     }
 
@@ -67,19 +115,72 @@ impl Buffer {
         offset: usize,
         alignment: vk::DeviceSize,
     ) -> Result<()> {
+        let buffer_size = self.allocation_info.get_size();
+        ensure!(
+            offset <= buffer_size,
+            "Failed to upload at offset {} to a buffer of {} bytes!",
+            offset,
+            buffer_size
+        );
+
         let data_pointer = self.map_memory()?;
         unsafe {
+            // Bounded by what is left of the buffer after the offset
             let mut align = ash::util::Align::new(
                 data_pointer.add(offset) as _,
                 alignment,
-                self.allocation_info.get_size() as _,
+                (buffer_size - offset) as _,
             );
             align.copy_from_slice(data);
         }
+
+        let written_size =
+            Self::aligned_write_size::<T>(data.len(), alignment, buffer_size - offset);
+        self.flush_if_not_coherent(offset, written_size)?;
+
         self.unmap_memory()?;
         Ok(())
     }
 
+    // Each element is padded out to the alignment,
+    // and the write is cut short at the end of the buffer
+    fn aligned_write_size<T>(count: usize, alignment: vk::DeviceSize, available: usize) -> usize {
+        let element_size = mem::size_of::<T>() as vk::DeviceSize;
+        let stride = element_size.div_ceil(alignment) * alignment;
+        (count * stride as usize).min(available)
+    }
+
+    fn flush_if_not_coherent(&self, offset: usize, size: usize) -> Result<()> {
+        if let Some((offset, size)) = Self::flush_range(
+            self.host_coherent,
+            offset,
+            size,
+            self.non_coherent_atom_size,
+            self.allocation_info.get_size(),
+        ) {
+            self.flush(offset, size)?;
+        }
+        Ok(())
+    }
+
+    // The range to flush after a write, widened to the non coherent atom size
+    // and cut short at the end of the buffer. Coherent memory and empty writes need no flush.
+    fn flush_range(
+        host_coherent: bool,
+        offset: usize,
+        size: usize,
+        atom_size: usize,
+        buffer_size: usize,
+    ) -> Option<(usize, usize)> {
+        if host_coherent || size == 0 {
+            return None;
+        }
+        let atom_size = atom_size.max(1);
+        let start = offset / atom_size * atom_size;
+        let end = (offset + size).div_ceil(atom_size) * atom_size;
+        Some((start, end.min(buffer_size) - start))
+    }
+
     pub fn map_memory(&self) -> vk_mem::error::Result<*mut u8> {
         self.context.allocator().map_memory(&self.allocation)
     }
@@ -89,11 +190,25 @@ impl Buffer {
     }
 
     pub fn flush(&self, offset: usize, size: usize) -> vk_mem::error::Result<()> {
+        assert!(
+            offset + size <= self.allocation_info.get_size(),
+            "Failed to flush {} bytes at offset {} of a buffer of {} bytes!",
+            size,
+            offset,
+            self.allocation_info.get_size()
+        );
         self.context
             .allocator()
             .flush_allocation(&self.allocation, offset, size)
     }
 
+    // Makes device writes visible to the host before reading mapped memory
+    pub fn invalidate(&self, offset: usize, size: usize) -> vk_mem::error::Result<()> {
+        self.context
+            .allocator()
+            .invalidate_allocation(&self.allocation, offset, size)
+    }
+
     pub fn buffer(&self) -> vk::Buffer {
         self.buffer
     }
@@ -156,7 +271,7 @@ impl GeometryBuffer {
         let region = vk::BufferCopy {
             src_offset: 0,
             dst_offset: 0,
-            size: (data.len() * std::mem::size_of::<T>()) as ash::vk::DeviceSize,
+            size: mem::size_of_val(data) as ash::vk::DeviceSize,
         };
         command_pool.create_device_local_buffer(usage_flags, &data, &[region])
     }
@@ -179,3 +294,155 @@ impl GeometryBuffer {
         }
     }
 }
+
+// Host visible vertex and index buffers that stay mapped and are written in place.
+// They are only reallocated when the data outgrows them.
+pub struct DynamicGeometryBuffer {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    context: Arc<VulkanContext>,
+}
+
+impl DynamicGeometryBuffer {
+    pub const MIN_CAPACITY: vk::DeviceSize = 64 * 1024;
+
+    pub fn new(context: Arc<VulkanContext>) -> Result<Self> {
+        let vertex_buffer = Self::create_buffer(
+            context.clone(),
+            Self::MIN_CAPACITY,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = Self::create_buffer(
+            context.clone(),
+            Self::MIN_CAPACITY,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            context,
+        })
+    }
+
+    fn create_buffer(
+        context: Arc<VulkanContext>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Buffer> {
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        Buffer::new(context, &allocation_create_info, &buffer_create_info)
+    }
+
+    pub fn upload_vertices<T: Copy>(&mut self, vertices: &[T]) -> Result<()> {
+        let size = mem::size_of_val(vertices) as vk::DeviceSize;
+        if size > self.vertex_buffer.allocation_info().get_size() as vk::DeviceSize {
+            self.vertex_buffer = self.grow(size, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+        }
+        self.vertex_buffer.upload_to_buffer(vertices, 0)
+    }
+
+    pub fn upload_indices(&mut self, indices: &[u32]) -> Result<()> {
+        let size = mem::size_of_val(indices) as vk::DeviceSize;
+        if size > self.index_buffer.allocation_info().get_size() as vk::DeviceSize {
+            self.index_buffer = self.grow(size, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        }
+        self.index_buffer.upload_to_buffer(indices, 0)
+    }
+
+    fn grow(&self, size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> Result<Buffer> {
+        // The old buffer may still be in use by a frame in flight
+        self.context.logical_device().wait_idle();
+        Self::create_buffer(
+            self.context.clone(),
+            size.next_power_of_two().max(Self::MIN_CAPACITY),
+            usage,
+        )
+    }
+
+    pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.buffer()], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer.buffer(),
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_land_at_the_offset() {
+        let mut memory = vec![0u8; 32];
+        Buffer::copy_bytes(&mut memory, &[0x0403_0201u32, 0x0807_0605], 16).unwrap();
+        assert!(memory[..16].iter().all(|byte| *byte == 0));
+        assert_eq!(&memory[16..24], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(memory[24..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn aligned_writes_are_padded_to_the_alignment() {
+        assert_eq!(Buffer::aligned_write_size::<[f32; 16]>(3, 64, 1024), 3 * 64);
+        assert_eq!(
+            Buffer::aligned_write_size::<[f32; 17]>(3, 64, 1024),
+            3 * 128
+        );
+        assert_eq!(
+            Buffer::aligned_write_size::<[f32; 4]>(2, 256, 1024),
+            2 * 256
+        );
+        assert_eq!(Buffer::aligned_write_size::<[f32; 4]>(0, 256, 1024), 0);
+    }
+
+    #[test]
+    fn aligned_writes_stop_at_the_end_of_the_buffer() {
+        assert_eq!(Buffer::aligned_write_size::<[f32; 4]>(4, 256, 800), 800);
+    }
+
+    #[test]
+    fn coherent_memory_is_not_flushed() {
+        assert_eq!(Buffer::flush_range(true, 16, 64, 64, 1024), None);
+        assert_eq!(Buffer::flush_range(true, 0, 0, 64, 1024), None);
+    }
+
+    #[test]
+    fn non_coherent_writes_are_flushed() {
+        assert_eq!(Buffer::flush_range(false, 0, 128, 64, 1024), Some((0, 128)));
+        assert_eq!(Buffer::flush_range(false, 0, 0, 64, 1024), None);
+    }
+
+    #[test]
+    fn flushes_are_rounded_to_the_atom_size() {
+        assert_eq!(Buffer::flush_range(false, 70, 20, 64, 1024), Some((64, 64)));
+        assert_eq!(Buffer::flush_range(false, 60, 10, 64, 1024), Some((0, 128)));
+        assert_eq!(
+            Buffer::flush_range(false, 1000, 20, 64, 1020),
+            Some((960, 60))
+        );
+    }
+
+    #[test]
+    fn uploads_past_the_end_are_rejected() {
+        let mut memory = vec![0u8; 32];
+        assert!(Buffer::copy_bytes(&mut memory, &[0u32; 4], 16).is_ok());
+        assert!(Buffer::copy_bytes(&mut memory, &[0u32; 4], 17).is_err());
+        assert!(Buffer::check_upload_range(1, usize::MAX, 32).is_err());
+        assert!(memory.iter().all(|byte| *byte == 0));
+    }
+}
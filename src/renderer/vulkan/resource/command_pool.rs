@@ -1,21 +1,79 @@
 use crate::renderer::vulkan::{
-    core::{CurrentFrameSynchronization, Fence, VulkanContext},
+    core::{CurrentFrameSynchronization, Fence, Semaphore, VulkanContext},
     resource::Buffer,
 };
 use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
 use std::sync::Arc;
 
+// A pool on the device's dedicated transfer queue family
+struct TransferPool {
+    pool: vk::CommandPool,
+    queue: vk::Queue,
+    queue_family_index: u32,
+}
+
+// Which queue family gives up ownership of a resource in a queue family ownership transfer
+#[derive(Clone, Copy)]
+enum Ownership {
+    Release,
+    Acquire,
+}
+
 pub struct CommandPool {
     pool: vk::CommandPool,
     context: Arc<VulkanContext>,
     command_buffers: Vec<vk::CommandBuffer>,
+    transfer: Option<TransferPool>,
 }
 
 impl CommandPool {
+    // In nanoseconds
+    const SUBMISSION_TIMEOUT: u64 = 100_000_000_000;
+
     pub fn new(context: Arc<VulkanContext>, flags: vk::CommandPoolCreateFlags) -> Result<Self> {
+        let pool = Self::create_pool(&context, context.graphics_queue_family_index(), flags)?;
+
+        let command_pool = CommandPool {
+            pool,
+            context,
+            command_buffers: Vec::new(),
+            transfer: None,
+        };
+
+        Ok(command_pool)
+    }
+
+    // Buffer and image uploads run on the dedicated transfer queue when the device has one,
+    // leaving the graphics queue free. Everything else still runs on the graphics queue.
+    pub fn with_transfer_queue(
+        context: Arc<VulkanContext>,
+        flags: vk::CommandPoolCreateFlags,
+    ) -> Result<Self> {
+        let mut command_pool = Self::new(context.clone(), flags)?;
+        // No ownership transfers are needed when the transfer queue is in the graphics family,
+        // so uploads simply stay on the graphics queue
+        let transfer_family = context
+            .transfer_queue_family_index()
+            .filter(|family| *family != context.graphics_queue_family_index());
+        if let (Some(queue_family_index), Some(queue)) = (transfer_family, context.transfer_queue())
+        {
+            command_pool.transfer = Some(TransferPool {
+                pool: Self::create_pool(&context, queue_family_index, flags)?,
+                queue,
+                queue_family_index,
+            });
+        }
+        Ok(command_pool)
+    }
+
+    fn create_pool(
+        context: &VulkanContext,
+        queue_family_index: u32,
+        flags: vk::CommandPoolCreateFlags,
+    ) -> Result<vk::CommandPool> {
         let command_pool_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(context.graphics_queue_family_index())
+            .queue_family_index(queue_family_index)
             .flags(flags)
             .build();
 
@@ -25,14 +83,7 @@ impl CommandPool {
                 .logical_device()
                 .create_command_pool(&command_pool_info, None)?
         };
-
-        let command_pool = CommandPool {
-            pool,
-            context,
-            command_buffers: Vec::new(),
-        };
-
-        Ok(command_pool)
+        Ok(pool)
     }
 
     pub fn pool(&self) -> vk::CommandPool {
@@ -43,10 +94,14 @@ impl CommandPool {
         &self.command_buffers
     }
 
-    pub fn allocate_command_buffers(&mut self, size: vk::DeviceSize) -> Result<()> {
+    pub fn allocate_command_buffers(
+        &mut self,
+        size: vk::DeviceSize,
+        level: vk::CommandBufferLevel,
+    ) -> Result<()> {
         let allocate_info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(self.pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
+            .level(level)
             .command_buffer_count(size as _)
             .build();
 
@@ -175,58 +230,282 @@ impl CommandPool {
         destination: vk::Buffer,
         regions: &[vk::BufferCopy],
     ) -> Result<()> {
-        self.execute_command_once(self.context.graphics_queue(), |command_buffer| {
-            unsafe {
+        let buffer_barrier = |source_family, destination_family| {
+            vk::BufferMemoryBarrier::builder()
+                .src_queue_family_index(source_family)
+                .dst_queue_family_index(destination_family)
+                .buffer(destination)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        };
+        self.execute_transfer(
+            |source_family, destination_family| {
+                (
+                    vec![buffer_barrier(source_family, destination_family)],
+                    Vec::new(),
+                )
+            },
+            |command_buffer| unsafe {
                 self.context
                     .logical_device()
                     .logical_device()
                     .cmd_copy_buffer(command_buffer, source, destination, &regions)
-            };
-        })
+            },
+        )
     }
 
+    // The image must already be in the transfer destination layout
     pub fn copy_buffer_to_image(
         &self,
         buffer: vk::Buffer,
         image: vk::Image,
         regions: &[vk::BufferImageCopy],
+    ) -> Result<()> {
+        let image_barriers = |source_family, destination_family| {
+            regions
+                .iter()
+                .map(|region| {
+                    let layers = region.image_subresource;
+                    vk::ImageMemoryBarrier::builder()
+                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .src_queue_family_index(source_family)
+                        .dst_queue_family_index(destination_family)
+                        .image(image)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: layers.aspect_mask,
+                            base_mip_level: layers.mip_level,
+                            level_count: 1,
+                            base_array_layer: layers.base_array_layer,
+                            layer_count: layers.layer_count,
+                        })
+                        .build()
+                })
+                .collect::<Vec<_>>()
+        };
+        self.execute_transfer(
+            |source_family, destination_family| {
+                (
+                    Vec::new(),
+                    image_barriers(source_family, destination_family),
+                )
+            },
+            |command_buffer| unsafe {
+                self.context
+                    .logical_device()
+                    .logical_device()
+                    .cmd_copy_buffer_to_image(
+                        command_buffer,
+                        buffer,
+                        image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        regions,
+                    )
+            },
+        )
+    }
+
+    // Runs a copy on the transfer queue if there is one, otherwise on the graphics queue.
+    // Exclusively owned resources are handed over to the transfer queue family
+    // and back again around the copy, so their contents and layouts are kept.
+    // The barriers are built for a given source and destination queue family.
+    // The release, copy and acquire submissions are ordered with semaphores,
+    // so only the last one is waited on.
+    fn execute_transfer<B, T>(&self, barriers: B, mut executor: T) -> Result<()>
+    where
+        B: Fn(u32, u32) -> (Vec<vk::BufferMemoryBarrier>, Vec<vk::ImageMemoryBarrier>),
+        T: FnMut(vk::CommandBuffer),
+    {
+        let graphics_queue = self.context.graphics_queue();
+        let graphics_family = self.context.graphics_queue_family_index();
+        let transfer = match self.transfer.as_ref() {
+            Some(transfer) => transfer,
+            None => return self.execute_command_once(graphics_queue, executor),
+        };
+
+        let transfer_family = transfer.queue_family_index;
+        let (to_transfer_buffers, to_transfer_images) = barriers(graphics_family, transfer_family);
+        let (to_graphics_buffers, to_graphics_images) = barriers(transfer_family, graphics_family);
+
+        let release = self.record_once(self.pool, |command_buffer| {
+            self.record_ownership_transfer(
+                command_buffer,
+                Ownership::Release,
+                &to_transfer_buffers,
+                &to_transfer_images,
+            );
+        })?;
+        let copy = self.record_once(transfer.pool, |command_buffer| {
+            self.record_ownership_transfer(
+                command_buffer,
+                Ownership::Acquire,
+                &to_transfer_buffers,
+                &to_transfer_images,
+            );
+            executor(command_buffer);
+            self.record_ownership_transfer(
+                command_buffer,
+                Ownership::Release,
+                &to_graphics_buffers,
+                &to_graphics_images,
+            );
+        })?;
+        let acquire = self.record_once(self.pool, |command_buffer| {
+            self.record_ownership_transfer(
+                command_buffer,
+                Ownership::Acquire,
+                &to_graphics_buffers,
+                &to_graphics_images,
+            );
+        })?;
+
+        let released = Semaphore::new(self.context.clone())?;
+        let copied = Semaphore::new(self.context.clone())?;
+        let fence = Fence::new(self.context.clone(), vk::FenceCreateFlags::empty())?;
+        self.submit_once(
+            graphics_queue,
+            release,
+            None,
+            Some(&released),
+            vk::Fence::null(),
+        )?;
+        self.submit_once(
+            transfer.queue,
+            copy,
+            Some(&released),
+            Some(&copied),
+            vk::Fence::null(),
+        )?;
+        self.submit_once(graphics_queue, acquire, Some(&copied), None, fence.fence())?;
+
+        let logical_device = self.context.logical_device().logical_device();
+        unsafe {
+            logical_device.wait_for_fences(&[fence.fence()], true, Self::SUBMISSION_TIMEOUT)?;
+            logical_device.free_command_buffers(self.pool, &[release, acquire]);
+            logical_device.free_command_buffers(transfer.pool, &[copy]);
+        }
+        Ok(())
+    }
+
+    // The release and acquire halves of a transfer must use the same barriers,
+    // only their access masks differ
+    fn record_ownership_transfer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        ownership: Ownership,
+        buffer_barriers: &[vk::BufferMemoryBarrier],
+        image_barriers: &[vk::ImageMemoryBarrier],
+    ) {
+        let (src_stage_mask, dst_stage_mask, src_access_mask, dst_access_mask) = match ownership {
+            Ownership::Release => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::empty(),
+            ),
+            Ownership::Acquire => (
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        };
+        let buffer_barriers = buffer_barriers
+            .iter()
+            .map(|barrier| vk::BufferMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                ..*barrier
+            })
+            .collect::<Vec<_>>();
+        let image_barriers = image_barriers
+            .iter()
+            .map(|barrier| vk::ImageMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                ..*barrier
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.context
+                .logical_device()
+                .logical_device()
+                .cmd_pipeline_barrier(
+                    command_buffer,
+                    src_stage_mask,
+                    dst_stage_mask,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &buffer_barriers,
+                    &image_barriers,
+                )
+        };
+    }
+
+    pub fn copy_image_to_buffer(
+        &self,
+        image: vk::Image,
+        buffer: vk::Buffer,
+        regions: &[vk::BufferImageCopy],
     ) -> Result<()> {
         self.execute_command_once(self.context.graphics_queue(), |command_buffer| unsafe {
             self.context
                 .logical_device()
                 .logical_device()
-                .cmd_copy_buffer_to_image(
+                .cmd_copy_image_to_buffer(
                     command_buffer,
-                    buffer,
                     image,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer,
                     regions,
                 )
         })
     }
 
-    // TODO: Refactor this to be smaller. Functionality can probably be reused
-    // in generic command buffer submission method
-    pub fn execute_command_once<T>(&self, queue: vk::Queue, mut executor: T) -> Result<()>
+    // The queue must belong to the graphics queue family
+    pub fn execute_command_once<T>(&self, queue: vk::Queue, executor: T) -> Result<()>
     where
         T: FnMut(vk::CommandBuffer),
     {
-        // Allocate a command buffer using the command pool
-        let command_buffer = {
-            let allocation_info = vk::CommandBufferAllocateInfo::builder()
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_pool(self.pool)
-                .command_buffer_count(1)
-                .build();
+        self.execute_once_on(self.pool(), queue, executor)
+    }
 
-            unsafe {
-                self.context
-                    .logical_device()
-                    .logical_device()
-                    .allocate_command_buffers(&allocation_info)
-            }
+    fn execute_once_on<T>(&self, pool: vk::CommandPool, queue: vk::Queue, executor: T) -> Result<()>
+    where
+        T: FnMut(vk::CommandBuffer),
+    {
+        let command_buffer = self.record_once(pool, executor)?;
+
+        // Create a fence to ensure that the command buffer has finished executing
+        let fence = Fence::new(self.context.clone(), vk::FenceCreateFlags::empty())?;
+        self.submit_once(queue, command_buffer, None, None, fence.fence())?;
+
+        let logical_device = self.context.logical_device().logical_device();
+        unsafe {
+            logical_device.wait_for_fences(&[fence.fence()], true, Self::SUBMISSION_TIMEOUT)?;
+            logical_device.free_command_buffers(pool, &[command_buffer]);
+        };
+
+        Ok(())
+    }
+
+    // Allocates a command buffer from the pool and records it for a single submission
+    fn record_once<T>(&self, pool: vk::CommandPool, mut executor: T) -> Result<vk::CommandBuffer>
+    where
+        T: FnMut(vk::CommandBuffer),
+    {
+        let allocation_info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(pool)
+            .command_buffer_count(1)
+            .build();
+        let command_buffer = unsafe {
+            self.context
+                .logical_device()
+                .logical_device()
+                .allocate_command_buffers(&allocation_info)
         }?[0];
-        let command_buffers = [command_buffer];
 
         self.context.logical_device().record_command_buffer(
             command_buffer,
@@ -235,31 +514,40 @@ impl CommandPool {
                 executor(command_buffer);
             },
         );
+        Ok(command_buffer)
+    }
 
-        // Build the submission info
+    // Transfers are the only work submitted after waiting on a semaphore here
+    fn submit_once(
+        &self,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        wait: Option<&Semaphore>,
+        signal: Option<&Semaphore>,
+        fence: vk::Fence,
+    ) -> Result<()> {
+        let command_buffers = [command_buffer];
+        let wait_semaphores = wait
+            .iter()
+            .map(|semaphore| semaphore.semaphore())
+            .collect::<Vec<_>>();
+        let wait_stages = vec![vk::PipelineStageFlags::TRANSFER; wait_semaphores.len()];
+        let signal_semaphores = signal
+            .iter()
+            .map(|semaphore| semaphore.semaphore())
+            .collect::<Vec<_>>();
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(&command_buffers)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .signal_semaphores(&signal_semaphores)
             .build();
-        let submit_info_arr = [submit_info];
-
-        // Create a fence to ensure that the command buffer has finished executing
-        let fence = Fence::new(self.context.clone(), vk::FenceCreateFlags::empty())?;
-
-        let logical_device = self.context.logical_device().logical_device();
-
         unsafe {
-            // Submit the command buffer
-            logical_device.queue_submit(queue, &submit_info_arr, fence.fence())?;
-
-            logical_device.wait_for_fences(&[fence.fence()], true, 100_000_000_000)?;
-
-            // Wait for the command buffer to be executed
-            logical_device.queue_wait_idle(queue)?;
-
-            // Free the command buffer
-            logical_device.free_command_buffers(self.pool(), &command_buffers);
+            self.context
+                .logical_device()
+                .logical_device()
+                .queue_submit(queue, &[submit_info], fence)?
         };
-
         Ok(())
     }
 
@@ -293,11 +581,12 @@ impl CommandPool {
 impl Drop for CommandPool {
     fn drop(&mut self) {
         self.clear_command_buffers();
+        let logical_device = self.context.logical_device().logical_device();
         unsafe {
-            self.context
-                .logical_device()
-                .logical_device()
-                .destroy_command_pool(self.pool, None);
+            if let Some(transfer) = self.transfer.as_ref() {
+                logical_device.destroy_command_pool(transfer.pool, None);
+            }
+            logical_device.destroy_command_pool(self.pool, None);
         }
     }
 }
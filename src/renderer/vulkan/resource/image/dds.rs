@@ -0,0 +1,253 @@
+use crate::renderer::vulkan::resource::image::Ktx2Texture;
+use anyhow::{bail, ensure, Result};
+use ash::vk;
+use std::convert::TryInto;
+
+// A DDS texture with the mip chain stored in the file.
+// Both the legacy header and the DX10 header extension are read,
+// for block-compressed, 8-bit RGBA and floating point formats.
+pub struct DdsTexture {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    // One layer for 2D textures, six for cubemaps in the order +X, -X, +Y, -Y, +Z, -Z.
+    // Each layer holds its mip levels, largest first.
+    pub layers: Vec<Vec<Vec<u8>>>,
+}
+
+impl DdsTexture {
+    pub const MAGIC: [u8; 4] = *b"DDS ";
+
+    // The magic number followed by the header
+    const HEADER_SIZE: usize = 128;
+    const DX10_HEADER_SIZE: usize = 20;
+
+    const PIXEL_FORMAT_ALPHA_PIXELS: u32 = 0x1;
+    const PIXEL_FORMAT_FOURCC: u32 = 0x4;
+    const PIXEL_FORMAT_RGB: u32 = 0x40;
+
+    const CAPS2_CUBEMAP: u32 = 0x200;
+    const CAPS2_CUBEMAP_ALL_FACES: u32 = 0xFC00;
+    const CAPS2_VOLUME: u32 = 0x20_0000;
+
+    const DX10_MISC_TEXTURE_CUBE: u32 = 0x4;
+    const DX10_DIMENSION_TEXTURE_2D: u32 = 3;
+
+    pub fn is_dds(bytes: &[u8]) -> bool {
+        bytes.starts_with(&Self::MAGIC)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(Self::is_dds(bytes), "Failed to find the DDS magic number!");
+        ensure!(
+            bytes.len() >= Self::HEADER_SIZE,
+            "DDS file is too small to hold a header!"
+        );
+
+        let height = Self::read_u32(bytes, 12)?;
+        let width = Self::read_u32(bytes, 16)?;
+        let mip_levels = Self::read_u32(bytes, 28)?.max(1);
+        let pixel_format_flags = Self::read_u32(bytes, 80)?;
+        let four_cc = Self::read_u32(bytes, 84)?;
+        let caps2 = Self::read_u32(bytes, 112)?;
+
+        ensure!(width > 0 && height > 0, "DDS texture has no area!");
+        ensure!(
+            caps2 & Self::CAPS2_VOLUME == 0,
+            "Volume DDS textures are not supported!"
+        );
+
+        let mut data_offset = Self::HEADER_SIZE;
+        let mut opaque = false;
+        let (format, number_of_layers) = if pixel_format_flags & Self::PIXEL_FORMAT_FOURCC != 0
+            && four_cc == Self::four_cc(b"DX10")
+        {
+            ensure!(
+                bytes.len() >= Self::HEADER_SIZE + Self::DX10_HEADER_SIZE,
+                "DDS file is too small to hold a DX10 header!"
+            );
+            let dxgi_format = Self::read_u32(bytes, 128)?;
+            let dimension = Self::read_u32(bytes, 132)?;
+            let misc_flags = Self::read_u32(bytes, 136)?;
+            let array_size = Self::read_u32(bytes, 140)?.max(1);
+            data_offset += Self::DX10_HEADER_SIZE;
+
+            ensure!(
+                dimension == Self::DX10_DIMENSION_TEXTURE_2D && array_size == 1,
+                "Only 2D DDS textures and single cubemaps are supported!"
+            );
+            let format = match Self::dxgi_format(dxgi_format) {
+                Some(format) => format,
+                None => bail!("DXGI format {} is not supported!", dxgi_format),
+            };
+            let number_of_layers = if misc_flags & Self::DX10_MISC_TEXTURE_CUBE != 0 {
+                6
+            } else {
+                1
+            };
+            (format, number_of_layers)
+        } else {
+            let format = if pixel_format_flags & Self::PIXEL_FORMAT_FOURCC != 0 {
+                match Self::four_cc_format(four_cc) {
+                    Some(format) => format,
+                    None => bail!(
+                        "DDS four character code {:?} is not supported!",
+                        four_cc.to_le_bytes()
+                    ),
+                }
+            } else if pixel_format_flags & Self::PIXEL_FORMAT_RGB != 0 {
+                opaque = pixel_format_flags & Self::PIXEL_FORMAT_ALPHA_PIXELS == 0;
+                Self::rgb_format(bytes)?
+            } else {
+                bail!("DDS pixel format is not supported!");
+            };
+
+            let number_of_layers = if caps2 & Self::CAPS2_CUBEMAP != 0 {
+                ensure!(
+                    caps2 & Self::CAPS2_CUBEMAP_ALL_FACES == Self::CAPS2_CUBEMAP_ALL_FACES,
+                    "DDS cubemaps must hold all six faces!"
+                );
+                6
+            } else {
+                1
+            };
+            (format, number_of_layers)
+        };
+
+        if number_of_layers == 6 {
+            ensure!(width == height, "DDS cubemap faces must be square!");
+        }
+
+        let mut offset = data_offset;
+        let mut layers = Vec::new();
+        for _ in 0..number_of_layers {
+            let mut levels = Vec::new();
+            for level in 0..mip_levels {
+                let length =
+                    Self::level_size(format, (width >> level).max(1), (height >> level).max(1))?;
+                ensure!(
+                    offset + length <= bytes.len(),
+                    "DDS mip level {} is out of bounds!",
+                    level
+                );
+                let mut pixels = bytes[offset..offset + length].to_vec();
+                if opaque {
+                    pixels.iter_mut().skip(3).step_by(4).for_each(|alpha| {
+                        *alpha = u8::MAX;
+                    });
+                }
+                levels.push(pixels);
+                offset += length;
+            }
+            layers.push(levels);
+        }
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            layers,
+        })
+    }
+
+    pub fn is_cubemap(&self) -> bool {
+        self.layers.len() == 6
+    }
+
+    // The size in bytes of a single mip level of a single layer
+    pub fn level_size(format: vk::Format, width: u32, height: u32) -> Result<usize> {
+        if let Some(block_size) = Ktx2Texture::block_size(format) {
+            return Ok(width.div_ceil(4) as usize * height.div_ceil(4) as usize * block_size);
+        }
+
+        let texel_size = match format {
+            vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB => 4,
+            vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT => 8,
+            vk::Format::R32G32B32A32_SFLOAT => 16,
+            _ => bail!("Failed to find the texel size of DDS format {:?}!", format),
+        };
+        Ok(width as usize * height as usize * texel_size)
+    }
+
+    fn dxgi_format(dxgi_format: u32) -> Option<vk::Format> {
+        let format = match dxgi_format {
+            2 => vk::Format::R32G32B32A32_SFLOAT,
+            10 => vk::Format::R16G16B16A16_SFLOAT,
+            11 => vk::Format::R16G16B16A16_UNORM,
+            28 => vk::Format::R8G8B8A8_UNORM,
+            29 => vk::Format::R8G8B8A8_SRGB,
+            71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            74 => vk::Format::BC2_UNORM_BLOCK,
+            75 => vk::Format::BC2_SRGB_BLOCK,
+            77 => vk::Format::BC3_UNORM_BLOCK,
+            78 => vk::Format::BC3_SRGB_BLOCK,
+            80 => vk::Format::BC4_UNORM_BLOCK,
+            81 => vk::Format::BC4_SNORM_BLOCK,
+            83 => vk::Format::BC5_UNORM_BLOCK,
+            84 => vk::Format::BC5_SNORM_BLOCK,
+            87 => vk::Format::B8G8R8A8_UNORM,
+            91 => vk::Format::B8G8R8A8_SRGB,
+            95 => vk::Format::BC6H_UFLOAT_BLOCK,
+            96 => vk::Format::BC6H_SFLOAT_BLOCK,
+            98 => vk::Format::BC7_UNORM_BLOCK,
+            99 => vk::Format::BC7_SRGB_BLOCK,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    // Legacy files identify some formats with a D3DFORMAT value instead of characters
+    fn four_cc_format(four_cc: u32) -> Option<vk::Format> {
+        let format = match &four_cc.to_le_bytes() {
+            b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+            b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+            b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+            b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+            b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+            b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+            _ => match four_cc {
+                36 => vk::Format::R16G16B16A16_UNORM,
+                113 => vk::Format::R16G16B16A16_SFLOAT,
+                116 => vk::Format::R32G32B32A32_SFLOAT,
+                _ => return None,
+            },
+        };
+        Some(format)
+    }
+
+    // Only 32-bit pixels with a byte per channel are supported
+    fn rgb_format(bytes: &[u8]) -> Result<vk::Format> {
+        let bit_count = Self::read_u32(bytes, 88)?;
+        let red_mask = Self::read_u32(bytes, 92)?;
+        let green_mask = Self::read_u32(bytes, 96)?;
+        let blue_mask = Self::read_u32(bytes, 100)?;
+        let format = match (bit_count, red_mask, green_mask, blue_mask) {
+            (32, 0xFF, 0xFF00, 0xFF_0000) => vk::Format::R8G8B8A8_UNORM,
+            (32, 0xFF_0000, 0xFF00, 0xFF) => vk::Format::B8G8R8A8_UNORM,
+            _ => bail!(
+                "DDS pixel format with {} bits per pixel and masks {:#x}, {:#x}, {:#x} is not supported!",
+                bit_count,
+                red_mask,
+                green_mask,
+                blue_mask
+            ),
+        };
+        Ok(format)
+    }
+
+    fn four_cc(characters: &[u8; 4]) -> u32 {
+        u32::from_le_bytes(*characters)
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+        match bytes.get(offset..offset + 4) {
+            Some(slice) => Ok(u32::from_le_bytes(slice.try_into()?)),
+            None => bail!("DDS file ended unexpectedly!"),
+        }
+    }
+}
@@ -22,6 +22,8 @@ impl DummyImage {
             height: 1,
             pixels: Vec::new(),
             mip_levels: 1,
+            is_srgb: false,
+            mip_sizes: Vec::new(),
         };
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
@@ -80,7 +82,7 @@ impl DummyImage {
             .address_mode_v(vk::SamplerAddressMode::REPEAT)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
             .anisotropy_enable(true)
-            .max_anisotropy(16.0)
+            .max_anisotropy(context.max_anisotropy())
             .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
             .unnormalized_coordinates(false)
             .compare_enable(false)
@@ -0,0 +1,128 @@
+use anyhow::{bail, ensure, Result};
+use ash::vk;
+use std::convert::TryInto;
+
+// A KTX2 texture holding block-compressed data with a precomputed mip chain.
+// Basis Universal payloads need to be transcoded before they can be uploaded,
+// and no transcoder is available, so only natively block-compressed formats are accepted.
+pub struct Ktx2Texture {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    // Largest mip level first
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Texture {
+    pub const IDENTIFIER: [u8; 12] = [
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+    ];
+
+    const HEADER_SIZE: usize = 80;
+    const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+    pub fn is_ktx2(bytes: &[u8]) -> bool {
+        bytes.starts_with(&Self::IDENTIFIER)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(Self::is_ktx2(bytes), "Failed to find the KTX2 identifier!");
+        ensure!(
+            bytes.len() >= Self::HEADER_SIZE,
+            "KTX2 file is too small to hold a header!"
+        );
+
+        let vk_format = Self::read_u32(bytes, 12)?;
+        let width = Self::read_u32(bytes, 20)?;
+        let height = Self::read_u32(bytes, 24)?;
+        let depth = Self::read_u32(bytes, 28)?;
+        let layer_count = Self::read_u32(bytes, 32)?;
+        let face_count = Self::read_u32(bytes, 36)?;
+        let level_count = Self::read_u32(bytes, 40)?.max(1);
+        let supercompression_scheme = Self::read_u32(bytes, 44)?;
+
+        if vk_format == 0 || supercompression_scheme != 0 {
+            bail!("Basis Universal and supercompressed KTX2 textures can't be transcoded!");
+        }
+        ensure!(
+            depth == 0 && layer_count <= 1 && face_count == 1,
+            "Only 2D KTX2 textures are supported!"
+        );
+
+        let format = vk::Format::from_raw(vk_format as i32);
+        let block_size = match Self::block_size(format) {
+            Some(block_size) => block_size,
+            None => bail!("KTX2 format {:?} is not block-compressed!", format),
+        };
+
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let entry = Self::HEADER_SIZE + level as usize * Self::LEVEL_INDEX_ENTRY_SIZE;
+            let offset = Self::read_u64(bytes, entry)? as usize;
+            let length = Self::read_u64(bytes, entry + 8)? as usize;
+
+            let level_width = (width >> level).max(1);
+            let level_height = (height >> level).max(1);
+            let expected_length =
+                level_width.div_ceil(4) as usize * level_height.div_ceil(4) as usize * block_size;
+            ensure!(
+                length == expected_length,
+                "KTX2 mip level {} holds {} bytes instead of {}!",
+                level,
+                length,
+                expected_length
+            );
+            ensure!(
+                offset + length <= bytes.len(),
+                "KTX2 mip level {} is out of bounds!",
+                level
+            );
+
+            levels.push(bytes[offset..offset + length].to_vec());
+        }
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    // Size in bytes of each 4x4 block
+    pub fn block_size(format: vk::Format) -> Option<usize> {
+        match format {
+            vk::Format::BC1_RGB_UNORM_BLOCK
+            | vk::Format::BC1_RGB_SRGB_BLOCK
+            | vk::Format::BC1_RGBA_UNORM_BLOCK
+            | vk::Format::BC1_RGBA_SRGB_BLOCK
+            | vk::Format::BC4_UNORM_BLOCK
+            | vk::Format::BC4_SNORM_BLOCK => Some(8),
+            vk::Format::BC2_UNORM_BLOCK
+            | vk::Format::BC2_SRGB_BLOCK
+            | vk::Format::BC3_UNORM_BLOCK
+            | vk::Format::BC3_SRGB_BLOCK
+            | vk::Format::BC5_UNORM_BLOCK
+            | vk::Format::BC5_SNORM_BLOCK
+            | vk::Format::BC6H_UFLOAT_BLOCK
+            | vk::Format::BC6H_SFLOAT_BLOCK
+            | vk::Format::BC7_UNORM_BLOCK
+            | vk::Format::BC7_SRGB_BLOCK => Some(16),
+            _ => None,
+        }
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+        match bytes.get(offset..offset + 4) {
+            Some(slice) => Ok(u32::from_le_bytes(slice.try_into()?)),
+            None => bail!("KTX2 file ended unexpectedly!"),
+        }
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+        match bytes.get(offset..offset + 8) {
+            Some(slice) => Ok(u64::from_le_bytes(slice.try_into()?)),
+            None => bail!("KTX2 file ended unexpectedly!"),
+        }
+    }
+}
@@ -1,6 +1,8 @@
-pub use self::{dummy::*, image_view::*, sampler::*, texture::*};
+pub use self::{dds::*, dummy::*, image_view::*, ktx2::*, sampler::*, texture::*};
 
+pub mod dds;
 pub mod dummy;
 pub mod image_view;
+pub mod ktx2;
 pub mod sampler;
 pub mod texture;
@@ -1,7 +1,86 @@
 use crate::renderer::vulkan::core::VulkanContext;
 use anyhow::Result;
 use ash::{version::DeviceV1_0, vk};
-use std::sync::Arc;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerSettings {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    // When disabled only the base mip level is sampled
+    pub mipmapping: bool,
+    // Added to the mip level the sampler picks, negative values sharpen
+    pub mip_lod_bias: f32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            mipmapping: true,
+            mip_lod_bias: 0.0,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        let mut settings = Self::default();
+
+        if let Some(mag_filter) = sampler.mag_filter() {
+            settings.mag_filter = match mag_filter {
+                MagFilter::Nearest => vk::Filter::NEAREST,
+                MagFilter::Linear => vk::Filter::LINEAR,
+            };
+        }
+
+        if let Some(min_filter) = sampler.min_filter() {
+            let (filter, mipmap_mode, mipmapping) = match min_filter {
+                MinFilter::Nearest => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, false),
+                MinFilter::Linear => (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST, false),
+                MinFilter::NearestMipmapNearest => {
+                    (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, true)
+                }
+                MinFilter::LinearMipmapNearest => {
+                    (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST, true)
+                }
+                MinFilter::NearestMipmapLinear => {
+                    (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR, true)
+                }
+                MinFilter::LinearMipmapLinear => {
+                    (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR, true)
+                }
+            };
+            settings.min_filter = filter;
+            settings.mipmap_mode = mipmap_mode;
+            settings.mipmapping = mipmapping;
+        }
+
+        settings.address_mode_u = Self::map_wrapping_mode(sampler.wrap_s());
+        settings.address_mode_v = Self::map_wrapping_mode(sampler.wrap_t());
+
+        settings
+    }
+
+    fn map_wrapping_mode(wrapping_mode: WrappingMode) -> vk::SamplerAddressMode {
+        match wrapping_mode {
+            WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
 
 pub struct Sampler {
     sampler: vk::Sampler,
@@ -10,6 +89,10 @@ pub struct Sampler {
 
 impl Sampler {
     pub fn new(context: Arc<VulkanContext>, create_info: vk::SamplerCreateInfo) -> Result<Self> {
+        let mut create_info = create_info;
+        context.clamp_anisotropy(&mut create_info);
+        context.clamp_mip_lod_bias(&mut create_info);
+
         let sampler = unsafe {
             context
                 .logical_device()
@@ -37,3 +120,77 @@ impl Drop for Sampler {
         };
     }
 }
+
+// The create info parameters that distinguish one sampler from another.
+// Floats are stored as bits so the key can be hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    mag_filter: vk::Filter,
+    min_filter: vk::Filter,
+    mipmap_mode: vk::SamplerMipmapMode,
+    address_mode_u: vk::SamplerAddressMode,
+    address_mode_v: vk::SamplerAddressMode,
+    address_mode_w: vk::SamplerAddressMode,
+    mip_lod_bias: u32,
+    anisotropy_enable: vk::Bool32,
+    max_anisotropy: u32,
+    compare_enable: vk::Bool32,
+    compare_op: vk::CompareOp,
+    min_lod: u32,
+    max_lod: u32,
+    border_color: vk::BorderColor,
+    unnormalized_coordinates: vk::Bool32,
+}
+
+impl From<&vk::SamplerCreateInfo> for SamplerKey {
+    fn from(create_info: &vk::SamplerCreateInfo) -> Self {
+        Self {
+            mag_filter: create_info.mag_filter,
+            min_filter: create_info.min_filter,
+            mipmap_mode: create_info.mipmap_mode,
+            address_mode_u: create_info.address_mode_u,
+            address_mode_v: create_info.address_mode_v,
+            address_mode_w: create_info.address_mode_w,
+            mip_lod_bias: create_info.mip_lod_bias.to_bits(),
+            anisotropy_enable: create_info.anisotropy_enable,
+            max_anisotropy: create_info.max_anisotropy.to_bits(),
+            compare_enable: create_info.compare_enable,
+            compare_op: create_info.compare_op,
+            min_lod: create_info.min_lod.to_bits(),
+            max_lod: create_info.max_lod.to_bits(),
+            border_color: create_info.border_color,
+            unnormalized_coordinates: create_info.unnormalized_coordinates,
+        }
+    }
+}
+
+// Shares samplers between textures that are sampled the same way.
+// Only weak references are kept, so a sampler is destroyed
+// once the last texture using it is dropped.
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerKey, Weak<Sampler>>>,
+}
+
+impl SamplerCache {
+    pub fn sampler(
+        &self,
+        context: Arc<VulkanContext>,
+        create_info: vk::SamplerCreateInfo,
+    ) -> Result<Arc<Sampler>> {
+        let key = SamplerKey::from(&create_info);
+        let mut samplers = self
+            .samplers
+            .lock()
+            .expect("Failed to lock the sampler cache!");
+
+        if let Some(sampler) = samplers.get(&key).and_then(Weak::upgrade) {
+            return Ok(sampler);
+        }
+
+        let sampler = Arc::new(Sampler::new(context, create_info)?);
+        samplers.retain(|_, sampler| sampler.strong_count() > 0);
+        samplers.insert(key, Arc::downgrade(&sampler));
+        Ok(sampler)
+    }
+}
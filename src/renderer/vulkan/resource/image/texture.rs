@@ -1,14 +1,14 @@
 use crate::renderer::vulkan::{
     core::VulkanContext,
     resource::{
-        image::{ImageView, Sampler},
+        image::{DdsTexture, ImageView, Ktx2Texture, Sampler, SamplerSettings},
         Buffer, CommandPool,
     },
 };
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use ash::{version::DeviceV1_0, vk};
 use gltf::image::Format;
-use image::{DynamicImage, ImageBuffer, Pixel, RgbImage};
+use image::DynamicImage;
 use std::{iter, sync::Arc};
 
 pub struct ImageLayoutTransition {
@@ -25,7 +25,13 @@ pub struct TextureDescription {
     pub width: u32,
     pub height: u32,
     pub pixels: Vec<u8>,
+    // Mipmaps are generated on upload when there is more than one mip level
     pub mip_levels: u32,
+    // Color data is stored in sRGB and decoded to linear when sampled
+    pub is_srgb: bool,
+    // Byte size of each mip level stored in the pixels, largest first.
+    // When empty, only the base level is stored and the mips are generated on upload.
+    pub mip_sizes: Vec<usize>,
 }
 
 impl TextureDescription {
@@ -36,6 +42,8 @@ impl TextureDescription {
             height,
             pixels: Vec::new(),
             mip_levels: Self::calculate_mip_levels(width, height),
+            is_srgb: false,
+            mip_sizes: Vec::new(),
         }
     }
 
@@ -65,12 +73,19 @@ impl TextureDescription {
             height,
             pixels,
             mip_levels,
+            is_srgb: false,
+            mip_sizes: Vec::new(),
         };
 
         Ok(description)
     }
 
+    // DDS files are read directly, since the image crate can't decode most of their formats
     pub fn from_file(path: &str) -> Result<Self> {
+        if path.to_lowercase().ends_with(".dds") {
+            let bytes = std::fs::read(path).with_context(|| format!("path: {}", path))?;
+            return Self::from_dds(&bytes, false).with_context(|| format!("path: {}", path));
+        }
         let image = image::open(path).with_context(|| format!("path: {}", path.to_string()))?;
         Self::from_image(&image)
     }
@@ -94,12 +109,14 @@ impl TextureDescription {
             height,
             pixels: image.to_bytes(),
             mip_levels: Self::calculate_mip_levels(width, height),
+            is_srgb: false,
+            mip_sizes: Vec::new(),
         };
-        description.convert_24bit_formats()?;
+        description.convert_rgb_formats()?;
         Ok(description)
     }
 
-    pub fn from_gltf(data: &gltf::image::Data) -> Result<Self> {
+    pub fn from_gltf(data: &gltf::image::Data, is_srgb: bool) -> Result<Self> {
         let format = Self::convert_to_vulkan_format(data.format);
         let mut description = Self {
             format,
@@ -107,26 +124,150 @@ impl TextureDescription {
             height: data.height,
             pixels: data.pixels.to_vec(),
             mip_levels: Self::calculate_mip_levels(data.width, data.height),
+            is_srgb: false,
+            mip_sizes: Vec::new(),
+        };
+        description.convert_rgb_formats()?;
+        if is_srgb {
+            description.convert_to_srgb();
+        }
+        Ok(description)
+    }
+
+    // Block-compressed textures keep the mip chain stored in the file
+    pub fn from_ktx2(bytes: &[u8], is_srgb: bool) -> Result<Self> {
+        let ktx2 = Ktx2Texture::from_bytes(bytes)?;
+        let mip_sizes = ktx2.levels.iter().map(Vec::len).collect::<Vec<_>>();
+        let mut description = Self {
+            format: ktx2.format,
+            width: ktx2.width,
+            height: ktx2.height,
+            pixels: ktx2.levels.concat(),
+            mip_levels: mip_sizes.len() as u32,
+            is_srgb: false,
+            mip_sizes,
         };
-        description.convert_24bit_formats()?;
+        if is_srgb {
+            description.convert_to_srgb();
+        }
         Ok(description)
     }
 
+    pub fn from_dds(bytes: &[u8], is_srgb: bool) -> Result<Self> {
+        let dds = DdsTexture::from_bytes(bytes)?;
+        ensure!(
+            !dds.is_cubemap(),
+            "Failed to load a DDS cubemap as a 2D texture!"
+        );
+        let levels = dds.layers.into_iter().next().unwrap_or_default();
+        Ok(Self::from_dds_levels(
+            dds.format, dds.width, dds.height, levels, is_srgb,
+        ))
+    }
+
+    // One description per face, in the order the cubemap layers are stored
+    pub fn from_dds_cubemap(bytes: &[u8]) -> Result<Vec<Self>> {
+        let dds = DdsTexture::from_bytes(bytes)?;
+        ensure!(dds.is_cubemap(), "DDS file doesn't hold a cubemap!");
+        let (format, width, height) = (dds.format, dds.width, dds.height);
+        Ok(dds
+            .layers
+            .into_iter()
+            .map(|levels| Self::from_dds_levels(format, width, height, levels, false))
+            .collect())
+    }
+
+    // The stored mip chain is uploaded as it is, unless there is only the base level
+    // of an uncompressed format, in which case the mips are generated on upload
+    fn from_dds_levels(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+        is_srgb: bool,
+    ) -> Self {
+        let generate_mips = levels.len() == 1 && Ktx2Texture::block_size(format).is_none();
+        let (mip_levels, mip_sizes) = if generate_mips {
+            (Self::calculate_mip_levels(width, height), Vec::new())
+        } else {
+            (levels.len() as u32, levels.iter().map(Vec::len).collect())
+        };
+        let mut description = Self {
+            format,
+            width,
+            height,
+            pixels: levels.concat(),
+            mip_levels,
+            is_srgb: Self::is_srgb_format(format),
+            mip_sizes,
+        };
+        if is_srgb {
+            description.convert_to_srgb();
+        }
+        description
+    }
+
+    fn is_srgb_format(format: vk::Format) -> bool {
+        matches!(
+            format,
+            vk::Format::R8G8B8A8_SRGB
+                | vk::Format::B8G8R8A8_SRGB
+                | vk::Format::BC1_RGBA_SRGB_BLOCK
+                | vk::Format::BC2_SRGB_BLOCK
+                | vk::Format::BC3_SRGB_BLOCK
+                | vk::Format::BC7_SRGB_BLOCK
+        )
+    }
+
+    // Only the base level is created and uploaded
+    pub fn without_mipmaps(mut self) -> Self {
+        if !self.has_precomputed_mips() {
+            self.mip_levels = 1;
+        }
+        self
+    }
+
+    pub fn has_precomputed_mips(&self) -> bool {
+        !self.mip_sizes.is_empty()
+    }
+
+    // 16-bit formats have no sRGB equivalent, so they are left as they are
+    fn convert_to_srgb(&mut self) {
+        let srgb_format = match self.format {
+            vk::Format::R8_UNORM => vk::Format::R8_SRGB,
+            vk::Format::R8G8_UNORM => vk::Format::R8G8_SRGB,
+            vk::Format::R8G8B8A8_UNORM => vk::Format::R8G8B8A8_SRGB,
+            vk::Format::B8G8R8A8_UNORM => vk::Format::B8G8R8A8_SRGB,
+            vk::Format::BC1_RGB_UNORM_BLOCK => vk::Format::BC1_RGB_SRGB_BLOCK,
+            vk::Format::BC1_RGBA_UNORM_BLOCK => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            vk::Format::BC2_UNORM_BLOCK => vk::Format::BC2_SRGB_BLOCK,
+            vk::Format::BC3_UNORM_BLOCK => vk::Format::BC3_SRGB_BLOCK,
+            vk::Format::BC7_UNORM_BLOCK => vk::Format::BC7_SRGB_BLOCK,
+            _ => return,
+        };
+        self.format = srgb_format;
+        self.is_srgb = true;
+    }
+
     pub fn calculate_mip_levels(width: u32, height: u32) -> u32 {
         ((width.min(height) as f32).log2().floor() + 1.0) as u32
     }
 
-    fn convert_24bit_formats(&mut self) -> Result<()> {
-        // 24-bit formats are unsupported, so they
-        // need to have an alpha channel added to make them 32-bit
+    fn convert_rgb_formats(&mut self) -> Result<()> {
+        // Three channel formats are unsupported, so they
+        // need to have an alpha channel added to make them four channel
         match self.format {
             vk::Format::R8G8B8_UNORM => {
                 self.format = vk::Format::R8G8B8A8_UNORM;
-                self.attach_alpha_channel()?;
+                self.attach_alpha_channel(1)?;
             }
             vk::Format::B8G8R8_UNORM => {
                 self.format = vk::Format::B8G8R8A8_UNORM;
-                self.attach_alpha_channel()?;
+                self.attach_alpha_channel(1)?;
+            }
+            vk::Format::R16G16B16_UNORM => {
+                self.format = vk::Format::R16G16B16A16_UNORM;
+                self.attach_alpha_channel(2)?;
             }
             _ => {}
         };
@@ -134,14 +275,27 @@ impl TextureDescription {
         Ok(())
     }
 
-    fn attach_alpha_channel(&mut self) -> Result<()> {
-        let image_buffer: RgbImage =
-            ImageBuffer::from_raw(self.width, self.height, self.pixels.to_vec())
-                .expect("Failed to load image rom raw pixels!");
+    // Appends an opaque alpha channel to tightly packed three channel pixels
+    fn attach_alpha_channel(&mut self, channel_size: usize) -> Result<()> {
+        let pixel_size = 3 * channel_size;
+        let expected_size = self.width as usize * self.height as usize * pixel_size;
+        if self.pixels.len() != expected_size {
+            bail!(
+                "Expected {} bytes of pixel data for a {}x{} image but found {}!",
+                expected_size,
+                self.width,
+                self.height,
+                self.pixels.len()
+            );
+        }
 
-        self.pixels = image_buffer
-            .pixels()
-            .flat_map(|pixel| pixel.to_rgba().channels().to_vec())
+        // The maximum value of an unsigned normalized channel has every bit set,
+        // which is the same in either byte order
+        let opaque = vec![u8::MAX; channel_size];
+        self.pixels = self
+            .pixels
+            .chunks_exact(pixel_size)
+            .flat_map(|pixel| pixel.iter().chain(opaque.iter()).copied())
             .collect::<Vec<_>>();
 
         Ok(())
@@ -197,6 +351,10 @@ impl Texture {
         command_pool: &CommandPool,
         description: &TextureDescription,
     ) -> Result<()> {
+        if description.has_precomputed_mips() {
+            return self.upload_mip_chain(command_pool, description);
+        }
+
         let region = vk::BufferImageCopy::builder()
             .buffer_offset(0)
             .buffer_row_length(0)
@@ -233,31 +391,107 @@ impl Texture {
             src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
             dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
         };
-        self.transition(&command_pool, &transition, description.mip_levels)?;
+        self.transition(command_pool, &transition, description.mip_levels)?;
 
         command_pool
             .copy_buffer_to_image(buffer.buffer(), self.image(), &regions)
             .unwrap();
 
-        self.generate_mipmaps(&command_pool, &description)?;
+        self.generate_mipmaps(command_pool, &description)?;
 
         Ok(())
     }
 
+    // Copies every mip level from the description instead of generating them
+    fn upload_mip_chain(
+        &self,
+        command_pool: &CommandPool,
+        description: &TextureDescription,
+    ) -> Result<()> {
+        let mut buffer_offset = 0;
+        let regions = description
+            .mip_sizes
+            .iter()
+            .enumerate()
+            .map(|(level, size)| {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(buffer_offset as _)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as _,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width: (description.width >> level).max(1),
+                        height: (description.height >> level).max(1),
+                        depth: 1,
+                    })
+                    .build();
+                buffer_offset += size;
+                region
+            })
+            .collect::<Vec<_>>();
+
+        let buffer = Buffer::new_mapped_basic(
+            self.context.clone(),
+            description.pixels.len() as _,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )?;
+        buffer.upload_to_buffer(&description.pixels, 0)?;
+
+        let transition = ImageLayoutTransition {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
+            dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+        };
+        self.transition(command_pool, &transition, description.mip_levels)?;
+
+        command_pool.copy_buffer_to_image(buffer.buffer(), self.image(), &regions)?;
+
+        let transition = ImageLayoutTransition {
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            src_stage_mask: vk::PipelineStageFlags::TRANSFER,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        };
+        self.transition(command_pool, &transition, description.mip_levels)
+    }
+
     pub fn generate_mipmaps(
         &self,
         command_pool: &CommandPool,
         texture_description: &TextureDescription,
+    ) -> Result<()> {
+        self.generate_layered_mipmaps(command_pool, texture_description, 1)
+    }
+
+    pub fn generate_layered_mipmaps(
+        &self,
+        command_pool: &CommandPool,
+        texture_description: &TextureDescription,
+        layer_count: u32,
     ) -> Result<()> {
         let format_properties = self
             .context
             .physical_device_format_properties(texture_description.format);
 
-        if !format_properties
-            .optimal_tiling_features
-            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        // A single mip level only needs to be transitioned
+        if texture_description.mip_levels > 1
+            && !format_properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
         {
-            panic!(
+            bail!(
                 "Linear blitting is not supported for format: {:?}",
                 texture_description.format
             );
@@ -285,7 +519,7 @@ impl Texture {
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count,
                     level_count: 1,
                     base_mip_level: level - 1,
                 })
@@ -315,7 +549,7 @@ impl Texture {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: level - 1,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count,
                 })
                 .dst_offsets([
                     vk::Offset3D { x: 0, y: 0, z: 0 },
@@ -329,7 +563,7 @@ impl Texture {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: level,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count,
                 })
                 .build();
             let blits = [blit];
@@ -359,7 +593,7 @@ impl Texture {
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count,
                     level_count: 1,
                     base_mip_level: level - 1,
                 })
@@ -387,7 +621,7 @@ impl Texture {
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count,
                 level_count: 1,
                 base_mip_level: texture_description.mip_levels - 1,
             })
@@ -472,6 +706,8 @@ pub struct CubemapFaces {
 }
 
 impl CubemapFaces {
+    // Vulkan expects cubemap layers in the order +X, -X, +Y, -Y, +Z, -Z.
+    // The viewer faces -Z, so the back face is +Z and comes before the front face.
     pub fn ordered_faces(&self) -> impl Iterator<Item = String> {
         iter::once(self.right.to_string())
             .chain(iter::once(self.left.to_string()))
@@ -491,17 +727,28 @@ impl CubemapFaces {
 pub struct Cubemap {
     pub texture: Texture,
     pub view: ImageView,
-    pub sampler: Sampler,
+    pub sampler: Arc<Sampler>,
     pub description: TextureDescription,
     context: Arc<VulkanContext>,
 }
 
 impl Cubemap {
     pub fn new(context: Arc<VulkanContext>, dimension: u32, format: vk::Format) -> Result<Self> {
-        let description = TextureDescription::empty(dimension, dimension, format);
+        let mip_levels = TextureDescription::calculate_mip_levels(dimension, dimension);
+        Self::with_mip_levels(context, dimension, format, mip_levels)
+    }
+
+    pub fn with_mip_levels(
+        context: Arc<VulkanContext>,
+        dimension: u32,
+        format: vk::Format,
+        mip_levels: u32,
+    ) -> Result<Self> {
+        let mut description = TextureDescription::empty(dimension, dimension, format);
+        description.mip_levels = mip_levels;
         let texture = Self::create_texture(context.clone(), &description)?;
         let view = Self::create_view(context.clone(), &texture, &description)?;
-        let sampler = Self::create_sampler(context.clone(), &description)?;
+        let sampler = Self::create_sampler(context.clone(), &description, 0.0)?;
 
         let cubemap = Self {
             texture,
@@ -514,6 +761,7 @@ impl Cubemap {
         Ok(cubemap)
     }
 
+    // Faces with precomputed mips have every level copied, otherwise the mips are generated
     pub fn upload_texture_data(
         &self,
         command_pool: &CommandPool,
@@ -541,7 +789,26 @@ impl Cubemap {
             src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
             dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
         };
-        self.transition(&command_pool, &transition)?;
+        self.transition(command_pool, &transition)?;
+
+        if descriptions
+            .iter()
+            .all(|description| description.has_precomputed_mips())
+        {
+            let regions = self.precomputed_mip_regions(descriptions);
+            command_pool.copy_buffer_to_image(buffer.buffer(), self.texture.image(), &regions)?;
+            return self.transition(
+                command_pool,
+                &ImageLayoutTransition {
+                    old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    src_stage_mask: vk::PipelineStageFlags::TRANSFER,
+                    dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                },
+            );
+        }
 
         let mut offset = 0;
         let regions = descriptions
@@ -572,19 +839,182 @@ impl Cubemap {
 
         command_pool.copy_buffer_to_image(buffer.buffer(), self.texture.image(), &regions)?;
 
-        let transition = ImageLayoutTransition {
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::SHADER_READ,
-            src_stage_mask: vk::PipelineStageFlags::TRANSFER,
-            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
-        };
-        self.transition(&command_pool, &transition)?;
+        // Mipmap generation leaves every level in the shader read only layout
+        self.texture
+            .generate_layered_mipmaps(command_pool, &self.description, 6)?;
+
+        Ok(())
+    }
+
+    // One region per mip level of each face, with the faces stored one after another
+    fn precomputed_mip_regions(
+        &self,
+        descriptions: &[TextureDescription],
+    ) -> Vec<vk::BufferImageCopy> {
+        let mut offset = 0;
+        let mut regions = Vec::new();
+        for (face_index, face) in descriptions.iter().enumerate() {
+            for (mip_level, size) in face.mip_sizes.iter().enumerate() {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(offset as _)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: mip_level as _,
+                        base_array_layer: face_index as _,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width: (face.width >> mip_level).max(1),
+                        height: (face.height >> mip_level).max(1),
+                        depth: 1,
+                    })
+                    .build();
+                regions.push(region);
+                offset += size;
+            }
+        }
+        regions
+    }
+
+    // Reads back every mip level of every face, ordered by mip level and then by face.
+    // The cubemap is expected to be in the shader read only layout and is left in it.
+    pub fn download(&self, command_pool: &CommandPool) -> Result<Vec<u8>> {
+        let (regions, size) = self.mip_chain_regions()?;
+
+        let buffer = Buffer::new_mapped_basic(
+            self.context.clone(),
+            size as _,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuToCpu,
+        )?;
+
+        self.transition(
+            command_pool,
+            &ImageLayoutTransition {
+                old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                src_access_mask: vk::AccessFlags::SHADER_READ,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+            },
+        )?;
+
+        command_pool.copy_image_to_buffer(self.texture.image(), buffer.buffer(), &regions)?;
+
+        self.transition(
+            command_pool,
+            &ImageLayoutTransition {
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                src_access_mask: vk::AccessFlags::TRANSFER_READ,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                src_stage_mask: vk::PipelineStageFlags::TRANSFER,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            },
+        )?;
+
+        buffer.invalidate(0, size)?;
+        let data_pointer = buffer.map_memory()?;
+        let pixels = unsafe { std::slice::from_raw_parts(data_pointer, size) }.to_vec();
+        buffer.unmap_memory()?;
+
+        Ok(pixels)
+    }
+
+    // Uploads pixels laid out the way `download` returns them,
+    // so no mipmaps are generated
+    pub fn upload_mip_chain(&self, command_pool: &CommandPool, pixels: &[u8]) -> Result<()> {
+        let (regions, size) = self.mip_chain_regions()?;
+        ensure!(
+            pixels.len() == size,
+            "Failed to upload {} bytes to a cubemap mip chain of {} bytes!",
+            pixels.len(),
+            size
+        );
+
+        let buffer = Buffer::new_mapped_basic(
+            self.context.clone(),
+            size as _,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )?;
+        buffer.upload_to_buffer(pixels, 0)?;
+
+        self.transition(
+            command_pool,
+            &ImageLayoutTransition {
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
+                dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+            },
+        )?;
+
+        command_pool.copy_buffer_to_image(buffer.buffer(), self.texture.image(), &regions)?;
+
+        self.transition(
+            command_pool,
+            &ImageLayoutTransition {
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                src_stage_mask: vk::PipelineStageFlags::TRANSFER,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            },
+        )?;
 
         Ok(())
     }
 
+    // One region per mip level, covering all six faces, along with the total size in bytes
+    fn mip_chain_regions(&self) -> Result<(Vec<vk::BufferImageCopy>, usize)> {
+        let texel_size = match self.description.format {
+            vk::Format::R32G32B32A32_SFLOAT => 16,
+            vk::Format::R16G16B16A16_SFLOAT => 8,
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => 4,
+            format => bail!(
+                "Failed to find the texel size of cubemap format {:?}!",
+                format
+            ),
+        };
+
+        let mut offset = 0;
+        let regions = (0..self.description.mip_levels)
+            .map(|mip_level| {
+                let width = (self.description.width >> mip_level).max(1);
+                let height = (self.description.height >> mip_level).max(1);
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(offset as _)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level,
+                        base_array_layer: 0,
+                        layer_count: 6,
+                    })
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    })
+                    .build();
+                offset += (width * height * texel_size * 6) as usize;
+                region
+            })
+            .collect::<Vec<_>>();
+
+        Ok((regions, offset))
+    }
+
     fn create_texture(
         context: Arc<VulkanContext>,
         description: &TextureDescription,
@@ -646,10 +1076,18 @@ impl Cubemap {
         Ok(image_view)
     }
 
+    // Negative biases sharpen the cubemap and positive biases soften it
+    pub fn set_mip_lod_bias(&mut self, mip_lod_bias: f32) -> Result<()> {
+        self.sampler = Self::create_sampler(self.context.clone(), &self.description, mip_lod_bias)?;
+        Ok(())
+    }
+
     fn create_sampler(
         context: Arc<VulkanContext>,
         description: &TextureDescription,
-    ) -> Result<Sampler> {
+        mip_lod_bias: f32,
+    ) -> Result<Arc<Sampler>> {
+        // Vulkan always filters cube views across face edges, so the address modes don't apply
         let sampler_info = vk::SamplerCreateInfo::builder()
             .mag_filter(vk::Filter::LINEAR)
             .min_filter(vk::Filter::LINEAR)
@@ -657,18 +1095,19 @@ impl Cubemap {
             .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
             .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
             .anisotropy_enable(true)
-            .max_anisotropy(16.0)
+            .max_anisotropy(context.max_anisotropy())
             .border_color(vk::BorderColor::INT_OPAQUE_WHITE)
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::ALWAYS)
             .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .mip_lod_bias(0.0)
+            .mip_lod_bias(mip_lod_bias)
             .min_lod(0.0)
             .max_lod(description.mip_levels as _)
             .build();
-        let sampler = Sampler::new(context, sampler_info)?;
-        Ok(sampler)
+        context
+            .sampler_cache()
+            .sampler(context.clone(), sampler_info)
     }
 
     // TODO: Merge this with the texture transition
@@ -708,7 +1147,8 @@ impl Cubemap {
 pub struct TextureBundle {
     pub texture: Texture,
     pub view: ImageView,
-    pub sampler: Sampler,
+    // Shared with every other texture sampled the same way
+    pub sampler: Arc<Sampler>,
 }
 
 impl TextureBundle {
@@ -716,14 +1156,15 @@ impl TextureBundle {
         context: Arc<VulkanContext>,
         command_pool: &CommandPool,
         description: &TextureDescription,
+        sampler_settings: &SamplerSettings,
     ) -> Result<Self> {
         let texture = Self::create_texture(context.clone(), &description)?;
 
-        texture.upload_texture_data(&command_pool, &description)?;
+        texture.upload_texture_data(command_pool, &description)?;
 
         let view = Self::create_image_view(context.clone(), &texture, &description)?;
 
-        let sampler = Self::create_sampler(context, description.mip_levels)?;
+        let sampler = Self::create_sampler(context, description.mip_levels, sampler_settings)?;
 
         let texture_bundle = Self {
             texture,
@@ -794,24 +1235,105 @@ impl TextureBundle {
         ImageView::new(context, create_info)
     }
 
-    fn create_sampler(context: Arc<VulkanContext>, mip_levels: u32) -> Result<Sampler> {
+    fn create_sampler(
+        context: Arc<VulkanContext>,
+        mip_levels: u32,
+        settings: &SamplerSettings,
+    ) -> Result<Arc<Sampler>> {
+        let max_lod = if settings.mipmapping {
+            mip_levels as f32
+        } else {
+            0.0
+        };
+
         let sampler_info = vk::SamplerCreateInfo::builder()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .mag_filter(settings.mag_filter)
+            .min_filter(settings.min_filter)
+            .address_mode_u(settings.address_mode_u)
+            .address_mode_v(settings.address_mode_v)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
             .anisotropy_enable(true)
-            .max_anisotropy(16.0)
+            .max_anisotropy(context.max_anisotropy())
             .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .mip_lod_bias(0.0)
+            .mipmap_mode(settings.mipmap_mode)
+            .mip_lod_bias(settings.mip_lod_bias)
             .min_lod(0.0)
-            .max_lod(mip_levels as _)
+            .max_lod(max_lod)
             .build();
-        Sampler::new(context, sampler_info)
+        context
+            .sampler_cache()
+            .sampler(context.clone(), sampler_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    ) -> TextureDescription {
+        TextureDescription {
+            pixels,
+            ..TextureDescription::empty(width, height, format)
+        }
+    }
+
+    #[test]
+    fn rgb8_gains_an_opaque_alpha_channel() {
+        let mut description = description(vk::Format::R8G8B8_UNORM, 2, 1, vec![1, 2, 3, 4, 5, 6]);
+        description.convert_rgb_formats().unwrap();
+        assert_eq!(description.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(description.pixels, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn bgr8_keeps_its_channel_order() {
+        let mut description = description(vk::Format::B8G8R8_UNORM, 1, 1, vec![30, 20, 10]);
+        description.convert_rgb_formats().unwrap();
+        assert_eq!(description.format, vk::Format::B8G8R8A8_UNORM);
+        assert_eq!(description.pixels, vec![30, 20, 10, 255]);
+    }
+
+    #[test]
+    fn rgb16_gains_a_two_byte_alpha_channel() {
+        let pixels = [0x0102u16, 0x0304, 0x0506, 0x0708, 0x090a, 0x0b0c]
+            .iter()
+            .flat_map(|channel| channel.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let mut description = description(vk::Format::R16G16B16_UNORM, 1, 2, pixels);
+        description.convert_rgb_formats().unwrap();
+        assert_eq!(description.format, vk::Format::R16G16B16A16_UNORM);
+
+        let channels = description
+            .pixels
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            channels,
+            vec![
+                0x0102,
+                0x0304,
+                0x0506,
+                u16::MAX,
+                0x0708,
+                0x090a,
+                0x0b0c,
+                u16::MAX
+            ]
+        );
+    }
+
+    #[test]
+    fn rgb_data_of_the_wrong_size_is_rejected() {
+        let mut description = description(vk::Format::R8G8B8_UNORM, 2, 2, vec![0; 6]);
+        assert!(description.convert_rgb_formats().is_err());
     }
 }
@@ -1,6 +1,12 @@
 pub use self::{buffer::*, command_pool::*, image::*, shader::*};
 
+#[cfg(feature = "shader-hot-reload")]
+pub use self::shader_watcher::*;
+
 pub mod buffer;
 pub mod command_pool;
 pub mod image;
 pub mod shader;
+
+#[cfg(feature = "shader-hot-reload")]
+pub mod shader_watcher;
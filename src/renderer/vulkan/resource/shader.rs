@@ -1,15 +1,20 @@
 use crate::renderer::vulkan::core::VulkanContext;
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use ash::{version::DeviceV1_0, vk};
 use derive_builder::Builder;
 use std::{
     collections::HashMap,
     ffi::CString,
+    fs::File,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
 };
 
-pub type ShaderMap = HashMap<String, Arc<Shader>>;
+// The same file can be requested for more than one stage, so the stage is part of the key
+pub type ShaderKey = (String, vk::ShaderStageFlags);
+
+pub type ShaderMap = HashMap<ShaderKey, Arc<Shader>>;
 
 #[derive(Default)]
 pub struct ShaderCache(ShaderMap);
@@ -29,12 +34,18 @@ impl DerefMut for ShaderCache {
 }
 
 impl ShaderCache {
+    // Shaders are only loaded, or compiled, the first time their path is requested for a stage
     pub fn add_shader(
         &mut self,
         context: Arc<VulkanContext>,
         path: &str,
         stage_flags: vk::ShaderStageFlags,
     ) -> Result<Arc<Shader>> {
+        let key = (path.to_string(), stage_flags);
+        if let Some(shader) = self.get(&key) {
+            return Ok(shader.clone());
+        }
+
         let shader = Arc::new(Shader::from_file(
             context,
             &path,
@@ -42,74 +53,97 @@ impl ShaderCache {
             Shader::SHADER_ENTRY_POINT_NAME,
         )?);
 
-        self.insert(path.to_string(), shader.clone());
+        self.insert(key, shader.clone());
 
         Ok(shader)
     }
 
+    // Paths are compared after resolving them, since watched paths are usually absolute.
+    // Returns whether any entry was removed.
+    #[cfg(feature = "shader-hot-reload")]
+    pub fn evict(&mut self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let count = self.len();
+        self.retain(|(key, _), _| {
+            Path::new(key)
+                .canonicalize()
+                .map_or(true, |key_path| key_path != path)
+        });
+        self.len() != count
+    }
+
     pub fn create_shader_set(
         &mut self,
         context: Arc<VulkanContext>,
         shader_paths: &ShaderPathSet,
-    ) -> Result<ShaderSet, std::string::String> {
+    ) -> Result<ShaderSet> {
         // TODO: Can this be made shorter with a macro????
         let mut shader_set_builder = ShaderSetBuilder::default();
-        let vertex_shader = self
-            .add_shader(
-                context.clone(),
-                &shader_paths.vertex,
-                vk::ShaderStageFlags::VERTEX,
-            )
-            .unwrap();
+        let vertex_shader = self.add_shader(
+            context.clone(),
+            &shader_paths.vertex,
+            vk::ShaderStageFlags::VERTEX,
+        )?;
         shader_set_builder.vertex_shader(vertex_shader);
 
         if let Some(fragment_shader_path) = shader_paths.fragment.as_ref() {
-            let fragment_shader = self
-                .add_shader(
-                    context.clone(),
-                    fragment_shader_path,
-                    vk::ShaderStageFlags::FRAGMENT,
-                )
-                .unwrap();
+            let fragment_shader = self.add_shader(
+                context.clone(),
+                fragment_shader_path,
+                vk::ShaderStageFlags::FRAGMENT,
+            )?;
             shader_set_builder.fragment_shader(fragment_shader);
         }
 
         if let Some(geometry_shader_path) = shader_paths.geometry.as_ref() {
-            let geometry_shader = self
-                .add_shader(
-                    context.clone(),
-                    geometry_shader_path,
-                    vk::ShaderStageFlags::GEOMETRY,
-                )
-                .unwrap();
+            let geometry_shader = self.add_shader(
+                context.clone(),
+                geometry_shader_path,
+                vk::ShaderStageFlags::GEOMETRY,
+            )?;
             shader_set_builder.geometry_shader(geometry_shader);
         }
 
         if let Some(tessellation_evaluation_shader_path) =
             shader_paths.tessellation_evaluation.as_ref()
         {
-            let tessellation_evaluation_shader = self
-                .add_shader(
-                    context.clone(),
-                    tessellation_evaluation_shader_path,
-                    vk::ShaderStageFlags::TESSELLATION_EVALUATION,
-                )
-                .unwrap();
+            let tessellation_evaluation_shader = self.add_shader(
+                context.clone(),
+                tessellation_evaluation_shader_path,
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            )?;
             shader_set_builder.tessellation_evaluation_shader(tessellation_evaluation_shader);
         }
 
         if let Some(tessellation_control_shader_path) = shader_paths.tessellation_control.as_ref() {
-            let tessellation_control_shader = self
-                .add_shader(
-                    context,
-                    &tessellation_control_shader_path,
-                    vk::ShaderStageFlags::TESSELLATION_CONTROL,
-                )
-                .unwrap();
+            let tessellation_control_shader = self.add_shader(
+                context,
+                &tessellation_control_shader_path,
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            )?;
             shader_set_builder.tessellation_control_shader(tessellation_control_shader);
         }
 
-        shader_set_builder.build()
+        shader_set_builder.build().map_err(anyhow::Error::msg)
+    }
+}
+
+// A shader cache that can be cloned into other threads, for async loading
+// or building pipelines in parallel. Every clone refers to the same shaders.
+// The lock is held while a shader is loaded, so a shader is never loaded twice.
+#[derive(Clone, Default)]
+pub struct SharedShaderCache(Arc<Mutex<ShaderCache>>);
+
+impl From<ShaderCache> for SharedShaderCache {
+    fn from(shader_cache: ShaderCache) -> Self {
+        Self(Arc::new(Mutex::new(shader_cache)))
+    }
+}
+
+impl SharedShaderCache {
+    // Allows passing the cache to code that takes a `&mut ShaderCache`
+    pub fn lock(&self) -> MutexGuard<'_, ShaderCache> {
+        self.0.lock().expect("Failed to lock the shader cache!")
     }
 }
 
@@ -148,6 +182,11 @@ pub struct Shader {
     _entry_point_name: CString,
 }
 
+// The stage info only points at the entry point name, which is owned by the shader
+// and never modified, so shaders can be shared between threads.
+unsafe impl Send for Shader {}
+unsafe impl Sync for Shader {}
+
 impl Shader {
     pub const SHADER_ENTRY_POINT_NAME: &'static str = "main";
 
@@ -157,10 +196,17 @@ impl Shader {
         flags: vk::ShaderStageFlags,
         entry_point_name: &str,
     ) -> Result<Self> {
+        // Missing SPIR-V usually means the build script couldn't compile the shaders
+        ensure!(
+            Path::new(path).is_file(),
+            "Failed to find {:?} shader '{}'! Check the build output for shader compilation warnings.",
+            flags,
+            path
+        );
+
         let entry_point_name = CString::new(entry_point_name)
             .expect("Failed to create CString for shader entry point name!");
-        let mut shader_file = std::fs::File::open(path)?;
-        let shader_source = ash::util::read_spv(&mut shader_file)?;
+        let shader_source = Self::read_spirv(path, flags, &entry_point_name)?;
         let shader_create_info = vk::ShaderModuleCreateInfo::builder()
             .code(&shader_source)
             .build();
@@ -187,6 +233,70 @@ impl Shader {
         Ok(shader)
     }
 
+    // Paths ending in `.spv` are loaded as SPIR-V, anything else is treated as GLSL source
+    fn read_spirv(
+        path: &str,
+        flags: vk::ShaderStageFlags,
+        entry_point_name: &CString,
+    ) -> Result<Vec<u32>> {
+        if Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            == Some("spv")
+        {
+            let mut shader_file = File::open(path).with_context(|| format!("path: {}", path))?;
+            return Ok(ash::util::read_spv(&mut shader_file)?);
+        }
+        Self::compile_glsl(path, flags, entry_point_name)
+    }
+
+    #[cfg(feature = "runtime-shader-compilation")]
+    fn compile_glsl(
+        path: &str,
+        flags: vk::ShaderStageFlags,
+        entry_point_name: &CString,
+    ) -> Result<Vec<u32>> {
+        let kind = match flags {
+            vk::ShaderStageFlags::VERTEX => shaderc::ShaderKind::Vertex,
+            vk::ShaderStageFlags::FRAGMENT => shaderc::ShaderKind::Fragment,
+            vk::ShaderStageFlags::GEOMETRY => shaderc::ShaderKind::Geometry,
+            vk::ShaderStageFlags::TESSELLATION_EVALUATION => shaderc::ShaderKind::TessEvaluation,
+            vk::ShaderStageFlags::TESSELLATION_CONTROL => shaderc::ShaderKind::TessControl,
+            vk::ShaderStageFlags::COMPUTE => shaderc::ShaderKind::Compute,
+            _ => bail!(
+                "Failed to compile '{}', unsupported shader stage {:?}!",
+                path,
+                flags
+            ),
+        };
+
+        let source = std::fs::read_to_string(path).with_context(|| format!("path: {}", path))?;
+        let mut compiler =
+            shaderc::Compiler::new().context("Failed to create the shader compiler!")?;
+        let artifact =
+            compiler.compile_into_spirv(&source, kind, path, entry_point_name.to_str()?, None)?;
+        if artifact.get_num_warnings() > 0 {
+            log::warn!(
+                "Compiled '{}' with warnings: {}",
+                path,
+                artifact.get_warning_messages()
+            );
+        }
+        Ok(artifact.as_binary().to_vec())
+    }
+
+    #[cfg(not(feature = "runtime-shader-compilation"))]
+    fn compile_glsl(
+        path: &str,
+        _flags: vk::ShaderStageFlags,
+        _entry_point_name: &CString,
+    ) -> Result<Vec<u32>> {
+        bail!(
+            "Failed to load '{}', compiling GLSL requires the runtime-shader-compilation feature!",
+            path
+        )
+    }
+
     pub fn state_info(&self) -> vk::PipelineShaderStageCreateInfo {
         self.state_info
     }
@@ -202,3 +312,34 @@ impl Drop for Shader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn shared_shader_cache_clones_share_one_cache() {
+        let shader_cache = SharedShaderCache::from(ShaderCache::default());
+        let address = {
+            let guard = shader_cache.lock();
+            &*guard as *const ShaderCache as usize
+        };
+
+        let threads = (0..2)
+            .map(|_| {
+                let shader_cache = shader_cache.clone();
+                thread::spawn(move || {
+                    let guard = shader_cache.lock();
+                    (&*guard as *const ShaderCache as usize, guard.len())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            let (thread_address, len) = thread.join().unwrap();
+            assert_eq!(thread_address, address);
+            assert_eq!(len, 0);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use log::debug;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver},
+    time::Duration,
+};
+
+// Reports the files in the shader directory that were written since it was last polled.
+// Both SPIR-V and GLSL source are reported, since either can be loaded into the shader cache.
+pub struct ShaderWatcher {
+    // Dropping the watcher stops the events
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<DebouncedEvent>,
+}
+
+impl ShaderWatcher {
+    // Compilers tend to write a file in several steps
+    const DEBOUNCE_DELAY: Duration = Duration::from_millis(250);
+
+    pub fn new(directory: &str) -> Result<Self> {
+        let (sender, receiver) = channel();
+        let mut watcher = notify::watcher(sender, Self::DEBOUNCE_DELAY)?;
+        watcher.watch(directory, RecursiveMode::Recursive)?;
+        debug!("Watching '{}' for shader changes", directory);
+        Ok(Self {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    // Doesn't block, returns an empty list if nothing changed
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut paths = self
+            .receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => Some(path),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}